@@ -1,17 +1,38 @@
 extern crate cbindgen;
 extern crate cc;
 
-use std::env;
-
 fn main() {
     let crate_dir = String::from(".");
+    let mut config = cbindgen::Config::default();
+    // C enum constants share one namespace, so DsError::NotFound becomes DS_ERROR_NOT_FOUND
+    config.enumeration.rename_variants = cbindgen::RenameRule::QualifiedScreamingSnakeCase;
+    // taken as a plain u32 by ds_best_path_algorithm_set, so not reachable from any signature
+    config
+        .export
+        .include
+        .push(String::from("BestPathAlgorithm"));
+    // the same for the action of ds_policy_term_add
+    config.export.include.push(String::from("PolicyAction"));
+    // and the table of ds_id_range_set and ds_id_stats_get
+    config.export.include.push(String::from("IdTableKind"));
 
     cbindgen::Builder::new()
+        .with_config(config)
         .with_crate(crate_dir)
         .with_language(cbindgen::Language::C)
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("src/c_rust_test.h");
+    // the generated header lives in src/ too, so watching the whole directory would loop
+    for entry in std::fs::read_dir("src").expect("Unable to read src") {
+        let path = entry.expect("Unable to read src entry").path();
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("rs") | Some("c")
+        ) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
     cc::Build::new()
         .file("src/c_rust_test.c")
         .compile("crusttest");
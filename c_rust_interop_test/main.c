@@ -9,6 +9,29 @@ int c_rust_peer_route_entry_iteration_test1();
 int c_rust_prefix_tree_test1();
 int c_rust_ftn_test1();
 int c_rust_ilm_test1();
+int c_rust_data_store_test1();
+int c_rust_vrf_test1();
+int c_rust_prefix_tree_test2();
+int c_rust_route_prefix_len_test1();
+int c_rust_error_test1();
+int c_rust_walk_test1();
+int c_rust_ecmp_test1();
+int c_rust_best_path_test1();
+int c_rust_notify_test1();
+int c_rust_resolve_test1();
+int c_rust_bulk_test1();
+int c_rust_tx_test1();
+int c_rust_snapshot_test1();
+int c_rust_json_test1();
+int c_rust_graceful_restart_test1();
+int c_rust_peer_state_test1();
+int c_rust_dampening_test1();
+int c_rust_policy_test1();
+int c_rust_label_stack_test1();
+int c_rust_ilm_update_test1();
+int c_rust_ilm_owner_test1();
+int c_rust_label_manager_test1();
+int c_rust_id_allocator_test1();
 
 int main(int argc, char**argv)
 {
@@ -39,5 +62,74 @@ int main(int argc, char**argv)
 	if (c_rust_ilm_test1() != 0) {
 		printf("ilm test1 is failed!\n");
 	}
+	if (c_rust_data_store_test1() != 0) {
+		printf("data store test1 is failed!\n");
+	}
+	if (c_rust_vrf_test1() != 0) {
+		printf("vrf test1 is failed!\n");
+	}
+	if (c_rust_prefix_tree_test2() != 0) {
+		printf("prefix_tree_test2 is failed!\n");
+	}
+	if (c_rust_route_prefix_len_test1() != 0) {
+		printf("route_prefix_len_test1 is failed!\n");
+	}
+	if (c_rust_error_test1() != 0) {
+		printf("error_test1 is failed!\n");
+	}
+	if (c_rust_walk_test1() != 0) {
+		printf("walk_test1 is failed!\n");
+	}
+	if (c_rust_ecmp_test1() != 0) {
+		printf("ecmp_test1 is failed!\n");
+	}
+	if (c_rust_best_path_test1() != 0) {
+		printf("best path is failed!\n");
+	}
+	if (c_rust_notify_test1() != 0) {
+		printf("notify is failed!\n");
+	}
+	if (c_rust_resolve_test1() != 0) {
+		printf("resolve test1 is failed!\n");
+	}
+	if (c_rust_bulk_test1() != 0) {
+		printf("bulk test1 is failed!\n");
+	}
+	if (c_rust_tx_test1() != 0) {
+		printf("tx test1 is failed!\n");
+	}
+	if (c_rust_snapshot_test1() != 0) {
+		printf("snapshot test1 is failed!\n");
+	}
+	if (c_rust_json_test1() != 0) {
+		printf("json test1 is failed!\n");
+	}
+	if (c_rust_graceful_restart_test1() != 0) {
+		printf("graceful restart test1 is failed!\n");
+	}
+	if (c_rust_peer_state_test1() != 0) {
+		printf("peer state test1 is failed!\n");
+	}
+	if (c_rust_dampening_test1() != 0) {
+		printf("dampening test1 is failed!\n");
+	}
+	if (c_rust_policy_test1() != 0) {
+		printf("policy test1 is failed!\n");
+	}
+	if (c_rust_label_stack_test1() != 0) {
+		printf("label stack test1 is failed!\n");
+	}
+	if (c_rust_ilm_update_test1() != 0) {
+		printf("ilm update test1 is failed!\n");
+	}
+	if (c_rust_ilm_owner_test1() != 0) {
+		printf("ilm owner test1 is failed!\n");
+	}
+	if (c_rust_label_manager_test1() != 0) {
+		printf("label manager test1 is failed!\n");
+	}
+	if (c_rust_id_allocator_test1() != 0) {
+		printf("id allocator test1 is failed!\n");
+	}
 	return 0;
 }
@@ -1,17 +1,24 @@
 #include<stdlib.h>
 #include<stdint.h>
 #include<stdio.h>
+#include<stddef.h>
+
+/* keep the returned block aligned for any type */
+#define POOL_HDR_SIZE sizeof(max_align_t)
+
+/* widest alignment pool_alloc honors, read by the Rust allocator */
+const size_t pool_max_align = POOL_HDR_SIZE;
 
 uint8_t *pool_alloc(size_t size)
 {
 	uint8_t *ptr;
-	ptr = (uint8_t *)malloc(size+sizeof(unsigned long));
+	ptr = (uint8_t *)malloc(size + POOL_HDR_SIZE);
 	*(unsigned long *)ptr = size;
-	return ptr + sizeof(unsigned long);
+	return ptr + POOL_HDR_SIZE;
 }
 
 void pool_free(uint8_t *ptr)
 {
-	ptr -= sizeof(unsigned long);
+	ptr -= POOL_HDR_SIZE;
 	free(ptr);
 }
@@ -0,0 +1,76 @@
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
+pub const ORIGIN_CONNECTED: u8 = 0;
+pub const ORIGIN_STATIC: u8 = 1;
+pub const ORIGIN_IGP: u8 = 2;
+pub const ORIGIN_BGP: u8 = 3;
+pub const ORIGIN_OTHER: u8 = 4;
+
+pub type PathRank = ((u32, u32, u32), u8, IpAddr);
+
+/// Attributes of the path a peer advertises for a route. Lower admin
+/// distance, metric and origin win, higher local preference wins.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteAttrs {
+    pub admin_distance: u8,
+    /// One of the `ORIGIN_*` constants.
+    pub origin: u8,
+    pub metric: u32,
+    pub local_pref: u32,
+}
+
+impl Default for RouteAttrs {
+    fn default() -> RouteAttrs {
+        RouteAttrs {
+            admin_distance: 0,
+            origin: ORIGIN_OTHER,
+            metric: 0,
+            local_pref: 100,
+        }
+    }
+}
+
+/// Order in which the attributes are compared. Origin and then the lowest
+/// peer address break the remaining ties, so the choice is deterministic.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestPathAlgorithm {
+    /// Admin distance, local preference, metric.
+    DistanceFirst = 0,
+    /// Local preference, admin distance, metric.
+    LocalPrefFirst = 1,
+    /// Metric, admin distance, local preference.
+    MetricFirst = 2,
+}
+
+impl BestPathAlgorithm {
+    pub fn from_u32(value: u32) -> Option<BestPathAlgorithm> {
+        match value {
+            0 => Some(BestPathAlgorithm::DistanceFirst),
+            1 => Some(BestPathAlgorithm::LocalPrefFirst),
+            2 => Some(BestPathAlgorithm::MetricFirst),
+            _ => None,
+        }
+    }
+
+    /// Sort key of a path: the smallest rank is the best path. Routes keep
+    /// their peers ordered by rank, so selection does not rescan them.
+    pub fn rank(&self, peer: &IpAddr, attrs: &RouteAttrs) -> PathRank {
+        let distance = u32::from(attrs.admin_distance);
+        let local_pref = u32::MAX - attrs.local_pref;
+        let metric = attrs.metric;
+        let ordered = match self {
+            BestPathAlgorithm::DistanceFirst => (distance, local_pref, metric),
+            BestPathAlgorithm::LocalPrefFirst => (local_pref, distance, metric),
+            BestPathAlgorithm::MetricFirst => (metric, distance, local_pref),
+        };
+        (ordered, attrs.origin, *peer)
+    }
+
+    /// `Less` if the path of peer `a` is preferred over the path of peer `b`.
+    pub fn compare(&self, a: (&IpAddr, &RouteAttrs), b: (&IpAddr, &RouteAttrs)) -> Ordering {
+        self.rank(a.0, a.1).cmp(&self.rank(b.0, b.1))
+    }
+}
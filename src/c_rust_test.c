@@ -1,5 +1,6 @@
 #include <stdio.h>
 #include <string.h>
+#include <unistd.h>
 #include <sys/socket.h>
 #include <netinet/in.h>
 #include <arpa/inet.h>
@@ -24,6 +25,7 @@ static void setup_peer_entry(PeerEntry *peer_entry, unsigned int *prefix, unsign
 	peer_entry->prefix.family = 1;
 	peer_entry->prefix.addr = (uint8_t *)prefix;
 	peer_entry->out_ifindex = out_ifindex;
+	peer_entry->vrf_id = DEFAULT_VRF_ID;
 }
 
 static void setup_forwarding_entry(ForwardingEntry *forwarding_entry, unsigned int *prefix, unsigned int out_ifindex)
@@ -31,6 +33,10 @@ static void setup_forwarding_entry(ForwardingEntry *forwarding_entry, unsigned i
 	forwarding_entry->next_hop.family = 1;
 	forwarding_entry->next_hop.addr = (uint8_t *)prefix;
 	forwarding_entry->out_ifindex = out_ifindex;
+	forwarding_entry->vrf_id = DEFAULT_VRF_ID;
+	forwarding_entry->prefix.family = 1;
+	forwarding_entry->prefix.addr = NULL;
+	forwarding_entry->prefix_len = 32;
 }
 
 static void setup_route_entry(RouteEntry *route_entry, unsigned int *prefix, unsigned int *mask, unsigned int *next_hop, unsigned int out_ifindex)
@@ -42,6 +48,7 @@ static void setup_route_entry(RouteEntry *route_entry, unsigned int *prefix, uns
 	route_entry->next_hop.family = 1;
 	route_entry->next_hop.addr = (uint8_t *)next_hop;
 	route_entry->out_ifindex = out_ifindex;
+	route_entry->vrf_id = DEFAULT_VRF_ID;
 }
 
 #define PEER_ENTRY_TEST_INITIAL_PEER "1.1.1.1"
@@ -129,7 +136,7 @@ int c_rust_peer_route_entry_test1()
 			return rc;
 		RouteEntry route_entry;
 		for (int j = 0; j < PEER_ROUTE_ENTRY_TEST1_ROUTE_NUMBER; j++) {
-			unsigned int mask = 0xFFFFFF00;
+			unsigned int mask = 0xFFFFFFFF;
 			unsigned int next_hop = 10;
 			build_ip_addr(PEER_ROUTE_ENTRY_TEST1_INITIAL_ROUTE,j, &current_route_prefix);
 			setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, PEER_ROUTE_ENTRY_TEST1_INITIAL_IFINDEX + i);
@@ -138,7 +145,7 @@ int c_rust_peer_route_entry_test1()
 				return rc;
 			next_hop = 0;
 			setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, 0);
-			rc = peer_route_lookup(&peer_entry.prefix,&route_entry.prefix, &route_entry);
+			rc = peer_route_lookup(&peer_entry.prefix,&route_entry.prefix, 32, &route_entry);
 			if (rc != 0)
 				return rc;
 		}
@@ -147,12 +154,12 @@ int c_rust_peer_route_entry_test1()
 		build_ip_addr(PEER_ROUTE_ENTRY_TEST1_INITIAL_PEER,i, &current_peer_prefix);
 		setup_ip_addr(&ip_addr, &current_peer_prefix);
 		for (int j = 0; j < PEER_ROUTE_ENTRY_TEST1_ROUTE_NUMBER; j++) {
-			unsigned int mask = 0xFFFFFF00;
+			unsigned int mask = 0xFFFFFFFF;
 			unsigned int next_hop = 10;
 			RouteEntry route_entry;
 			build_ip_addr(PEER_ROUTE_ENTRY_TEST1_INITIAL_ROUTE,j, &current_route_prefix);
 			setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, 0);
-			rc = peer_route_delete(&ip_addr,&route_entry.prefix);
+			rc = peer_route_delete(&ip_addr,&route_entry.prefix, 32);
 			if (rc != 0)
 				return rc;
 		}
@@ -192,7 +199,7 @@ int c_rust_peer_route_entry_test2()
 		return rc;
 	RouteEntry route_entry;
 	unsigned int current_route_prefix;
-	unsigned int mask = 0xFFFFFF00;
+	unsigned int mask = 0xFFFFFFFF;
 	unsigned int next_hop = 10;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_ROUTE,0, &current_route_prefix);
 	setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, PEER_ROUTE_ENTRY_TEST2_INITIAL_IFINDEX);
@@ -200,7 +207,7 @@ int c_rust_peer_route_entry_test2()
 	rc = peer_route_add_modify(&ip_addr,&route_entry);
 	if (rc != 0)
 		return rc;
-	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, &route_entry);
+	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	// adding one more peer
@@ -215,12 +222,12 @@ int c_rust_peer_route_entry_test2()
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_PEER,1, &current_peer_prefix);
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_ROUTE,0, &current_route_prefix);
-	rc = peer_route_lookup(&peer_entry.prefix,&route_entry.prefix, &route_entry);
+	rc = peer_route_lookup(&peer_entry.prefix,&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_PEER,0, &current_peer_prefix);
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_ROUTE,0, &current_route_prefix);
-	rc = peer_route_delete(&ip_addr,&route_entry.prefix);
+	rc = peer_route_delete(&ip_addr,&route_entry.prefix, 32);
 	if (rc != 0)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_PEER,0, &current_peer_prefix);
@@ -228,7 +235,7 @@ int c_rust_peer_route_entry_test2()
 	if (rc != 0)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_ROUTE,0, &current_route_prefix);
-	rc = route_lookup(&route_entry.prefix, &route_entry);
+	rc = route_lookup(&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_PEER,1, &current_peer_prefix);
@@ -236,7 +243,7 @@ int c_rust_peer_route_entry_test2()
 	if (rc != 0)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST2_INITIAL_ROUTE,0, &current_route_prefix);
-	rc = route_lookup(&route_entry.prefix, &route_entry);
+	rc = route_lookup(&route_entry.prefix, 32, &route_entry);
 	if (rc == 0)
 		return -1;
 	return 0;
@@ -330,7 +337,7 @@ int c_rust_peer_route_entry_test3()
 	printf("done\n");
 	RouteEntry route_entry;
 	unsigned int current_route_prefix;
-	unsigned int mask = 0xFFFFFF00;
+	unsigned int mask = 0xFFFFFFFF;
 	unsigned int next_hop = PEER_ROUTE_ENTRY_TEST3_INITIAL_NEXT_HOP;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST3_INITIAL_ROUTE,0, &current_route_prefix);
 	setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, PEER_ROUTE_ENTRY_TEST3_INITIAL_IFINDEX);
@@ -339,7 +346,7 @@ int c_rust_peer_route_entry_test3()
 	if (rc != 0)
 		return rc;
 
-	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, &route_entry);
+	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	// adding one more peer
@@ -353,7 +360,7 @@ int c_rust_peer_route_entry_test3()
 	if (rc != 0)
 		return rc;
 
-	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, &route_entry);
+	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	//now modify
@@ -365,7 +372,7 @@ int c_rust_peer_route_entry_test3()
 	if (rc != 1)
 		return rc;
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST3_INITIAL_PEER,1, &current_peer_prefix);
-	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, &route_entry);
+	rc = peer_route_lookup(&ip_addr,&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return rc;
 	next_hop = *((unsigned int *)route_entry.next_hop.addr);
@@ -376,7 +383,7 @@ int c_rust_peer_route_entry_test3()
 	}
 	printf("deleting peer1 route\n");
 	build_ip_addr(PEER_ROUTE_ENTRY_TEST3_INITIAL_PEER,0, &current_peer_prefix);
-	rc = peer_route_delete(&ip_addr,&route_entry.prefix);
+	rc = peer_route_delete(&ip_addr,&route_entry.prefix, 32);
 	if (rc != 0)
 		return rc;
 	printf("deleting peer1\n");
@@ -385,7 +392,7 @@ int c_rust_peer_route_entry_test3()
 		return rc;
 
 	printf("looking up for route\n");
-	rc = route_lookup(&route_entry.prefix, &route_entry);
+	rc = route_lookup(&route_entry.prefix, 32, &route_entry);
 	if (rc != 0)
 		return 0;
 	printf("deleting peer2\n");
@@ -395,66 +402,118 @@ int c_rust_peer_route_entry_test3()
 		return rc;
 
 	printf("looking up route\n");
-	rc = route_lookup(&route_entry.prefix, &route_entry);
+	rc = route_lookup(&route_entry.prefix, 32, &route_entry);
 	if (rc == 0)
 		return -1;
 	return 0;
 }
-int callbacks_count = 0;
-int on_peer(const IpAddrC * ip_addr)
-{
-	callbacks_count++;
-	printf("on_peer %s %d %d\n",__FILE__,__LINE__,callbacks_count);
-	return 0;
-}
-
 #define PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER "80.0.0.1"
 #define PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER 2
 #define PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_IFINDEX 3000
-#define PEER_ROUTE_ENTRY_ITERATION_TEST1_MODIFIED_IFINDEX 5000
-#define PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_NEXT_HOP 10
-#define PEER_ROUTE_ENTRY_ITERATION_TEST1_MODIFIED_NEXT_HOP 30
-#define PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_ROUTE "200.200.0.1"
-#define PEER_ROUTE_ENTRY_ITERATION_TEST1_ROUTE_NUMBER 1
+
+typedef struct PeerIterationCtx {
+	DataStore *ds;
+	int callbacks_count;
+	int bad_entries;
+	int stop_after;
+	int delete_peer;
+} PeerIterationCtx;
+
+static int on_peer(const PeerEntry *peer_entry, void *ctx)
+{
+	PeerIterationCtx *iteration_ctx = (PeerIterationCtx *)ctx;
+	unsigned int first_peer;
+	unsigned int peer;
+	int peer_ix;
+
+	iteration_ctx->callbacks_count++;
+	build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER, 0, &first_peer);
+	memcpy(&peer, peer_entry->prefix.addr, sizeof(peer));
+	peer_ix = ntohl(peer) - ntohl(first_peer);
+	printf("on_peer %s %d %d\n",__FILE__,__LINE__,iteration_ctx->callbacks_count);
+	if (peer_entry->prefix.family != 1 ||
+	    peer_ix < 0 || peer_ix >= PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER ||
+	    peer_entry->out_ifindex != (uint32_t)(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_IFINDEX + peer_ix))
+		iteration_ctx->bad_entries++;
+	if (iteration_ctx->delete_peer &&
+	    ds_peer_delete(iteration_ctx->ds, peer_entry->vrf_id, &peer_entry->prefix) != DS_ERROR_OK)
+		iteration_ctx->bad_entries++;
+	return iteration_ctx->callbacks_count == iteration_ctx->stop_after;
+}
 
 int c_rust_peer_route_entry_iteration_test1()
 {
 	int rc = 0;
+	int i;
 	PeerEntry peer_entry;
 	IpAddrC ip_addr;
 	unsigned int current_peer_prefix;
+	PeerIterationCtx ctx;
+	DataStore *ds;
 
 	init_logger();
 
-	build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER,0, &current_peer_prefix);
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	memset(&ctx, 0, sizeof(ctx));
+	ctx.ds = ds;
+
 	setup_ip_addr(&ip_addr, &current_peer_prefix);
-	setup_peer_entry(&peer_entry, &current_peer_prefix, 3);
-	printf("adding first peer\n");
-	rc = peer_add_modify(&ip_addr, &peer_entry);
-	if (rc != 0)
-		return rc;
-	printf("done. lookup...\n");
-	
-	rc = peer_lookup(&ip_addr,&peer_entry);
-	if (rc != 0)
-		return rc;
-	printf("done\n");
-	// adding one more peer
-	build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER,1, &current_peer_prefix);
-	printf("adding second peer\n");
-	rc = peer_add_modify(&ip_addr, &peer_entry);
-	if (rc != 0)
-		return rc;
-	peer_iterate(1);
-	build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER,0, &current_peer_prefix);
-	rc = peer_delete(&ip_addr);
-	if (rc != 0)
-		return rc;
-	build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER,1, &current_peer_prefix);
-	rc = peer_delete(&ip_addr);
-	if (rc != 0)
-		return rc;
-	return callbacks_count == 2 ? 0 : -1;
+	for (i = 0; i < PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER; i++) {
+		build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER, i, &current_peer_prefix);
+		setup_peer_entry(&peer_entry, &current_peer_prefix,
+				 PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_IFINDEX + i);
+		printf("adding peer %d\n", i);
+		rc = ds_peer_add_modify(ds, &ip_addr, &peer_entry);
+		if (rc != 0)
+			goto out;
+	}
+
+	rc = ds_peer_iterate(ds, DEFAULT_VRF_ID, 1, NULL, &ctx);
+	if (rc != DS_ERROR_INVALID_ARGUMENT) {
+		rc = -1;
+		goto out;
+	}
+
+	rc = ds_peer_iterate(ds, DEFAULT_VRF_ID, 1, on_peer, &ctx);
+	if (rc != 0 || ctx.bad_entries != 0 ||
+	    ctx.callbacks_count != PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER) {
+		rc = -1;
+		goto out;
+	}
+
+	printf("stopping after the first peer\n");
+	ctx.callbacks_count = 0;
+	ctx.stop_after = 1;
+	rc = ds_peer_iterate(ds, DEFAULT_VRF_ID, 1, on_peer, &ctx);
+	if (rc != 0 || ctx.bad_entries != 0 || ctx.callbacks_count != 1) {
+		rc = -1;
+		goto out;
+	}
+
+	printf("deleting peers from the callback\n");
+	ctx.callbacks_count = 0;
+	ctx.stop_after = 0;
+	ctx.delete_peer = 1;
+	rc = ds_peer_iterate(ds, DEFAULT_VRF_ID, 1, on_peer, &ctx);
+	if (rc != 0 || ctx.bad_entries != 0 ||
+	    ctx.callbacks_count != PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER) {
+		rc = -1;
+		goto out;
+	}
+	for (i = 0; i < PEER_ROUTE_ENTRY_ITERATION_TEST1_PEER_NUMBER; i++) {
+		build_ip_addr(PEER_ROUTE_ENTRY_ITERATION_TEST1_INITIAL_PEER, i, &current_peer_prefix);
+		peer_entry.vrf_id = DEFAULT_VRF_ID;
+		if (ds_peer_lookup(ds, &ip_addr, &peer_entry) != DS_ERROR_NOT_FOUND) {
+			rc = -1;
+			goto out;
+		}
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
 }
 
 #define PREFIX_TREE_TEST1_INITIAL_PREFIX "180.0.0.1"
@@ -498,11 +557,122 @@ int c_rust_prefix_tree_test1()
 	for (int i = 0; i < PREFIX_TREE_TEST1_ROUTE_NUMBER; i++) {
 		build_ip_addr(PREFIX_TREE_TEST1_INITIAL_PREFIX,i, &current_prefix);
 		setup_ip_addr(&ip_addr, &current_prefix);
-		if (longest_match_delete(&ip_addr) != 0) {
+		if (longest_match_delete(&ip_addr, 32) != 0) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+	}
+	return 0;
+}
+
+#define PREFIX_TREE_TEST2_NEXT_HOP "1.1.1.1"
+
+struct prefix_tree_test2_route {
+	char *prefix;
+	unsigned char prefix_len;
+	unsigned int out_ifindex;
+};
+
+static struct prefix_tree_test2_route prefix_tree_test2_routes[] = {
+	{ "0.0.0.0", 0, 1 },
+	{ "120.0.0.0", 8, 8 },
+	{ "120.1.0.0", 16, 16 },
+	{ "120.1.2.0", 23, 23 },
+};
+
+struct prefix_tree_test2_lookup {
+	char *addr;
+	char *prefix;
+	unsigned char prefix_len;
+	unsigned int out_ifindex;
+};
+
+static struct prefix_tree_test2_lookup prefix_tree_test2_lookups[] = {
+	{ "120.1.3.5", "120.1.2.0", 23, 23 },
+	{ "120.1.2.1", "120.1.2.0", 23, 23 },
+	{ "120.1.4.1", "120.1.0.0", 16, 16 },
+	{ "120.2.0.1", "120.0.0.0", 8, 8 },
+	{ "121.0.0.1", "0.0.0.0", 0, 1 },
+};
+
+#define PREFIX_TREE_TEST2_ROUTES (sizeof(prefix_tree_test2_routes) / sizeof(prefix_tree_test2_routes[0]))
+#define PREFIX_TREE_TEST2_LOOKUPS (sizeof(prefix_tree_test2_lookups) / sizeof(prefix_tree_test2_lookups[0]))
+
+int c_rust_prefix_tree_test2()
+{
+	ForwardingEntry forwarding_entry;
+	IpAddrC ip_addr;
+	unsigned int current_prefix;
+	unsigned int current_next_hop;
+	unsigned int matched_prefix;
+	unsigned int expected_prefix;
+
+	init_logger();
+
+	build_ip_addr(PREFIX_TREE_TEST2_NEXT_HOP,0, &current_next_hop);
+	for (unsigned int i = 0; i < PREFIX_TREE_TEST2_ROUTES; i++) {
+		build_ip_addr(prefix_tree_test2_routes[i].prefix,0, &current_prefix);
+		setup_ip_addr(&ip_addr, &current_prefix);
+		setup_forwarding_entry(&forwarding_entry, &current_next_hop, prefix_tree_test2_routes[i].out_ifindex);
+		forwarding_entry.prefix_len = prefix_tree_test2_routes[i].prefix_len;
+		if (longest_match_add(&ip_addr, &forwarding_entry) != 0) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+	}
+	/* prefix length beyond the address size is rejected */
+	forwarding_entry.prefix_len = 33;
+	if (longest_match_add(&ip_addr, &forwarding_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	for (unsigned int i = 0; i < PREFIX_TREE_TEST2_LOOKUPS; i++) {
+		build_ip_addr(prefix_tree_test2_lookups[i].addr,0, &current_prefix);
+		setup_ip_addr(&ip_addr, &current_prefix);
+		setup_forwarding_entry(&forwarding_entry, &current_next_hop, 0);
+		forwarding_entry.prefix.addr = (uint8_t *)&matched_prefix;
+		if (longest_match_lookup(&ip_addr, &forwarding_entry) != 0) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+		build_ip_addr(prefix_tree_test2_lookups[i].prefix,0, &expected_prefix);
+		if (forwarding_entry.out_ifindex != prefix_tree_test2_lookups[i].out_ifindex ||
+		    forwarding_entry.prefix_len != prefix_tree_test2_lookups[i].prefix_len ||
+		    matched_prefix != expected_prefix) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+	}
+	/* the /23 goes away, the /16 covers its addresses again */
+	build_ip_addr("120.1.2.0",0, &current_prefix);
+	setup_ip_addr(&ip_addr, &current_prefix);
+	if (longest_match_delete(&ip_addr, 24) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (longest_match_delete(&ip_addr, 23) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	build_ip_addr("120.1.3.5",0, &current_prefix);
+	setup_forwarding_entry(&forwarding_entry, &current_next_hop, 0);
+	if (longest_match_lookup(&ip_addr, &forwarding_entry) != 0 ||
+	    forwarding_entry.prefix_len != 16) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	for (unsigned int i = 0; i < PREFIX_TREE_TEST2_ROUTES - 1; i++) {
+		build_ip_addr(prefix_tree_test2_routes[i].prefix,0, &current_prefix);
+		if (longest_match_delete(&ip_addr, prefix_tree_test2_routes[i].prefix_len) != 0) {
 			printf("failed here %s %d\n",__FILE__,__LINE__);
 			return -1;
 		}
 	}
+	build_ip_addr("120.1.3.5",0, &current_prefix);
+	if (longest_match_lookup(&ip_addr, &forwarding_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
 	return 0;
 }
 
@@ -519,11 +689,13 @@ void setup_ftn_entry_add(FtnAddData *ftn_add_data, unsigned int *current_label,
 	ftn_add_data->out_label_number = 1;
 	ftn_add_data->out_label = current_label;
 	ftn_add_data->ftn_ix = ftn_ix;
+	ftn_add_data->vrf_id = DEFAULT_VRF_ID;
 }
 
 void setup_ftn_entry_del(FtnDelData *ftn_del_data, unsigned int ftn_ix)
 {
 	ftn_del_data->ftn_ix = ftn_ix;
+	ftn_del_data->vrf_id = DEFAULT_VRF_ID;
 }
 
 int c_rust_ftn_test1()
@@ -675,6 +847,10 @@ int c_rust_ilm_test1()
 
 	init_logger();
 
+	if (label_block_reserve(ILM_TEST1_INITIAL_OWNER, FTN_TEST1_INITIAL_LABEL, ILM_TEST1_ENTRIES_NUMBER) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
 	for (int i = 0; i < ILM_TEST1_ENTRIES_NUMBER; i++) {
 		build_ip_addr(ILM_TEST1_INITIAL_NEXT_HOP,0, &current_next_hop);
 		setup_ip_addr(&ilm_add_data.next_hop, &current_next_hop);
@@ -732,5 +908,2731 @@ int c_rust_ilm_test1()
 			return -1;
 		}
 	}
+	if (label_block_release(ILM_TEST1_INITIAL_OWNER, FTN_TEST1_INITIAL_LABEL) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+#define DATA_STORE_TEST1_PEER "50.0.0.1"
+#define DATA_STORE_TEST1_IFINDEX 7
+
+int c_rust_data_store_test1()
+{
+	int rc = 0;
+	PeerEntry peer_entry;
+	IpAddrC ip_addr;
+	unsigned int current_peer_prefix;
+	DataStore *ds1, *ds2;
+
+	init_logger();
+
+	ds1 = ds_create();
+	ds2 = ds_create();
+	if (!ds1 || !ds2) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	build_ip_addr(DATA_STORE_TEST1_PEER,0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &current_peer_prefix, DATA_STORE_TEST1_IFINDEX);
+	if (ds_peer_add_modify(ds1, &ip_addr, &peer_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+	setup_peer_entry(&peer_entry, &current_peer_prefix, DATA_STORE_TEST1_IFINDEX);
+	if (ds_peer_add_modify(ds2, &ip_addr, &peer_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+	/* the default store must not see peers of the private ones */
+	if (peer_lookup(&ip_addr, &peer_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+	if (ds_peer_delete(ds1, DEFAULT_VRF_ID, &ip_addr) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+	if (ds_peer_lookup(ds1, &ip_addr, &peer_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+	if (ds_peer_lookup(ds2, &ip_addr, &peer_entry) != 0 ||
+	    peer_entry.out_ifindex != DATA_STORE_TEST1_IFINDEX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		rc = -1;
+		goto out;
+	}
+out:
+	ds_destroy(ds1);
+	ds_destroy(ds2);
+	return rc;
+}
+
+#define VRF_TEST1_VRF_ID 5
+#define VRF_TEST1_PEER "60.0.0.1"
+#define VRF_TEST1_ROUTE "60.10.0.0"
+#define VRF_TEST1_NEXT_HOP "60.0.0.2"
+#define VRF_TEST1_DEFAULT_IFINDEX 3
+#define VRF_TEST1_VRF_IFINDEX 5
+#define VRF_TEST1_FTN_IX 60
+#define VRF_TEST1_LABEL 600
+
+int c_rust_vrf_test1()
+{
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	FtnAddData ftn_add_data;
+	FtnDelData ftn_del_data;
+	IpAddrC ip_addr;
+	unsigned int current_peer_prefix;
+	unsigned int current_route_prefix;
+	unsigned int current_next_hop;
+	unsigned int current_label = VRF_TEST1_LABEL;
+	unsigned int mask = 0x0000FFFF;
+
+	init_logger();
+
+	if (vrf_create(VRF_TEST1_VRF_ID) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (vrf_create(VRF_TEST1_VRF_ID) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* same peer in the default VRF and in the new one */
+	build_ip_addr(VRF_TEST1_PEER,0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &current_peer_prefix, VRF_TEST1_DEFAULT_IFINDEX);
+	if (peer_add_modify(&ip_addr, &peer_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_peer_entry(&peer_entry, &current_peer_prefix, VRF_TEST1_VRF_IFINDEX);
+	peer_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (peer_add_modify(&ip_addr, &peer_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_peer_entry(&peer_entry, &current_peer_prefix, 0);
+	if (peer_lookup(&ip_addr, &peer_entry) != 0 ||
+	    peer_entry.out_ifindex != VRF_TEST1_DEFAULT_IFINDEX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	peer_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (peer_lookup(&ip_addr, &peer_entry) != 0 ||
+	    peer_entry.out_ifindex != VRF_TEST1_VRF_IFINDEX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* route, prefix and FTN only in the new VRF */
+	build_ip_addr(VRF_TEST1_ROUTE,0, &current_route_prefix);
+	build_ip_addr(VRF_TEST1_NEXT_HOP,0, &current_next_hop);
+	setup_route_entry(&route_entry, &current_route_prefix, &mask, &current_next_hop, VRF_TEST1_VRF_IFINDEX);
+	route_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (peer_route_add_modify(&ip_addr, &route_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (route_lookup(&route_entry.prefix, 16, &route_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	route_entry.vrf_id = DEFAULT_VRF_ID;
+	if (route_lookup(&route_entry.prefix, 16, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_forwarding_entry(&forwarding_entry, &current_next_hop, VRF_TEST1_VRF_IFINDEX);
+	forwarding_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (longest_match_add(&route_entry.prefix, &forwarding_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_ip_addr(&ftn_add_data.fec, &current_route_prefix);
+	setup_ip_addr(&ftn_add_data.next_hop, &current_next_hop);
+	setup_ftn_entry_add(&ftn_add_data, &current_label, VRF_TEST1_VRF_IFINDEX, VRF_TEST1_FTN_IX);
+	ftn_add_data.vrf_id = VRF_TEST1_VRF_ID;
+	if (ftn_add(&ftn_add_data) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* deleting the VRF takes everything in it along */
+	if (vrf_delete(VRF_TEST1_VRF_ID) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	peer_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (peer_lookup(&ip_addr, &peer_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (vrf_create(VRF_TEST1_VRF_ID) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (peer_lookup(&ip_addr, &peer_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	route_entry.vrf_id = VRF_TEST1_VRF_ID;
+	if (route_lookup(&route_entry.prefix, 16, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (longest_match_lookup(&route_entry.prefix, &forwarding_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_ip_addr(&ftn_del_data.fec, &current_route_prefix);
+	setup_ftn_entry_del(&ftn_del_data, VRF_TEST1_FTN_IX);
+	ftn_del_data.vrf_id = VRF_TEST1_VRF_ID;
+	if (ftn_del(&ftn_del_data) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* the default VRF is untouched */
+	peer_entry.vrf_id = DEFAULT_VRF_ID;
+	if (peer_lookup(&ip_addr, &peer_entry) != 0 ||
+	    peer_entry.out_ifindex != VRF_TEST1_DEFAULT_IFINDEX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (peer_delete(&ip_addr) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (vrf_delete(VRF_TEST1_VRF_ID) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (vrf_delete(DEFAULT_VRF_ID) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
 	return 0;
-}
\ No newline at end of file
+}
+
+#define ROUTE_PREFIX_LEN_TEST1_PEER "70.0.0.1"
+#define ROUTE_PREFIX_LEN_TEST1_ROUTE "110.0.0.0"
+#define ROUTE_PREFIX_LEN_TEST1_NEXT_HOP "70.0.0.2"
+
+int c_rust_route_prefix_len_test1()
+{
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	IpAddrC ip_addr;
+	IpAddrC route_prefix;
+	unsigned int current_peer_prefix;
+	unsigned int current_route_prefix;
+	unsigned int current_next_hop;
+	unsigned int mask8 = htonl(0xFF000000);
+	unsigned int mask24 = htonl(0xFFFFFF00);
+	unsigned int bad_mask = htonl(0xFF00FF00);
+
+	init_logger();
+
+	build_ip_addr(ROUTE_PREFIX_LEN_TEST1_PEER,0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &current_peer_prefix, 3);
+	if (peer_add_modify(&ip_addr, &peer_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	build_ip_addr(ROUTE_PREFIX_LEN_TEST1_ROUTE,0, &current_route_prefix);
+	setup_ip_addr(&route_prefix, &current_route_prefix);
+	build_ip_addr(ROUTE_PREFIX_LEN_TEST1_NEXT_HOP,0, &current_next_hop);
+	/* same network address, two lengths */
+	setup_route_entry(&route_entry, &current_route_prefix, &mask8, &current_next_hop, 8);
+	if (peer_route_add_modify(&ip_addr, &route_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_route_entry(&route_entry, &current_route_prefix, &mask24, &current_next_hop, 24);
+	if (peer_route_add_modify(&ip_addr, &route_entry) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* non contiguous mask and host bits are rejected */
+	setup_route_entry(&route_entry, &current_route_prefix, &bad_mask, &current_next_hop, 0);
+	if (peer_route_add_modify(&ip_addr, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	build_ip_addr(ROUTE_PREFIX_LEN_TEST1_ROUTE,1, &current_route_prefix);
+	setup_route_entry(&route_entry, &current_route_prefix, &mask24, &current_next_hop, 0);
+	if (peer_route_add_modify(&ip_addr, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	build_ip_addr(ROUTE_PREFIX_LEN_TEST1_ROUTE,0, &current_route_prefix);
+	setup_route_entry(&route_entry, &current_route_prefix, &mask8, &current_next_hop, 0);
+	if (route_lookup(&route_prefix, 8, &route_entry) != 0 || route_entry.out_ifindex != 8) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_route_entry(&route_entry, &current_route_prefix, &mask8, &current_next_hop, 0);
+	if (peer_route_lookup(&ip_addr, &route_prefix, 24, &route_entry) != 0 ||
+	    route_entry.out_ifindex != 24 || mask8 != htonl(0xFFFFFF00)) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	mask8 = htonl(0xFF000000);
+	if (route_lookup(&route_prefix, 16, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* withdrawing the /24 leaves the /8 alone */
+	if (peer_route_delete(&ip_addr, &route_prefix, 24) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (route_lookup(&route_prefix, 24, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (peer_route_lookup(&ip_addr, &route_prefix, 8, &route_entry) != 0 ||
+	    route_entry.out_ifindex != 8) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (peer_route_delete(&ip_addr, &route_prefix, 8) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (route_lookup(&route_prefix, 8, &route_entry) == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return peer_delete(&ip_addr);
+}
+
+#define ERROR_TEST1_PEER "71.0.0.1"
+#define ERROR_TEST1_ROUTE "111.0.0.0"
+#define ERROR_TEST1_VRF_ID 7
+
+int c_rust_error_test1()
+{
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	IpAddrC ip_addr;
+	unsigned int current_peer_prefix;
+	unsigned int current_route_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int next_hop = 0;
+	unsigned int dummy = 0;
+
+	init_logger();
+
+	build_ip_addr(ERROR_TEST1_PEER,0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &dummy, 0);
+	if (peer_lookup(&ip_addr, &peer_entry) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (strstr(ds_last_error(), ERROR_TEST1_PEER) == NULL) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (ds_peer_lookup(NULL, &ip_addr, &peer_entry) != DS_ERROR_INVALID_HANDLE) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	peer_entry.vrf_id = ERROR_TEST1_VRF_ID;
+	if (peer_lookup(&ip_addr, &peer_entry) != DS_ERROR_VRF_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (vrf_delete(DEFAULT_VRF_ID) != DS_ERROR_INVALID_ARGUMENT ||
+	    vrf_create(DEFAULT_VRF_ID) != DS_ERROR_ALREADY_EXISTS) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* route under a peer that does not exist, then with a bad mask */
+	build_ip_addr(ERROR_TEST1_ROUTE,0, &current_route_prefix);
+	setup_route_entry(&route_entry, &current_route_prefix, &mask, &next_hop, 0);
+	if (peer_route_add_modify(&ip_addr, &route_entry) != DS_ERROR_PEER_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_peer_entry(&peer_entry, &current_peer_prefix, 3);
+	if (peer_add_modify(&ip_addr, &peer_entry) != DS_ERROR_OK ||
+	    peer_add_modify(&ip_addr, &peer_entry) != DS_ERROR_MODIFIED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	mask = htonl(0xFF00FF00);
+	if (peer_route_add_modify(&ip_addr, &route_entry) != DS_ERROR_INVALID_PREFIX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (peer_route_lookup(&ip_addr, &route_entry.prefix, 24, &route_entry) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	setup_forwarding_entry(&forwarding_entry, &next_hop, 0);
+	forwarding_entry.prefix_len = 33;
+	if (longest_match_add(&route_entry.prefix, &forwarding_entry) != DS_ERROR_INVALID_PREFIX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	if (strcmp(ds_strerror(DS_ERROR_NOT_FOUND), "entry not found") != 0 ||
+	    strcmp(ds_strerror(12345), "unknown error") != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return peer_delete(&ip_addr);
+}
+
+#define WALK_TEST1_PEER "73.0.0.1"
+#define WALK_TEST1_ROUTE "113.0.0.0"
+#define WALK_TEST1_FEC "114.0.0.1"
+#define WALK_TEST1_NEXT_HOP "2.2.2.2"
+#define WALK_TEST1_ENTRIES_NUMBER 5
+#define WALK_TEST1_PAGE_SIZE 2
+#define WALK_TEST1_INITIAL_LABEL 500
+
+static int walk_test1_routes(DataStore *ds, IpAddrC *peer)
+{
+	RouteEntry entries[WALK_TEST1_PAGE_SIZE];
+	unsigned int prefixes[WALK_TEST1_PAGE_SIZE];
+	unsigned int masks[WALK_TEST1_PAGE_SIZE];
+	unsigned int next_hops[WALK_TEST1_PAGE_SIZE];
+	unsigned int last_prefix = 0;
+	unsigned int last_mask = 0;
+	unsigned int count;
+	int total = 0;
+	int i;
+	int rc;
+
+	for (i = 0; i < WALK_TEST1_PAGE_SIZE; i++)
+		setup_route_entry(&entries[i], &prefixes[i], &masks[i], &next_hops[i], 0);
+	if (peer != NULL)
+		rc = ds_peer_route_get_first(ds, DEFAULT_VRF_ID, peer, entries, WALK_TEST1_PAGE_SIZE, &count);
+	else
+		rc = ds_route_get_first(ds, DEFAULT_VRF_ID, 1, entries, WALK_TEST1_PAGE_SIZE, &count);
+	while (rc == DS_ERROR_OK && count > 0) {
+		for (i = 0; i < (int)count; i++) {
+			if (total > 0 &&
+			    (ntohl(prefixes[i]) < last_prefix ||
+			     (ntohl(prefixes[i]) == last_prefix && ntohl(masks[i]) <= last_mask))) {
+				printf("failed here %s %d\n",__FILE__,__LINE__);
+				return -1;
+			}
+			last_prefix = ntohl(prefixes[i]);
+			last_mask = ntohl(masks[i]);
+			total++;
+		}
+		if (peer != NULL)
+			rc = ds_peer_route_get_next(ds, DEFAULT_VRF_ID, peer, &entries[count - 1],
+						    entries, WALK_TEST1_PAGE_SIZE, &count);
+		else
+			rc = ds_route_get_next(ds, DEFAULT_VRF_ID, &entries[count - 1],
+					       entries, WALK_TEST1_PAGE_SIZE, &count);
+	}
+	if (rc != DS_ERROR_OK || total != WALK_TEST1_ENTRIES_NUMBER + 1) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+static int walk_test1_ftns(DataStore *ds)
+{
+	FtnAddData entries[WALK_TEST1_PAGE_SIZE];
+	unsigned int fecs[WALK_TEST1_PAGE_SIZE];
+	unsigned int next_hops[WALK_TEST1_PAGE_SIZE];
+	unsigned int labels[WALK_TEST1_PAGE_SIZE];
+	unsigned int count;
+	int total = 0;
+	int i;
+	int rc;
+
+	for (i = 0; i < WALK_TEST1_PAGE_SIZE; i++) {
+		setup_ftn_entry_add(&entries[i], &labels[i], 0, 0);
+		setup_ip_addr(&entries[i].fec, &fecs[i]);
+		setup_ip_addr(&entries[i].next_hop, &next_hops[i]);
+	}
+	rc = ds_ftn_get_first(ds, DEFAULT_VRF_ID, 1, entries, WALK_TEST1_PAGE_SIZE, &count);
+	while (rc == DS_ERROR_OK && count > 0) {
+		for (i = 0; i < (int)count; i++) {
+			unsigned int fec;
+			build_ip_addr(WALK_TEST1_FEC, total, &fec);
+			if (fecs[i] != fec || labels[i] != (unsigned int)(WALK_TEST1_INITIAL_LABEL + total)) {
+				printf("failed here %s %d\n",__FILE__,__LINE__);
+				return -1;
+			}
+			total++;
+		}
+		rc = ds_ftn_get_next(ds, DEFAULT_VRF_ID, &entries[count - 1],
+				     entries, WALK_TEST1_PAGE_SIZE, &count);
+	}
+	if (rc != DS_ERROR_OK || total != WALK_TEST1_ENTRIES_NUMBER) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+static int walk_test1_ilms(DataStore *ds)
+{
+	IlmAddData entries[WALK_TEST1_PAGE_SIZE];
+	unsigned int next_hops[WALK_TEST1_PAGE_SIZE];
+	unsigned int count;
+	int total = 0;
+	int i;
+	int rc;
+
+	for (i = 0; i < WALK_TEST1_PAGE_SIZE; i++)
+		setup_ip_addr(&entries[i].next_hop, &next_hops[i]);
+	rc = ds_ilm_get_first(ds, entries, WALK_TEST1_PAGE_SIZE, &count);
+	while (rc == DS_ERROR_OK && count > 0) {
+		for (i = 0; i < (int)count; i++) {
+			if (entries[i].in_label != (unsigned int)(WALK_TEST1_INITIAL_LABEL + total)) {
+				printf("failed here %s %d\n",__FILE__,__LINE__);
+				return -1;
+			}
+			total++;
+		}
+		rc = ds_ilm_get_next(ds, &entries[count - 1], entries, WALK_TEST1_PAGE_SIZE, &count);
+	}
+	if (rc != DS_ERROR_OK || total != WALK_TEST1_ENTRIES_NUMBER) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_walk_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	FtnAddData ftn_add_data;
+	IlmAddData ilm_add_data;
+	IpAddrC ip_addr;
+	unsigned int current_peer_prefix;
+	unsigned int current_prefix;
+	unsigned int next_hop;
+	unsigned int mask;
+	unsigned int current_label;
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(WALK_TEST1_PEER, 0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &current_peer_prefix, 3);
+	if (ds_peer_add_modify(ds, &ip_addr, &peer_entry) != DS_ERROR_OK)
+		goto out;
+	/* added backwards, plus a /16 sharing the network address of the first /24 */
+	build_ip_addr(WALK_TEST1_PEER, 0, &next_hop);
+	setup_route_entry(&route_entry, &current_prefix, &mask, &next_hop, 3);
+	mask = htonl(0xFFFFFF00);
+	for (i = WALK_TEST1_ENTRIES_NUMBER - 1; i >= 0; i--) {
+		build_ip_addr(WALK_TEST1_ROUTE, i << 8, &current_prefix);
+		if (ds_peer_route_add_modify(ds, &ip_addr, &route_entry) != DS_ERROR_OK)
+			goto out;
+	}
+	build_ip_addr(WALK_TEST1_ROUTE, 0, &current_prefix);
+	mask = htonl(0xFFFF0000);
+	if (ds_peer_route_add_modify(ds, &ip_addr, &route_entry) != DS_ERROR_OK)
+		goto out;
+	if (walk_test1_routes(ds, NULL) != 0 || walk_test1_routes(ds, &ip_addr) != 0)
+		goto out;
+
+	build_ip_addr(WALK_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&ftn_add_data.fec, &current_prefix);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hop);
+	for (i = WALK_TEST1_ENTRIES_NUMBER - 1; i >= 0; i--) {
+		build_ip_addr(WALK_TEST1_FEC, i, &current_prefix);
+		current_label = WALK_TEST1_INITIAL_LABEL + i;
+		setup_ftn_entry_add(&ftn_add_data, &current_label, 1, 1);
+		if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_OK)
+			goto out;
+	}
+	if (walk_test1_ftns(ds) != 0)
+		goto out;
+
+	if (ds_label_block_reserve(ds, 1, WALK_TEST1_INITIAL_LABEL, WALK_TEST1_ENTRIES_NUMBER) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	for (i = WALK_TEST1_ENTRIES_NUMBER - 1; i >= 0; i--) {
+		ilm_add_data.in_label = WALK_TEST1_INITIAL_LABEL + i;
+		ilm_add_data.in_iface = 1;
+		ilm_add_data.out_ifindex = 1;
+		ilm_add_data.out_label = WALK_TEST1_INITIAL_LABEL + i;
+		ilm_add_data.ilm_ix = 0;
+		ilm_add_data.owner = 1;
+		if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK)
+			goto out;
+	}
+	if (walk_test1_ilms(ds) != 0)
+		goto out;
+	if (ds_route_get_next(ds, DEFAULT_VRF_ID, NULL, &route_entry, 1, &current_label) != DS_ERROR_INVALID_ARGUMENT)
+		goto out;
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define ECMP_TEST1_PEER "74.0.0.1"
+#define ECMP_TEST1_ROUTE "115.0.0.0"
+#define ECMP_TEST1_PREFIX "116.0.0.0"
+#define ECMP_TEST1_DST "116.1.2.3"
+#define ECMP_TEST1_NEXT_HOP "10.0.0.1"
+#define ECMP_TEST1_FLOWS 1000
+
+static void setup_next_hop_entry(NextHopEntry *path, unsigned int *next_hop, unsigned int out_ifindex, unsigned int weight)
+{
+	path->next_hop.family = 1;
+	path->next_hop.addr = (uint8_t *)next_hop;
+	path->out_ifindex = out_ifindex;
+	path->weight = weight;
+}
+
+int c_rust_ecmp_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	NextHopEntry path;
+	NextHopEntry paths[4];
+	unsigned int path_addrs[4];
+	FlowKey flow;
+	IpAddrC ip_addr;
+	IpAddrC prefix_addr;
+	unsigned int current_peer_prefix;
+	unsigned int current_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int next_hop;
+	unsigned int path_next_hop;
+	unsigned int first_next_hop;
+	unsigned int selected_next_hop;
+	unsigned int src;
+	unsigned int dst;
+	unsigned int count;
+	int hits[2] = {0, 0};
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(ECMP_TEST1_PEER, 0, &current_peer_prefix);
+	setup_ip_addr(&ip_addr, &current_peer_prefix);
+	setup_peer_entry(&peer_entry, &current_peer_prefix, 3);
+	if (ds_peer_add_modify(ds, &ip_addr, &peer_entry) != DS_ERROR_OK)
+		goto out;
+	build_ip_addr(ECMP_TEST1_ROUTE, 0, &current_prefix);
+	build_ip_addr(ECMP_TEST1_NEXT_HOP, 0, &next_hop);
+	first_next_hop = next_hop;
+	setup_ip_addr(&prefix_addr, &current_prefix);
+	setup_route_entry(&route_entry, &current_prefix, &mask, &next_hop, 1);
+	if (ds_peer_route_add_modify(ds, &ip_addr, &route_entry) != DS_ERROR_OK)
+		goto out;
+
+	build_ip_addr(ECMP_TEST1_NEXT_HOP, 1, &path_next_hop);
+	setup_next_hop_entry(&path, &path_next_hop, 2, 1);
+	if (ds_route_path_add(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	path.weight = 3;
+	if (ds_route_path_add(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_MODIFIED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	path.weight = 0;
+	if (ds_route_path_add(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	for (i = 0; i < 4; i++)
+		setup_next_hop_entry(&paths[i], &path_addrs[i], 0, 0);
+	if (ds_route_lookup_paths(ds, DEFAULT_VRF_ID, &prefix_addr, 24, paths, 1, &count) != DS_ERROR_OK ||
+	    count != 2) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_route_lookup_paths(ds, DEFAULT_VRF_ID, &prefix_addr, 24, paths, 4, &count) != DS_ERROR_OK ||
+	    count != 2 ||
+	    path_addrs[0] != first_next_hop || paths[0].out_ifindex != 1 || paths[0].weight != 1 ||
+	    path_addrs[1] != path_next_hop || paths[1].out_ifindex != 2 || paths[1].weight != 3) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the same flow always takes the same path, the heavier path takes more flows */
+	build_ip_addr(ECMP_TEST1_PEER, 0, &src);
+	build_ip_addr(ECMP_TEST1_ROUTE, 1, &dst);
+	setup_ip_addr(&flow.src, &src);
+	setup_ip_addr(&flow.dst, &dst);
+	flow.protocol = 6;
+	flow.dst_port = 179;
+	setup_next_hop_entry(&path, &selected_next_hop, 0, 0);
+	for (i = 0; i < ECMP_TEST1_FLOWS; i++) {
+		unsigned int first_selected;
+
+		flow.src_port = 1024 + i;
+		if (ds_route_select_path(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &flow, &path) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			goto out;
+		}
+		first_selected = selected_next_hop;
+		if (ds_route_select_path(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &flow, &path) != DS_ERROR_OK ||
+		    selected_next_hop != first_selected) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			goto out;
+		}
+		hits[selected_next_hop == first_next_hop ? 0 : 1]++;
+	}
+	if (hits[0] == 0 || hits[1] <= hits[0]) {
+		printf("failed here %s %d %d %d\n",__FILE__,__LINE__, hits[0], hits[1]);
+		goto out;
+	}
+
+	setup_next_hop_entry(&path, &path_next_hop, 2, 1);
+	if (ds_route_path_delete(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_OK ||
+	    ds_route_path_delete(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_next_hop_entry(&path, &next_hop, 1, 1);
+	if (ds_route_path_delete(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &path) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* prefix tree entries */
+	build_ip_addr(ECMP_TEST1_PREFIX, 0, &current_prefix);
+	setup_forwarding_entry(&forwarding_entry, &next_hop, 1);
+	forwarding_entry.prefix_len = 8;
+	if (ds_longest_match_add(ds, &prefix_addr, &forwarding_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_next_hop_entry(&path, &path_next_hop, 2, 1);
+	if (ds_longest_match_path_add(ds, DEFAULT_VRF_ID, &prefix_addr, 8, &path) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	build_ip_addr(ECMP_TEST1_DST, 0, &dst);
+	if (ds_longest_match_lookup_paths(ds, DEFAULT_VRF_ID, &flow.dst, paths, 4, &count) != DS_ERROR_OK ||
+	    count != 2) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_next_hop_entry(&path, &selected_next_hop, 0, 0);
+	if (ds_longest_match_select_path(ds, DEFAULT_VRF_ID, &flow, &path) != DS_ERROR_OK ||
+	    (selected_next_hop != first_next_hop && selected_next_hop != path_next_hop)) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_next_hop_entry(&path, &path_next_hop, 2, 1);
+	if (ds_longest_match_path_delete(ds, DEFAULT_VRF_ID, &prefix_addr, 8, &path) != DS_ERROR_OK ||
+	    ds_longest_match_lookup_paths(ds, DEFAULT_VRF_ID, &flow.dst, paths, 4, &count) != DS_ERROR_OK ||
+	    count != 1) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define BEST_PATH_TEST1_PEER "75.0.0.1"
+#define BEST_PATH_TEST1_PEERS 3
+#define BEST_PATH_TEST1_ROUTE "117.0.0.0"
+#define BEST_PATH_TEST1_NEXT_HOP "10.1.0.1"
+
+static int best_path_test1_check(DataStore *ds, IpAddrC *prefix_addr, unsigned int expected_peer, unsigned int expected_next_hop)
+{
+	RouteEntry route_entry;
+	RouteAttrs attrs;
+	IpAddrC best_peer;
+	unsigned int best_peer_prefix = 0;
+	unsigned int prefix = 0;
+	unsigned int mask = 0;
+	unsigned int next_hop = 0;
+
+	setup_ip_addr(&best_peer, &best_peer_prefix);
+	if (ds_route_lookup_best(ds, DEFAULT_VRF_ID, prefix_addr, 24, &best_peer, &attrs) != DS_ERROR_OK ||
+	    best_peer_prefix != expected_peer) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	/* the plain lookup returns the path of the best peer */
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 0);
+	if (ds_route_lookup(ds, prefix_addr, 24, &route_entry) != DS_ERROR_OK ||
+	    next_hop != expected_next_hop) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_best_path_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	RouteAttrs attrs[BEST_PATH_TEST1_PEERS] = {
+		{ .admin_distance = 20, .origin = ORIGIN_BGP, .metric = 10, .local_pref = 100 },
+		{ .admin_distance = 110, .origin = ORIGIN_IGP, .metric = 5, .local_pref = 200 },
+		{ .admin_distance = 200, .origin = ORIGIN_STATIC, .metric = 1, .local_pref = 50 },
+	};
+	IpAddrC peer_addrs[BEST_PATH_TEST1_PEERS];
+	unsigned int peer_prefixes[BEST_PATH_TEST1_PEERS];
+	unsigned int next_hops[BEST_PATH_TEST1_PEERS];
+	IpAddrC prefix_addr;
+	unsigned int current_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(BEST_PATH_TEST1_ROUTE, 0, &current_prefix);
+	setup_ip_addr(&prefix_addr, &current_prefix);
+	for (i = 0; i < BEST_PATH_TEST1_PEERS; i++) {
+		build_ip_addr(BEST_PATH_TEST1_PEER, i, &peer_prefixes[i]);
+		build_ip_addr(BEST_PATH_TEST1_NEXT_HOP, i, &next_hops[i]);
+		setup_ip_addr(&peer_addrs[i], &peer_prefixes[i]);
+		setup_peer_entry(&peer_entry, &peer_prefixes[i], i + 1);
+		if (ds_peer_add_modify(ds, &peer_addrs[i], &peer_entry) != DS_ERROR_OK)
+			goto out;
+		setup_route_entry(&route_entry, &current_prefix, &mask, &next_hops[i], i + 1);
+		if (ds_peer_route_add_modify_attrs(ds, &peer_addrs[i], &route_entry, &attrs[i]) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			goto out;
+		}
+	}
+
+	/* lowest distance, highest local preference, lowest metric */
+	if (best_path_test1_check(ds, &prefix_addr, peer_prefixes[0], next_hops[0]) != 0)
+		goto out;
+	if (ds_best_path_algorithm_set(ds, BEST_PATH_ALGORITHM_LOCAL_PREF_FIRST) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[1], next_hops[1]) != 0)
+		goto out;
+	if (ds_best_path_algorithm_set(ds, BEST_PATH_ALGORITHM_METRIC_FIRST) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[2], next_hops[2]) != 0)
+		goto out;
+	if (ds_best_path_algorithm_set(ds, 42) != DS_ERROR_INVALID_ARGUMENT)
+		goto out;
+
+	/* a better path replaces the best one in place */
+	attrs[0].metric = 0;
+	setup_route_entry(&route_entry, &current_prefix, &mask, &next_hops[0], 1);
+	if (ds_peer_route_add_modify_attrs(ds, &peer_addrs[0], &route_entry, &attrs[0]) != DS_ERROR_MODIFIED ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[0], next_hops[0]) != 0)
+		goto out;
+	attrs[0].metric = 10;
+	if (ds_peer_route_add_modify_attrs(ds, &peer_addrs[0], &route_entry, &attrs[0]) != DS_ERROR_MODIFIED ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[2], next_hops[2]) != 0)
+		goto out;
+
+	/* withdrawals re-select among the remaining peers */
+	if (ds_peer_route_delete(ds, DEFAULT_VRF_ID, &peer_addrs[2], &prefix_addr, 24) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[1], next_hops[1]) != 0)
+		goto out;
+	if (ds_peer_delete(ds, DEFAULT_VRF_ID, &peer_addrs[1]) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[0], next_hops[0]) != 0)
+		goto out;
+	if (ds_peer_route_delete(ds, DEFAULT_VRF_ID, &peer_addrs[0], &prefix_addr, 24) != DS_ERROR_OK ||
+	    ds_route_lookup_best(ds, DEFAULT_VRF_ID, &prefix_addr, 24, NULL, NULL) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define NOTIFY_TEST1_PEER "76.0.0.1"
+#define NOTIFY_TEST1_ROUTE "118.0.0.0"
+#define NOTIFY_TEST1_NEXT_HOP "10.2.0.1"
+#define NOTIFY_TEST1_LABEL 3000
+#define NOTIFY_TEST1_MAX_EVENTS 16
+
+struct notify_test1_events {
+	DataStore *ds;
+	int count;
+	uint32_t types[NOTIFY_TEST1_MAX_EVENTS];
+	EventOp ops[NOTIFY_TEST1_MAX_EVENTS];
+	uint32_t addrs[NOTIFY_TEST1_MAX_EVENTS];
+	int lookup_failed;
+};
+
+static void on_event(const EventEntry *event, void *ctx)
+{
+	struct notify_test1_events *events = ctx;
+	RouteEntry route_entry;
+	unsigned int prefix = 0;
+	unsigned int mask = 0;
+	unsigned int next_hop = 0;
+
+	if (events->count == NOTIFY_TEST1_MAX_EVENTS)
+		return;
+	events->types[events->count] = event->event_type;
+	events->ops[events->count] = event->op;
+	events->addrs[events->count] = 0;
+	if (event->prefix.addr != NULL)
+		memcpy(&events->addrs[events->count], event->prefix.addr, sizeof(uint32_t));
+	events->count++;
+	/* no table lock is held, so the store can be used from the callback */
+	if (event->event_type == EVENT_ROUTE && event->op == EVENT_OP_ADD) {
+		setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 0);
+		if (ds_route_lookup(events->ds, &event->prefix, event->prefix_len, &route_entry) != DS_ERROR_OK)
+			events->lookup_failed = 1;
+	}
+}
+
+static int notify_test1_expect(struct notify_test1_events *events, int count, const uint32_t *types, const EventOp *ops)
+{
+	int i;
+
+	if (events->count != count || events->lookup_failed) {
+		printf("failed here %s %d: %d events\n",__FILE__,__LINE__, events->count);
+		return -1;
+	}
+	for (i = 0; i < count; i++) {
+		if (events->types[i] != types[i] || events->ops[i] != ops[i]) {
+			printf("failed here %s %d: event %d is %u/%d\n",__FILE__,__LINE__, i, events->types[i], events->ops[i]);
+			return -1;
+		}
+	}
+	events->count = 0;
+	return 0;
+}
+
+int c_rust_notify_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	RouteAttrs attrs = { .admin_distance = 20, .origin = ORIGIN_BGP, .metric = 0, .local_pref = 100 };
+	FtnAddData ftn_add_data;
+	FtnDelData ftn_del_data;
+	IlmAddData ilm_add_data;
+	IlmDelData ilm_del_data;
+	NhAddDel nh_add_del;
+	IpAddrC peer_addrs[2];
+	unsigned int peer_prefixes[2];
+	IpAddrC prefix_addr;
+	unsigned int current_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int next_hop;
+	unsigned int current_label = NOTIFY_TEST1_LABEL;
+	struct notify_test1_events events = { 0 };
+	uint32_t rib_id;
+	uint32_t lfib_id;
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	events.ds = ds;
+	if (ds_subscribe(ds, 0, on_event, &events, &rib_id) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_subscribe(ds, EVENT_ROUTE, NULL, &events, &rib_id) != DS_ERROR_INVALID_ARGUMENT)
+		goto out;
+	if (ds_subscribe(ds, EVENT_ROUTE | EVENT_PEER, on_event, &events, &rib_id) != DS_ERROR_OK)
+		goto out;
+
+	build_ip_addr(NOTIFY_TEST1_ROUTE, 0, &current_prefix);
+	build_ip_addr(NOTIFY_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&prefix_addr, &current_prefix);
+	setup_route_entry(&route_entry, &current_prefix, &mask, &next_hop, 1);
+	for (i = 0; i < 2; i++) {
+		build_ip_addr(NOTIFY_TEST1_PEER, i, &peer_prefixes[i]);
+		setup_ip_addr(&peer_addrs[i], &peer_prefixes[i]);
+		setup_peer_entry(&peer_entry, &peer_prefixes[i], 1);
+		if (ds_peer_add_modify(ds, &peer_addrs[i], &peer_entry) != DS_ERROR_OK)
+			goto out;
+	}
+	if (ds_peer_route_add_modify(ds, &peer_addrs[0], &route_entry) != DS_ERROR_OK)
+		goto out;
+	/* the second peer's path loses best-path selection, so nothing changes */
+	if (ds_peer_route_add_modify_attrs(ds, &peer_addrs[1], &route_entry, &attrs) != DS_ERROR_OK)
+		goto out;
+	{
+		uint32_t types[] = { EVENT_PEER, EVENT_PEER, EVENT_ROUTE };
+		EventOp ops[] = { EVENT_OP_ADD, EVENT_OP_ADD, EVENT_OP_ADD };
+
+		if (notify_test1_expect(&events, 3, types, ops) != 0 || events.addrs[0] != peer_prefixes[0])
+			goto out;
+	}
+	if (ds_peer_delete(ds, DEFAULT_VRF_ID, &peer_addrs[0]) != DS_ERROR_OK ||
+	    ds_peer_route_delete(ds, DEFAULT_VRF_ID, &peer_addrs[1], &prefix_addr, 24) != DS_ERROR_OK)
+		goto out;
+	{
+		uint32_t types[] = { EVENT_ROUTE, EVENT_PEER, EVENT_ROUTE };
+		EventOp ops[] = { EVENT_OP_MODIFY, EVENT_OP_DELETE, EVENT_OP_DELETE };
+
+		if (notify_test1_expect(&events, 3, types, ops) != 0 || events.addrs[2] != current_prefix)
+			goto out;
+	}
+
+	/* FTN and ILM events go to their own subscription */
+	if (ds_subscribe(ds, EVENT_FTN | EVENT_ILM, on_event, &events, &lfib_id) != DS_ERROR_OK ||
+	    ds_unsubscribe(ds, rib_id) != DS_ERROR_OK ||
+	    ds_unsubscribe(ds, rib_id) != DS_ERROR_NOT_FOUND)
+		goto out;
+	if (ds_peer_delete(ds, DEFAULT_VRF_ID, &peer_addrs[1]) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ftn_add_data.fec, &current_prefix);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hop);
+	setup_ftn_entry_add(&ftn_add_data, &current_label, 1, 1);
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ftn_del_data.fec, &current_prefix);
+	setup_ftn_entry_del(&ftn_del_data, 1);
+	if (ds_ftn_del(ds, &ftn_del_data) != DS_ERROR_OK)
+		goto out;
+	if (ds_label_block_reserve(ds, 1, NOTIFY_TEST1_LABEL, 1) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	ilm_add_data.in_label = NOTIFY_TEST1_LABEL;
+	ilm_add_data.in_iface = 1;
+	ilm_add_data.out_ifindex = 1;
+	ilm_add_data.out_label = NOTIFY_TEST1_LABEL + 1;
+	ilm_add_data.ilm_ix = 0;
+	ilm_add_data.owner = 1;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK)
+		goto out;
+	ilm_del_data.in_label = NOTIFY_TEST1_LABEL;
+	ilm_del_data.in_iface = 1;
+	ilm_del_data.ilm_ix = 0;
+	ilm_del_data.owner = 1;
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK)
+		goto out;
+	{
+		uint32_t types[] = { EVENT_FTN, EVENT_FTN, EVENT_FTN, EVENT_ILM, EVENT_ILM };
+		EventOp ops[] = { EVENT_OP_ADD, EVENT_OP_UP, EVENT_OP_DELETE, EVENT_OP_ADD, EVENT_OP_DELETE };
+
+		if (notify_test1_expect(&events, 5, types, ops) != 0 || events.addrs[0] != current_prefix)
+			goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define RESOLVE_TEST1_CONNECTED "10.5.0.0"
+#define RESOLVE_TEST1_LOOPBACK "118.0.0.1"
+#define RESOLVE_TEST1_COVERING "119.0.0.1"
+#define RESOLVE_TEST1_PEER "10.5.0.9"
+#define RESOLVE_TEST1_ROUTE "118.1.0.0"
+
+static int resolve_test1_expect(DataStore *ds, IpAddrC *prefix_addr, unsigned int expected_count, unsigned int expected_next_hop, unsigned int expected_ifindex)
+{
+	NextHopEntry paths[2];
+	unsigned int path_addrs[2] = { 0 };
+	unsigned int count = 0;
+	int i;
+
+	for (i = 0; i < 2; i++)
+		setup_next_hop_entry(&paths[i], &path_addrs[i], 0, 0);
+	if (ds_route_lookup_resolved(ds, DEFAULT_VRF_ID, prefix_addr, 24, paths, 2, &count) != DS_ERROR_OK ||
+	    count != expected_count) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		return -1;
+	}
+	if (count > 0 && (path_addrs[0] != expected_next_hop || paths[0].out_ifindex != expected_ifindex)) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_resolve_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	NhAddDel nh_add_del;
+	IpAddrC peer_addr;
+	IpAddrC prefix_addr;
+	IpAddrC loopback_addr;
+	IpAddrC covering_addr;
+	IpAddrC connected_addr;
+	unsigned int connected = 0;
+	unsigned int unspecified = 0;
+	unsigned int loopback;
+	unsigned int covering;
+	unsigned int gateways[2];
+	unsigned int peer_prefix;
+	unsigned int current_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int count;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+
+	/* 10.5.0.0/24 is on link 5, the loopback is reached through 10.5.0.1 */
+	build_ip_addr(RESOLVE_TEST1_CONNECTED, 0, &connected);
+	build_ip_addr(RESOLVE_TEST1_CONNECTED, 1, &gateways[0]);
+	build_ip_addr(RESOLVE_TEST1_CONNECTED, 2, &gateways[1]);
+	build_ip_addr(RESOLVE_TEST1_LOOPBACK, 0, &loopback);
+	build_ip_addr(RESOLVE_TEST1_COVERING, 0, &covering);
+	setup_ip_addr(&connected_addr, &connected);
+	setup_ip_addr(&loopback_addr, &loopback);
+	setup_ip_addr(&covering_addr, &covering);
+	setup_forwarding_entry(&forwarding_entry, &unspecified, 5);
+	forwarding_entry.prefix_len = 24;
+	if (ds_longest_match_add(ds, &connected_addr, &forwarding_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_forwarding_entry(&forwarding_entry, &gateways[0], 0);
+	if (ds_longest_match_add(ds, &loopback_addr, &forwarding_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the route via the loopback resolves to the gateway on link 5 */
+	build_ip_addr(RESOLVE_TEST1_PEER, 0, &peer_prefix);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_peer_entry(&peer_entry, &peer_prefix, 5);
+	build_ip_addr(RESOLVE_TEST1_ROUTE, 0, &current_prefix);
+	setup_ip_addr(&prefix_addr, &current_prefix);
+	setup_route_entry(&route_entry, &current_prefix, &mask, &loopback, 0);
+	if (ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK)
+		goto out;
+	if (resolve_test1_expect(ds, &prefix_addr, 1, gateways[0], 5) != 0)
+		goto out;
+
+	/* moving the loopback behind the other gateway moves the route too */
+	setup_forwarding_entry(&forwarding_entry, &gateways[1], 0);
+	if (ds_longest_match_add(ds, &loopback_addr, &forwarding_entry) != DS_ERROR_OK ||
+	    resolve_test1_expect(ds, &prefix_addr, 1, gateways[1], 5) != 0)
+		goto out;
+	if (ds_longest_match_delete(ds, DEFAULT_VRF_ID, &loopback_addr, 32) != DS_ERROR_OK ||
+	    resolve_test1_expect(ds, &prefix_addr, 0, 0, 0) != 0)
+		goto out;
+
+	/* 118.0.0.0/8 and 119.0.0.0/8 point at each other */
+	setup_forwarding_entry(&forwarding_entry, &covering, 0);
+	forwarding_entry.prefix_len = 8;
+	if (ds_longest_match_add(ds, &loopback_addr, &forwarding_entry) != DS_ERROR_OK)
+		goto out;
+	setup_forwarding_entry(&forwarding_entry, &loopback, 0);
+	forwarding_entry.prefix_len = 8;
+	if (ds_longest_match_add(ds, &covering_addr, &forwarding_entry) != DS_ERROR_OK ||
+	    resolve_test1_expect(ds, &prefix_addr, 0, 0, 0) != 0)
+		goto out;
+
+	/* an adjacent next hop breaks the loop */
+	setup_ip_addr(&nh_add_del.addr, &covering);
+	nh_add_del.ifindex = 7;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK ||
+	    resolve_test1_expect(ds, &prefix_addr, 1, covering, 7) != 0)
+		goto out;
+	nh_add_del.is_add = false;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK ||
+	    resolve_test1_expect(ds, &prefix_addr, 0, 0, 0) != 0)
+		goto out;
+
+	/* the route itself goes away with its peer */
+	if (ds_peer_delete(ds, DEFAULT_VRF_ID, &peer_addr) != DS_ERROR_OK ||
+	    ds_route_lookup_resolved(ds, DEFAULT_VRF_ID, &prefix_addr, 24, NULL, 0, &count) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define BULK_TEST1_PEER "76.0.0.1"
+#define BULK_TEST1_ROUTE "120.0.0.0"
+#define BULK_TEST1_ROUTES 1000
+#define BULK_TEST1_BAD_ROUTE 500
+#define BULK_TEST1_PREFIX "121.0.0.0"
+#define BULK_TEST1_PREFIXES 100
+#define BULK_TEST1_NEXT_HOP "10.6.0.1"
+#define BULK_TEST1_LSPS 10
+#define BULK_TEST1_LABEL 3000
+
+static int bulk_test1_check(DsError *results, int count, int failed, DsError failed_result, DsError ok_result)
+{
+	int i;
+
+	for (i = 0; i < count; i++) {
+		if (results[i] != (i == failed ? failed_result : ok_result)) {
+			printf("failed here %s %d entry %d result %d\n",__FILE__,__LINE__, i, results[i]);
+			return -1;
+		}
+	}
+	return 0;
+}
+
+int c_rust_bulk_test1()
+{
+	static RouteEntry route_entries[BULK_TEST1_ROUTES];
+	static IpAddrC route_addrs[BULK_TEST1_ROUTES];
+	static unsigned int route_prefixes[BULK_TEST1_ROUTES];
+	static uint8_t prefix_lens[BULK_TEST1_ROUTES];
+	static DsError results[BULK_TEST1_ROUTES];
+	ForwardingEntry forwarding_entries[BULK_TEST1_PREFIXES];
+	IpAddrC prefix_addrs[BULK_TEST1_PREFIXES];
+	unsigned int prefixes[BULK_TEST1_PREFIXES];
+	FtnAddData ftn_add_data[BULK_TEST1_LSPS];
+	FtnDelData ftn_del_data[BULK_TEST1_LSPS];
+	IlmAddData ilm_add_data[BULK_TEST1_LSPS];
+	IlmDelData ilm_del_data[BULK_TEST1_LSPS];
+	unsigned int labels[BULK_TEST1_LSPS];
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	NhAddDel nh_add_del;
+	IpAddrC peer_addr;
+	unsigned int peer_prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int bad_mask = htonl(0xFF00FF00);
+	unsigned int next_hop;
+	unsigned int prefix = 0;
+	unsigned int lookup_mask = 0;
+	unsigned int lookup_next_hop = 0;
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+
+	build_ip_addr(BULK_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(BULK_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	if (ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK)
+		goto out;
+
+	/* a route with a broken mask fails on its own */
+	for (i = 0; i < BULK_TEST1_ROUTES; i++) {
+		build_ip_addr(BULK_TEST1_ROUTE, i << 8, &route_prefixes[i]);
+		setup_ip_addr(&route_addrs[i], &route_prefixes[i]);
+		setup_route_entry(&route_entries[i], &route_prefixes[i], i == BULK_TEST1_BAD_ROUTE ? &bad_mask : &mask, &next_hop, 1);
+		prefix_lens[i] = 24;
+	}
+	if (ds_peer_route_add_modify_bulk(ds, DEFAULT_VRF_ID, &peer_addr, route_entries, NULL, BULK_TEST1_ROUTES, results) != DS_ERROR_INVALID_PREFIX ||
+	    bulk_test1_check(results, BULK_TEST1_ROUTES, BULK_TEST1_BAD_ROUTE, DS_ERROR_INVALID_PREFIX, DS_ERROR_OK) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_route_add_modify_bulk(ds, DEFAULT_VRF_ID, &peer_addr, route_entries, NULL, 2, results) != DS_ERROR_OK ||
+	    results[0] != DS_ERROR_MODIFIED || results[1] != DS_ERROR_MODIFIED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_route_entry(&route_entry, &prefix, &lookup_mask, &lookup_next_hop, 0);
+	if (ds_route_lookup(ds, &route_addrs[BULK_TEST1_ROUTES - 1], 24, &route_entry) != DS_ERROR_OK ||
+	    lookup_next_hop != next_hop) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_route_add_modify_bulk(ds, 9, &peer_addr, route_entries, NULL, 2, results) != DS_ERROR_VRF_NOT_FOUND ||
+	    results[1] != DS_ERROR_VRF_NOT_FOUND ||
+	    ds_peer_route_add_modify_bulk(ds, DEFAULT_VRF_ID, &peer_addr, NULL, NULL, 2, results) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_peer_route_add_modify_bulk(ds, DEFAULT_VRF_ID, &peer_addr, NULL, NULL, 0, NULL) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_route_delete_bulk(ds, DEFAULT_VRF_ID, &peer_addr, route_addrs, prefix_lens, BULK_TEST1_ROUTES, results) != DS_ERROR_NOT_FOUND ||
+	    bulk_test1_check(results, BULK_TEST1_ROUTES, BULK_TEST1_BAD_ROUTE, DS_ERROR_NOT_FOUND, DS_ERROR_OK) != 0 ||
+	    ds_route_lookup(ds, &route_addrs[0], 24, &route_entry) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* prefixes, the last delete repeats the first one */
+	for (i = 0; i < BULK_TEST1_PREFIXES; i++) {
+		build_ip_addr(BULK_TEST1_PREFIX, i << 16, &prefixes[i]);
+		setup_ip_addr(&prefix_addrs[i], &prefixes[i]);
+		setup_forwarding_entry(&forwarding_entries[i], &next_hop, 1);
+		forwarding_entries[i].prefix_len = 16;
+		prefix_lens[i] = 16;
+	}
+	if (ds_longest_match_add_bulk(ds, DEFAULT_VRF_ID, prefix_addrs, forwarding_entries, BULK_TEST1_PREFIXES, results) != DS_ERROR_OK ||
+	    bulk_test1_check(results, BULK_TEST1_PREFIXES, -1, DS_ERROR_OK, DS_ERROR_OK) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	prefix_addrs[BULK_TEST1_PREFIXES - 1] = prefix_addrs[0];
+	if (ds_longest_match_delete_bulk(ds, DEFAULT_VRF_ID, prefix_addrs, prefix_lens, BULK_TEST1_PREFIXES, results) != DS_ERROR_NOT_FOUND ||
+	    bulk_test1_check(results, BULK_TEST1_PREFIXES, BULK_TEST1_PREFIXES - 1, DS_ERROR_NOT_FOUND, DS_ERROR_OK) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* FTN and ILM entries, one of each is invalid */
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, BULK_TEST1_LABEL, BULK_TEST1_LSPS) != DS_ERROR_OK)
+		goto out;
+	for (i = 0; i < BULK_TEST1_LSPS; i++) {
+		labels[i] = BULK_TEST1_LABEL + i;
+		setup_ip_addr(&ftn_add_data[i].fec, &prefixes[i]);
+		setup_ip_addr(&ftn_add_data[i].next_hop, &next_hop);
+		setup_ftn_entry_add(&ftn_add_data[i], &labels[i], 1, 1);
+		setup_ip_addr(&ftn_del_data[i].fec, &prefixes[i]);
+		setup_ftn_entry_del(&ftn_del_data[i], 1);
+		setup_ip_addr(&ilm_add_data[i].next_hop, &next_hop);
+		setup_ilm_entry_add(&ilm_add_data[i], &labels[i], 1, 1, 0);
+		ilm_add_data[i].out_label = labels[i] + BULK_TEST1_LSPS;
+		setup_ilm_entry_del(&ilm_del_data[i], &labels[i], 1, 1, 0);
+	}
+	ftn_add_data[3].out_label = NULL;
+	ilm_add_data[4].in_label = labels[0];
+	ilm_del_data[4].in_label = labels[0];
+	if (ds_ftn_add_bulk(ds, ftn_add_data, BULK_TEST1_LSPS, results) != DS_ERROR_INVALID_ARGUMENT ||
+	    bulk_test1_check(results, BULK_TEST1_LSPS, 3, DS_ERROR_INVALID_ARGUMENT, DS_ERROR_OK) != 0 ||
+	    ds_ftn_del_bulk(ds, ftn_del_data, BULK_TEST1_LSPS, results) != DS_ERROR_NOT_FOUND ||
+	    bulk_test1_check(results, BULK_TEST1_LSPS, 3, DS_ERROR_NOT_FOUND, DS_ERROR_OK) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_ilm_add_bulk(ds, ilm_add_data, BULK_TEST1_LSPS, results) != DS_ERROR_ALREADY_EXISTS ||
+	    bulk_test1_check(results, BULK_TEST1_LSPS, 4, DS_ERROR_ALREADY_EXISTS, DS_ERROR_OK) != 0 ||
+	    ds_ilm_del_bulk(ds, ilm_del_data, BULK_TEST1_LSPS, results) != DS_ERROR_NOT_FOUND ||
+	    bulk_test1_check(results, BULK_TEST1_LSPS, 4, DS_ERROR_NOT_FOUND, DS_ERROR_OK) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_ftn_add_bulk(ds, NULL, 1, results) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_ilm_del_bulk(ds, ilm_del_data, 1, NULL) != DS_ERROR_INVALID_ARGUMENT)
+		goto out;
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define TX_TEST1_PEER "77.0.0.1"
+#define TX_TEST1_MISSING_PEER "77.0.0.2"
+#define TX_TEST1_FEC "122.0.0.0"
+#define TX_TEST1_NEXT_HOP "10.7.0.1"
+#define TX_TEST1_LABEL 4000
+
+static void tx_test1_on_event(const EventEntry *event, void *ctx)
+{
+	int *count = ctx;
+
+	(void)event;
+	(*count)++;
+}
+
+/* the only FTN of the FEC goes through next_hop */
+static int tx_test1_ftn_check(DataStore *ds, unsigned int expected_next_hop)
+{
+	FtnAddData entries[2];
+	IpAddrC fecs[2];
+	unsigned int fec_addrs[2];
+	unsigned int next_hops[2];
+	unsigned int labels[2];
+	uint32_t count = 0;
+	int i;
+
+	for (i = 0; i < 2; i++) {
+		setup_ip_addr(&fecs[i], &fec_addrs[i]);
+		entries[i].fec = fecs[i];
+		setup_ip_addr(&entries[i].next_hop, &next_hops[i]);
+		entries[i].out_label = &labels[i];
+		entries[i].out_label_number = 1;
+	}
+	if (ds_ftn_get_first(ds, DEFAULT_VRF_ID, 1, entries, 2, &count) != DS_ERROR_OK ||
+	    count != 1 || next_hops[0] != expected_next_hop) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_tx_test1()
+{
+	DataStore *ds;
+	Transaction *tx;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	NhAddDel nh_add_del;
+	FtnAddData ftn_add_data;
+	FtnDelData ftn_del_data;
+	IlmAddData ilm_add_data;
+	IlmDelData ilm_del_data;
+	IlmAddData ilm_entries[2];
+	IpAddrC peer_addr;
+	IpAddrC missing_peer_addr;
+	IpAddrC fec_addr;
+	unsigned int peer_prefix;
+	unsigned int missing_peer_prefix;
+	unsigned int fec;
+	unsigned int next_hops[2];
+	unsigned int ilm_next_hops[2];
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int label = TX_TEST1_LABEL;
+	unsigned int prefix = 0;
+	unsigned int lookup_mask = 0;
+	unsigned int lookup_next_hop = 0;
+	uint32_t failed_op = 0;
+	uint32_t count = 0;
+	uint32_t subscription;
+	int events = 0;
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	if (ds_subscribe(ds, EVENT_ALL, tx_test1_on_event, &events, &subscription) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, TX_TEST1_LABEL, 1) != DS_ERROR_OK)
+		goto out;
+
+	build_ip_addr(TX_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(TX_TEST1_MISSING_PEER, 0, &missing_peer_prefix);
+	build_ip_addr(TX_TEST1_FEC, 0, &fec);
+	build_ip_addr(TX_TEST1_NEXT_HOP, 0, &next_hops[0]);
+	build_ip_addr(TX_TEST1_NEXT_HOP, 1, &next_hops[1]);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&missing_peer_addr, &missing_peer_prefix);
+	setup_ip_addr(&fec_addr, &fec);
+
+	/* everything for the FEC in one go */
+	tx = ds_tx_begin();
+	setup_ip_addr(&nh_add_del.addr, &next_hops[0]);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	setup_route_entry(&route_entry, &fec, &mask, &next_hops[0], 1);
+	setup_forwarding_entry(&forwarding_entry, &next_hops[0], 1);
+	forwarding_entry.prefix_len = 24;
+	setup_ip_addr(&ftn_add_data.fec, &fec);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hops[0]);
+	setup_ftn_entry_add(&ftn_add_data, &label, 1, 1);
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hops[0]);
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	if (ds_tx_nh_add_del(tx, &nh_add_del) != DS_ERROR_OK ||
+	    ds_tx_peer_add_modify(tx, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    ds_tx_peer_route_add_modify(tx, &peer_addr, &route_entry, NULL) != DS_ERROR_OK ||
+	    ds_tx_longest_match_add(tx, &fec_addr, &forwarding_entry) != DS_ERROR_OK ||
+	    ds_tx_ftn_add(tx, &ftn_add_data) != DS_ERROR_OK ||
+	    ds_tx_ilm_add(tx, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		ds_tx_abort(tx);
+		goto out;
+	}
+	if (events != 0 || ds_tx_commit(ds, tx, &failed_op) != DS_ERROR_OK || events == 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (tx_test1_ftn_check(ds, next_hops[0]) != 0)
+		goto out;
+
+	/* moving the FEC fails on its last step and leaves everything as it was */
+	events = 0;
+	tx = ds_tx_begin();
+	setup_ip_addr(&ftn_del_data.fec, &fec);
+	setup_ftn_entry_del(&ftn_del_data, 1);
+	setup_ip_addr(&nh_add_del.addr, &next_hops[1]);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hops[1]);
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 1, 0);
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hops[1]);
+	setup_route_entry(&route_entry, &fec, &mask, &next_hops[1], 1);
+	if (ds_tx_ftn_del(tx, &ftn_del_data) != DS_ERROR_OK ||
+	    ds_tx_nh_add_del(tx, &nh_add_del) != DS_ERROR_OK ||
+	    ds_tx_ftn_add(tx, &ftn_add_data) != DS_ERROR_OK ||
+	    ds_tx_ilm_del(tx, &ilm_del_data) != DS_ERROR_OK ||
+	    ds_tx_ilm_add(tx, &ilm_add_data) != DS_ERROR_OK ||
+	    ds_tx_peer_route_add_modify(tx, &peer_addr, &route_entry, NULL) != DS_ERROR_OK ||
+	    ds_tx_longest_match_delete(tx, DEFAULT_VRF_ID, &fec_addr, 24) != DS_ERROR_OK ||
+	    ds_tx_peer_delete(tx, DEFAULT_VRF_ID, &peer_addr) != DS_ERROR_OK ||
+	    ds_tx_peer_route_add_modify(tx, &missing_peer_addr, &route_entry, NULL) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		ds_tx_abort(tx);
+		goto out;
+	}
+	if (ds_tx_commit(ds, tx, &failed_op) != DS_ERROR_PEER_NOT_FOUND || failed_op != 8 || events != 0) {
+		printf("failed here %s %d %u %d\n",__FILE__,__LINE__, failed_op, events);
+		goto out;
+	}
+	/* the detail of the failed step survives the rollback */
+	if (strncmp(ds_last_error(), "transaction op 8 failed: ", 25) != 0 ||
+	    strstr(ds_last_error(), TX_TEST1_MISSING_PEER) == NULL) {
+		printf("failed here %s %d %s\n",__FILE__,__LINE__, ds_last_error());
+		goto out;
+	}
+	if (tx_test1_ftn_check(ds, next_hops[0]) != 0)
+		goto out;
+	setup_route_entry(&route_entry, &prefix, &lookup_mask, &lookup_next_hop, 0);
+	if (ds_route_lookup(ds, &fec_addr, 24, &route_entry) != DS_ERROR_OK ||
+	    lookup_next_hop != next_hops[0] ||
+	    ds_longest_match_lookup(ds, &fec_addr, &forwarding_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	for (i = 0; i < 2; i++)
+		setup_ip_addr(&ilm_entries[i].next_hop, &ilm_next_hops[i]);
+	if (ds_ilm_get_first(ds, ilm_entries, 2, &count) != DS_ERROR_OK || count != 1 ||
+	    ilm_entries[0].in_label != TX_TEST1_LABEL || ilm_next_hops[0] != next_hops[0]) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* without the failing step the move goes through */
+	tx = ds_tx_begin();
+	if (ds_tx_ftn_del(tx, &ftn_del_data) != DS_ERROR_OK ||
+	    ds_tx_nh_add_del(tx, &nh_add_del) != DS_ERROR_OK ||
+	    ds_tx_ftn_add(tx, &ftn_add_data) != DS_ERROR_OK ||
+	    ds_tx_commit(ds, tx, NULL) != DS_ERROR_OK ||
+	    tx_test1_ftn_check(ds, next_hops[1]) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_tx_commit(ds, NULL, NULL) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_tx_ftn_add(NULL, &ftn_add_data) != DS_ERROR_INVALID_ARGUMENT)
+		goto out;
+	tx = ds_tx_begin();
+	if (ds_tx_peer_add_modify(tx, &peer_addr, NULL) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_tx_peer_route_add_modify(tx, &peer_addr, NULL, NULL) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_tx_longest_match_add(tx, &fec_addr, NULL) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		ds_tx_abort(tx);
+		goto out;
+	}
+	ds_tx_abort(tx);
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define SNAPSHOT_TEST1_PEER "78.0.0.1"
+#define SNAPSHOT_TEST1_FEC "123.0.0.0"
+#define SNAPSHOT_TEST1_NEXT_HOP "10.8.0.1"
+#define SNAPSHOT_TEST1_LABEL 5000
+#define SNAPSHOT_TEST1_VRF 5
+#define SNAPSHOT_TEST1_PATH "/tmp/c_rust_snapshot_test1.snap"
+#define SNAPSHOT_TEST1_COPY_PATH "/tmp/c_rust_snapshot_test1_copy.snap"
+#define SNAPSHOT_TEST1_MAX_SIZE 4096
+
+static long snapshot_test1_read(const char *path, unsigned char *buf)
+{
+	FILE *file = fopen(path, "rb");
+	long size;
+
+	if (file == NULL)
+		return -1;
+	size = fread(buf, 1, SNAPSHOT_TEST1_MAX_SIZE, file);
+	fclose(file);
+	return size;
+}
+
+static int snapshot_test1_write(const char *path, unsigned char *buf, long size)
+{
+	FILE *file = fopen(path, "wb");
+	long written;
+
+	if (file == NULL)
+		return -1;
+	written = fwrite(buf, 1, size, file);
+	fclose(file);
+	return written == size ? 0 : -1;
+}
+
+int c_rust_snapshot_test1()
+{
+	static unsigned char saved[SNAPSHOT_TEST1_MAX_SIZE];
+	static unsigned char copy[SNAPSHOT_TEST1_MAX_SIZE];
+	DataStore *ds;
+	DataStore *restored;
+	DataStore *corrupt;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	ForwardingEntry forwarding_entry;
+	NhAddDel nh_add_del;
+	FtnAddData ftn_add_data;
+	FtnDelData ftn_del_data;
+	IlmAddData ilm_add_data;
+	IpAddrC peer_addr;
+	IpAddrC fec_addr;
+	unsigned int peer_prefix;
+	unsigned int fec;
+	unsigned int next_hops[2];
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int labels[3] = {SNAPSHOT_TEST1_LABEL, SNAPSHOT_TEST1_LABEL + 1, SNAPSHOT_TEST1_LABEL + 2};
+	unsigned int prefix = 0;
+	unsigned int lookup_mask = 0;
+	unsigned int lookup_next_hop = 0;
+	LabelBlock blocks[2];
+	uint32_t count = 0;
+	long saved_size;
+	long copy_size;
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	restored = ds_create();
+	corrupt = ds_create();
+	if (ds == NULL || restored == NULL || corrupt == NULL)
+		goto out;
+
+	build_ip_addr(SNAPSHOT_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(SNAPSHOT_TEST1_FEC, 0, &fec);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&fec_addr, &fec);
+	if (ds_vrf_create(ds, SNAPSHOT_TEST1_VRF) != DS_ERROR_OK)
+		goto out;
+	for (i = 0; i < 2; i++) {
+		build_ip_addr(SNAPSHOT_TEST1_NEXT_HOP, i, &next_hops[i]);
+		setup_ip_addr(&nh_add_del.addr, &next_hops[i]);
+		nh_add_del.ifindex = i + 1;
+		nh_add_del.is_add = true;
+		if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+			goto out;
+	}
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	setup_route_entry(&route_entry, &fec, &mask, &next_hops[0], 1);
+	setup_forwarding_entry(&forwarding_entry, &next_hops[0], 1);
+	forwarding_entry.prefix_len = 24;
+	if (ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK ||
+	    ds_longest_match_add(ds, &fec_addr, &forwarding_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* leave a hole in the id tables, so that adding the entries again in
+	 * order would not give them the indexes they have */
+	setup_ip_addr(&ftn_add_data.fec, &fec);
+	for (i = 0; i < 2; i++) {
+		setup_ip_addr(&ftn_add_data.next_hop, &next_hops[i]);
+		setup_ftn_entry_add(&ftn_add_data, &labels[i], i + 1, i + 1);
+		if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_OK)
+			goto out;
+	}
+	setup_ip_addr(&ftn_del_data.fec, &fec);
+	setup_ftn_entry_del(&ftn_del_data, 1);
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hops[0]);
+	setup_ilm_entry_add(&ilm_add_data, &labels[2], 1, 1, 0);
+	if (ds_ftn_del(ds, &ftn_del_data) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, labels[2], 1) != DS_ERROR_OK ||
+	    ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	if (ds_snapshot_save(ds, SNAPSHOT_TEST1_PATH) != DS_ERROR_OK ||
+	    ds_snapshot_restore(restored, SNAPSHOT_TEST1_PATH) != DS_ERROR_OK) {
+		printf("failed here %s %d %s\n",__FILE__,__LINE__, ds_last_error());
+		goto out;
+	}
+	/* the restored store saves to the same bytes, indexes included */
+	if (ds_snapshot_save(restored, SNAPSHOT_TEST1_COPY_PATH) != DS_ERROR_OK)
+		goto out;
+	saved_size = snapshot_test1_read(SNAPSHOT_TEST1_PATH, saved);
+	copy_size = snapshot_test1_read(SNAPSHOT_TEST1_COPY_PATH, copy);
+	if (saved_size <= 0 || saved_size != copy_size || memcmp(saved, copy, saved_size) != 0) {
+		printf("failed here %s %d %ld %ld\n",__FILE__,__LINE__, saved_size, copy_size);
+		goto out;
+	}
+	setup_route_entry(&route_entry, &prefix, &lookup_mask, &lookup_next_hop, 0);
+	if (ds_route_lookup(restored, &fec_addr, 24, &route_entry) != DS_ERROR_OK ||
+	    lookup_next_hop != next_hops[0] ||
+	    ds_longest_match_lookup(restored, &fec_addr, &forwarding_entry) != DS_ERROR_OK ||
+	    ds_vrf_create(restored, SNAPSHOT_TEST1_VRF) != DS_ERROR_ALREADY_EXISTS) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_snapshot_restore(restored, SNAPSHOT_TEST1_PATH) != DS_ERROR_ALREADY_EXISTS) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* the owner keeps its label block, in use by the restored entry */
+	if (ds_label_blocks_get(restored, 0, blocks, 2, &count) != DS_ERROR_OK ||
+	    count != 1 || blocks[0].owner != 1 || blocks[0].start != labels[2] ||
+	    blocks[0].used != 1) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		goto out;
+	}
+
+	saved[saved_size / 2] ^= 0xff;
+	if (snapshot_test1_write(SNAPSHOT_TEST1_COPY_PATH, saved, saved_size) != 0 ||
+	    ds_snapshot_restore(corrupt, SNAPSHOT_TEST1_COPY_PATH) != DS_ERROR_INVALID_DATA ||
+	    ds_snapshot_restore(corrupt, "/nonexistent/snapshot") != DS_ERROR_IO ||
+	    ds_snapshot_save(corrupt, NULL) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	remove(SNAPSHOT_TEST1_PATH);
+	remove(SNAPSHOT_TEST1_COPY_PATH);
+	ds_destroy(corrupt);
+	ds_destroy(restored);
+	ds_destroy(ds);
+	return rc;
+}
+
+#define JSON_TEST1_PEER "80.0.0.1"
+#define JSON_TEST1_FEC "125.0.0.0"
+#define JSON_TEST1_NEXT_HOP "10.10.0.1"
+#define JSON_TEST1_LABEL 6000
+#define JSON_TEST1_PATH "/tmp/c_rust_json_test1.json"
+#define JSON_TEST1_COPY_PATH "/tmp/c_rust_json_test1_copy.json"
+
+int c_rust_json_test1()
+{
+	static unsigned char exported[SNAPSHOT_TEST1_MAX_SIZE];
+	static unsigned char copy[SNAPSHOT_TEST1_MAX_SIZE];
+	DataStore *ds;
+	DataStore *imported;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	NhAddDel nh_add_del;
+	FtnAddData ftn_add_data;
+	IlmAddData ilm_add_data;
+	IpAddrC peer_addr;
+	unsigned int peer_prefix;
+	unsigned int fec;
+	unsigned int next_hop;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int labels[2] = {JSON_TEST1_LABEL, JSON_TEST1_LABEL + 1};
+	long exported_size;
+	long copy_size;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	imported = ds_create();
+	if (ds == NULL || imported == NULL)
+		goto out;
+
+	build_ip_addr(JSON_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(JSON_TEST1_FEC, 0, &fec);
+	build_ip_addr(JSON_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	setup_route_entry(&route_entry, &fec, &mask, &next_hop, 1);
+	setup_ip_addr(&ftn_add_data.fec, &fec);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hop);
+	setup_ftn_entry_add(&ftn_add_data, &labels[0], 1, 1);
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	setup_ilm_entry_add(&ilm_add_data, &labels[1], 1, 1, 0);
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK ||
+	    ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK ||
+	    ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, labels[1], 1) != DS_ERROR_OK ||
+	    ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a dump reloaded and dumped again is the same dump */
+	if (ds_json_export(ds, JSON_TEST1_PATH) != DS_ERROR_OK ||
+	    ds_json_import(imported, JSON_TEST1_PATH) != DS_ERROR_OK ||
+	    ds_json_export(imported, JSON_TEST1_COPY_PATH) != DS_ERROR_OK) {
+		printf("failed here %s %d %s\n",__FILE__,__LINE__, ds_last_error());
+		goto out;
+	}
+	exported_size = snapshot_test1_read(JSON_TEST1_PATH, exported);
+	copy_size = snapshot_test1_read(JSON_TEST1_COPY_PATH, copy);
+	if (exported_size <= 0 || exported_size != copy_size ||
+	    memcmp(exported, copy, exported_size) != 0) {
+		printf("failed here %s %d %ld %ld\n",__FILE__,__LINE__, exported_size, copy_size);
+		goto out;
+	}
+	if (ds_json_import(imported, JSON_TEST1_PATH) != DS_ERROR_ALREADY_EXISTS ||
+	    ds_json_import(imported, "/nonexistent/dump.json") != DS_ERROR_IO) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	remove(JSON_TEST1_PATH);
+	remove(JSON_TEST1_COPY_PATH);
+	ds_destroy(imported);
+	ds_destroy(ds);
+	return rc;
+}
+
+#define GRACEFUL_RESTART_TEST1_PEER "81.0.0.1"
+#define GRACEFUL_RESTART_TEST1_ROUTE "126.0.0.0"
+#define GRACEFUL_RESTART_TEST1_NEXT_HOP "10.11.0.1"
+#define GRACEFUL_RESTART_TEST1_STALE_TIME_MS 100
+
+/* Adds route `i` of the test on behalf of the peer. */
+static int graceful_restart_test1_add(DataStore *ds, IpAddrC *peer_addr, int i)
+{
+	RouteEntry route_entry;
+	unsigned int prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int next_hop;
+
+	build_ip_addr(GRACEFUL_RESTART_TEST1_ROUTE, i << 8, &prefix);
+	build_ip_addr(GRACEFUL_RESTART_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 1);
+	return ds_peer_route_add_modify(ds, peer_addr, &route_entry);
+}
+
+static int graceful_restart_test1_lookup(DataStore *ds, IpAddrC *peer_addr, int i)
+{
+	RouteEntry route_entry;
+	IpAddrC route_addr;
+	unsigned int prefix;
+	unsigned int mask = 0;
+	unsigned int next_hop = 0;
+
+	build_ip_addr(GRACEFUL_RESTART_TEST1_ROUTE, i << 8, &prefix);
+	setup_ip_addr(&route_addr, &prefix);
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 0);
+	return ds_peer_route_lookup(ds, peer_addr, &route_addr, 24, &route_entry);
+}
+
+int c_rust_graceful_restart_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	IpAddrC peer_addr;
+	IpAddrC unknown_addr;
+	unsigned int peer_prefix;
+	unsigned int unknown_prefix;
+	unsigned int swept = 0;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		goto out;
+
+	build_ip_addr(GRACEFUL_RESTART_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(GRACEFUL_RESTART_TEST1_PEER, 1, &unknown_prefix);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&unknown_addr, &unknown_prefix);
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	if (ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    graceful_restart_test1_add(ds, &peer_addr, 0) != DS_ERROR_OK ||
+	    graceful_restart_test1_add(ds, &peer_addr, 1) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the routes survive the restart, the one not added again goes at end-of-RIB */
+	if (ds_peer_restart(ds, DEFAULT_VRF_ID, &peer_addr, 0) != DS_ERROR_OK ||
+	    graceful_restart_test1_lookup(ds, &peer_addr, 0) != DS_ERROR_OK ||
+	    graceful_restart_test1_lookup(ds, &peer_addr, 1) != DS_ERROR_OK ||
+	    graceful_restart_test1_add(ds, &peer_addr, 0) != DS_ERROR_MODIFIED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_end_of_rib(ds, DEFAULT_VRF_ID, &peer_addr, &swept) != DS_ERROR_OK || swept != 1 ||
+	    graceful_restart_test1_lookup(ds, &peer_addr, 0) != DS_ERROR_OK ||
+	    graceful_restart_test1_lookup(ds, &peer_addr, 1) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, swept);
+		goto out;
+	}
+
+	/* without end-of-RIB the stale timer sweeps them */
+	if (ds_peer_restart(ds, DEFAULT_VRF_ID, &peer_addr, GRACEFUL_RESTART_TEST1_STALE_TIME_MS) != DS_ERROR_OK ||
+	    graceful_restart_test1_lookup(ds, &peer_addr, 0) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	usleep(GRACEFUL_RESTART_TEST1_STALE_TIME_MS * 5 * 1000);
+	if (graceful_restart_test1_lookup(ds, &peer_addr, 0) != DS_ERROR_NOT_FOUND ||
+	    ds_peer_end_of_rib(ds, DEFAULT_VRF_ID, &peer_addr, &swept) != DS_ERROR_OK || swept != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	if (ds_peer_restart(ds, DEFAULT_VRF_ID, &unknown_addr, 0) != DS_ERROR_PEER_NOT_FOUND ||
+	    ds_peer_end_of_rib(ds, DEFAULT_VRF_ID, &unknown_addr, NULL) != DS_ERROR_PEER_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define PEER_STATE_TEST1_PEER "83.0.0.1"
+#define PEER_STATE_TEST1_ROUTE "128.0.0.0"
+#define PEER_STATE_TEST1_NEXT_HOP "10.13.0.1"
+
+int c_rust_peer_state_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	PeerStatus status;
+	RouteAttrs attrs[2] = {
+		{ .admin_distance = 20, .origin = ORIGIN_BGP, .metric = 0, .local_pref = 100 },
+		{ .admin_distance = 110, .origin = ORIGIN_IGP, .metric = 0, .local_pref = 100 },
+	};
+	IpAddrC peer_addrs[2];
+	unsigned int peer_prefixes[2];
+	unsigned int next_hops[2];
+	IpAddrC prefix_addr;
+	unsigned int prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	int rc = -1;
+	int i;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(PEER_STATE_TEST1_ROUTE, 0, &prefix);
+	setup_ip_addr(&prefix_addr, &prefix);
+	for (i = 0; i < 2; i++) {
+		build_ip_addr(PEER_STATE_TEST1_PEER, i, &peer_prefixes[i]);
+		build_ip_addr(PEER_STATE_TEST1_NEXT_HOP, i, &next_hops[i]);
+		setup_ip_addr(&peer_addrs[i], &peer_prefixes[i]);
+		setup_peer_entry(&peer_entry, &peer_prefixes[i], i + 1);
+		setup_route_entry(&route_entry, &prefix, &mask, &next_hops[i], i + 1);
+		if (ds_peer_add_modify(ds, &peer_addrs[i], &peer_entry) != DS_ERROR_OK ||
+		    ds_peer_route_add_modify_attrs(ds, &peer_addrs[i], &route_entry, &attrs[i]) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			goto out;
+		}
+	}
+	if (best_path_test1_check(ds, &prefix_addr, peer_prefixes[0], next_hops[0]) != 0)
+		goto out;
+
+	/* going down withdraws the best path and refuses new routes */
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hops[0], 1);
+	if (ds_peer_state_set(ds, DEFAULT_VRF_ID, &peer_addrs[0], PEER_STATE_DOWN) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[1], next_hops[1]) != 0 ||
+	    ds_peer_route_add_modify_attrs(ds, &peer_addrs[0], &route_entry, &attrs[0]) != DS_ERROR_PEER_NOT_ESTABLISHED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_status(ds, DEFAULT_VRF_ID, &peer_addrs[0], &status) != DS_ERROR_OK ||
+	    status.state != PEER_STATE_DOWN || status.flaps != 1 || status.last_change_ms == 0 ||
+	    status.routes_received != 2 || status.routes_accepted != 1) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* only an established session can go down */
+	if (ds_peer_state_set(ds, DEFAULT_VRF_ID, &peer_addrs[0], PEER_STATE_IDLE) != DS_ERROR_OK ||
+	    ds_peer_state_set(ds, DEFAULT_VRF_ID, &peer_addrs[0], PEER_STATE_DOWN) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_peer_state_set(ds, DEFAULT_VRF_ID, &peer_addrs[0], 7) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* back up, the peer wins again once it re-advertises */
+	if (ds_peer_state_set(ds, DEFAULT_VRF_ID, &peer_addrs[0], PEER_STATE_ESTABLISHED) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify_attrs(ds, &peer_addrs[0], &route_entry, &attrs[0]) != DS_ERROR_OK ||
+	    best_path_test1_check(ds, &prefix_addr, peer_prefixes[0], next_hops[0]) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_peer_status(ds, DEFAULT_VRF_ID, &peer_addrs[0], &status) != DS_ERROR_OK ||
+	    status.state != PEER_STATE_ESTABLISHED || status.flaps != 1 ||
+	    status.routes_received != 3 || status.routes_accepted != 2 ||
+	    ds_peer_status(ds, DEFAULT_VRF_ID, &peer_addrs[0], NULL) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define DAMPENING_TEST1_PEER "84.0.0.1"
+#define DAMPENING_TEST1_ROUTE "129.0.0.0"
+#define DAMPENING_TEST1_NEXT_HOP "10.14.0.1"
+#define DAMPENING_TEST1_HALF_LIFE_MS 200
+
+static int dampening_test1_lookup(DataStore *ds, IpAddrC *prefix_addr)
+{
+	RouteEntry route_entry;
+	unsigned int prefix = 0;
+	unsigned int mask = 0;
+	unsigned int next_hop = 0;
+
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 0);
+	return ds_route_lookup(ds, prefix_addr, 24, &route_entry);
+}
+
+/* Three withdrawals in a row push the penalty over the suppress threshold. */
+static int dampening_test1_flap(DataStore *ds, IpAddrC *peer_addr, IpAddrC *prefix_addr, RouteEntry *route_entry)
+{
+	for (int i = 0; i < 3; i++) {
+		if (ds_peer_route_add_modify(ds, peer_addr, route_entry) != DS_ERROR_OK ||
+		    ds_peer_route_delete(ds, DEFAULT_VRF_ID, peer_addr, prefix_addr, 24) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+	}
+	/* the update is kept aside instead of installed */
+	if (ds_peer_route_add_modify(ds, peer_addr, route_entry) != DS_ERROR_OK ||
+	    dampening_test1_lookup(ds, prefix_addr) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_dampening_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	DampenedEntry dampened[2];
+	DampeningConfig config = {
+		.half_life_ms = DAMPENING_TEST1_HALF_LIFE_MS,
+		.reuse_threshold = 750,
+		.suppress_threshold = 2000,
+		.max_suppress_ms = 5 * DAMPENING_TEST1_HALF_LIFE_MS,
+	};
+	DampeningConfig invalid = config;
+	unsigned int dampened_peers[2];
+	unsigned int dampened_prefixes[2];
+	IpAddrC peer_addr;
+	IpAddrC prefix_addr;
+	unsigned int peer_prefix;
+	unsigned int prefix;
+	unsigned int next_hop;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int count = 0;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(DAMPENING_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(DAMPENING_TEST1_ROUTE, 0, &prefix);
+	build_ip_addr(DAMPENING_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&prefix_addr, &prefix);
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 1);
+	for (int i = 0; i < 2; i++) {
+		setup_ip_addr(&dampened[i].peer, &dampened_peers[i]);
+		setup_ip_addr(&dampened[i].prefix, &dampened_prefixes[i]);
+	}
+	invalid.reuse_threshold = invalid.suppress_threshold;
+	if (ds_dampening_set(ds, &invalid) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_dampening_set(ds, &config) != DS_ERROR_OK ||
+	    ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	if (dampening_test1_flap(ds, &peer_addr, &prefix_addr, &route_entry) != 0)
+		goto out;
+	if (ds_dampened_paths_get(ds, DEFAULT_VRF_ID, dampened, 2, &count) != DS_ERROR_OK || count != 1 ||
+	    dampened_peers[0] != peer_prefix || dampened_prefixes[0] != prefix ||
+	    dampened[0].prefix_len != 24 || dampened[0].penalty <= config.suppress_threshold ||
+	    dampened[0].reuse_in_ms == 0) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		goto out;
+	}
+
+	/* the penalty decays below the reuse threshold and the update gets installed */
+	usleep(5 * DAMPENING_TEST1_HALF_LIFE_MS * 1000);
+	if (dampening_test1_lookup(ds, &prefix_addr) != DS_ERROR_OK ||
+	    ds_dampened_paths_get(ds, DEFAULT_VRF_ID, dampened, 2, &count) != DS_ERROR_OK || count != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* turning dampening off releases suppressed paths at once */
+	if (ds_peer_route_delete(ds, DEFAULT_VRF_ID, &peer_addr, &prefix_addr, 24) != DS_ERROR_OK ||
+	    dampening_test1_flap(ds, &peer_addr, &prefix_addr, &route_entry) != 0)
+		goto out;
+	if (ds_dampening_set(ds, NULL) != DS_ERROR_OK ||
+	    dampening_test1_lookup(ds, &prefix_addr) != DS_ERROR_OK ||
+	    ds_dampened_paths_get(ds, DEFAULT_VRF_ID, dampened, 2, &count) != DS_ERROR_OK || count != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define POLICY_TEST1_PEER "85.0.0.1"
+#define POLICY_TEST1_ROUTE "130.1.0.0"
+#define POLICY_TEST1_NEXT_HOP "10.15.0.1"
+#define POLICY_TEST1_DENIED_NEXT_HOP "10.15.0.66"
+#define POLICY_TEST1_SET_NEXT_HOP "10.15.0.2"
+
+static int policy_test1_hits(DataStore *ds, uint64_t expected_deny, uint64_t expected_permit)
+{
+	uint64_t hits[2];
+
+	for (int i = 0; i < 2; i++) {
+		if (ds_policy_term_hits(ds, 1, i, &hits[i]) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			return -1;
+		}
+	}
+	if (hits[0] != expected_deny || hits[1] != expected_permit) {
+		printf("failed here %s %d %lu %lu\n",__FILE__,__LINE__, hits[0], hits[1]);
+		return -1;
+	}
+	return 0;
+}
+
+int c_rust_policy_test1()
+{
+	DataStore *ds;
+	PeerEntry peer_entry;
+	RouteEntry route_entry;
+	RouteAttrs attrs;
+	PrefixListEntry list_entry;
+	PolicyTermEntry deny_term = { .prefix_list_id = 0 };
+	PolicyTermEntry permit_term = { .prefix_list_id = 1 };
+	IpAddrC peer_addr;
+	IpAddrC prefix_addr;
+	IpAddrC best_peer;
+	unsigned int peer_prefix;
+	unsigned int list_prefix;
+	unsigned int prefix;
+	unsigned int mask = htonl(0xFFFFFF00);
+	unsigned int next_hop;
+	unsigned int denied_next_hop;
+	unsigned int set_next_hop;
+	unsigned int best_peer_prefix = 0;
+	unsigned int found_prefix = 0;
+	unsigned int found_mask = 0;
+	unsigned int found_next_hop = 0;
+	uint64_t hits;
+	uint32_t term_ix = 0;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(POLICY_TEST1_PEER, 0, &peer_prefix);
+	build_ip_addr(POLICY_TEST1_ROUTE, 0, &prefix);
+	build_ip_addr("130.0.0.0", 0, &list_prefix);
+	build_ip_addr(POLICY_TEST1_NEXT_HOP, 0, &next_hop);
+	build_ip_addr(POLICY_TEST1_DENIED_NEXT_HOP, 0, &denied_next_hop);
+	build_ip_addr(POLICY_TEST1_SET_NEXT_HOP, 0, &set_next_hop);
+	setup_ip_addr(&peer_addr, &peer_prefix);
+	setup_ip_addr(&prefix_addr, &prefix);
+	setup_ip_addr(&best_peer, &best_peer_prefix);
+	setup_peer_entry(&peer_entry, &peer_prefix, 1);
+
+	/* 130.0.0.0/8 ge 24 le 24, with a start length below the prefix length first */
+	setup_ip_addr(&list_entry.prefix, &list_prefix);
+	list_entry.prefix_len = 8;
+	list_entry.ge = 4;
+	list_entry.le = 24;
+	if (ds_prefix_list_add(ds, 1, &list_entry) != DS_ERROR_INVALID_PREFIX) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	list_entry.ge = 24;
+	if (ds_prefix_list_add(ds, 1, &list_entry) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* deny whatever comes with the bad next hop, permit the list with a lower metric and another next hop */
+	setup_ip_addr(&deny_term.next_hop, &denied_next_hop);
+	deny_term.action = POLICY_ACTION_DENY;
+	permit_term.action = POLICY_ACTION_PERMIT;
+	permit_term.set_flags = POLICY_SET_METRIC | POLICY_SET_NEXT_HOP;
+	permit_term.metric = 42;
+	setup_ip_addr(&permit_term.set_next_hop, &set_next_hop);
+	if (ds_policy_term_add(ds, 1, &deny_term, &term_ix) != DS_ERROR_OK || term_ix != 0 ||
+	    ds_policy_term_add(ds, 1, &permit_term, &term_ix) != DS_ERROR_OK || term_ix != 1) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a route learnt before the policy is attached */
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 1);
+	if (ds_peer_add_modify(ds, &peer_addr, &peer_entry) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK ||
+	    ds_peer_policy_set(ds, DEFAULT_VRF_ID, &peer_addr, 9) != DS_ERROR_NOT_FOUND ||
+	    ds_peer_policy_set(ds, DEFAULT_VRF_ID, &peer_addr, 1) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a denied update withdraws what the peer announced before */
+	setup_route_entry(&route_entry, &prefix, &mask, &denied_next_hop, 1);
+	if (ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_DENIED ||
+	    ds_route_lookup_best(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &best_peer, &attrs) != DS_ERROR_NOT_FOUND ||
+	    policy_test1_hits(ds, 1, 0) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the permit term rewrites the metric and the next hop */
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 1);
+	if (ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK ||
+	    ds_route_lookup_best(ds, DEFAULT_VRF_ID, &prefix_addr, 24, &best_peer, &attrs) != DS_ERROR_OK ||
+	    best_peer_prefix != peer_prefix || attrs.metric != 42 ||
+	    policy_test1_hits(ds, 1, 1) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_route_entry(&route_entry, &found_prefix, &found_mask, &found_next_hop, 0);
+	if (ds_route_lookup(ds, &prefix_addr, 24, &route_entry) != DS_ERROR_OK ||
+	    found_next_hop != set_next_hop) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a /16 is outside the list and falls through to the implicit deny */
+	mask = htonl(0xFFFF0000);
+	setup_route_entry(&route_entry, &prefix, &mask, &next_hop, 1);
+	if (ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_DENIED ||
+	    policy_test1_hits(ds, 1, 1) != 0 ||
+	    ds_policy_term_hits(ds, 1, 2, &hits) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* without the policy the /16 is accepted */
+	if (ds_peer_policy_set(ds, DEFAULT_VRF_ID, &peer_addr, 0) != DS_ERROR_OK ||
+	    ds_peer_route_add_modify(ds, &peer_addr, &route_entry) != DS_ERROR_OK ||
+	    ds_policy_delete(ds, 1) != DS_ERROR_OK ||
+	    ds_policy_term_hits(ds, 1, 0, &hits) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define LABEL_STACK_TEST1_FEC "131.0.0.0"
+#define LABEL_STACK_TEST1_NEXT_HOP "10.16.0.1"
+#define LABEL_STACK_TEST1_DEPTH 3
+
+int c_rust_label_stack_test1()
+{
+	DataStore *ds;
+	NhAddDel nh_add_del;
+	FtnAddData ftn_add_data;
+	FtnAddData entry;
+	unsigned int fec;
+	unsigned int next_hop;
+	unsigned int found_fec;
+	unsigned int found_next_hop;
+	unsigned int labels[LABEL_STACK_TEST1_DEPTH + 1] = {16, 17, 18, 19};
+	unsigned int found_labels[LABEL_STACK_TEST1_DEPTH + 1];
+	uint32_t count = 0;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(LABEL_STACK_TEST1_FEC, 0, &fec);
+	build_ip_addr(LABEL_STACK_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK ||
+	    ds_max_label_depth_set(ds, 0) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_max_label_depth_set(ds, LABEL_DEPTH_LIMIT + 1) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_max_label_depth_set(ds, LABEL_STACK_TEST1_DEPTH) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* stacks deeper than the maximum or empty ones are refused */
+	setup_ip_addr(&ftn_add_data.fec, &fec);
+	setup_ip_addr(&ftn_add_data.next_hop, &next_hop);
+	setup_ftn_entry_add(&ftn_add_data, labels, 1, 1);
+	ftn_add_data.out_label_number = LABEL_STACK_TEST1_DEPTH + 1;
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	ftn_add_data.out_label_number = 0;
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* the count is checked before the stack is read */
+	ftn_add_data.out_label_number = UINT32_MAX;
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* labels have 20 bits */
+	labels[1] = LABEL_MAX + 1;
+	ftn_add_data.out_label_number = LABEL_STACK_TEST1_DEPTH;
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	labels[1] = 17;
+	if (ds_ftn_add(ds, &ftn_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the whole stack comes back, top label first */
+	memset(found_labels, 0, sizeof(found_labels));
+	setup_ftn_entry_add(&entry, found_labels, 0, 0);
+	setup_ip_addr(&entry.fec, &found_fec);
+	setup_ip_addr(&entry.next_hop, &found_next_hop);
+	entry.out_label_number = LABEL_STACK_TEST1_DEPTH + 1;
+	if (ds_ftn_get_first(ds, DEFAULT_VRF_ID, 1, &entry, 1, &count) != DS_ERROR_OK || count != 1 ||
+	    entry.out_label_number != LABEL_STACK_TEST1_DEPTH ||
+	    memcmp(found_labels, labels, LABEL_STACK_TEST1_DEPTH * sizeof(labels[0])) != 0 ||
+	    found_labels[LABEL_STACK_TEST1_DEPTH] != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a buffer too small gets the top of the stack and its depth */
+	memset(found_labels, 0, sizeof(found_labels));
+	entry.out_label_number = 1;
+	if (ds_ftn_get_first(ds, DEFAULT_VRF_ID, 1, &entry, 1, &count) != DS_ERROR_OK || count != 1 ||
+	    entry.out_label_number != LABEL_STACK_TEST1_DEPTH ||
+	    found_labels[0] != labels[0] || found_labels[1] != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define ILM_UPDATE_TEST1_LABEL 920
+#define ILM_UPDATE_TEST1_ILM_IX 5
+#define ILM_UPDATE_TEST1_NEXT_HOP "10.19.0.1"
+
+int c_rust_ilm_update_test1()
+{
+	DataStore *ds;
+	NhAddDel nh_add_del;
+	IlmAddData ilm_add_data;
+	IlmAddData entries[2];
+	unsigned int next_hops[2];
+	unsigned int found_next_hops[2];
+	unsigned int label = ILM_UPDATE_TEST1_LABEL;
+	uint32_t count = 0;
+	int i;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	for (i = 0; i < 2; i++) {
+		build_ip_addr(ILM_UPDATE_TEST1_NEXT_HOP, i, &next_hops[i]);
+		setup_ip_addr(&nh_add_del.addr, &next_hops[i]);
+		nh_add_del.ifindex = i + 1;
+		nh_add_del.is_add = true;
+		if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+			goto out;
+		setup_ip_addr(&entries[i].next_hop, &found_next_hops[i]);
+	}
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hops[0]);
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, ILM_UPDATE_TEST1_ILM_IX);
+	ilm_add_data.out_label = label + 1;
+	if (ds_label_block_reserve(ds, 1, label, 1) != DS_ERROR_OK ||
+	    ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* adding the same in_label, in_iface and ilm_ix again replaces the entry */
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hops[1]);
+	ilm_add_data.out_ifindex = 2;
+	ilm_add_data.out_label = label + 2;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK ||
+	    ds_ilm_get_first(ds, entries, 2, &count) != DS_ERROR_OK || count != 1 ||
+	    entries[0].ilm_ix != ILM_UPDATE_TEST1_ILM_IX || entries[0].out_label != label + 2 ||
+	    entries[0].out_ifindex != 2 || found_next_hops[0] != next_hops[1]) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define ILM_OWNER_TEST1_LABEL 930
+#define ILM_OWNER_TEST1_NEXT_HOP "10.20.0.1"
+#define ILM_OWNER_TEST1_OWNERS 3
+
+static int ilm_owner_test1_expect(DataStore *ds, uint32_t expected_count, const unsigned int *expected_owners, const unsigned int *expected_ixs)
+{
+	IlmAddData entries[ILM_OWNER_TEST1_OWNERS];
+	unsigned int next_hops[ILM_OWNER_TEST1_OWNERS];
+	uint32_t count = 0;
+
+	for (int i = 0; i < ILM_OWNER_TEST1_OWNERS; i++)
+		setup_ip_addr(&entries[i].next_hop, &next_hops[i]);
+	if (ds_ilm_get_first(ds, entries, ILM_OWNER_TEST1_OWNERS, &count) != DS_ERROR_OK ||
+	    count != expected_count) {
+		printf("failed here %s %d %u\n",__FILE__,__LINE__, count);
+		return -1;
+	}
+	for (uint32_t i = 0; i < count; i++) {
+		if (entries[i].owner != expected_owners[i] || entries[i].ilm_ix != expected_ixs[i] ||
+		    entries[i].out_label != ILM_OWNER_TEST1_LABEL + expected_owners[i]) {
+			printf("failed here %s %d %u\n",__FILE__,__LINE__, i);
+			return -1;
+		}
+	}
+	return 0;
+}
+
+int c_rust_ilm_owner_test1()
+{
+	DataStore *ds;
+	NhAddDel nh_add_del;
+	IlmAddData ilm_add_data;
+	IlmDelData ilm_del_data;
+	unsigned int next_hop;
+	unsigned int label = ILM_OWNER_TEST1_LABEL;
+	unsigned int both_owners[2] = {1, 2};
+	unsigned int both_ixs[2] = {1, 2};
+	unsigned int new_owners[2] = {3, 2};
+	unsigned int new_ixs[2] = {1, 2};
+	unsigned int owner;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(ILM_OWNER_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+		goto out;
+	/* the owners share one label, which no label block allows */
+	if (ds_label_check_set(ds, false) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	for (owner = 1; owner <= 2; owner++) {
+		setup_ilm_entry_add(&ilm_add_data, &label, 1, owner, 0);
+		ilm_add_data.out_label = label + owner;
+		if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+			printf("failed here %s %d\n",__FILE__,__LINE__);
+			goto out;
+		}
+	}
+	if (ilm_owner_test1_expect(ds, 2, both_owners, both_ixs) != 0)
+		goto out;
+
+	/* deleting the entry of owner 1 leaves the one of owner 2 alone */
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 1, 0);
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK ||
+	    ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_NOT_FOUND ||
+	    ilm_owner_test1_expect(ds, 1, &both_owners[1], &both_ixs[1]) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* the ilm_ix of the deleted entry is handed out again */
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 3, 0);
+	ilm_add_data.out_label = label + 3;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK ||
+	    ilm_owner_test1_expect(ds, 2, new_owners, new_ixs) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* by ilm_ix, then the last entry of the key */
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 0, 2);
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK ||
+	    ilm_owner_test1_expect(ds, 1, new_owners, new_ixs) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 3, 0);
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK ||
+	    ilm_owner_test1_expect(ds, 0, NULL, NULL) != 0) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define LABEL_MANAGER_TEST1_NEXT_HOP "10.21.0.1"
+#define LABEL_MANAGER_TEST1_STATIC 5000
+
+int c_rust_label_manager_test1()
+{
+	DataStore *ds;
+	NhAddDel nh_add_del;
+	IlmAddData ilm_add_data;
+	IlmDelData ilm_del_data;
+	LabelBlock blocks[4];
+	unsigned int next_hop;
+	unsigned int start1, start2;
+	unsigned int label;
+	unsigned int count;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(LABEL_MANAGER_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+		goto out;
+
+	/* blocks start above the reserved labels, in the free range they fit best */
+	if (ds_label_block_allocate(ds, 1, 10, &start1) != DS_ERROR_OK ||
+	    start1 != LABEL_FIRST_UNRESERVED ||
+	    ds_label_block_allocate(ds, 2, 1, &start2) != DS_ERROR_OK ||
+	    start2 != LABEL_FIRST_UNRESERVED + 10 ||
+	    ds_label_block_allocate(ds, 1, 0, &label) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_label_block_allocate(ds, 1, LABEL_MAX, &label) != DS_ERROR_ID_EXHAUSTED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	if (ds_label_block_reserve(ds, 3, LABEL_MANAGER_TEST1_STATIC, 100) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, LABEL_MANAGER_TEST1_STATIC + 99, 1) != DS_ERROR_ALREADY_EXISTS ||
+	    ds_label_block_reserve(ds, 1, 3, 1) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_label_block_reserve(ds, 1, LABEL_MAX, 2) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* owners only program their own labels */
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	label = start1 + 9;
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = 100;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 2, 0);
+	ilm_add_data.out_label = 100;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_NOT_ALLOCATED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	label = LABEL_MANAGER_TEST1_STATIC - 1;
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = 100;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_NOT_ALLOCATED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* reserved and over 20-bit labels are refused with checking off too */
+	if (ds_label_check_set(ds, false) != DS_ERROR_OK)
+		goto out;
+	label = 3;
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = 100;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	label = LABEL_MAX + 1;
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = 100;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_label_check_set(ds, true) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	if (ds_label_blocks_get(ds, 0, blocks, 4, &count) != DS_ERROR_OK ||
+	    count != 3 ||
+	    blocks[0].owner != 1 || blocks[0].start != start1 ||
+	    blocks[0].count != 10 || blocks[0].is_static || blocks[0].used != 1 ||
+	    blocks[1].owner != 2 || blocks[1].used != 0 ||
+	    blocks[2].owner != 3 || !blocks[2].is_static ||
+	    blocks[2].start != LABEL_MANAGER_TEST1_STATIC) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* a page goes on one past the start of the last block */
+	if (ds_label_blocks_get(ds, 0, blocks, 2, &count) != DS_ERROR_OK ||
+	    count != 2 || blocks[1].start != start2 ||
+	    ds_label_blocks_get(ds, start2 + 1, blocks, 2, &count) != DS_ERROR_OK ||
+	    count != 1 || blocks[0].start != LABEL_MANAGER_TEST1_STATIC) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* a block goes back once its labels are no longer used */
+	if (ds_label_block_release(ds, 1, start1) != DS_ERROR_IN_USE ||
+	    ds_label_block_release(ds, 2, start1) != DS_ERROR_NOT_FOUND) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	label = start1 + 9;
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 1, 0);
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK ||
+	    ds_label_block_release(ds, 1, start1) != DS_ERROR_OK ||
+	    ds_label_block_allocate(ds, 4, 10, &label) != DS_ERROR_OK ||
+	    label != start1) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
+
+#define ID_ALLOCATOR_TEST1_NEXT_HOP "10.24.0.1"
+#define ID_ALLOCATOR_TEST1_LABEL 960
+
+int c_rust_id_allocator_test1()
+{
+	DataStore *ds;
+	NhAddDel nh_add_del;
+	IlmAddData ilm_add_data;
+	IlmDelData ilm_del_data;
+	IdStats stats;
+	unsigned int next_hop;
+	unsigned int label = ID_ALLOCATOR_TEST1_LABEL;
+	int rc = -1;
+
+	init_logger();
+
+	ds = ds_create();
+	if (ds == NULL)
+		return -1;
+	build_ip_addr(ID_ALLOCATOR_TEST1_NEXT_HOP, 0, &next_hop);
+	setup_ip_addr(&nh_add_del.addr, &next_hop);
+	nh_add_del.ifindex = 1;
+	nh_add_del.is_add = true;
+	if (ds_nh_add_del(ds, &nh_add_del) != DS_ERROR_OK)
+		goto out;
+
+	if (ds_id_stats_get(ds, ID_TABLE_KIND_XC, &stats) != DS_ERROR_OK ||
+	    stats.first != ID_FIRST || stats.last != ID_LAST || stats.used != 0 ||
+	    ds_id_stats_get(ds, 3, &stats) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_id_stats_get(ds, ID_TABLE_KIND_XC, NULL) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_id_range_set(ds, 3, 1, 1) != DS_ERROR_INVALID_ARGUMENT ||
+	    ds_id_range_set(ds, ID_TABLE_KIND_XC, 5, 4) != DS_ERROR_INVALID_ARGUMENT) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	/* one XC index leaves room for one ILM entry */
+	if (ds_id_range_set(ds, ID_TABLE_KIND_XC, 100, 100) != DS_ERROR_OK ||
+	    ds_label_block_reserve(ds, 1, ID_ALLOCATOR_TEST1_LABEL, 2) != DS_ERROR_OK)
+		goto out;
+	setup_ip_addr(&ilm_add_data.next_hop, &next_hop);
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = label;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	label = ID_ALLOCATOR_TEST1_LABEL + 1;
+	setup_ilm_entry_add(&ilm_add_data, &label, 1, 1, 0);
+	ilm_add_data.out_label = label;
+	if (ds_ilm_add(ds, &ilm_add_data) != DS_ERROR_ID_EXHAUSTED) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	/* the XC table ran out, so the failed entry took no NHLFE index either */
+	if (ds_id_stats_get(ds, ID_TABLE_KIND_XC, &stats) != DS_ERROR_OK ||
+	    stats.first != 100 || stats.used != 1 || stats.peak != 1 ||
+	    stats.exhausted != 1 ||
+	    ds_id_stats_get(ds, ID_TABLE_KIND_NHLFE, &stats) != DS_ERROR_OK ||
+	    stats.used != 1 ||
+	    ds_id_range_set(ds, ID_TABLE_KIND_XC, 200, 300) != DS_ERROR_IN_USE) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+
+	label = ID_ALLOCATOR_TEST1_LABEL;
+	setup_ilm_entry_del(&ilm_del_data, &label, 1, 1, 0);
+	if (ds_ilm_del(ds, &ilm_del_data) != DS_ERROR_OK ||
+	    ds_id_stats_get(ds, ID_TABLE_KIND_XC, &stats) != DS_ERROR_OK ||
+	    stats.used != 0 ||
+	    ds_id_range_set(ds, ID_TABLE_KIND_XC, 200, 300) != DS_ERROR_OK) {
+		printf("failed here %s %d\n",__FILE__,__LINE__);
+		goto out;
+	}
+	rc = 0;
+out:
+	ds_destroy(ds);
+	return rc;
+}
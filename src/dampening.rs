@@ -0,0 +1,134 @@
+//! Route flap dampening after RFC 2439. Every (peer, prefix) collects a
+//! penalty when the peer withdraws the route or changes what it advertises;
+//! the penalty halves every half-life. Above the suppress threshold the
+//! peer's path is taken out of best-path selection, and the last update the
+//! peer sent is kept aside until the penalty is back below the reuse
+//! threshold.
+
+use crate::errors::*;
+use crate::Route;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+pub const DAMPENING_WITHDRAW_PENALTY: u32 = 1000;
+/// Penalty of a re-advertisement with other paths or attributes.
+pub const DAMPENING_UPDATE_PENALTY: u32 = 500;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DampeningConfig {
+    pub half_life_ms: u32,
+    pub reuse_threshold: u32,
+    pub suppress_threshold: u32,
+    /// Longest a path stays suppressed after its last flap; caps the
+    /// penalty.
+    pub max_suppress_ms: u32,
+}
+
+impl Default for DampeningConfig {
+    fn default() -> DampeningConfig {
+        DampeningConfig {
+            half_life_ms: 15 * 60 * 1000,
+            reuse_threshold: 750,
+            suppress_threshold: 2000,
+            max_suppress_ms: 60 * 60 * 1000,
+        }
+    }
+}
+
+impl DampeningConfig {
+    pub(crate) fn check(&self) -> Result<(), DsError> {
+        if self.half_life_ms == 0
+            || self.reuse_threshold == 0
+            || self.reuse_threshold >= self.suppress_threshold
+            || self.max_suppress_ms == 0
+        {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!("invalid dampening config {:?}", self),
+            ));
+        }
+        Ok(())
+    }
+    fn max_penalty(&self) -> f64 {
+        f64::from(self.reuse_threshold)
+            * 2f64.powf(f64::from(self.max_suppress_ms) / f64::from(self.half_life_ms))
+    }
+}
+
+/// A suppressed path, as listed by `Rib::dampened_paths`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DampenedPath {
+    pub peer: IpAddr,
+    pub prefix: IpAddr,
+    pub prefix_len: u8,
+    pub penalty: u32,
+    /// Time left until the penalty decays to the reuse threshold.
+    pub reuse_in: Duration,
+}
+
+/// Flap history of one route of one peer.
+#[derive(Debug, Clone)]
+pub(crate) struct FlapState {
+    penalty: f64,
+    updated: Instant,
+    pub(crate) suppressed: bool,
+    /// Last update of the peer while suppressed, installed on reuse.
+    pub(crate) pending: Option<Route>,
+}
+
+impl FlapState {
+    pub(crate) fn new() -> FlapState {
+        FlapState {
+            penalty: 0.0,
+            updated: Instant::now(),
+            suppressed: false,
+            pending: None,
+        }
+    }
+    fn decayed(&self, config: &DampeningConfig) -> f64 {
+        let half_lives =
+            self.updated.elapsed().as_secs_f64() * 1000.0 / f64::from(config.half_life_ms);
+        self.penalty * 0.5f64.powf(half_lives)
+    }
+    fn decay(&mut self, config: &DampeningConfig) {
+        self.penalty = self.decayed(config);
+        self.updated = Instant::now();
+    }
+    /// Adds `amount` to the penalty. Returns true if that suppressed the
+    /// path.
+    pub(crate) fn penalize(&mut self, config: &DampeningConfig, amount: u32) -> bool {
+        self.decay(config);
+        self.penalty = (self.penalty + f64::from(amount)).min(config.max_penalty());
+        if !self.suppressed && self.penalty > f64::from(config.suppress_threshold) {
+            self.suppressed = true;
+            return true;
+        }
+        false
+    }
+    /// Lifts the suppression if the penalty decayed below the reuse
+    /// threshold. Returns true if it did.
+    pub(crate) fn try_reuse(&mut self, config: &DampeningConfig) -> bool {
+        self.decay(config);
+        if self.suppressed && self.penalty < f64::from(config.reuse_threshold) {
+            self.suppressed = false;
+            return true;
+        }
+        false
+    }
+    pub(crate) fn penalty(&self, config: &DampeningConfig) -> u32 {
+        self.decayed(config).round() as u32
+    }
+    pub(crate) fn reuse_in(&self, config: &DampeningConfig) -> Duration {
+        let ratio = self.decayed(config) / f64::from(config.reuse_threshold);
+        if ratio <= 1.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(ratio.log2() * f64::from(config.half_life_ms) / 1000.0)
+    }
+    /// Whether the history is of no use any more: not suppressed and below
+    /// half the reuse threshold, as the RFC suggests.
+    pub(crate) fn is_forgotten(&self, config: &DampeningConfig) -> bool {
+        !self.suppressed && self.decayed(config) < f64::from(config.reuse_threshold) / 2.0
+    }
+}
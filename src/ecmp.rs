@@ -0,0 +1,109 @@
+use crate::utils::ip_addr_octets;
+use std::net::IpAddr;
+
+/// One path of a multipath route or prefix entry. Paths are identified by
+/// next hop and out interface; `weight` is their share of the flows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NextHop {
+    pub addr: IpAddr,
+    pub out_ifindex: u32,
+    pub weight: u32,
+}
+
+impl NextHop {
+    pub fn new(addr: IpAddr, out_ifindex: u32) -> NextHop {
+        NextHop {
+            addr,
+            out_ifindex,
+            weight: 1,
+        }
+    }
+    fn key(&self) -> (IpAddr, u32) {
+        (self.addr, self.out_ifindex)
+    }
+}
+
+/// Adds the path or updates its weight, keeping the set ordered so that
+/// path selection does not depend on insertion order. True if it is new.
+pub(crate) fn path_add(paths: &mut Vec<NextHop>, path: &NextHop) -> bool {
+    match paths.binary_search_by(|p| p.key().cmp(&path.key())) {
+        Ok(i) => {
+            paths[i].weight = path.weight;
+            false
+        }
+        Err(i) => {
+            paths.insert(i, path.clone());
+            true
+        }
+    }
+}
+
+pub(crate) fn path_delete(paths: &mut Vec<NextHop>, addr: &IpAddr, out_ifindex: u32) -> bool {
+    match paths.binary_search_by(|p| p.key().cmp(&(*addr, out_ifindex))) {
+        Ok(i) => {
+            paths.remove(i);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Ordered path set built from `paths`, `None` if it is empty or has a
+/// zero weight path.
+pub(crate) fn path_set(paths: &[NextHop]) -> Option<Vec<NextHop>> {
+    if paths.is_empty() || paths.iter().any(|p| p.weight == 0) {
+        return None;
+    }
+    let mut set = Vec::with_capacity(paths.len());
+    for path in paths {
+        path_add(&mut set, path);
+    }
+    Some(set)
+}
+
+/// 5-tuple a path is selected for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flow {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: u8,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl Flow {
+    /// FNV-1a over the tuple with a final mix. Stable across runs and
+    /// platforms, unlike `std::hash`.
+    pub fn hash(&self) -> u64 {
+        let mut bytes = ip_addr_octets(&self.src);
+        bytes.extend(ip_addr_octets(&self.dst));
+        bytes.push(self.protocol);
+        bytes.extend(&self.src_port.to_be_bytes());
+        bytes.extend(&self.dst_port.to_be_bytes());
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^ (hash >> 33)
+    }
+}
+
+/// Weighted pick of one path for the flow. The same flow always gets the
+/// same path as long as the path set does not change.
+pub fn select_path<'a>(paths: &'a [NextHop], flow: &Flow) -> Option<&'a NextHop> {
+    let total: u64 = paths.iter().map(|p| u64::from(p.weight)).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut point = flow.hash() % total;
+    for path in paths {
+        if point < u64::from(path.weight) {
+            return Some(path);
+        }
+        point -= u64::from(path.weight);
+    }
+    None
+}
@@ -0,0 +1,158 @@
+use log::*;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::os::raw::c_char;
+
+/// Result of every C API call. The numeric values are part of the ABI and
+/// must not change; new codes go at the end.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsError {
+    Ok = 0,
+    /// The entry already existed and has been updated in place.
+    Modified = 1,
+    NotFound = -1,
+    InvalidHandle = -2,
+    VrfNotFound = -3,
+    AlreadyExists = -4,
+    FamilyMismatch = -5,
+    InvalidPrefix = -6,
+    InvalidArgument = -7,
+    IdExhausted = -8,
+    PeerNotFound = -9,
+    /// Reading or writing a file failed.
+    Io = -10,
+    /// A snapshot or other input is truncated, corrupt or of an unknown
+    /// version.
+    InvalidData = -11,
+    /// The peer exists but its session is not established.
+    PeerNotEstablished = -12,
+    /// The inbound policy of the peer denied the route.
+    Denied = -13,
+    /// The label is outside the label blocks of the owner.
+    NotAllocated = -14,
+    /// The entry is still in use.
+    InUse = -15,
+    /// A failed transaction could not be undone completely; the store may
+    /// hold part of it.
+    RollbackFailed = -16,
+}
+
+impl DsError {
+    fn as_str(&self) -> &'static [u8] {
+        match self {
+            DsError::Ok => b"success\0",
+            DsError::Modified => b"entry modified\0",
+            DsError::NotFound => b"entry not found\0",
+            DsError::InvalidHandle => b"invalid data store handle\0",
+            DsError::VrfNotFound => b"vrf not found\0",
+            DsError::AlreadyExists => b"entry already exists\0",
+            DsError::FamilyMismatch => b"address family mismatch\0",
+            DsError::InvalidPrefix => b"invalid prefix or prefix length\0",
+            DsError::InvalidArgument => b"invalid argument\0",
+            DsError::IdExhausted => b"no free index left\0",
+            DsError::PeerNotFound => b"peer not found\0",
+            DsError::Io => b"i/o error\0",
+            DsError::InvalidData => b"malformed data\0",
+            DsError::PeerNotEstablished => b"peer not established\0",
+            DsError::Denied => b"denied by policy\0",
+            DsError::NotAllocated => b"label not allocated to the owner\0",
+            DsError::InUse => b"entry in use\0",
+            DsError::RollbackFailed => b"transaction rollback failed\0",
+        }
+    }
+    fn from_i32(code: i32) -> Option<DsError> {
+        [
+            DsError::Ok,
+            DsError::Modified,
+            DsError::NotFound,
+            DsError::InvalidHandle,
+            DsError::VrfNotFound,
+            DsError::AlreadyExists,
+            DsError::FamilyMismatch,
+            DsError::InvalidPrefix,
+            DsError::InvalidArgument,
+            DsError::IdExhausted,
+            DsError::PeerNotFound,
+            DsError::Io,
+            DsError::InvalidData,
+            DsError::PeerNotEstablished,
+            DsError::Denied,
+            DsError::NotAllocated,
+            DsError::InUse,
+            DsError::RollbackFailed,
+        ]
+        .iter()
+        .find(|err| **err as i32 == code)
+        .copied()
+    }
+}
+
+impl fmt::Display for DsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.as_str();
+        f.write_str(std::str::from_utf8(&text[..text.len() - 1]).unwrap_or_default())
+    }
+}
+
+impl std::error::Error for DsError {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Records `detail` as the calling thread's last error and hands `err` back,
+/// so failures read `return ds_error(DsError::NotFound, format!(..))`.
+pub(crate) fn ds_error(err: DsError, detail: String) -> DsError {
+    trace!("{:?}: {}", err, detail);
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = CString::new(detail).unwrap_or_default();
+    });
+    err
+}
+
+/// Detail `ds_error` recorded last on this thread.
+pub(crate) fn last_error_detail() -> String {
+    LAST_ERROR.with(|last| last.borrow().to_string_lossy().into_owned())
+}
+
+/// `Ok` for a successful result, the error code otherwise.
+pub(crate) fn ds_result<T>(result: Result<T, DsError>) -> DsError {
+    match result {
+        Ok(_) => DsError::Ok,
+        Err(err) => err,
+    }
+}
+
+/// Like `ds_result` for add-or-modify calls, where `Ok(false)` means modified.
+pub(crate) fn ds_add_result(result: Result<bool, DsError>) -> DsError {
+    match result {
+        Ok(true) => DsError::Ok,
+        Ok(false) => DsError::Modified,
+        Err(err) => err,
+    }
+}
+
+pub(crate) fn ds_status(status: DsError) -> Result<(), DsError> {
+    match status {
+        DsError::Ok => Ok(()),
+        err => Err(err),
+    }
+}
+
+/// Static description of an error code, never NULL.
+#[no_mangle]
+pub extern "C" fn ds_strerror(err: i32) -> *const c_char {
+    match DsError::from_i32(err) {
+        Some(err) => err.as_str().as_ptr() as *const c_char,
+        None => b"unknown error\0".as_ptr() as *const c_char,
+    }
+}
+
+/// Detail of the last failed call on this thread. The string stays valid
+/// until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn ds_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
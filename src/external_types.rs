@@ -1,3 +1,5 @@
+use crate::notify::EventOp;
+
 #[repr(C)]
 #[allow(dead_code)]
 pub struct IpAddrC {
@@ -12,6 +14,7 @@ pub struct RouteEntry {
     pub mask: IpAddrC,
     pub next_hop: IpAddrC,
     pub out_ifindex: u32,
+    pub vrf_id: u32,
 }
 
 #[repr(C)]
@@ -19,6 +22,7 @@ pub struct RouteEntry {
 pub struct PeerEntry {
     pub prefix: IpAddrC,
     pub out_ifindex: u32,
+    pub vrf_id: u32,
 }
 
 #[repr(C)]
@@ -26,6 +30,9 @@ pub struct PeerEntry {
 pub struct ForwardingEntry {
     pub next_hop: IpAddrC,
     pub out_ifindex: u32,
+    pub vrf_id: u32,
+    pub prefix: IpAddrC,
+    pub prefix_len: u8,
 }
 
 #[repr(C)]
@@ -56,8 +63,10 @@ pub struct FtnAddData {
     pub ftn_ix: u32,
     pub next_hop: IpAddrC,
     pub out_ifindex: u32,
+    /// Depth of the label stack `out_label` points to, top label first.
     pub out_label_number: u32,
     pub out_label: *mut u32,
+    pub vrf_id: u32,
 }
 
 #[repr(C)]
@@ -65,6 +74,7 @@ pub struct FtnAddData {
 pub struct FtnDelData {
     pub fec: IpAddrC,
     pub ftn_ix: u32,
+    pub vrf_id: u32,
 }
 
 #[repr(C)]
@@ -74,3 +84,84 @@ pub struct NhAddDel {
     pub ifindex: u32,
     pub is_add: bool,
 }
+
+#[repr(C)]
+#[allow(dead_code)]
+pub struct NextHopEntry {
+    pub next_hop: IpAddrC,
+    pub out_ifindex: u32,
+    pub weight: u32,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+pub struct FlowKey {
+    pub src: IpAddrC,
+    pub dst: IpAddrC,
+    pub protocol: u8,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+/// Entry of a prefix list, see `PrefixRange`.
+#[repr(C)]
+#[allow(dead_code)]
+pub struct PrefixListEntry {
+    pub prefix: IpAddrC,
+    pub prefix_len: u8,
+    pub ge: u8,
+    pub le: u8,
+}
+
+/// Term of a policy, see `PolicyTerm`. A zero `prefix_list_id` or
+/// `out_ifindex` and a null `addr` of `peer` or `next_hop` match every
+/// route. `set_flags` holds the `POLICY_SET_*` bits of the fields after it
+/// that are set.
+#[repr(C)]
+#[allow(dead_code)]
+pub struct PolicyTermEntry {
+    pub prefix_list_id: u32,
+    pub peer: IpAddrC,
+    pub next_hop: IpAddrC,
+    pub out_ifindex: u32,
+    /// A `PolicyAction` value.
+    pub action: u32,
+    pub set_flags: u32,
+    pub metric: u32,
+    pub local_pref: u32,
+    pub set_next_hop: IpAddrC,
+}
+
+/// Suppressed path listed by `ds_dampened_paths_get`.
+#[repr(C)]
+#[allow(dead_code)]
+pub struct DampenedEntry {
+    pub peer: IpAddrC,
+    pub prefix: IpAddrC,
+    pub prefix_len: u8,
+    pub penalty: u32,
+    /// Time until the path can be used again.
+    pub reuse_in_ms: u64,
+}
+
+/// Event handed to `ds_subscribe` callbacks. Fields that do not apply to
+/// the event type are zero, addresses that do not apply have a null `addr`.
+#[repr(C)]
+#[allow(dead_code)]
+pub struct EventEntry {
+    /// One of the `EVENT_*` bits.
+    pub event_type: u32,
+    pub op: EventOp,
+    pub vrf_id: u32,
+    /// Route or prefix, peer address or FTN FEC.
+    pub prefix: IpAddrC,
+    pub prefix_len: u8,
+    pub next_hop: IpAddrC,
+    pub out_ifindex: u32,
+    pub in_label: u32,
+    pub in_iface: u32,
+    /// Top label of the stack of an FTN entry.
+    pub out_label: u32,
+    /// ftn_ix or ilm_ix.
+    pub index: u32,
+}
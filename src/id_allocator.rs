@@ -0,0 +1,408 @@
+//! Index allocator of the XC, NHLFE and ILM id tables. The free indexes of
+//! the table's range are kept as a map of free runs, so allocating, freeing
+//! and reserving take O(log n) in the number of runs and a table of 2^32 - 1
+//! indexes costs no more than one of 1024. The lowest free index is handed
+//! out first.
+
+use crate::errors::*;
+use crate::{data_store_from_handle, default_data_store, DataStore};
+use log::*;
+use std::collections::BTreeMap;
+
+/// Range a new id table hands indexes out of.
+pub const ID_FIRST: u32 = 1;
+pub const ID_LAST: u32 = u32::MAX;
+
+/// Id table of an `Lfib`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdTableKind {
+    Xc = 0,
+    Nhlfe = 1,
+    Ilm = 2,
+}
+
+impl IdTableKind {
+    pub fn from_u32(value: u32) -> Option<IdTableKind> {
+        match value {
+            0 => Some(IdTableKind::Xc),
+            1 => Some(IdTableKind::Nhlfe),
+            2 => Some(IdTableKind::Ilm),
+            _ => None,
+        }
+    }
+}
+
+fn table_from_user(table: u32) -> Result<IdTableKind, DsError> {
+    IdTableKind::from_u32(table).ok_or_else(|| {
+        ds_error(
+            DsError::InvalidArgument,
+            format!("unknown id table {}", table),
+        )
+    })
+}
+
+/// Usage of an id table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdStats {
+    pub first: u32,
+    pub last: u32,
+    pub used: u32,
+    /// Most indexes ever taken at once.
+    pub peak: u32,
+    /// Allocations that failed because the range was full.
+    pub exhausted: u32,
+}
+
+/// Range and taken indexes of an id table, what a snapshot keeps of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TableIds {
+    pub(crate) first: u32,
+    pub(crate) last: u32,
+    pub(crate) used: Vec<u32>,
+}
+
+fn check_bounds(first: u32, last: u32) -> Result<(), DsError> {
+    if first == 0 || first > last {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            format!("invalid index range {}..={}", first, last),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) struct IdAllocator {
+    first: u32,
+    last: u32,
+    /// Free runs, first index to last index.
+    free: BTreeMap<u32, u32>,
+    used: u32,
+    peak: u32,
+    exhausted: u32,
+}
+
+impl Default for IdAllocator {
+    fn default() -> IdAllocator {
+        IdAllocator::new(ID_FIRST, ID_LAST)
+    }
+}
+
+impl IdAllocator {
+    fn new(first: u32, last: u32) -> IdAllocator {
+        let mut free = BTreeMap::new();
+        free.insert(first, last);
+        IdAllocator {
+            first,
+            last,
+            free,
+            used: 0,
+            peak: 0,
+            exhausted: 0,
+        }
+    }
+    fn check_range(&self, ix: u32) -> Result<(), DsError> {
+        if ix < self.first || ix > self.last {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "index {} is out of range {}..={}",
+                    ix, self.first, self.last
+                ),
+            ));
+        }
+        Ok(())
+    }
+    /// The free run `ix` is in.
+    fn free_run(&self, ix: u32) -> Option<(u32, u32)> {
+        self.free
+            .range(..=ix)
+            .next_back()
+            .map(|(first, last)| (*first, *last))
+            .filter(|(_, last)| ix <= *last)
+    }
+    fn take(&mut self) {
+        self.used += 1;
+        self.peak = self.peak.max(self.used);
+    }
+    /// Lowest free index.
+    pub(crate) fn allocate(&mut self) -> Result<u32, DsError> {
+        let (first, last) = match self.free.iter().next() {
+            Some((first, last)) => (*first, *last),
+            None => {
+                self.exhausted += 1;
+                return Err(ds_error(
+                    DsError::IdExhausted,
+                    format!("no free index in {}..={}", self.first, self.last),
+                ));
+            }
+        };
+        self.free.remove(&first);
+        if first < last {
+            self.free.insert(first + 1, last);
+        }
+        self.take();
+        Ok(first)
+    }
+    /// Takes `ix`, as restoring an entry with the index it had does.
+    pub(crate) fn reserve(&mut self, ix: u32) -> Result<(), DsError> {
+        self.check_range(ix)?;
+        let (first, last) = match self.free_run(ix) {
+            Some(run) => run,
+            None => {
+                return Err(ds_error(
+                    DsError::AlreadyExists,
+                    format!("index {} is already taken", ix),
+                ))
+            }
+        };
+        self.free.remove(&first);
+        if first < ix {
+            self.free.insert(first, ix - 1);
+        }
+        if ix < last {
+            self.free.insert(ix + 1, last);
+        }
+        self.take();
+        Ok(())
+    }
+    /// Gives a taken index back. Freeing one that is not taken, freed
+    /// already or never handed out, is `InvalidArgument` as is one outside
+    /// the range.
+    pub(crate) fn free(&mut self, ix: u32) -> Result<(), DsError> {
+        self.check_range(ix)?;
+        if self.free_run(ix).is_some() {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!("index {} is not taken: freed twice or never allocated", ix),
+            ));
+        }
+        let mut first = ix;
+        let mut last = ix;
+        if let Some((before, before_last)) = ix.checked_sub(1).and_then(|ix| self.free_run(ix)) {
+            self.free.remove(&before);
+            first = before;
+            last = last.max(before_last);
+        }
+        if let Some(after_last) = ix.checked_add(1).and_then(|ix| self.free.remove(&ix)) {
+            last = after_last;
+        }
+        self.free.insert(first, last);
+        self.used -= 1;
+        Ok(())
+    }
+    /// Taken indexes in order.
+    pub(crate) fn used(&self) -> Vec<u32> {
+        let mut used = Vec::with_capacity(self.used as usize);
+        let mut next = u64::from(self.first);
+        for (first, last) in self.free.iter() {
+            used.extend((next..u64::from(*first)).map(|ix| ix as u32));
+            next = u64::from(*last) + 1;
+        }
+        used.extend((next..=u64::from(self.last)).map(|ix| ix as u32));
+        used
+    }
+    pub(crate) fn ids(&self) -> TableIds {
+        TableIds {
+            first: self.first,
+            last: self.last,
+            used: self.used(),
+        }
+    }
+    /// The allocator with the range and taken indexes of `ids` and the
+    /// counters of this one, leaving this one as it is.
+    pub(crate) fn with_ids(&self, ids: &TableIds) -> Result<IdAllocator, DsError> {
+        check_bounds(ids.first, ids.last)?;
+        let mut allocator = IdAllocator::new(ids.first, ids.last);
+        allocator.peak = self.peak;
+        allocator.exhausted = self.exhausted;
+        for ix in ids.used.iter() {
+            allocator.reserve(*ix)?;
+        }
+        Ok(allocator)
+    }
+    /// Hands indexes out of `first..=last` from now on, which fails with
+    /// `InUse` while an index outside it is taken.
+    pub(crate) fn set_range(&mut self, first: u32, last: u32) -> Result<(), DsError> {
+        check_bounds(first, last)?;
+        let used = self.used();
+        if let Some(ix) = used.iter().find(|ix| **ix < first || **ix > last) {
+            return Err(ds_error(
+                DsError::InUse,
+                format!("index {} outside {}..={} is taken", ix, first, last),
+            ));
+        }
+        *self = self.with_ids(&TableIds { first, last, used })?;
+        Ok(())
+    }
+    pub(crate) fn stats(&self) -> IdStats {
+        IdStats {
+            first: self.first,
+            last: self.last,
+            used: self.used,
+            peak: self.peak,
+            exhausted: self.exhausted,
+        }
+    }
+}
+
+/// Limits the indexes the id table hands out to `first..=last`, for
+/// instance to keep them in the range of a hardware table.
+#[no_mangle]
+pub extern "C" fn ds_id_range_set(
+    ds: *mut DataStore,
+    table: u32,
+    first: u32,
+    last: u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    trace!("id_range_set {} {} {}", table, first, last);
+    let table = match table_from_user(table) {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    ds_result(ds.lfib().set_id_range(table, first, last))
+}
+
+#[no_mangle]
+pub extern "C" fn id_range_set(table: u32, first: u32, last: u32) -> DsError {
+    ds_id_range_set(default_data_store(), table, first, last)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_id_stats_get(ds: *mut DataStore, table: u32, stats: *mut IdStats) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if stats.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("stats is null"));
+    }
+    let table = match table_from_user(table) {
+        Ok(table) => table,
+        Err(err) => return err,
+    };
+    unsafe { *stats = ds.lfib().id_stats(table) };
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn id_stats_get(table: u32, stats: *mut IdStats) -> DsError {
+    ds_id_stats_get(default_data_store(), table, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runs(ids: &IdAllocator) -> Vec<(u32, u32)> {
+        ids.free
+            .iter()
+            .map(|(first, last)| (*first, *last))
+            .collect()
+    }
+
+    #[test]
+    fn allocate_free_merges_runs() {
+        let mut ids = IdAllocator::new(1, 10);
+        for ix in 1..=4 {
+            assert_eq!(ids.allocate(), Ok(ix));
+        }
+        assert_eq!(runs(&ids), vec![(5, 10)]);
+        // freeing in the middle leaves a run of its own
+        assert_eq!(ids.free(2), Ok(()));
+        assert_eq!(runs(&ids), vec![(2, 2), (5, 10)]);
+        // the lowest free index goes first
+        assert_eq!(ids.allocate(), Ok(2));
+        assert_eq!(ids.free(2), Ok(()));
+        // joins the run before it
+        assert_eq!(ids.free(3), Ok(()));
+        assert_eq!(runs(&ids), vec![(2, 3), (5, 10)]);
+        // joins the runs on both sides
+        assert_eq!(ids.free(4), Ok(()));
+        assert_eq!(runs(&ids), vec![(2, 10)]);
+        assert_eq!(ids.free(1), Ok(()));
+        assert_eq!(runs(&ids), vec![(1, 10)]);
+        assert_eq!(ids.stats().used, 0);
+        assert_eq!(ids.stats().peak, 4);
+    }
+
+    #[test]
+    fn allocate_exhausted() {
+        let mut ids = IdAllocator::new(u32::MAX - 1, u32::MAX);
+        assert_eq!(ids.allocate(), Ok(u32::MAX - 1));
+        assert_eq!(ids.allocate(), Ok(u32::MAX));
+        assert_eq!(ids.allocate(), Err(DsError::IdExhausted));
+        assert_eq!(ids.stats().exhausted, 1);
+        // the last index of the id space has no run after it
+        assert_eq!(ids.free(u32::MAX), Ok(()));
+        assert_eq!(runs(&ids), vec![(u32::MAX, u32::MAX)]);
+    }
+
+    #[test]
+    fn free_twice_or_out_of_range() {
+        let mut ids = IdAllocator::new(1, 10);
+        assert_eq!(ids.allocate(), Ok(1));
+        assert_eq!(ids.free(1), Ok(()));
+        assert_eq!(ids.free(1), Err(DsError::InvalidArgument));
+        assert_eq!(ids.free(7), Err(DsError::InvalidArgument));
+        assert_eq!(ids.free(0), Err(DsError::InvalidArgument));
+        assert_eq!(ids.free(11), Err(DsError::InvalidArgument));
+        assert_eq!(ids.reserve(11), Err(DsError::InvalidArgument));
+        assert_eq!(runs(&ids), vec![(1, 10)]);
+        assert_eq!(ids.stats().used, 0);
+    }
+
+    #[test]
+    fn reserve_splits_runs() {
+        let mut ids = IdAllocator::new(1, 10);
+        // inside a run
+        assert_eq!(ids.reserve(5), Ok(()));
+        assert_eq!(runs(&ids), vec![(1, 4), (6, 10)]);
+        // at the first and the last index of runs
+        assert_eq!(ids.reserve(1), Ok(()));
+        assert_eq!(ids.reserve(4), Ok(()));
+        assert_eq!(ids.reserve(10), Ok(()));
+        assert_eq!(runs(&ids), vec![(2, 3), (6, 9)]);
+        assert_eq!(ids.reserve(5), Err(DsError::AlreadyExists));
+        // a run of one goes away
+        assert_eq!(ids.reserve(2), Ok(()));
+        assert_eq!(ids.reserve(3), Ok(()));
+        assert_eq!(runs(&ids), vec![(6, 9)]);
+        assert_eq!(ids.used(), vec![1, 2, 3, 4, 5, 10]);
+        assert_eq!(ids.allocate(), Ok(6));
+    }
+
+    #[test]
+    fn with_ids_and_set_range() {
+        let mut ids = IdAllocator::new(1, 10);
+        assert_eq!(ids.allocate(), Ok(1));
+        let restored = ids
+            .with_ids(&TableIds {
+                first: 5,
+                last: 8,
+                used: vec![6],
+            })
+            .unwrap();
+        assert_eq!(runs(&restored), vec![(5, 5), (7, 8)]);
+        assert_eq!(restored.stats().peak, 1);
+        let bad = TableIds {
+            first: 5,
+            last: 8,
+            used: vec![6, 9],
+        };
+        assert_eq!(
+            ids.with_ids(&bad).map(|_| ()),
+            Err(DsError::InvalidArgument)
+        );
+        assert_eq!(ids.used(), vec![1]);
+        assert_eq!(ids.set_range(2, 10), Err(DsError::InUse));
+        assert_eq!(ids.set_range(0, 10), Err(DsError::InvalidArgument));
+        assert_eq!(ids.set_range(1, 3), Ok(()));
+        assert_eq!(runs(&ids), vec![(2, 3)]);
+    }
+}
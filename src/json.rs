@@ -0,0 +1,564 @@
+//! JSON dump of a whole store, for debugging and for test fixtures.
+//!
+//! The dump holds everything `export` can see, derived state included: the
+//! peers and resolved paths of every route, the FTN and ILM entries waiting
+//! on each FEC, and the XC and NHLFE entries. `import` only reads what
+//! cannot be derived, the same state a snapshot keeps, and rebuilds the rest
+//! by adding the entries. Like a snapshot, the dump leaves out graceful
+//! restarts and dampening penalties.
+
+use crate::errors::*;
+use crate::id_allocator::TableIds;
+use crate::mpls_sim::{UsedIds, XcIndexed};
+use crate::policy::{PolicySnapshot, PrefixListSnapshot};
+use crate::snapshot::{io_error, path_from_user, PeerRoute, PeerSnapshot, Snapshot, VrfSnapshot};
+use crate::{
+    data_store_from_handle, default_data_store, BestPathAlgorithm, DampeningConfig, DataStore, Ftn,
+    Ilm, LabelBlock, NextHop, Peer, PeerState, PeerStatus, PolicyAction, PolicyTerm, PrefixEntry,
+    PrefixRange, Route, RouteAttrs,
+};
+use log::*;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::fs;
+use std::net::IpAddr;
+use std::os::raw::c_char;
+
+/// Version of the dump layout, checked by `import`.
+const JSON_VERSION: u32 = 5;
+
+pub(crate) fn paths_json(paths: &[NextHop]) -> Value {
+    paths
+        .iter()
+        .map(|path| {
+            json!({
+                "addr": path.addr.to_string(),
+                "out_ifindex": path.out_ifindex,
+                "weight": path.weight,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn route_json(route: &Route) -> Value {
+    json!({
+        "prefix": route.prefix.to_string(),
+        "prefix_len": route.prefix_len,
+        "attrs": {
+            "admin_distance": route.attrs.admin_distance,
+            "origin": route.attrs.origin,
+            "metric": route.attrs.metric,
+            "local_pref": route.attrs.local_pref,
+        },
+        "paths": paths_json(&route.paths),
+    })
+}
+
+pub(crate) fn prefix_json(entry: &PrefixEntry) -> Value {
+    json!({
+        "prefix": entry.prefix.to_string(),
+        "prefix_len": entry.prefix_len,
+        "paths": paths_json(&entry.paths),
+    })
+}
+
+pub(crate) fn peer_status_json(status: &PeerStatus) -> Value {
+    json!({
+        "state": status.state as u32,
+        "flaps": status.flaps,
+        "last_change_ms": status.last_change_ms,
+        "routes_received": status.routes_received,
+        "routes_accepted": status.routes_accepted,
+    })
+}
+
+fn dampening_json(config: &Option<DampeningConfig>) -> Value {
+    match config {
+        Some(config) => json!({
+            "half_life_ms": config.half_life_ms,
+            "reuse_threshold": config.reuse_threshold,
+            "suppress_threshold": config.suppress_threshold,
+            "max_suppress_ms": config.max_suppress_ms,
+        }),
+        None => Value::Null,
+    }
+}
+
+fn prefix_list_json(list: &PrefixListSnapshot) -> Value {
+    let ranges: Vec<Value> = list
+        .ranges
+        .iter()
+        .map(|range| {
+            json!({
+                "prefix": range.prefix.to_string(),
+                "prefix_len": range.prefix_len,
+                "ge": range.ge,
+                "le": range.le,
+            })
+        })
+        .collect();
+    json!({"list_id": list.list_id, "ranges": ranges})
+}
+
+fn policy_json(policy: &PolicySnapshot) -> Value {
+    let addr = |addr: &Option<IpAddr>| addr.map(|addr| addr.to_string());
+    let terms: Vec<Value> = policy
+        .terms
+        .iter()
+        .map(|(term, hits)| {
+            json!({
+                "prefix_list": term.prefix_list,
+                "peer": addr(&term.peer),
+                "next_hop": addr(&term.next_hop),
+                "out_ifindex": term.out_ifindex,
+                "action": term.action as u32,
+                "set_metric": term.set_metric,
+                "set_local_pref": term.set_local_pref,
+                "set_next_hop": addr(&term.set_next_hop),
+                "hits": hits,
+            })
+        })
+        .collect();
+    json!({"policy_id": policy.policy_id, "terms": terms})
+}
+
+fn ids_json(ids: &TableIds) -> Value {
+    json!({"first": ids.first, "last": ids.last, "used": ids.used})
+}
+
+pub(crate) fn export(ds: &DataStore) -> Result<String, DsError> {
+    let rib = ds.rib();
+    let lfib = ds.lfib();
+    let dump = rib.locked(|| {
+        lfib.locked(|| -> Result<Value, DsError> {
+            let mut vrfs = Vec::new();
+            for vrf_id in rib.vrf_ids() {
+                let mut vrf = rib.dump_vrf(vrf_id)?;
+                vrf["fecs"] = Value::from(lfib.dump_fecs(vrf_id)?);
+                vrfs.push(vrf);
+            }
+            let next_hops: Vec<Value> = lfib
+                .next_hops()
+                .iter()
+                .map(|(addr, ifindex)| json!({"addr": addr.to_string(), "ifindex": ifindex}))
+                .collect();
+            let ids = lfib.used_ids();
+            let label_blocks: Vec<Value> = lfib
+                .label_blocks(0, usize::MAX)
+                .iter()
+                .map(|block| {
+                    json!({
+                        "owner": block.owner,
+                        "start": block.start,
+                        "count": block.count,
+                        "is_static": block.is_static,
+                        "used": block.used,
+                    })
+                })
+                .collect();
+            let prefix_lists: Vec<Value> = rib
+                .prefix_list_snapshots()
+                .iter()
+                .map(prefix_list_json)
+                .collect();
+            let policies: Vec<Value> = rib.policy_snapshots().iter().map(policy_json).collect();
+            Ok(json!({
+                "version": JSON_VERSION,
+                "best_path_algorithm": rib.best_path_algorithm() as u32,
+                "dampening": dampening_json(&rib.dampening()),
+                "prefix_lists": prefix_lists,
+                "policies": policies,
+                "vrfs": vrfs,
+                "next_hops": next_hops,
+                "ilms": lfib.dump_ilms(),
+                "xcs": lfib.dump_xcs(),
+                "nhlfes": lfib.dump_nhlfes(),
+                "ids": {
+                    "xc": ids_json(&ids.xc),
+                    "nhlfe": ids_json(&ids.nhlfe),
+                    "ilm": ids_json(&ids.ilm),
+                },
+                "max_label_depth": lfib.max_label_depth(),
+                "labels": {
+                    "checking": lfib.label_checking(),
+                    "blocks": label_blocks,
+                },
+            }))
+        })
+    })?;
+    Ok(format!("{:#}", dump))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, DsError> {
+    value
+        .get(name)
+        .ok_or_else(|| ds_error(DsError::InvalidData, format!("\"{}\" is missing", name)))
+}
+
+fn get_u32(value: &Value, name: &str) -> Result<u32, DsError> {
+    field(value, name)?
+        .as_u64()
+        .and_then(|number| u32::try_from(number).ok())
+        .ok_or_else(|| ds_error(DsError::InvalidData, format!("\"{}\" is not a u32", name)))
+}
+
+fn get_u64(value: &Value, name: &str) -> Result<u64, DsError> {
+    field(value, name)?
+        .as_u64()
+        .ok_or_else(|| ds_error(DsError::InvalidData, format!("\"{}\" is not a u64", name)))
+}
+
+/// `None` if the field is null.
+fn get_optional<T>(
+    value: &Value,
+    name: &str,
+    get: impl FnOnce(&Value, &str) -> Result<T, DsError>,
+) -> Result<Option<T>, DsError> {
+    if field(value, name)?.is_null() {
+        Ok(None)
+    } else {
+        get(value, name).map(Some)
+    }
+}
+
+fn get_bool(value: &Value, name: &str) -> Result<bool, DsError> {
+    field(value, name)?
+        .as_bool()
+        .ok_or_else(|| ds_error(DsError::InvalidData, format!("\"{}\" is not a bool", name)))
+}
+
+fn get_u8(value: &Value, name: &str) -> Result<u8, DsError> {
+    u8::try_from(get_u32(value, name)?)
+        .map_err(|_| ds_error(DsError::InvalidData, format!("\"{}\" is not a u8", name)))
+}
+
+fn get_addr(value: &Value, name: &str) -> Result<IpAddr, DsError> {
+    field(value, name)?
+        .as_str()
+        .and_then(|addr| addr.parse().ok())
+        .ok_or_else(|| {
+            ds_error(
+                DsError::InvalidData,
+                format!("\"{}\" is not an address", name),
+            )
+        })
+}
+
+/// Index of an id table, which is never 0.
+fn get_index(value: &Value, name: &str) -> Result<u32, DsError> {
+    match get_u32(value, name)? {
+        0 => Err(ds_error(
+            DsError::InvalidData,
+            format!("\"{}\" 0 out of range", name),
+        )),
+        ix => Ok(ix),
+    }
+}
+
+fn list<'a>(value: &'a Value, name: &str) -> Result<&'a Vec<Value>, DsError> {
+    field(value, name)?
+        .as_array()
+        .ok_or_else(|| ds_error(DsError::InvalidData, format!("\"{}\" is not a list", name)))
+}
+
+fn get_list<T>(
+    value: &Value,
+    name: &str,
+    get: impl FnMut(&Value) -> Result<T, DsError>,
+) -> Result<Vec<T>, DsError> {
+    list(value, name)?.iter().map(get).collect()
+}
+
+fn get_ids(value: &Value, name: &str) -> Result<TableIds, DsError> {
+    let ids = field(value, name)?;
+    Ok(TableIds {
+        first: get_u32(ids, "first")?,
+        last: get_u32(ids, "last")?,
+        used: get_list(ids, "used", |ix| {
+            ix.as_u64()
+                .and_then(|ix| u32::try_from(ix).ok())
+                .ok_or_else(|| ds_error(DsError::InvalidData, format!("bad index in \"{}\"", name)))
+        })?,
+    })
+}
+
+fn get_labels(value: &Value, name: &str) -> Result<Vec<u32>, DsError> {
+    get_list(value, name, |label| {
+        label
+            .as_u64()
+            .and_then(|label| u32::try_from(label).ok())
+            .ok_or_else(|| ds_error(DsError::InvalidData, format!("bad label in \"{}\"", name)))
+    })
+}
+
+fn get_paths(value: &Value) -> Result<Vec<NextHop>, DsError> {
+    get_list(value, "paths", |path| {
+        Ok(NextHop {
+            addr: get_addr(path, "addr")?,
+            out_ifindex: get_u32(path, "out_ifindex")?,
+            weight: get_u32(path, "weight")?,
+        })
+    })
+}
+
+fn get_route(value: &Value) -> Result<Route, DsError> {
+    let attrs = field(value, "attrs")?;
+    Ok(Route {
+        prefix: get_addr(value, "prefix")?,
+        prefix_len: get_u8(value, "prefix_len")?,
+        paths: get_paths(value)?,
+        attrs: RouteAttrs {
+            admin_distance: get_u8(attrs, "admin_distance")?,
+            origin: get_u8(attrs, "origin")?,
+            metric: get_u32(attrs, "metric")?,
+            local_pref: get_u32(attrs, "local_pref")?,
+        },
+    })
+}
+
+fn get_peer_status(value: &Value) -> Result<PeerStatus, DsError> {
+    let state = get_u32(value, "state")?;
+    Ok(PeerStatus {
+        state: PeerState::from_u32(state).ok_or_else(|| {
+            ds_error(
+                DsError::InvalidData,
+                format!("unknown peer state {}", state),
+            )
+        })?,
+        flaps: get_u32(value, "flaps")?,
+        last_change_ms: get_u64(value, "last_change_ms")?,
+        routes_received: get_u64(value, "routes_received")?,
+        routes_accepted: get_u64(value, "routes_accepted")?,
+    })
+}
+
+fn get_dampening(value: &Value, name: &str) -> Result<DampeningConfig, DsError> {
+    let config = field(value, name)?;
+    Ok(DampeningConfig {
+        half_life_ms: get_u32(config, "half_life_ms")?,
+        reuse_threshold: get_u32(config, "reuse_threshold")?,
+        suppress_threshold: get_u32(config, "suppress_threshold")?,
+        max_suppress_ms: get_u32(config, "max_suppress_ms")?,
+    })
+}
+
+fn get_prefix_list(value: &Value) -> Result<PrefixListSnapshot, DsError> {
+    Ok(PrefixListSnapshot {
+        list_id: get_u32(value, "list_id")?,
+        ranges: get_list(value, "ranges", |range| {
+            Ok(PrefixRange {
+                prefix: get_addr(range, "prefix")?,
+                prefix_len: get_u8(range, "prefix_len")?,
+                ge: get_u8(range, "ge")?,
+                le: get_u8(range, "le")?,
+            })
+        })?,
+    })
+}
+
+fn get_policy(value: &Value) -> Result<PolicySnapshot, DsError> {
+    Ok(PolicySnapshot {
+        policy_id: get_u32(value, "policy_id")?,
+        terms: get_list(value, "terms", |term| {
+            let action = get_u32(term, "action")?;
+            let action = PolicyAction::from_u32(action).ok_or_else(|| {
+                ds_error(
+                    DsError::InvalidData,
+                    format!("unknown policy action {}", action),
+                )
+            })?;
+            let policy_term = PolicyTerm {
+                prefix_list: get_optional(term, "prefix_list", get_u32)?,
+                peer: get_optional(term, "peer", get_addr)?,
+                next_hop: get_optional(term, "next_hop", get_addr)?,
+                out_ifindex: get_optional(term, "out_ifindex", get_u32)?,
+                action,
+                set_metric: get_optional(term, "set_metric", get_u32)?,
+                set_local_pref: get_optional(term, "set_local_pref", get_u32)?,
+                set_next_hop: get_optional(term, "set_next_hop", get_addr)?,
+            };
+            Ok((policy_term, get_u64(term, "hits")?))
+        })?,
+    })
+}
+
+fn get_vrf(value: &Value, ftns: &mut Vec<XcIndexed<Ftn>>) -> Result<VrfSnapshot, DsError> {
+    let vrf_id = get_u32(value, "vrf_id")?;
+    let mut routes = Vec::new();
+    let peers = get_list(value, "peers", |peer| {
+        let addr = get_addr(peer, "addr")?;
+        for route in get_list(peer, "routes", get_route)? {
+            routes.push(PeerRoute { peer: addr, route });
+        }
+        Ok(PeerSnapshot {
+            peer: Peer {
+                addr,
+                out_ifindex: get_u32(peer, "out_ifindex")?,
+            },
+            status: get_peer_status(field(peer, "status")?)?,
+            policy: get_optional(peer, "policy", get_u32)?,
+        })
+    });
+    let prefixes = get_list(value, "prefixes", |entry| {
+        Ok(PrefixEntry {
+            prefix: get_addr(entry, "prefix")?,
+            prefix_len: get_u8(entry, "prefix_len")?,
+            paths: get_paths(entry)?,
+        })
+    })?;
+    for fec_entry in list(value, "fecs")?.iter() {
+        let fec = get_addr(fec_entry, "fec")?;
+        ftns.extend(get_list(fec_entry, "ftns", |ftn| {
+            Ok(XcIndexed {
+                entry: Ftn {
+                    fec,
+                    ftn_ix: get_u32(ftn, "ftn_ix")?,
+                    next_hop: get_addr(ftn, "next_hop")?,
+                    out_ifindex: get_u32(ftn, "out_ifindex")?,
+                    out_labels: get_labels(ftn, "out_labels")?,
+                    vrf_id,
+                },
+                xc_ix: get_index(ftn, "xc_ix")?,
+                nhlfe_ix: get_index(ftn, "nhlfe_ix")?,
+            })
+        })?);
+    }
+    Ok(VrfSnapshot {
+        vrf_id,
+        peers: peers?,
+        routes,
+        prefixes,
+    })
+}
+
+fn get_ilms(value: &Value) -> Result<Vec<XcIndexed<Ilm>>, DsError> {
+    let mut ilms = Vec::new();
+    for ilm_list in list(value, "ilms")?.iter() {
+        let in_label = get_u32(ilm_list, "in_label")?;
+        let in_iface = get_u32(ilm_list, "in_iface")?;
+        ilms.extend(get_list(ilm_list, "entries", |ilm| {
+            Ok(XcIndexed {
+                entry: Ilm {
+                    in_label,
+                    in_iface,
+                    next_hop: get_addr(ilm, "next_hop")?,
+                    out_ifindex: get_u32(ilm, "out_ifindex")?,
+                    out_label: get_u32(ilm, "out_label")?,
+                    ilm_ix: get_index(ilm, "ilm_ix")?,
+                    owner: get_u32(ilm, "owner")?,
+                },
+                xc_ix: get_index(ilm, "xc_ix")?,
+                nhlfe_ix: get_index(ilm, "nhlfe_ix")?,
+            })
+        })?);
+    }
+    Ok(ilms)
+}
+
+fn snapshot_from_json(value: &Value) -> Result<Snapshot, DsError> {
+    let version = get_u32(value, "version")?;
+    if version != JSON_VERSION {
+        return Err(ds_error(
+            DsError::InvalidData,
+            format!("dump version {} is not supported", version),
+        ));
+    }
+    let best_path = get_u32(value, "best_path_algorithm")?;
+    let best_path = BestPathAlgorithm::from_u32(best_path).ok_or_else(|| {
+        ds_error(
+            DsError::InvalidData,
+            format!("unknown best path algorithm {}", best_path),
+        )
+    })?;
+    let mut ftns = Vec::new();
+    let vrfs = get_list(value, "vrfs", |vrf| get_vrf(vrf, &mut ftns));
+    let next_hops = get_list(value, "next_hops", |next_hop| {
+        Ok((get_addr(next_hop, "addr")?, get_u32(next_hop, "ifindex")?))
+    })?;
+    let ids = field(value, "ids")?;
+    let labels = field(value, "labels")?;
+    let label_blocks = get_list(labels, "blocks", |block| {
+        Ok(LabelBlock {
+            owner: get_u32(block, "owner")?,
+            start: get_u32(block, "start")?,
+            count: get_u32(block, "count")?,
+            is_static: get_bool(block, "is_static")?,
+            used: 0,
+        })
+    })?;
+    Ok(Snapshot {
+        best_path,
+        dampening: get_optional(value, "dampening", get_dampening)?,
+        prefix_lists: get_list(value, "prefix_lists", get_prefix_list)?,
+        policies: get_list(value, "policies", get_policy)?,
+        vrfs: vrfs?,
+        next_hops,
+        ftns,
+        ilms: get_ilms(value)?,
+        ids: UsedIds {
+            xc: get_ids(ids, "xc")?,
+            nhlfe: get_ids(ids, "nhlfe")?,
+            ilm: get_ids(ids, "ilm")?,
+        },
+        max_label_depth: get_u32(value, "max_label_depth")?,
+        label_checking: get_bool(labels, "checking")?,
+        label_blocks,
+    })
+}
+
+pub(crate) fn import(ds: &DataStore, json: &str) -> Result<(), DsError> {
+    let value: Value = serde_json::from_str(json)
+        .map_err(|err| ds_error(DsError::InvalidData, format!("invalid JSON: {}", err)))?;
+    trace!("json::import");
+    snapshot_from_json(&value)?.restore(ds)
+}
+
+/// Writes the whole store as JSON to the file at `path`, see
+/// `ds_json_import` for what reading it back restores.
+#[no_mangle]
+pub extern "C" fn ds_json_export(ds: *mut DataStore, path: *const c_char) -> DsError {
+    trace!("json_export");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let path = match path_from_user(path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    match ds.export_json() {
+        Ok(json) => ds_result(fs::write(&path, json).map_err(|err| io_error(err, &path))),
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn json_export(path: *const c_char) -> DsError {
+    ds_json_export(default_data_store(), path)
+}
+
+/// Rebuilds the state dumped by `ds_json_export` in `ds`, which must be
+/// empty. Entries get back the XC, NHLFE and ILM indexes of the dump and
+/// peers their status and policy; as with `ds_snapshot_restore`, graceful
+/// restarts in progress and dampening penalties are not restored.
+#[no_mangle]
+pub extern "C" fn ds_json_import(ds: *mut DataStore, path: *const c_char) -> DsError {
+    trace!("json_import");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let path = match path_from_user(path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    match fs::read_to_string(&path) {
+        Ok(json) => ds_result(ds.import_json(&json)),
+        Err(err) => io_error(err, &path),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn json_import(path: *const c_char) -> DsError {
+    ds_json_import(default_data_store(), path)
+}
@@ -0,0 +1,401 @@
+//! Label manager. It owns the 20-bit label space and hands blocks of it to
+//! owners, the `owner` of the ILM entries, either picked by the manager or
+//! reserved statically at a given start. Labels 0 to 15 are reserved and
+//! ILM entries never use them. With checking on, as it is in a new store,
+//! ILM entries may only use incoming labels of their owner's blocks.
+
+use crate::errors::*;
+use crate::{data_store_from_handle, default_data_store, walk_args_are_valid, DataStore};
+use log::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// First label the manager hands out; the ones below are reserved.
+pub const LABEL_FIRST_UNRESERVED: u32 = 16;
+pub const LABEL_MAX: u32 = (1 << 20) - 1;
+
+/// Labels `start` to `start + count - 1` of `owner`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelBlock {
+    pub owner: u32,
+    pub start: u32,
+    pub count: u32,
+    /// Reserved by the owner at a start of its choice.
+    pub is_static: bool,
+    /// Labels of the block incoming ILM entries of the owner use.
+    pub used: u32,
+}
+
+impl LabelBlock {
+    fn end(&self) -> u64 {
+        u64::from(self.start) + u64::from(self.count)
+    }
+    fn contains(&self, label: u32) -> bool {
+        label >= self.start && u64::from(label) < self.end()
+    }
+}
+
+pub(crate) struct LabelManager {
+    checking: bool,
+    /// By start label.
+    blocks: BTreeMap<u32, LabelBlock>,
+    /// Labels between the blocks, end by start label.
+    free: BTreeMap<u32, u32>,
+    /// The same by size and start, to find the range a block fits best.
+    free_sizes: BTreeSet<(u32, u32)>,
+    /// ILM entries using each incoming label, by owner and label.
+    users: BTreeMap<(u32, u32), u32>,
+}
+
+impl Default for LabelManager {
+    fn default() -> LabelManager {
+        let mut labels = LabelManager {
+            checking: true,
+            blocks: BTreeMap::new(),
+            free: BTreeMap::new(),
+            free_sizes: BTreeSet::new(),
+            users: BTreeMap::new(),
+        };
+        labels.add_free(LABEL_FIRST_UNRESERVED, LABEL_MAX + 1);
+        labels
+    }
+}
+
+impl LabelManager {
+    pub(crate) fn checking(&self) -> bool {
+        self.checking
+    }
+    pub(crate) fn set_checking(&mut self, checking: bool) {
+        self.checking = checking;
+    }
+    /// `count` labels for the owner, at the start of the smallest free
+    /// range they fit in, the lowest of those.
+    pub(crate) fn allocate(&mut self, owner: u32, count: u32) -> Result<LabelBlock, DsError> {
+        if count == 0 {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                String::from("cannot allocate an empty label block"),
+            ));
+        }
+        match self.free_sizes.range((count, 0)..).next() {
+            Some(&(_, start)) => self.insert(owner, start, count, false),
+            None => Err(ds_error(
+                DsError::IdExhausted,
+                format!("no free block of {} labels", count),
+            )),
+        }
+    }
+    /// Reserves the labels `start` to `start + count - 1` for the owner.
+    pub(crate) fn reserve(
+        &mut self,
+        owner: u32,
+        start: u32,
+        count: u32,
+    ) -> Result<LabelBlock, DsError> {
+        self.check_free(start, count)?;
+        self.insert(owner, start, count, true)
+    }
+    /// Puts back a block of a snapshot.
+    pub(crate) fn restore(&mut self, block: &LabelBlock) -> Result<(), DsError> {
+        self.check_free(block.start, block.count)?;
+        self.insert(block.owner, block.start, block.count, block.is_static)
+            .map(|_| ())
+    }
+    /// Whether a block of `count` labels fits at `start`.
+    fn check_free(&self, start: u32, count: u32) -> Result<(), DsError> {
+        let end = u64::from(start) + u64::from(count);
+        if count == 0 || start < LABEL_FIRST_UNRESERVED || end > u64::from(LABEL_MAX) + 1 {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!("cannot reserve {} labels at {}", count, start),
+            ));
+        }
+        // blocks do not overlap, so only the last one starting before the
+        // end can reach into the labels
+        if let Some((_, block)) = self
+            .blocks
+            .range(..end as u32)
+            .next_back()
+            .filter(|(_, block)| u64::from(start) < block.end())
+        {
+            return Err(ds_error(
+                DsError::AlreadyExists,
+                format!(
+                    "labels {}..{} overlap the block at {} of owner {}",
+                    start, end, block.start, block.owner
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn insert(
+        &mut self,
+        owner: u32,
+        start: u32,
+        count: u32,
+        is_static: bool,
+    ) -> Result<LabelBlock, DsError> {
+        let end = u64::from(start) + u64::from(count);
+        let used = self
+            .users
+            .range((owner, start)..)
+            .take_while(|((user, label), _)| *user == owner && u64::from(*label) < end)
+            .count();
+        let block = LabelBlock {
+            owner,
+            start,
+            count,
+            is_static,
+            used: used as u32,
+        };
+        trace!("LabelManager::insert {:?}", block);
+        self.take_free(start, end as u32);
+        self.blocks.insert(start, block);
+        Ok(block)
+    }
+    fn add_free(&mut self, start: u32, end: u32) {
+        if start < end {
+            self.free.insert(start, end);
+            self.free_sizes.insert((end - start, start));
+        }
+    }
+    fn remove_free(&mut self, start: u32) -> Option<u32> {
+        let end = self.free.remove(&start)?;
+        self.free_sizes.remove(&(end - start, start));
+        Some(end)
+    }
+    /// Takes the labels `start` to `end - 1` out of the free range holding
+    /// them.
+    fn take_free(&mut self, start: u32, end: u32) {
+        let free_start = match self.free.range(..=start).next_back() {
+            Some((free_start, free_end)) if end <= *free_end => *free_start,
+            _ => return,
+        };
+        if let Some(free_end) = self.remove_free(free_start) {
+            self.add_free(free_start, start);
+            self.add_free(end, free_end);
+        }
+    }
+    /// Gives the labels `start` to `end - 1` back, joining the free ranges
+    /// next to them.
+    fn give_free(&mut self, mut start: u32, mut end: u32) {
+        let before = self
+            .free
+            .range(..start)
+            .next_back()
+            .filter(|(_, free_end)| **free_end == start)
+            .map(|(free_start, _)| *free_start);
+        if let Some(free_start) = before {
+            self.remove_free(free_start);
+            start = free_start;
+        }
+        if let Some(free_end) = self.remove_free(end) {
+            end = free_end;
+        }
+        self.add_free(start, end);
+    }
+    /// The block of the owner starting at `start`.
+    pub(crate) fn lookup(&self, owner: u32, start: u32) -> Result<LabelBlock, DsError> {
+        match self.blocks.get(&start) {
+            Some(block) if block.owner == owner => Ok(*block),
+            _ => Err(ds_error(
+                DsError::NotFound,
+                format!("owner {} has no label block at {}", owner, start),
+            )),
+        }
+    }
+    pub(crate) fn release(&mut self, start: u32) {
+        if let Some(block) = self.blocks.remove(&start) {
+            self.give_free(block.start, block.end() as u32);
+        }
+    }
+    /// Up to `limit` blocks starting at `start` or above.
+    pub(crate) fn blocks(&self, start: u32, limit: usize) -> Vec<LabelBlock> {
+        self.blocks
+            .range(start..)
+            .map(|(_, block)| *block)
+            .take(limit)
+            .collect()
+    }
+    fn block_of(&mut self, label: u32) -> Option<&mut LabelBlock> {
+        self.blocks
+            .range_mut(..=label)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.contains(label))
+    }
+    /// Counts an ILM entry of the owner for `in_label`.
+    pub(crate) fn take(&mut self, owner: u32, in_label: u32) {
+        let users = self.users.entry((owner, in_label)).or_insert(0);
+        *users += 1;
+        if *users == 1 {
+            if let Some(block) = self.block_of(in_label).filter(|block| block.owner == owner) {
+                block.used += 1;
+            }
+        }
+    }
+    /// Forgets an ILM entry `take` counted.
+    pub(crate) fn put(&mut self, owner: u32, in_label: u32) {
+        let users = match self.users.get_mut(&(owner, in_label)) {
+            Some(users) => users,
+            None => return,
+        };
+        *users -= 1;
+        if *users == 0 {
+            self.users.remove(&(owner, in_label));
+            if let Some(block) = self.block_of(in_label).filter(|block| block.owner == owner) {
+                block.used -= 1;
+            }
+        }
+    }
+    /// Whether the owner may program an ILM entry for `in_label`. Reserved
+    /// and out of range labels are refused whether checking is on or not.
+    pub(crate) fn check(&self, owner: u32, in_label: u32) -> Result<(), DsError> {
+        if !(LABEL_FIRST_UNRESERVED..=LABEL_MAX).contains(&in_label) {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "label {} is outside {}..={}",
+                    in_label, LABEL_FIRST_UNRESERVED, LABEL_MAX
+                ),
+            ));
+        }
+        if !self.checking {
+            return Ok(());
+        }
+        let block = self
+            .blocks
+            .range(..=in_label)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.contains(in_label));
+        match block {
+            Some(block) if block.owner == owner => Ok(()),
+            _ => Err(ds_error(
+                DsError::NotAllocated,
+                format!("label {} is not allocated to owner {}", in_label, owner),
+            )),
+        }
+    }
+}
+
+/// Allocates `count` labels to the owner and stores the first of them in
+/// `start`. A single label is a block of one.
+#[no_mangle]
+pub extern "C" fn ds_label_block_allocate(
+    ds: *mut DataStore,
+    owner: u32,
+    count: u32,
+    start: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if start.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("start is null"));
+    }
+    trace!("label_block_allocate {} {}", owner, count);
+    match ds.lfib().allocate_labels(owner, count) {
+        Ok(block) => {
+            unsafe { *start = block.start };
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn label_block_allocate(owner: u32, count: u32, start: *mut u32) -> DsError {
+    ds_label_block_allocate(default_data_store(), owner, count, start)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_label_block_reserve(
+    ds: *mut DataStore,
+    owner: u32,
+    start: u32,
+    count: u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    trace!("label_block_reserve {} {} {}", owner, start, count);
+    ds_result(ds.lfib().reserve_labels(owner, start, count))
+}
+
+#[no_mangle]
+pub extern "C" fn label_block_reserve(owner: u32, start: u32, count: u32) -> DsError {
+    ds_label_block_reserve(default_data_store(), owner, start, count)
+}
+
+/// Gives the block of the owner starting at `start` back, which fails with
+/// `InUse` while ILM entries of the owner use its labels.
+#[no_mangle]
+pub extern "C" fn ds_label_block_release(ds: *mut DataStore, owner: u32, start: u32) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    trace!("label_block_release {} {}", owner, start);
+    ds_result(ds.lfib().release_labels(owner, start))
+}
+
+#[no_mangle]
+pub extern "C" fn label_block_release(owner: u32, start: u32) -> DsError {
+    ds_label_block_release(default_data_store(), owner, start)
+}
+
+/// Up to `page_size` label blocks with their usage, by start label, from
+/// the one starting at `start` or the next one on. The next page starts
+/// one past the start of the last block returned.
+#[no_mangle]
+pub extern "C" fn ds_label_blocks_get(
+    ds: *mut DataStore,
+    start: u32,
+    entries: *mut LabelBlock,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = walk_args_are_valid(entries, count) {
+        return err;
+    }
+    let blocks = ds.lfib().label_blocks(start, page_size as usize);
+    unsafe {
+        std::ptr::copy_nonoverlapping(blocks.as_ptr(), entries, blocks.len());
+        *count = blocks.len() as u32;
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn label_blocks_get(
+    start: u32,
+    entries: *mut LabelBlock,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_label_blocks_get(default_data_store(), start, entries, page_size, count)
+}
+
+/// Turns checking the incoming labels of ILM entries against the blocks of
+/// their owner on or off. It is on in a new store.
+#[no_mangle]
+pub extern "C" fn ds_label_check_set(ds: *mut DataStore, enabled: bool) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds.lfib().set_label_checking(enabled);
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn label_check_set(enabled: bool) -> DsError {
+    ds_label_check_set(default_data_store(), enabled)
+}
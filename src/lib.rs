@@ -3,955 +3,4369 @@ extern crate lazy_static;
 extern crate parking_lot;
 use parking_lot::ReentrantMutex;
 use std::cell::RefCell;
-use std::collections::hash_map::Iter;
-use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::ops::Bound;
+use std::os::raw::c_void;
+use std::path::Path;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 extern crate log;
 extern crate patricia_tree;
 use patricia_tree::*;
+use serde_json::{json, Value};
+mod errors;
+pub use errors::DsError;
+use errors::*;
+mod best_path;
+mod dampening;
+pub use best_path::{
+    BestPathAlgorithm, PathRank, RouteAttrs, ORIGIN_BGP, ORIGIN_CONNECTED, ORIGIN_IGP,
+    ORIGIN_OTHER, ORIGIN_STATIC,
+};
+use dampening::FlapState;
+pub use dampening::{
+    DampenedPath, DampeningConfig, DAMPENING_UPDATE_PENALTY, DAMPENING_WITHDRAW_PENALTY,
+};
+mod ecmp;
+use ecmp::{path_add, path_delete, path_set};
+pub use ecmp::{select_path, Flow, NextHop};
 mod external_types;
+mod id_allocator;
 use external_types::*;
+pub use id_allocator::{IdStats, IdTableKind, ID_FIRST, ID_LAST};
 mod simple_logger;
 use log::*;
 #[macro_use]
 mod macros;
 mod mpls_sim;
+pub use mpls_sim::{Ftn, Ilm, Lfib, DEFAULT_MAX_LABEL_DEPTH, LABEL_DEPTH_LIMIT};
+use mpls_sim::{NhTable, NhTableGen};
+mod notify;
+mod peer_state;
+mod policy;
+use notify::Notifier;
+pub use notify::{
+    Event, EventOp, EVENT_ALL, EVENT_FTN, EVENT_ILM, EVENT_PEER, EVENT_PREFIX, EVENT_ROUTE,
+};
+mod json;
+mod label_manager;
+pub use label_manager::{LabelBlock, LABEL_FIRST_UNRESERVED, LABEL_MAX};
+pub use peer_state::{PeerState, PeerStatus};
+use policy::{Policies, PolicySnapshot, PrefixListSnapshot};
+pub use policy::{
+    PolicyAction, PolicyTerm, PrefixRange, POLICY_SET_LOCAL_PREF, POLICY_SET_METRIC,
+    POLICY_SET_NEXT_HOP,
+};
+mod resolve;
+mod snapshot;
+pub use resolve::MAX_RESOLVE_DEPTH;
+use resolve::{resolve, Via};
+pub use snapshot::SNAPSHOT_VERSION;
+mod timer;
+mod transaction;
+use timer::Timer;
+pub use transaction::{Transaction, TxError};
 mod utils;
 use utils::*;
 
-//#[link(name = "c_callbacks")]
-extern "C" {
-    fn on_peer(ip_addr: &IpAddrC) -> i32;
-}
-
 type RouteIntEntryWrapped = Arc<ReentrantMutex<RefCell<Box<RouteIntEntry>>>>;
 
 type PeerIntEntryWrapped = Arc<ReentrantMutex<RefCell<Box<PeerIntEntry>>>>;
 
-type ForwardingIntEntryWrapped = Arc<ReentrantMutex<RefCell<Box<ForwardingEntryInt>>>>;
+type PrefixEntryWrapped = Arc<ReentrantMutex<RefCell<Box<PrefixEntry>>>>;
+
+/// Routes are keyed by network address and prefix length, so that e.g.
+/// 10.0.0.0/8 and 10.0.0.0/24 are separate entries.
+pub type RouteKey = (IpAddr, u8);
 
-type RouteTable = Arc<ReentrantMutex<RefCell<HashMap<IpAddr, RouteIntEntryWrapped>>>>;
-type PrefixTree = Arc<ReentrantMutex<RefCell<PatriciaMap<ForwardingIntEntryWrapped>>>>;
+/// Ordered by key, so that cursor walks seek to the cursor.
+type RouteTable = Arc<ReentrantMutex<RefCell<BTreeMap<RouteKey, RouteIntEntryWrapped>>>>;
+type PrefixTree = Arc<ReentrantMutex<RefCell<PatriciaMap<PrefixEntryWrapped>>>>;
 
 type PeerTable = Arc<ReentrantMutex<RefCell<HashMap<IpAddr, PeerIntEntryWrapped>>>>;
 
-lazy_static! {
-    pub static ref ROUTE_TABLE_V4: RouteTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref ROUTE_TABLE_V6: RouteTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref PEER_TABLE_V4: PeerTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref PEER_TABLE_V6: PeerTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref PREFIX_TREE4: PrefixTree =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-    pub static ref PREFIX_TREE6: PrefixTree =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-}
-
-pub enum PrefixTreeGen {
-    V4(&'static PREFIX_TREE4),
-    V6(&'static PREFIX_TREE6),
-}
-
-pub struct ForwardingEntryInt {
-    next_hop: IpAddr,
-    out_ifindex: u32,
-}
+type BestPathConfig = Arc<ReentrantMutex<RefCell<BestPathAlgorithm>>>;
 
-impl PrefixTreeGen {
-    fn insert(&self, key: IpAddr, fwd_entry: ForwardingIntEntryWrapped) -> i32 {
-        match self {
-            PrefixTreeGen::V4(_) => match key {
-                IpAddr::V4(ipv4) => {
-                    write_val!(PREFIX_TREE4).insert(ipv4.octets(), fwd_entry);
-                    0
-                }
-                IpAddr::V6(_) => {
-                    trace!("wrong argument type, expected ipv4");
-                    -1
-                }
-            },
+/// Flap dampening settings, `None` while dampening is off.
+type DampeningShared = Arc<ReentrantMutex<RefCell<Option<DampeningConfig>>>>;
 
-            PrefixTreeGen::V6(_) => match key {
-                IpAddr::V6(ipv6) => {
-                    write_val!(PREFIX_TREE6).insert(ipv6.octets(), fwd_entry);
-                    0
-                }
-                IpAddr::V4(_) => {
-                    trace!("wrong argument type, expected ipv6");
-                    -1
-                }
-            },
-        }
-    }
+type PolicyShared = Arc<ReentrantMutex<RefCell<Policies>>>;
 
-    fn get_longest_common_prefix(&self, key: IpAddr) -> Option<ForwardingIntEntryWrapped> {
-        match self {
-            PrefixTreeGen::V4(_) => match key {
-                IpAddr::V4(ipv4) => {
-                    match read_val!(PREFIX_TREE4).get_longest_common_prefix(&ipv4.octets()) {
-                        Some((_k, v)) => Some(Arc::clone(v)),
-                        None => None,
-                    }
-                }
-                IpAddr::V6(_) => {
-                    trace!("wrong argument type, expected ipv4");
-                    None
-                }
-            },
+/// Routes depending on each next hop address they looked up to resolve.
+type NextHopDependents = ReentrantMutex<RefCell<HashMap<IpAddr, HashSet<RouteKey>>>>;
 
-            PrefixTreeGen::V6(_) => match key {
-                IpAddr::V6(ipv6) => {
-                    match read_val!(PREFIX_TREE6).get_longest_common_prefix(&ipv6.octets()) {
-                        Some((_k, v)) => Some(Arc::clone(v)),
-                        None => None,
-                    }
+/// What the routes of a VRF need from it to select, resolve and report
+/// best paths.
+struct RouteContext {
+    vrf_id: u32,
+    /// For the timers that come back to the VRF later.
+    vrf: Weak<RibVrf>,
+    best_path: BestPathConfig,
+    dampening: DampeningShared,
+    policies: PolicyShared,
+    notifier: Notifier,
+    timer: Timer,
+    prefix_tree4: PrefixTree,
+    prefix_tree6: PrefixTree,
+    nh_table4: NhTable,
+    nh_table6: NhTable,
+    nh_dependents: NextHopDependents,
+}
+
+impl RouteContext {
+    /// Adjacent next hops resolve to their interface, any other address
+    /// through the longest prefix covering it. A prefix with an unspecified
+    /// next hop is connected.
+    fn lookup_next_hop(&self, addr: &IpAddr) -> Via {
+        let (nh_table, prefix_tree) = match addr {
+            IpAddr::V4(_) => (
+                NhTableGen::V4(&self.nh_table4),
+                PrefixTreeGen::V4(&self.prefix_tree4),
+            ),
+            IpAddr::V6(_) => (
+                NhTableGen::V6(&self.nh_table6),
+                PrefixTreeGen::V6(&self.prefix_tree6),
+            ),
+        };
+        if let Ok(out_ifindex) = nh_table.lookup(*addr) {
+            return Via::Connected(out_ifindex);
+        }
+        match prefix_tree.get_longest_common_prefix(*addr) {
+            Some(fe) => {
+                let guard = fe.lock();
+                let fe = guard.borrow();
+                match fe.paths.iter().find(|path| path.addr.is_unspecified()) {
+                    Some(path) => Via::Connected(path.out_ifindex),
+                    None => Via::Paths(fe.paths.clone()),
                 }
-                IpAddr::V4(_) => {
-                    trace!("wrong argument type, expected ipv6");
-                    None
+            }
+            None => Via::Unresolved,
+        }
+    }
+    fn set_dependencies(&self, route_key: &RouteKey, old: &[IpAddr], new: &[IpAddr]) {
+        let guard = self.nh_dependents.lock();
+        let mut nh_dependents = guard.borrow_mut();
+        for addr in old.iter() {
+            if let Some(routes) = nh_dependents.get_mut(addr) {
+                routes.remove(route_key);
+                if routes.is_empty() {
+                    nh_dependents.remove(addr);
                 }
-            },
+            }
+        }
+        for addr in new.iter() {
+            nh_dependents.entry(*addr).or_default().insert(*route_key);
         }
     }
+}
 
-    fn remove(&self, key: IpAddr) -> i32 {
-        match self {
-            PrefixTreeGen::V4(_) => match key {
-                IpAddr::V4(ipv4) => match write_val!(PREFIX_TREE4).remove(ipv4.octets()) {
-                    Some(_rc) => 0,
-                    None => -1,
-                },
-                IpAddr::V6(_) => {
-                    trace!("wrong argument type, expected ipv4");
-                    -1
-                }
-            },
+type VrfTable<T> = Arc<ReentrantMutex<RefCell<HashMap<u32, Arc<T>>>>>;
 
-            PrefixTreeGen::V6(_) => match key {
-                IpAddr::V6(ipv6) => match write_val!(PREFIX_TREE6).remove(ipv6.octets()) {
-                    Some(_rc) => 0,
-                    None => -1,
-                },
-                IpAddr::V4(_) => {
-                    trace!("wrong argument type, expected ipv6");
-                    -1
-                }
-            },
-        }
+/// Runs `f` inside `lock` of every item, for when the number of tables to
+/// lock is only known at run time.
+fn lock_each<T>(items: &[T], lock: &dyn Fn(&T, &mut dyn FnMut()), f: &mut dyn FnMut()) {
+    match items.split_first() {
+        Some((item, rest)) => lock(item, &mut || lock_each(rest, lock, f)),
+        None => f(),
     }
 }
 
-pub enum RouteTableGen {
-    V4(&'static ROUTE_TABLE_V4),
-    V6(&'static ROUTE_TABLE_V6),
+/// VRF every store starts with. It cannot be deleted.
+pub const DEFAULT_VRF_ID: u32 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub addr: IpAddr,
+    pub out_ifindex: u32,
 }
 
-impl RouteTableGen {
-    fn contains_key(&self, ip_addr: &IpAddr) -> bool {
-        match self {
-            RouteTableGen::V4(_) => read_val!(ROUTE_TABLE_V4).contains_key(ip_addr),
-            RouteTableGen::V6(_) => read_val!(ROUTE_TABLE_V6).contains_key(ip_addr),
+/// Route with its equal-cost paths, ordered by next hop and out interface.
+/// Looked up by prefix it is the best path over all advertising peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: IpAddr,
+    pub prefix_len: u8,
+    pub paths: Vec<NextHop>,
+    pub attrs: RouteAttrs,
+}
+
+/// Entry of the longest-match prefix tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixEntry {
+    pub prefix: IpAddr,
+    pub prefix_len: u8,
+    pub paths: Vec<NextHop>,
+}
+
+pub struct RibVrf {
+    route_table_v4: RouteTable,
+    route_table_v6: RouteTable,
+    peer_table_v4: PeerTable,
+    peer_table_v6: PeerTable,
+    prefix_tree4: PrefixTree,
+    prefix_tree6: PrefixTree,
+    context: Arc<RouteContext>,
+}
+
+impl RibVrf {
+    fn new(vrf_id: u32, rib: &Rib, vrf: Weak<RibVrf>) -> RibVrf {
+        let prefix_tree4: PrefixTree =
+            Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
+        let prefix_tree6: PrefixTree =
+            Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
+        RibVrf {
+            route_table_v4: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            route_table_v6: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            peer_table_v4: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            peer_table_v6: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            prefix_tree4: Arc::clone(&prefix_tree4),
+            prefix_tree6: Arc::clone(&prefix_tree6),
+            context: Arc::new(RouteContext {
+                vrf_id,
+                vrf,
+                best_path: Arc::clone(&rib.best_path),
+                dampening: Arc::clone(&rib.dampening),
+                policies: Arc::clone(&rib.policies),
+                notifier: rib.notifier.clone(),
+                timer: rib.timer.clone(),
+                prefix_tree4,
+                prefix_tree6,
+                nh_table4: Arc::clone(&rib.nh_table4),
+                nh_table6: Arc::clone(&rib.nh_table6),
+                nh_dependents: ReentrantMutex::new(RefCell::new(HashMap::new())),
+            }),
         }
     }
-    #[inline]
-    fn get(&self, ip_addr: &IpAddr) -> RouteIntEntryWrapped {
-        match self {
-            RouteTableGen::V4(_) => Arc::clone(&read_val!(ROUTE_TABLE_V4)[ip_addr]),
-            RouteTableGen::V6(_) => Arc::clone(&read_val!(ROUTE_TABLE_V6)[ip_addr]),
+    /// Runs `f` with every table of the VRF locked, so that a batch takes
+    /// each lock once rather than once per entry.
+    fn locked<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _peers4 = self.peer_table_v4.lock();
+        let _peers6 = self.peer_table_v6.lock();
+        let _routes4 = self.route_table_v4.lock();
+        let _routes6 = self.route_table_v6.lock();
+        let _prefixes4 = self.prefix_tree4.lock();
+        let _prefixes6 = self.prefix_tree6.lock();
+        let _dependents = self.context.nh_dependents.lock();
+        f()
+    }
+    /// Resolves again the routes with a next hop inside the prefix.
+    fn reresolve(&self, prefix: &IpAddr, prefix_len: u8) {
+        let network = ip_addr_apply_mask(prefix, prefix_len);
+        let route_keys: HashSet<RouteKey> = read_val!(self.context.nh_dependents)
+            .iter()
+            .filter(|(addr, _)| {
+                addr.is_ipv4() == prefix.is_ipv4()
+                    && ip_addr_apply_mask(addr, prefix_len) == network
+            })
+            .flat_map(|(_, route_keys)| route_keys.iter().copied())
+            .collect();
+        trace!(
+            "RibVrf::reresolve {}/{}: {} routes",
+            prefix,
+            prefix_len,
+            route_keys.len()
+        );
+        for route_key in route_keys.iter() {
+            let route_table = self.route_table(&route_key.0);
+            if route_table.contains_key(route_key) {
+                write_val!(route_table.get(route_key)).resolve();
+            }
         }
     }
-
-    fn insert(&self, ip_addr: &IpAddr, entry: RouteIntEntryWrapped) {
-        match self {
-            RouteTableGen::V4(_) => {
-                write_val!(ROUTE_TABLE_V4).insert(*ip_addr, entry);
+    /// Withdraws the routes the peer left stale and ends its restart. With
+    /// `restart` set only that restart is ended, so that the timer of an
+    /// earlier one leaves a newer restart alone.
+    fn sweep_stale(&self, addr: &IpAddr, restart: Option<u32>) -> Result<usize, DsError> {
+        self.locked(|| {
+            let peer_table = self.peer_table(addr);
+            if !peer_table.contains_key(addr) {
+                return Err(ds_error(
+                    DsError::PeerNotFound,
+                    format!("peer {} not found", addr),
+                ));
             }
-            RouteTableGen::V6(_) => {
-                write_val!(ROUTE_TABLE_V6).insert(*ip_addr, entry);
+            let pe = peer_table.get(addr);
+            let restarts = read_val!(pe).restarts;
+            if !read_val!(pe).restarting || restart.is_some_and(|r| r != restarts) {
+                return Ok(0);
             }
-        }
+            let stale: Vec<RouteKey> = write_val!(pe).stale.drain().collect();
+            write_val!(pe).restarting = false;
+            trace!("RibVrf::sweep_stale {}: {} routes", addr, stale.len());
+            for route_key in stale.iter() {
+                _peer_route_delete(addr, route_key, &peer_table, &self.route_table(addr))?;
+            }
+            Ok(stale.len())
+        })
     }
-    fn remove(&self, ip_addr: &IpAddr) {
-        match self {
-            RouteTableGen::V4(_) => {
-                write_val!(ROUTE_TABLE_V4).remove(ip_addr);
+    /// Lifts the suppression of the peer's path once its penalty decayed,
+    /// installing the update kept aside, or waits some more.
+    fn reuse_dampened(&self, peer: &IpAddr, route_key: &RouteKey) {
+        let config = *read_val!(self.context.dampening);
+        let config = match config {
+            Some(config) => config,
+            None => return,
+        };
+        self.locked(|| {
+            let peer_table = self.peer_table(peer);
+            if !peer_table.contains_key(peer) {
+                return;
+            }
+            let pe = peer_table.get(peer);
+            let pending = {
+                let guard = pe.lock();
+                let mut pe_entry = guard.borrow_mut();
+                let state = match pe_entry.flaps.get_mut(route_key) {
+                    Some(state) if state.suppressed => state,
+                    _ => return,
+                };
+                if !state.try_reuse(&config) {
+                    let reuse_in = state.reuse_in(&config);
+                    start_reuse_timer(&self.context, *peer, *route_key, reuse_in);
+                    return;
+                }
+                trace!(
+                    "RibVrf::reuse_dampened {} {}/{}",
+                    peer,
+                    route_key.0,
+                    route_key.1
+                );
+                state.pending.take()
+            };
+            if let Some(route) = pending {
+                self.install_pending(&pe, peer, route);
             }
-            RouteTableGen::V6(_) => {
-                write_val!(ROUTE_TABLE_V6).remove(ip_addr);
+        })
+    }
+    /// Installs an update that was kept aside while its path was suppressed.
+    fn install_pending(&self, pe: &PeerIntEntryWrapped, peer: &IpAddr, route: Route) {
+        write_val!(pe).route_add_modify(
+            &(route.prefix, route.prefix_len),
+            &prefix_len_mask(&route.prefix, route.prefix_len),
+            PeerPath {
+                paths: route.paths,
+                attrs: route.attrs,
+            },
+            peer,
+            &mut self.route_table(peer),
+            &mut self.peer_table(peer),
+            &self.context,
+        );
+    }
+    /// Forgets every flap history, installing the updates kept aside.
+    fn release_dampened(&self) {
+        self.locked(|| {
+            for peer_table in [&self.peer_table_v4, &self.peer_table_v6].iter() {
+                let peers: Vec<(IpAddr, PeerIntEntryWrapped)> = read_val!(peer_table)
+                    .iter()
+                    .map(|(addr, pe)| (*addr, Arc::clone(pe)))
+                    .collect();
+                for (addr, pe) in peers.iter() {
+                    let flaps = std::mem::take(&mut write_val!(pe).flaps);
+                    for route in flaps.into_values().filter_map(|state| state.pending) {
+                        self.install_pending(pe, addr, route);
+                    }
+                }
+            }
+        })
+    }
+    fn reselect_best_paths(&self) {
+        for route_table in [&self.route_table_v4, &self.route_table_v6].iter() {
+            for re in read_val!(route_table).values() {
+                write_val!(re).select_best();
             }
         }
     }
-    fn clone(&self, ip_addr: &IpAddr) -> RouteIntEntryWrapped {
-        match self {
-            RouteTableGen::V4(_) => Arc::clone(&read_val!(ROUTE_TABLE_V4)[ip_addr]),
-            RouteTableGen::V6(_) => Arc::clone(&read_val!(ROUTE_TABLE_V6)[ip_addr]),
+    fn route_table(&self, addr: &IpAddr) -> RouteTableGen<'_> {
+        match addr {
+            IpAddr::V4(_) => RouteTableGen::V4(&self.route_table_v4),
+            IpAddr::V6(_) => RouteTableGen::V6(&self.route_table_v6),
         }
     }
-    fn add_modify(
-        &mut self,
-        route_prefix: &IpAddr,
-        route_mask: &IpAddr,
-        next_hop_addr: &IpAddr,
-        out_ifindex: u32,
-        peer_ip_addr: &IpAddr,
-        peer_table: &PeerTableGen,
-    ) -> Option<RouteIntEntryWrapped> {
-        let route_entry: RouteIntEntryWrapped;
-        if self.contains_key(route_prefix) {
-            let re: RouteIntEntryWrapped = self.get(&route_prefix);
-            write_val!(re).next_hop = *next_hop_addr;
-            write_val!(re).out_ifindex = out_ifindex;
-            write_val!(re).mask = *route_mask;
-            write_val!(re).creator = *peer_ip_addr;
-            if !read_val!(re).peer_exists(*peer_ip_addr) {
-                trace!(
-                    "RouteTableGen::add_modify: peer {} is not present in peer list, adding",
-                    peer_ip_addr
-                );
-                write_val!(re).add_peer(*peer_ip_addr, peer_table.clone(peer_ip_addr));
+    fn peer_table(&self, addr: &IpAddr) -> PeerTableGen<'_> {
+        match addr {
+            IpAddr::V4(_) => PeerTableGen::V4(&self.peer_table_v4),
+            IpAddr::V6(_) => PeerTableGen::V6(&self.peer_table_v6),
+        }
+    }
+    fn prefix_tree(&self, addr: &IpAddr) -> PrefixTreeGen<'_> {
+        match addr {
+            IpAddr::V4(_) => PrefixTreeGen::V4(&self.prefix_tree4),
+            IpAddr::V6(_) => PrefixTreeGen::V6(&self.prefix_tree6),
+        }
+    }
+    fn cleanup(&self) {
+        trace!("RibVrf::cleanup");
+        // routes and peers reference each other, break the cycles before the tables go
+        for val in read_val!(self.peer_table_v4).values() {
+            write_val!(val).cleanup(&RouteTableGen::V4(&self.route_table_v4));
+        }
+        for val in read_val!(self.peer_table_v6).values() {
+            write_val!(val).cleanup(&RouteTableGen::V6(&self.route_table_v6));
+        }
+        let notifier = &self.context.notifier;
+        if notifier.wants(EVENT_PEER) {
+            for peer_table in [&self.peer_table_v4, &self.peer_table_v6].iter() {
+                for pe in read_val!(peer_table).values() {
+                    notifier.publish(Event::Peer {
+                        vrf_id: self.context.vrf_id,
+                        op: EventOp::Delete,
+                        peer: read_val!(pe).peer(),
+                    });
+                }
             }
-            trace!(
-                "RouteTableGen::add_modify: cloned route entry prefix {} for peer {}",
-                route_prefix,
-                peer_ip_addr
-            );
-            return Some(self.clone(route_prefix));
-        } else {
-            let new_entry = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-                RouteIntEntry::new(
-                    *route_prefix,
-                    *route_mask,
-                    *next_hop_addr,
-                    out_ifindex,
-                    *peer_ip_addr,
-                ),
-            ))));
-            route_entry = Arc::clone(&new_entry);
-            self.insert(&route_prefix, new_entry);
-            write_val!(route_entry).add_peer(*peer_ip_addr, peer_table.clone(peer_ip_addr));
-            trace!(
-                "RouteTableGen::add_modify: new route entry prefix {} for peer {}",
-                route_prefix,
-                peer_ip_addr
-            );
         }
-        Some(route_entry)
+        if notifier.wants(EVENT_PREFIX) {
+            for prefix_tree in [&self.prefix_tree4, &self.prefix_tree6].iter() {
+                for fe in read_val!(prefix_tree).values() {
+                    notifier.publish(Event::Prefix {
+                        vrf_id: self.context.vrf_id,
+                        op: EventOp::Delete,
+                        entry: (**read_val!(fe)).clone(),
+                    });
+                }
+            }
+        }
+        write_val!(self.peer_table_v4).clear();
+        write_val!(self.peer_table_v6).clear();
+        write_val!(self.route_table_v4).clear();
+        write_val!(self.route_table_v6).clear();
+        *write_val!(self.prefix_tree4) = PatriciaMap::new();
+        *write_val!(self.prefix_tree6) = PatriciaMap::new();
+    }
+}
+
+impl Drop for RibVrf {
+    fn drop(&mut self) {
+        trace!("RibVrf::Drop");
+        self.cleanup();
     }
 }
 
-pub enum PeerTableGen {
-    V4(&'static PEER_TABLE_V4),
-    V6(&'static PEER_TABLE_V6),
+pub struct Rib {
+    vrf_table: VrfTable<RibVrf>,
+    best_path: BestPathConfig,
+    dampening: DampeningShared,
+    policies: PolicyShared,
+    notifier: Notifier,
+    /// Stale and reuse timers of every VRF.
+    timer: Timer,
+    nh_table4: NhTable,
+    nh_table6: NhTable,
 }
 
-impl PeerTableGen {
-    fn contains_key(&self, ip_addr: &IpAddr) -> bool {
-        match self {
-            PeerTableGen::V4(_) => read_val!(PEER_TABLE_V4).contains_key(ip_addr),
-            PeerTableGen::V6(_) => read_val!(PEER_TABLE_V6).contains_key(ip_addr),
+impl Drop for Rib {
+    fn drop(&mut self) {
+        self.timer.stop();
+    }
+}
+
+impl Rib {
+    fn new(notifier: &Notifier, lfib: &Lfib) -> Rib {
+        let (nh_table4, nh_table6) = lfib.nh_tables();
+        let rib = Rib {
+            vrf_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            best_path: Arc::new(ReentrantMutex::new(RefCell::new(
+                BestPathAlgorithm::DistanceFirst,
+            ))),
+            dampening: Arc::new(ReentrantMutex::new(RefCell::new(None))),
+            policies: Arc::new(ReentrantMutex::new(RefCell::new(Policies::default()))),
+            notifier: notifier.clone(),
+            timer: Timer::new(),
+            nh_table4,
+            nh_table6,
+        };
+        let _ = rib.vrf_create(DEFAULT_VRF_ID);
+        rib
+    }
+    fn vrf(&self, vrf_id: u32) -> Result<Arc<RibVrf>, DsError> {
+        match read_val!(self.vrf_table).get(&vrf_id) {
+            Some(vrf) => Ok(Arc::clone(vrf)),
+            None => Err(ds_error(
+                DsError::VrfNotFound,
+                format!("Rib::vrf {} not found", vrf_id),
+            )),
         }
     }
-    fn get(&self, ip_addr: &IpAddr) -> PeerIntEntryWrapped {
-        match self {
-            PeerTableGen::V4(_) => Arc::clone(&read_val!(PEER_TABLE_V4)[ip_addr]),
-            PeerTableGen::V6(_) => Arc::clone(&read_val!(PEER_TABLE_V6)[ip_addr]),
+    fn vrf_create(&self, vrf_id: u32) -> Result<(), DsError> {
+        if read_val!(self.vrf_table).contains_key(&vrf_id) {
+            return Err(ds_error(
+                DsError::AlreadyExists,
+                format!("Rib::vrf_create {} already exists", vrf_id),
+            ));
         }
+        let vrf = Arc::new_cyclic(|weak| RibVrf::new(vrf_id, self, Weak::clone(weak)));
+        write_val!(self.vrf_table).insert(vrf_id, vrf);
+        Ok(())
     }
-
-    fn insert(&self, ip_addr: &IpAddr, entry: PeerIntEntryWrapped) {
-        match self {
-            PeerTableGen::V4(_) => {
-                write_val!(PEER_TABLE_V4).insert(*ip_addr, entry);
+    fn vrf_delete(&self, vrf_id: u32) -> Result<(), DsError> {
+        let vrf = write_val!(self.vrf_table).remove(&vrf_id);
+        match vrf {
+            Some(vrf) => {
+                vrf.cleanup();
+                Ok(())
             }
-            PeerTableGen::V6(_) => {
-                write_val!(PEER_TABLE_V6).insert(*ip_addr, entry);
+            None => Err(ds_error(
+                DsError::VrfNotFound,
+                format!("Rib::vrf_delete {} not found", vrf_id),
+            )),
+        }
+    }
+    fn vrf_ids(&self) -> Vec<u32> {
+        let mut vrf_ids: Vec<u32> = read_val!(self.vrf_table).keys().copied().collect();
+        vrf_ids.sort_unstable();
+        vrf_ids
+    }
+    /// True if only the default VRF is there and it holds nothing.
+    fn is_empty(&self) -> bool {
+        let guard = self.vrf_table.lock();
+        let vrf_table = guard.borrow();
+        vrf_table.keys().all(|vrf_id| *vrf_id == DEFAULT_VRF_ID)
+            && vrf_table.values().all(|vrf| {
+                read_val!(vrf.peer_table_v4).is_empty()
+                    && read_val!(vrf.peer_table_v6).is_empty()
+                    && read_val!(vrf.route_table_v4).is_empty()
+                    && read_val!(vrf.route_table_v6).is_empty()
+                    && read_val!(vrf.prefix_tree4).is_empty()
+                    && read_val!(vrf.prefix_tree6).is_empty()
+            })
+            && read_val!(self.policies).is_empty()
+    }
+    /// Runs `f` with the VRF table and every table of every VRF locked.
+    fn locked<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _vrf_table = self.vrf_table.lock();
+        let vrfs: Vec<Arc<RibVrf>> = read_val!(self.vrf_table).values().cloned().collect();
+        let mut f = Some(f);
+        let mut result = None;
+        lock_each(&vrfs, &|vrf, inner| vrf.locked(inner), &mut || {
+            result = f.take().map(|f| f());
+        });
+        result.expect("lock_each runs the closure once")
+    }
+    pub fn best_path_algorithm(&self) -> BestPathAlgorithm {
+        *read_val!(self.best_path)
+    }
+    /// Switches the best-path algorithm and re-selects every route.
+    pub fn set_best_path_algorithm(&self, algorithm: BestPathAlgorithm) {
+        *write_val!(self.best_path) = algorithm;
+        for vrf in read_val!(self.vrf_table).values() {
+            vrf.reselect_best_paths();
+        }
+        self.notifier.deliver();
+    }
+    /// Turns route flap dampening on with `config`, or off with `None`.
+    /// Turning it off forgets every penalty and installs the updates
+    /// suppressed paths kept aside.
+    pub fn set_dampening(&self, config: Option<DampeningConfig>) -> Result<(), DsError> {
+        if let Some(config) = &config {
+            config.check()?;
+        }
+        *write_val!(self.dampening) = config;
+        if config.is_none() {
+            for vrf in read_val!(self.vrf_table).values() {
+                vrf.release_dampened();
             }
         }
+        self.notifier.deliver();
+        Ok(())
     }
-    fn remove(&self, ip_addr: &IpAddr) {
-        match self {
-            PeerTableGen::V4(_) => {
-                if self.contains_key(ip_addr) {
-                    write_val!(read_val!(PEER_TABLE_V4)[ip_addr])
-                        .cleanup(&RouteTableGen::V4(&ROUTE_TABLE_V4));
-                }
-                write_val!(PEER_TABLE_V4).remove(ip_addr);
+    pub fn dampening(&self) -> Option<DampeningConfig> {
+        *read_val!(self.dampening)
+    }
+    /// Adds `range` to prefix list `list_id`, creating the list.
+    pub fn add_prefix_range(&self, list_id: u32, range: &PrefixRange) -> Result<(), DsError> {
+        write_val!(self.policies).add_prefix_range(list_id, *range)
+    }
+    pub fn delete_prefix_list(&self, list_id: u32) -> Result<(), DsError> {
+        write_val!(self.policies).delete_prefix_list(list_id)
+    }
+    /// Appends `term` to policy `policy_id`, creating the policy. Returns
+    /// the index of the term, as in `policy_hits`.
+    pub fn add_policy_term(&self, policy_id: u32, term: &PolicyTerm) -> Result<usize, DsError> {
+        write_val!(self.policies).add_term(policy_id, term.clone())
+    }
+    /// Deletes the policy. Peers it is attached to get all their routes
+    /// denied until it is defined again or they are detached.
+    pub fn delete_policy(&self, policy_id: u32) -> Result<(), DsError> {
+        write_val!(self.policies).delete_policy(policy_id)
+    }
+    /// How many routes each term of the policy decided, in term order.
+    pub fn policy_hits(&self, policy_id: u32) -> Result<Vec<u64>, DsError> {
+        read_val!(self.policies).hits(policy_id)
+    }
+    /// Puts back what `policy_hits` returned, without counting anything.
+    pub(crate) fn restore_policy_hits(&self, policy_id: u32, hits: &[u64]) -> Result<(), DsError> {
+        write_val!(self.policies).set_hits(policy_id, hits)
+    }
+    pub(crate) fn prefix_list_snapshots(&self) -> Vec<PrefixListSnapshot> {
+        read_val!(self.policies).prefix_list_snapshots()
+    }
+    pub(crate) fn policy_snapshots(&self) -> Vec<PolicySnapshot> {
+        read_val!(self.policies).policy_snapshots()
+    }
+    /// Puts back the prefix lists and policies of a snapshot, hits included.
+    pub(crate) fn restore_policies(
+        &self,
+        lists: &[PrefixListSnapshot],
+        policies: &[PolicySnapshot],
+    ) -> Result<(), DsError> {
+        write_val!(self.policies).restore(lists, policies)
+    }
+    /// Inbound policy attached to the peer.
+    pub fn peer_policy(&self, vrf_id: u32, addr: &IpAddr) -> Result<Option<u32>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        let policy = read_val!(peer_table.get(addr)).policy;
+        Ok(policy)
+    }
+    /// Attaches the inbound policy to the peer, or detaches it with `None`.
+    /// It applies to the routes the peer adds from then on.
+    pub fn set_peer_policy(
+        &self,
+        vrf_id: u32,
+        addr: &IpAddr,
+        policy_id: Option<u32>,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        if let Some(policy_id) = policy_id {
+            if !read_val!(self.policies).contains(policy_id) {
+                return Err(ds_error(
+                    DsError::NotFound,
+                    format!("policy {} not found", policy_id),
+                ));
             }
-            PeerTableGen::V6(_) => {
-                if self.contains_key(ip_addr) {
-                    write_val!(read_val!(PEER_TABLE_V6)[ip_addr])
-                        .cleanup(&RouteTableGen::V6(&ROUTE_TABLE_V6));
+        }
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        write_val!(peer_table.get(addr)).policy = policy_id;
+        Ok(())
+    }
+    /// Paths currently suppressed in the VRF, by peer and prefix.
+    pub fn dampened_paths(&self, vrf_id: u32) -> Result<Vec<DampenedPath>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let config = match self.dampening() {
+            Some(config) => config,
+            None => return Ok(Vec::new()),
+        };
+        let mut paths = Vec::new();
+        vrf.locked(|| {
+            for peer_table in [&vrf.peer_table_v4, &vrf.peer_table_v6].iter() {
+                for (addr, pe) in read_val!(peer_table).iter() {
+                    for (route_key, state) in read_val!(pe).flaps.iter() {
+                        if state.suppressed {
+                            paths.push(DampenedPath {
+                                peer: *addr,
+                                prefix: route_key.0,
+                                prefix_len: route_key.1,
+                                penalty: state.penalty(&config),
+                                reuse_in: state.reuse_in(&config),
+                            });
+                        }
+                    }
                 }
-                write_val!(PEER_TABLE_V6).remove(ip_addr);
             }
+        });
+        paths.sort_by_key(|path| (path.peer, path.prefix, path.prefix_len));
+        Ok(paths)
+    }
+    /// Adds the peer or updates an existing one. `Ok(true)` means it was created.
+    pub fn add_peer(&self, vrf_id: u32, peer: &Peer) -> Result<bool, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let created = _peer_add_modify(&peer.addr, &vrf.peer_table(&peer.addr), peer.out_ifindex);
+        self.notifier.publish(Event::Peer {
+            vrf_id,
+            op: if created {
+                EventOp::Add
+            } else {
+                EventOp::Modify
+            },
+            peer: peer.clone(),
+        });
+        self.notifier.deliver();
+        Ok(created)
+    }
+    pub fn lookup_peer(&self, vrf_id: u32, addr: &IpAddr) -> Result<Peer, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        _peer_lookup(addr, &vrf.peer_table(addr))
+    }
+    /// Snapshot of the IPv4 or IPv6 peers of the VRF.
+    pub fn peers(&self, vrf_id: u32, ipv4: bool) -> Result<Vec<Peer>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = if ipv4 {
+            PeerTableGen::V4(&vrf.peer_table_v4)
+        } else {
+            PeerTableGen::V6(&vrf.peer_table_v6)
+        };
+        Ok(peer_table.peers())
+    }
+    /// Removes the peer and withdraws every route it announced.
+    pub fn delete_peer(&self, vrf_id: u32, addr: &IpAddr) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer = _peer_lookup(addr, &vrf.peer_table(addr));
+        let deleted = _peer_delete(addr, &vrf.peer_table(addr), &vrf.route_table(addr));
+        if let (Ok(()), Ok(peer)) = (&deleted, peer) {
+            self.notifier.publish(Event::Peer {
+                vrf_id,
+                op: EventOp::Delete,
+                peer,
+            });
         }
+        self.notifier.deliver();
+        deleted
     }
-    fn clone(&self, ip_addr: &IpAddr) -> PeerIntEntryWrapped {
-        match self {
-            PeerTableGen::V4(_) => Arc::clone(&read_val!(PEER_TABLE_V4)[ip_addr]),
-            PeerTableGen::V6(_) => Arc::clone(&read_val!(PEER_TABLE_V6)[ip_addr]),
+    /// Graceful restart of the peer's session: its routes stay installed,
+    /// marked stale, instead of being withdrawn. Routes the peer adds again
+    /// are fresh; `end_of_rib` withdraws the others, and so does a timer
+    /// after `stale_time` unless that is zero.
+    pub fn restart_peer(
+        &self,
+        vrf_id: u32,
+        addr: &IpAddr,
+        stale_time: Duration,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let restart = vrf.locked(|| {
+            let peer_table = vrf.peer_table(addr);
+            if !peer_table.contains_key(addr) {
+                return Err(ds_error(
+                    DsError::PeerNotFound,
+                    format!("peer {} not found", addr),
+                ));
+            }
+            let pe = peer_table.get(addr);
+            let guard = pe.lock();
+            let mut pe = guard.borrow_mut();
+            let stale = read_val!(pe.peer_route_table).keys().copied().collect();
+            pe.stale = stale;
+            pe.restarting = true;
+            pe.restarts = pe.restarts.wrapping_add(1);
+            trace!(
+                "Rib::restart_peer {}: {} stale routes",
+                addr,
+                pe.stale.len()
+            );
+            Ok(pe.restarts)
+        })?;
+        if stale_time > Duration::from_secs(0) {
+            start_stale_timer(&vrf.context, *addr, restart, stale_time);
         }
+        Ok(())
     }
-    fn _iterate(&self, keys_vals: Iter<IpAddr, PeerIntEntryWrapped>) {
-        for (key, val) in keys_vals {
-            println!("key: {} val: {}", key, read_val!(val).prefix);
-            unsafe {
-                let ip: IpAddrC = IpAddrC {
-                    family: 1,
-                    addr: ptr::null_mut(),
-                };
-                if on_peer(&ip) == 1 {
-                    break;
+    /// Ends the restart of the peer, withdrawing the routes it did not add
+    /// again. Returns how many were withdrawn, 0 if it was not restarting.
+    pub fn end_of_rib(&self, vrf_id: u32, addr: &IpAddr) -> Result<usize, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let swept = vrf.sweep_stale(addr, None);
+        self.notifier.deliver();
+        swept
+    }
+    /// Routes of the peer still stale from its restart, sorted.
+    pub fn stale_routes(&self, vrf_id: u32, addr: &IpAddr) -> Result<Vec<RouteKey>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        let mut stale: Vec<RouteKey> = read_val!(peer_table.get(addr))
+            .stale
+            .iter()
+            .copied()
+            .collect();
+        stale.sort_unstable();
+        Ok(stale)
+    }
+    /// Moves the session of the peer to `state`. Leaving `Established`
+    /// withdraws every route of the peer, stale ones included, and the
+    /// routes it shared with other peers fall back to their next best path.
+    /// A peer going `Down` during a graceful restart keeps its routes, still
+    /// stale, for `end_of_rib` or the stale timer to sweep.
+    pub fn set_peer_state(
+        &self,
+        vrf_id: u32,
+        addr: &IpAddr,
+        state: PeerState,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let changed = vrf.locked(|| {
+            let peer_table = vrf.peer_table(addr);
+            if !peer_table.contains_key(addr) {
+                return Err(ds_error(
+                    DsError::PeerNotFound,
+                    format!("peer {} not found", addr),
+                ));
+            }
+            let pe = peer_table.get(addr);
+            let old = read_val!(pe).status.state;
+            if old == state {
+                return Ok(false);
+            }
+            if !old.can_move_to(state) {
+                return Err(ds_error(
+                    DsError::InvalidArgument,
+                    format!("peer {} cannot go from {:?} to {:?}", addr, old, state),
+                ));
+            }
+            trace!("Rib::set_peer_state {} {:?} -> {:?}", addr, old, state);
+            write_val!(pe).status.set_state(state);
+            let keep_stale = state == PeerState::Down && read_val!(pe).restarting;
+            if old == PeerState::Established && !keep_stale {
+                write_val!(pe).withdraw(&vrf.route_table(addr));
+            }
+            Ok(true)
+        });
+        if let Ok(true) = changed {
+            self.notifier.publish(Event::Peer {
+                vrf_id,
+                op: if state == PeerState::Established {
+                    EventOp::Up
+                } else {
+                    EventOp::Down
+                },
+                peer: self.lookup_peer(vrf_id, addr)?,
+            });
+        }
+        self.notifier.deliver();
+        changed.map(|_| ())
+    }
+    pub fn peer_status(&self, vrf_id: u32, addr: &IpAddr) -> Result<PeerStatus, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        let status = read_val!(peer_table.get(addr)).status;
+        Ok(status)
+    }
+    pub(crate) fn peer_session(&self, vrf_id: u32, addr: &IpAddr) -> Result<PeerSession, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        let session = read_val!(peer_table.get(addr)).session();
+        Ok(session)
+    }
+    /// Puts back what `peer_session` returned, once the peer and its routes
+    /// are there again. The routes are left as they are.
+    pub(crate) fn restore_peer_session(
+        &self,
+        vrf_id: u32,
+        addr: &IpAddr,
+        session: &PeerSession,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
+        }
+        write_val!(peer_table.get(addr)).set_session(session);
+        Ok(())
+    }
+    /// Makes `route` what the peer announces for the prefix again, or takes
+    /// back what it announces if `None`. Unlike `add_route` and
+    /// `delete_route` this goes around policies, dampening and the session
+    /// of the peer, which `restore_peer_session` puts back.
+    pub(crate) fn restore_peer_route(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        route_key: &RouteKey,
+        route: Option<&Route>,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let mut peer_table = vrf.peer_table(peer);
+        let mut route_table = vrf.route_table(peer);
+        if !peer_table.contains_key(peer) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", peer),
+            ));
+        }
+        let pe = peer_table.get(peer);
+        let installed = read_val!(read_val!(pe).peer_route_table).contains_key(route_key);
+        let restored = match route {
+            Some(route) => {
+                let rc = write_val!(pe).route_add_modify(
+                    route_key,
+                    &prefix_len_mask(&route_key.0, route_key.1),
+                    PeerPath {
+                        paths: route.paths.clone(),
+                        attrs: route.attrs,
+                    },
+                    peer,
+                    &mut route_table,
+                    &mut peer_table,
+                    &vrf.context,
+                );
+                match rc {
+                    DsError::Ok | DsError::Modified => Ok(()),
+                    err => Err(err),
                 }
             }
+            None if installed => _peer_route_delete(peer, route_key, &peer_table, &route_table),
+            None => Ok(()),
+        };
+        self.notifier.deliver();
+        restored
+    }
+    /// Puts back the status and policy of a snapshot's peer once its routes
+    /// are there again, without withdrawing them or checking the policy.
+    pub(crate) fn restore_peer_status(
+        &self,
+        vrf_id: u32,
+        addr: &IpAddr,
+        status: &PeerStatus,
+        policy: Option<u32>,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(addr);
+        if !peer_table.contains_key(addr) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", addr),
+            ));
         }
+        let pe = peer_table.get(addr);
+        let guard = pe.lock();
+        let mut pe = guard.borrow_mut();
+        pe.status = *status;
+        pe.policy = policy;
+        Ok(())
     }
-    fn iterate(&mut self) {
-        match self {
-            PeerTableGen::V4(_) => self._iterate(read_val!(PEER_TABLE_V4).iter()),
-            PeerTableGen::V6(_) => self._iterate(read_val!(PEER_TABLE_V6).iter()),
+    /// Adds the route on behalf of `peer` or updates it. `Ok(true)` means the
+    /// peer did not announce it before.
+    pub fn add_route(&self, vrf_id: u32, peer: &IpAddr, route: &Route) -> Result<bool, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let added = Rib::_add_route(&vrf, peer, route);
+        self.notifier.deliver();
+        added
+    }
+    /// Adds or updates routes of one peer in a single batch. The result of
+    /// each route is what `add_route` returns for it; the batch only fails
+    /// as a whole if the VRF does not exist.
+    pub fn add_routes(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        routes: &[Route],
+    ) -> Result<Vec<Result<bool, DsError>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        trace!("Rib::add_routes peer {} routes {}", peer, routes.len());
+        let results = vrf.locked(|| {
+            routes
+                .iter()
+                .map(|route| Rib::_add_route(&vrf, peer, route))
+                .collect()
+        });
+        self.notifier.deliver();
+        Ok(results)
+    }
+    fn _add_route(vrf: &RibVrf, peer: &IpAddr, route: &Route) -> Result<bool, DsError> {
+        if peer.is_ipv4() != route.prefix.is_ipv4() {
+            return Err(ds_error(
+                DsError::FamilyMismatch,
+                format!("peer {} and prefix {} families differ", peer, route.prefix),
+            ));
         }
+        _peer_route_add_modify(
+            peer,
+            route,
+            &mut vrf.peer_table(peer),
+            &mut vrf.route_table(peer),
+            &vrf.context,
+        )
     }
-}
-
-pub struct RouteIntEntry {
-    prefix: IpAddr,
-    mask: IpAddr,
-    next_hop: IpAddr,
-    out_ifindex: u32,
-    peer_table: PeerTable,
-    creator: IpAddr,
-}
-
-impl Clone for RouteIntEntry {
-    fn clone(&self) -> Self {
-        RouteIntEntry {
-            prefix: self.prefix,
-            mask: self.mask,
-            next_hop: self.next_hop,
-            out_ifindex: self.out_ifindex,
-            peer_table: Arc::clone(&self.peer_table),
-            creator: self.creator,
+    pub fn lookup_route(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<Route, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        _route_lookup(&(*prefix, prefix_len), &vrf.route_table(prefix))
+    }
+    /// Adjacent next hops the installed paths of the route resolve to, empty
+    /// if none of them resolves.
+    pub fn lookup_resolved_paths(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<Vec<NextHop>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let re = _route_entry(&(*prefix, prefix_len), &vrf.route_table(prefix))?;
+        let resolved = read_val!(re).resolved.clone();
+        Ok(resolved)
+    }
+    fn next_hop_changed(&self, addr: &IpAddr) {
+        for vrf in read_val!(self.vrf_table).values() {
+            vrf.reresolve(addr, max_prefix_len(addr));
         }
     }
-}
-
-impl RouteIntEntry {
-    pub fn new(
-        _prefix: IpAddr,
-        _mask: IpAddr,
-        _next_hop: IpAddr,
-        _out_ifindex: u32,
-        _creator: IpAddr,
-    ) -> RouteIntEntry {
-        RouteIntEntry {
-            prefix: _prefix,
-            mask: _mask,
-            next_hop: _next_hop,
-            out_ifindex: _out_ifindex,
-            peer_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
-            creator: _creator,
+    /// Peer whose path won best-path selection, with its route.
+    pub fn lookup_best_route(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<(IpAddr, Route), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let re = _route_entry(&(*prefix, prefix_len), &vrf.route_table(prefix))?;
+        let best_peer = read_val!(re).best_peer();
+        match best_peer {
+            Some(peer) => Ok((peer, read_val!(re).route())),
+            None => Err(no_paths_error(prefix, prefix_len)),
         }
     }
-    pub fn add_peer(&mut self, peer_prefix: IpAddr, peer: PeerIntEntryWrapped) -> i32 {
-        if read_val!(self.peer_table).contains_key(&peer_prefix) {
-            trace!("RouteIntEntry::add_peer {} already exists", peer_prefix);
-            -1
+    pub fn lookup_peer_route(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<Route, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        _peer_route_lookup(peer, &(*prefix, prefix_len), &vrf.peer_table(peer))
+    }
+    /// Up to `limit` routes of the VRF in (prefix, prefix_len) order,
+    /// starting after `after` or at the first route if it is `None`.
+    pub fn routes(
+        &self,
+        vrf_id: u32,
+        ipv4: bool,
+        after: Option<&RouteKey>,
+        limit: usize,
+    ) -> Result<Vec<Route>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let route_table = if ipv4 {
+            &vrf.route_table_v4
         } else {
-            trace!("RouteIntEntry::add_peer {}", peer_prefix);
-            write_val!(self.peer_table).insert(peer_prefix, Arc::clone(&peer));
-            0
+            &vrf.route_table_v6
+        };
+        Ok(route_table_page(route_table, None, after, limit))
+    }
+    /// Like `routes`, for the routes announced by `peer`.
+    pub fn peer_routes(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        after: Option<&RouteKey>,
+        limit: usize,
+    ) -> Result<Vec<Route>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let peer_table = vrf.peer_table(peer);
+        if !peer_table.contains_key(peer) {
+            return Err(ds_error(
+                DsError::PeerNotFound,
+                format!("peer {} not found", peer),
+            ));
         }
+        let pe = peer_table.get(peer);
+        let routes = route_table_page(&read_val!(pe).peer_route_table, Some(peer), after, limit);
+        Ok(routes)
     }
-    pub fn delete_peer(&self, peer_prefix: IpAddr, route_table: &RouteTableGen) -> i32 {
-        trace!("RouteIntEntry::delete_peer {}", peer_prefix);
-        let rc: i32;
-        if read_val!(self.peer_table).contains_key(&peer_prefix) {
-            write_val!(self.peer_table).remove(&peer_prefix);
-            rc = 0;
-        } else {
-            trace!("RouteIntEntry::delete_peer {} does not exist", peer_prefix);
-            rc = -1;
+    pub fn delete_route(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let deleted = _peer_route_withdraw(
+            peer,
+            &(*prefix, prefix_len),
+            &vrf.peer_table(peer),
+            &vrf.route_table(peer),
+            &vrf.context,
+        );
+        self.notifier.deliver();
+        deleted
+    }
+    /// Withdraws routes of one peer in a single batch, see `add_routes`.
+    pub fn delete_routes(
+        &self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        route_keys: &[RouteKey],
+    ) -> Result<Vec<Result<(), DsError>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        trace!(
+            "Rib::delete_routes peer {} routes {}",
+            peer,
+            route_keys.len()
+        );
+        let results = vrf.locked(|| {
+            route_keys
+                .iter()
+                .map(|route_key| {
+                    _peer_route_withdraw(
+                        peer,
+                        route_key,
+                        &vrf.peer_table(peer),
+                        &vrf.route_table(peer),
+                        &vrf.context,
+                    )
+                })
+                .collect()
+        });
+        self.notifier.deliver();
+        Ok(results)
+    }
+    pub fn add_prefix(&self, vrf_id: u32, entry: &PrefixEntry) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let added = self._add_prefix(&vrf, entry);
+        self.notifier.deliver();
+        added
+    }
+    /// Adds or replaces prefixes in a single batch, see `add_routes`.
+    pub fn add_prefixes(
+        &self,
+        vrf_id: u32,
+        entries: &[PrefixEntry],
+    ) -> Result<Vec<Result<(), DsError>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        trace!("Rib::add_prefixes {}", entries.len());
+        let results = vrf.locked(|| {
+            entries
+                .iter()
+                .map(|entry| self._add_prefix(&vrf, entry))
+                .collect()
+        });
+        self.notifier.deliver();
+        Ok(results)
+    }
+    fn _add_prefix(&self, vrf: &RibVrf, entry: &PrefixEntry) -> Result<(), DsError> {
+        let prefix_tree = vrf.prefix_tree(&entry.prefix);
+        let existed = prefix_tree.get(entry.prefix, entry.prefix_len).is_some();
+        _longest_match_add(&prefix_tree, entry.clone())?;
+        self.prefix_event(vrf, &entry.prefix, entry.prefix_len, existed);
+        Ok(())
+    }
+    /// The entry of exactly `prefix/prefix_len`, unlike `longest_match`.
+    pub fn lookup_prefix(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<PrefixEntry, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let fe = _prefix_entry(prefix, prefix_len, &vrf.prefix_tree(prefix))?;
+        let entry = (**read_val!(fe)).clone();
+        Ok(entry)
+    }
+    /// Peers with the routes they announced, routes with their peers and
+    /// prefix tree entries of the VRF.
+    fn dump_vrf(&self, vrf_id: u32) -> Result<Value, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let mut peers = Vec::new();
+        let mut routes = Vec::new();
+        for (peer_table, route_table) in [
+            (&vrf.peer_table_v4, &vrf.route_table_v4),
+            (&vrf.peer_table_v6, &vrf.route_table_v6),
+        ]
+        .iter()
+        {
+            let mut page: Vec<(IpAddr, Value)> = read_val!(peer_table)
+                .iter()
+                .map(|(addr, pe)| (*addr, read_val!(pe).dump()))
+                .collect();
+            page.sort_by_key(|(addr, _)| *addr);
+            peers.extend(page.into_iter().map(|(_, dump)| dump));
+            let mut page: Vec<(RouteKey, Value)> = read_val!(route_table)
+                .iter()
+                .map(|(route_key, re)| (*route_key, read_val!(re).dump()))
+                .collect();
+            page.sort_by_key(|(route_key, _)| *route_key);
+            routes.extend(page.into_iter().map(|(_, dump)| dump));
         }
-        if self.get_number_of_peers() == 0 {
-            trace!("RouteIntEntry::delete_peer {} last gone", peer_prefix);
-            route_table.remove(&self.prefix);
+        let prefixes: Vec<Value> = self
+            .prefixes(vrf_id)?
+            .iter()
+            .map(json::prefix_json)
+            .collect();
+        Ok(json!({
+            "vrf_id": vrf_id,
+            "peers": peers,
+            "routes": routes,
+            "prefixes": prefixes,
+        }))
+    }
+    /// Every entry of the prefix tree of the VRF, IPv4 first.
+    fn prefixes(&self, vrf_id: u32) -> Result<Vec<PrefixEntry>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let mut entries = Vec::new();
+        for prefix_tree in [&vrf.prefix_tree4, &vrf.prefix_tree6].iter() {
+            for fe in read_val!(prefix_tree).values() {
+                entries.push((**read_val!(fe)).clone());
+            }
         }
-        return rc;
+        Ok(entries)
     }
-    pub fn peer_exists(&self, _peer_prefix: IpAddr) -> bool {
-        read_val!(self.peer_table).contains_key(&_peer_prefix)
+    /// Most specific prefix covering `addr`.
+    pub fn longest_match(&self, vrf_id: u32, addr: &IpAddr) -> Result<PrefixEntry, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        _longest_match_lookup(addr, &vrf.prefix_tree(addr))
     }
-    pub fn get_number_of_peers(&self) -> usize {
-        read_val!(self.peer_table).len()
+    pub fn delete_prefix(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let deleted = self._delete_prefix(&vrf, prefix, prefix_len);
+        self.notifier.deliver();
+        deleted
+    }
+    /// Deletes prefixes in a single batch, see `add_routes`.
+    pub fn delete_prefixes(
+        &self,
+        vrf_id: u32,
+        prefixes: &[RouteKey],
+    ) -> Result<Vec<Result<(), DsError>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        trace!("Rib::delete_prefixes {}", prefixes.len());
+        let results = vrf.locked(|| {
+            prefixes
+                .iter()
+                .map(|(prefix, prefix_len)| self._delete_prefix(&vrf, prefix, *prefix_len))
+                .collect()
+        });
+        self.notifier.deliver();
+        Ok(results)
+    }
+    fn _delete_prefix(&self, vrf: &RibVrf, prefix: &IpAddr, prefix_len: u8) -> Result<(), DsError> {
+        let entry = vrf.prefix_tree(prefix).get(*prefix, prefix_len);
+        _longest_match_delete(prefix, prefix_len, &vrf.prefix_tree(prefix))?;
+        vrf.reresolve(prefix, prefix_len);
+        if let Some(fe) = entry {
+            self.notifier.publish(Event::Prefix {
+                vrf_id: vrf.context.vrf_id,
+                op: EventOp::Delete,
+                entry: (**read_val!(fe)).clone(),
+            });
+        }
+        Ok(())
+    }
+    /// Reports the prefix as added, or as modified if it `existed`, and
+    /// resolves again the routes whose next hops it may cover.
+    fn prefix_event(&self, vrf: &RibVrf, prefix: &IpAddr, prefix_len: u8, existed: bool) {
+        vrf.reresolve(prefix, prefix_len);
+        if let Some(fe) = vrf.prefix_tree(prefix).get(*prefix, prefix_len) {
+            self.notifier.publish(Event::Prefix {
+                vrf_id: vrf.context.vrf_id,
+                op: if existed {
+                    EventOp::Modify
+                } else {
+                    EventOp::Add
+                },
+                entry: (**read_val!(fe)).clone(),
+            });
+        }
+    }
+    /// Adds a path to the route or updates the weight of an existing one.
+    /// `Ok(true)` means the path is new.
+    pub fn add_route_path(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+        path: &NextHop,
+    ) -> Result<bool, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let re = _route_entry(&(*prefix, prefix_len), &vrf.route_table(prefix))?;
+        let result = {
+            let guard = re.lock();
+            let mut re = guard.borrow_mut();
+            let before = re.installed_route();
+            let result = match re.best_paths_mut() {
+                Some(paths) => _paths_add(paths, path),
+                None => Err(no_paths_error(prefix, prefix_len)),
+            };
+            re.resolve();
+            re.route_event(before);
+            result
+        };
+        self.notifier.deliver();
+        result
+    }
+    /// Removes one path. The last path cannot be removed, delete the route instead.
+    pub fn delete_route_path(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+        addr: &IpAddr,
+        out_ifindex: u32,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let re = _route_entry(&(*prefix, prefix_len), &vrf.route_table(prefix))?;
+        let result = {
+            let guard = re.lock();
+            let mut re = guard.borrow_mut();
+            let before = re.installed_route();
+            let result = match re.best_paths_mut() {
+                Some(paths) => _paths_delete(paths, addr, out_ifindex),
+                None => Err(no_paths_error(prefix, prefix_len)),
+            };
+            re.resolve();
+            re.route_event(before);
+            result
+        };
+        self.notifier.deliver();
+        result
+    }
+    pub fn select_route_path(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+        flow: &Flow,
+    ) -> Result<NextHop, DsError> {
+        let route = self.lookup_route(vrf_id, prefix, prefix_len)?;
+        _select_path(&route.paths, flow)
+    }
+    pub fn add_prefix_path(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+        path: &NextHop,
+    ) -> Result<bool, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let fe = _prefix_entry(prefix, prefix_len, &vrf.prefix_tree(prefix))?;
+        let added = _paths_add(&mut write_val!(fe).paths, path)?;
+        self.prefix_event(&vrf, prefix, prefix_len, true);
+        self.notifier.deliver();
+        Ok(added)
+    }
+    pub fn delete_prefix_path(
+        &self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+        addr: &IpAddr,
+        out_ifindex: u32,
+    ) -> Result<(), DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let fe = _prefix_entry(prefix, prefix_len, &vrf.prefix_tree(prefix))?;
+        _paths_delete(&mut write_val!(fe).paths, addr, out_ifindex)?;
+        self.prefix_event(&vrf, prefix, prefix_len, true);
+        self.notifier.deliver();
+        Ok(())
+    }
+    /// Path for the flow through the most specific prefix covering its
+    /// destination.
+    pub fn select_forwarding_path(&self, vrf_id: u32, flow: &Flow) -> Result<NextHop, DsError> {
+        let fe = self.longest_match(vrf_id, &flow.dst)?;
+        _select_path(&fe.paths, flow)
+    }
+}
+
+/// Sweeps the stale routes of restart `restart` of the peer once
+/// `stale_time` is over, unless the VRF is gone by then.
+fn start_stale_timer(context: &RouteContext, addr: IpAddr, restart: u32, stale_time: Duration) {
+    let vrf = Weak::clone(&context.vrf);
+    context.timer.start(stale_time, move || {
+        if let Some(vrf) = vrf.upgrade() {
+            trace!("stale timer of peer {} restart {} expired", addr, restart);
+            let _ = vrf.sweep_stale(&addr, Some(restart));
+            vrf.context.notifier.deliver();
+        }
+    });
+}
+
+/// Tries to reuse the suppressed path of the peer once `after` is over.
+fn start_reuse_timer(context: &RouteContext, peer: IpAddr, route_key: RouteKey, after: Duration) {
+    let vrf = Weak::clone(&context.vrf);
+    // a little late rather than early, when the penalty is not down yet
+    context
+        .timer
+        .start(after + Duration::from_millis(1), move || {
+            if let Some(vrf) = vrf.upgrade() {
+                vrf.reuse_dampened(&peer, &route_key);
+                vrf.context.notifier.deliver();
+            }
+        });
+}
+
+/// Owns every RIB and LFIB table. C callers get it as an opaque handle from
+/// `ds_create` and pass it to the `ds_*` functions.
+pub struct DataStore {
+    rib: Rib,
+    lfib: Lfib,
+    notifier: Notifier,
+}
+
+impl DataStore {
+    pub fn new() -> DataStore {
+        let notifier = Notifier::new();
+        let lfib = Lfib::new(&notifier);
+        DataStore {
+            rib: Rib::new(&notifier, &lfib),
+            lfib,
+            notifier,
+        }
+    }
+    pub fn rib(&self) -> &Rib {
+        &self.rib
+    }
+    pub fn lfib(&self) -> &Lfib {
+        &self.lfib
+    }
+    pub fn vrf_create(&self, vrf_id: u32) -> Result<(), DsError> {
+        self.rib.vrf_create(vrf_id)?;
+        self.lfib.vrf_create(vrf_id)
+    }
+    /// Drops the VRF with everything in it. The default VRF cannot be deleted.
+    pub fn vrf_delete(&self, vrf_id: u32) -> Result<(), DsError> {
+        if vrf_id == DEFAULT_VRF_ID {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                String::from("default vrf cannot be deleted"),
+            ));
+        }
+        let deleted = self
+            .rib
+            .vrf_delete(vrf_id)
+            .and_then(|()| self.lfib.vrf_delete(vrf_id));
+        self.notifier.deliver();
+        deleted
+    }
+    /// Adds an adjacent next hop and resolves again the routes reaching it.
+    pub fn add_nh(&self, addr: &IpAddr, ifindex: u32) {
+        self.lfib.add_nh(addr, ifindex);
+        self.rib.next_hop_changed(addr);
+    }
+    pub fn delete_nh(&self, addr: &IpAddr) {
+        self.lfib.delete_nh(addr);
+        self.rib.next_hop_changed(addr);
+    }
+    /// Applies every operation of `tx` in order, or none of them: when one
+    /// fails the ones before it are undone and the error tells which one
+    /// it was. Readers wait for the commit to finish and subscribers only
+    /// hear about committed changes.
+    pub fn commit(&self, tx: &Transaction) -> Result<(), TxError> {
+        self.notifier.stage();
+        let committed = self.rib.locked(|| self.lfib.locked(|| tx.apply(self)));
+        self.notifier.unstage(committed.is_ok());
+        self.notifier.deliver();
+        committed
+    }
+    /// Writes everything the store holds, the XC, NHLFE and ILM indexes
+    /// included, in the snapshot format of `SNAPSHOT_VERSION`.
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> Result<(), DsError> {
+        snapshot::write(self, writer)
+    }
+    /// Loads a snapshot from `write_snapshot` into the store, which must be
+    /// empty. Entries get back the indexes they had, so references kept
+    /// outside the store stay valid. A snapshot that fails to load may
+    /// leave part of it in the store.
+    pub fn read_snapshot<R: Read>(&self, reader: &mut R) -> Result<(), DsError> {
+        snapshot::read(self, reader)
+    }
+    /// Like `write_snapshot`, into the file at `path`. The file is only
+    /// replaced once the snapshot is complete.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), DsError> {
+        snapshot::save(self, path.as_ref())
+    }
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), DsError> {
+        snapshot::load(self, path.as_ref())
+    }
+    /// Everything the store holds as pretty-printed JSON, derived state such
+    /// as the peers of each route, XC and NHLFE entries included.
+    pub fn export_json(&self) -> Result<String, DsError> {
+        json::export(self)
+    }
+    /// Rebuilds the state of an `export_json` dump in the store, which must
+    /// be empty. Derived state in the dump is not read, it comes back by
+    /// adding the entries; XC, NHLFE and ILM indexes come back as dumped.
+    pub fn import_json(&self, json: &str) -> Result<(), DsError> {
+        json::import(self, json)
+    }
+    /// Calls `callback` for every change whose `EVENT_*` bit is in `mask`,
+    /// in the order the changes were made. Returns the subscription id.
+    pub fn subscribe<F>(&self, mask: u32, callback: F) -> Result<u32, DsError>
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.notifier.subscribe(mask, Box::new(callback))
+    }
+    pub fn unsubscribe(&self, id: u32) -> Result<(), DsError> {
+        self.notifier.unsubscribe(id)
+    }
+}
+
+impl Default for DataStore {
+    fn default() -> Self {
+        DataStore::new()
+    }
+}
+
+lazy_static! {
+    pub static ref DEFAULT_DATA_STORE: DataStore = DataStore::new();
+}
+
+fn default_data_store() -> *mut DataStore {
+    &*DEFAULT_DATA_STORE as *const DataStore as *mut DataStore
+}
+
+fn data_store_from_handle<'a>(ds: *mut DataStore) -> Result<&'a DataStore, DsError> {
+    match unsafe { ds.as_ref() } {
+        Some(ds) => Ok(ds),
+        None => Err(ds_error(
+            DsError::InvalidHandle,
+            String::from("data store handle is null"),
+        )),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ds_create() -> *mut DataStore {
+    trace!("ds_create");
+    Box::into_raw(Box::new(DataStore::new()))
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_destroy(ds: *mut DataStore) {
+    trace!("ds_destroy");
+    if ds.is_null() || ds == default_data_store() {
+        trace!("ds_destroy: nothing to destroy");
+        return;
+    }
+    unsafe {
+        drop(Box::from_raw(ds));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ds_vrf_create(ds: *mut DataStore, vrf_id: u32) -> DsError {
+    trace!("vrf_create {}", vrf_id);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.vrf_create(vrf_id))
+}
+
+#[no_mangle]
+pub extern "C" fn vrf_create(vrf_id: u32) -> DsError {
+    ds_vrf_create(default_data_store(), vrf_id)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_vrf_delete(ds: *mut DataStore, vrf_id: u32) -> DsError {
+    trace!("vrf_delete {}", vrf_id);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.vrf_delete(vrf_id))
+}
+
+#[no_mangle]
+pub extern "C" fn vrf_delete(vrf_id: u32) -> DsError {
+    ds_vrf_delete(default_data_store(), vrf_id)
+}
+
+pub enum PrefixTreeGen<'a> {
+    V4(&'a PrefixTree),
+    V6(&'a PrefixTree),
+}
+
+impl<'a> PrefixTreeGen<'a> {
+    #[inline]
+    fn tree(&self) -> &PrefixTree {
+        match self {
+            PrefixTreeGen::V4(tree) => tree,
+            PrefixTreeGen::V6(tree) => tree,
+        }
+    }
+    fn family_matches(&self, key: &IpAddr) -> bool {
+        match self {
+            PrefixTreeGen::V4(_) => {
+                if key.is_ipv6() {
+                    trace!("wrong argument type, expected ipv4");
+                    return false;
+                }
+            }
+            PrefixTreeGen::V6(_) => {
+                if key.is_ipv4() {
+                    trace!("wrong argument type, expected ipv6");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+    fn check_key(&self, key: &IpAddr, prefix_len: u8) -> DsError {
+        if !self.family_matches(key) {
+            return ds_error(
+                DsError::FamilyMismatch,
+                format!("{} does not match the prefix tree family", key),
+            );
+        }
+        if prefix_len > max_prefix_len(key) {
+            return ds_error(
+                DsError::InvalidPrefix,
+                format!("prefix length {} is out of range for {}", prefix_len, key),
+            );
+        }
+        DsError::Ok
+    }
+    fn insert(&self, key: IpAddr, prefix_len: u8, fwd_entry: PrefixEntryWrapped) -> DsError {
+        match self.check_key(&key, prefix_len) {
+            DsError::Ok => {}
+            err => return err,
+        }
+        write_val!(self.tree()).insert(ip_addr_prefix_bits(&key, prefix_len), fwd_entry);
+        DsError::Ok
+    }
+
+    fn get(&self, key: IpAddr, prefix_len: u8) -> Option<PrefixEntryWrapped> {
+        if !self.family_matches(&key) || prefix_len > max_prefix_len(&key) {
+            return None;
+        }
+        read_val!(self.tree())
+            .get(ip_addr_prefix_bits(&key, prefix_len))
+            .map(Arc::clone)
+    }
+
+    fn get_longest_common_prefix(&self, key: IpAddr) -> Option<PrefixEntryWrapped> {
+        if !self.family_matches(&key) {
+            return None;
+        }
+        read_val!(self.tree())
+            .get_longest_common_prefix(&ip_addr_prefix_bits(&key, max_prefix_len(&key)))
+            .map(|(_k, v)| Arc::clone(v))
+    }
+
+    fn remove(&self, key: IpAddr, prefix_len: u8) -> DsError {
+        match self.check_key(&key, prefix_len) {
+            DsError::Ok => {}
+            err => return err,
+        }
+        match write_val!(self.tree()).remove(ip_addr_prefix_bits(&key, prefix_len)) {
+            Some(_rc) => DsError::Ok,
+            None => ds_error(
+                DsError::NotFound,
+                format!("prefix {}/{} not found", key, prefix_len),
+            ),
+        }
+    }
+}
+
+/// Best routes, or the routes as advertised by `peer` if it is set.
+fn route_table_page(
+    table: &RouteTable,
+    peer: Option<&IpAddr>,
+    after: Option<&RouteKey>,
+    limit: usize,
+) -> Vec<Route> {
+    let guard = table.lock();
+    let routes = guard.borrow();
+    let start = match after {
+        Some(after) => Bound::Excluded(*after),
+        None => Bound::Unbounded,
+    };
+    routes
+        .range((start, Bound::Unbounded))
+        .take(limit)
+        .filter_map(|(_, re)| match peer {
+            Some(peer) => read_val!(re).peer_route(peer),
+            None => Some(read_val!(re).route()),
+        })
+        .collect()
+}
+
+pub enum RouteTableGen<'a> {
+    V4(&'a RouteTable),
+    V6(&'a RouteTable),
+}
+
+impl<'a> RouteTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &RouteTable {
+        match self {
+            RouteTableGen::V4(table) => table,
+            RouteTableGen::V6(table) => table,
+        }
+    }
+    fn contains_key(&self, route_key: &RouteKey) -> bool {
+        read_val!(self.table()).contains_key(route_key)
+    }
+    #[inline]
+    fn get(&self, route_key: &RouteKey) -> RouteIntEntryWrapped {
+        Arc::clone(&read_val!(self.table())[route_key])
+    }
+
+    fn insert(&self, route_key: &RouteKey, entry: RouteIntEntryWrapped) {
+        write_val!(self.table()).insert(*route_key, entry);
+    }
+    fn remove(&self, route_key: &RouteKey) {
+        write_val!(self.table()).remove(route_key);
+    }
+    fn clone(&self, route_key: &RouteKey) -> RouteIntEntryWrapped {
+        Arc::clone(&read_val!(self.table())[route_key])
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn add_modify(
+        &mut self,
+        route_key: &RouteKey,
+        route_mask: &IpAddr,
+        peer_path: PeerPath,
+        peer_ip_addr: &IpAddr,
+        peer_table: &PeerTableGen,
+        context: &Arc<RouteContext>,
+    ) -> Option<RouteIntEntryWrapped> {
+        let route_entry: RouteIntEntryWrapped;
+        if self.contains_key(route_key) {
+            let re: RouteIntEntryWrapped = self.get(route_key);
+            write_val!(re).mask = *route_mask;
+            write_val!(re).set_peer_path(*peer_ip_addr, peer_path);
+            if !read_val!(re).peer_exists(*peer_ip_addr) {
+                trace!(
+                    "RouteTableGen::add_modify: peer {} is not present in peer list, adding",
+                    peer_ip_addr
+                );
+                write_val!(re).add_peer(*peer_ip_addr, peer_table.clone(peer_ip_addr));
+            }
+            trace!(
+                "RouteTableGen::add_modify: cloned route entry prefix {}/{} for peer {}",
+                route_key.0,
+                route_key.1,
+                peer_ip_addr
+            );
+            return Some(self.clone(route_key));
+        } else {
+            let new_entry = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
+                RouteIntEntry::new(route_key.0, route_key.1, *route_mask, context),
+            ))));
+            route_entry = Arc::clone(&new_entry);
+            self.insert(route_key, new_entry);
+            write_val!(route_entry).set_peer_path(*peer_ip_addr, peer_path);
+            write_val!(route_entry).add_peer(*peer_ip_addr, peer_table.clone(peer_ip_addr));
+            trace!(
+                "RouteTableGen::add_modify: new route entry prefix {}/{} for peer {}",
+                route_key.0,
+                route_key.1,
+                peer_ip_addr
+            );
+        }
+        Some(route_entry)
+    }
+}
+
+pub enum PeerTableGen<'a> {
+    V4(&'a PeerTable),
+    V6(&'a PeerTable),
+}
+
+impl<'a> PeerTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &PeerTable {
+        match self {
+            PeerTableGen::V4(table) => table,
+            PeerTableGen::V6(table) => table,
+        }
+    }
+    fn contains_key(&self, ip_addr: &IpAddr) -> bool {
+        read_val!(self.table()).contains_key(ip_addr)
+    }
+    fn get(&self, ip_addr: &IpAddr) -> PeerIntEntryWrapped {
+        Arc::clone(&read_val!(self.table())[ip_addr])
+    }
+
+    fn insert(&self, ip_addr: &IpAddr, entry: PeerIntEntryWrapped) {
+        write_val!(self.table()).insert(*ip_addr, entry);
+    }
+    fn remove(&self, ip_addr: &IpAddr, route_table: &RouteTableGen) {
+        if self.contains_key(ip_addr) {
+            write_val!(read_val!(self.table())[ip_addr]).cleanup(route_table);
+        }
+        write_val!(self.table()).remove(ip_addr);
+    }
+    fn clone(&self, ip_addr: &IpAddr) -> PeerIntEntryWrapped {
+        Arc::clone(&read_val!(self.table())[ip_addr])
+    }
+    /// Copy of every peer, taken under the table lock so that callers may
+    /// modify the table while walking the result.
+    fn peers(&self) -> Vec<Peer> {
+        read_val!(self.table())
+            .values()
+            .map(|pe| read_val!(pe).peer())
+            .collect()
+    }
+}
+
+/// What one peer advertises for a route.
+#[derive(Clone)]
+struct PeerPath {
+    paths: Vec<NextHop>,
+    attrs: RouteAttrs,
+}
+
+pub struct RouteIntEntry {
+    prefix: IpAddr,
+    prefix_len: u8,
+    mask: IpAddr,
+    peer_table: PeerTable,
+    peer_paths: HashMap<IpAddr, PeerPath>,
+    ranking: BTreeSet<PathRank>,
+    algorithm: BestPathAlgorithm,
+    resolved: Vec<NextHop>,
+    looked_up: Vec<IpAddr>,
+    context: Arc<RouteContext>,
+}
+
+impl Clone for RouteIntEntry {
+    fn clone(&self) -> Self {
+        RouteIntEntry {
+            prefix: self.prefix,
+            prefix_len: self.prefix_len,
+            mask: self.mask,
+            peer_table: Arc::clone(&self.peer_table),
+            peer_paths: self.peer_paths.clone(),
+            ranking: self.ranking.clone(),
+            algorithm: self.algorithm,
+            resolved: self.resolved.clone(),
+            looked_up: self.looked_up.clone(),
+            context: Arc::clone(&self.context),
+        }
+    }
+}
+
+impl RouteIntEntry {
+    fn new(
+        _prefix: IpAddr,
+        _prefix_len: u8,
+        _mask: IpAddr,
+        context: &Arc<RouteContext>,
+    ) -> RouteIntEntry {
+        RouteIntEntry {
+            prefix: _prefix,
+            prefix_len: _prefix_len,
+            mask: _mask,
+            peer_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            peer_paths: HashMap::new(),
+            ranking: BTreeSet::new(),
+            algorithm: *read_val!(context.best_path),
+            resolved: Vec::new(),
+            looked_up: Vec::new(),
+            context: Arc::clone(context),
+        }
+    }
+    pub fn add_peer(&mut self, peer_prefix: IpAddr, peer: PeerIntEntryWrapped) -> DsError {
+        if read_val!(self.peer_table).contains_key(&peer_prefix) {
+            trace!("RouteIntEntry::add_peer {} already exists", peer_prefix);
+            DsError::AlreadyExists
+        } else {
+            trace!("RouteIntEntry::add_peer {}", peer_prefix);
+            write_val!(self.peer_table).insert(peer_prefix, Arc::clone(&peer));
+            DsError::Ok
+        }
+    }
+    /// Withdraws the path of the peer. The route goes away with its last peer.
+    pub fn delete_peer(&mut self, peer_prefix: IpAddr, route_table: &RouteTableGen) -> DsError {
+        trace!("RouteIntEntry::delete_peer {}", peer_prefix);
+        let rc = if read_val!(self.peer_table).contains_key(&peer_prefix) {
+            write_val!(self.peer_table).remove(&peer_prefix);
+            DsError::Ok
+        } else {
+            trace!("RouteIntEntry::delete_peer {} does not exist", peer_prefix);
+            DsError::PeerNotFound
+        };
+        let before = self.installed_route();
+        self.remove_peer_path(&peer_prefix);
+        self.resolve();
+        self.route_event(before);
+        if self.get_number_of_peers() == 0 {
+            trace!("RouteIntEntry::delete_peer {} last gone", peer_prefix);
+            route_table.remove(&(self.prefix, self.prefix_len));
+        }
+        rc
+    }
+    fn set_peer_path(&mut self, peer_prefix: IpAddr, peer_path: PeerPath) {
+        let before = self.installed_route();
+        self.remove_peer_path(&peer_prefix);
+        self.ranking
+            .insert(self.algorithm.rank(&peer_prefix, &peer_path.attrs));
+        self.peer_paths.insert(peer_prefix, peer_path);
+        self.resolve();
+        self.route_event(before);
+    }
+    fn remove_peer_path(&mut self, peer_prefix: &IpAddr) {
+        if let Some(old) = self.peer_paths.remove(peer_prefix) {
+            self.ranking
+                .remove(&self.algorithm.rank(peer_prefix, &old.attrs));
+        }
+    }
+    /// Re-ranks the peers after the best-path algorithm changed.
+    fn select_best(&mut self) {
+        let algorithm = *read_val!(self.context.best_path);
+        if algorithm == self.algorithm {
+            return;
+        }
+        trace!(
+            "RouteIntEntry::select_best {}/{} {:?} -> {:?}",
+            self.prefix,
+            self.prefix_len,
+            self.algorithm,
+            algorithm
+        );
+        let before = self.installed_route();
+        self.algorithm = algorithm;
+        self.ranking = self
+            .peer_paths
+            .iter()
+            .map(|(peer, peer_path)| algorithm.rank(peer, &peer_path.attrs))
+            .collect();
+        self.resolve();
+        self.route_event(before);
+    }
+    /// Resolves the installed paths down to adjacent next hops and records
+    /// what they depend on. A route without paths depends on nothing.
+    fn resolve(&mut self) {
+        let paths = match self.best_peer().and_then(|best| self.peer_paths.get(&best)) {
+            Some(peer_path) => peer_path.paths.clone(),
+            None => Vec::new(),
+        };
+        let context = Arc::clone(&self.context);
+        let resolution = resolve(&paths, &|addr| context.lookup_next_hop(addr));
+        context.set_dependencies(
+            &(self.prefix, self.prefix_len),
+            &self.looked_up,
+            &resolution.looked_up,
+        );
+        self.resolved = resolution.paths;
+        self.looked_up = resolution.looked_up;
+    }
+    /// Installed route, if there is one and anyone listens for route events.
+    fn installed_route(&self) -> Option<Route> {
+        if !self.context.notifier.wants(EVENT_ROUTE) {
+            return None;
+        }
+        self.best_peer().map(|_| self.route())
+    }
+    /// Publishes how the installed route changed since `before`.
+    fn route_event(&self, before: Option<Route>) {
+        let notifier = &self.context.notifier;
+        if !notifier.wants(EVENT_ROUTE) {
+            return;
+        }
+        let after = self.best_peer().map(|_| self.route());
+        let (op, route) = match (before, after) {
+            (None, Some(after)) => (EventOp::Add, after),
+            (Some(before), None) => (EventOp::Delete, before),
+            (Some(before), Some(after)) if before != after => (EventOp::Modify, after),
+            _ => return,
+        };
+        notifier.publish(Event::Route {
+            vrf_id: self.context.vrf_id,
+            op,
+            route,
+        });
+    }
+    fn best_paths_mut(&mut self) -> Option<&mut Vec<NextHop>> {
+        let best = self.best_peer()?;
+        self.peer_paths
+            .get_mut(&best)
+            .map(|peer_path| &mut peer_path.paths)
+    }
+    fn route_from(&self, peer_path: Option<&PeerPath>) -> Route {
+        Route {
+            prefix: self.prefix,
+            prefix_len: self.prefix_len,
+            paths: peer_path.map_or_else(Vec::new, |p| p.paths.clone()),
+            attrs: peer_path.map_or_else(RouteAttrs::default, |p| p.attrs),
+        }
+    }
+    /// The route as installed, i.e. with the best peer's path.
+    fn route(&self) -> Route {
+        self.route_from(self.best_peer().and_then(|best| self.peer_paths.get(&best)))
+    }
+    fn peer_route(&self, peer_prefix: &IpAddr) -> Option<Route> {
+        self.peer_paths
+            .get(peer_prefix)
+            .map(|peer_path| self.route_from(Some(peer_path)))
+    }
+    /// Peer whose path is installed.
+    fn best_peer(&self) -> Option<IpAddr> {
+        self.ranking.iter().next().map(|(_, _, peer)| *peer)
+    }
+    fn dump(&self) -> Value {
+        let mut peers: Vec<IpAddr> = read_val!(self.peer_table).keys().copied().collect();
+        peers.sort_unstable();
+        let mut dump = json::route_json(&self.route());
+        dump["peers"] = peers.iter().map(|peer| peer.to_string()).collect();
+        dump["best_peer"] = json!(self.best_peer().map(|peer| peer.to_string()));
+        dump["resolved_paths"] = json::paths_json(&self.resolved);
+        dump
+    }
+    pub fn peer_exists(&self, _peer_prefix: IpAddr) -> bool {
+        read_val!(self.peer_table).contains_key(&_peer_prefix)
+    }
+    pub fn get_number_of_peers(&self) -> usize {
+        read_val!(self.peer_table).len()
+    }
+}
+
+fn no_paths_error(prefix: &IpAddr, prefix_len: u8) -> DsError {
+    ds_error(
+        DsError::InvalidArgument,
+        format!(
+            "{}/{} needs at least one path and no zero weights",
+            prefix, prefix_len
+        ),
+    )
+}
+
+fn _paths_add(paths: &mut Vec<NextHop>, path: &NextHop) -> Result<bool, DsError> {
+    if path.weight == 0 {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            format!("path {} has zero weight", path.addr),
+        ));
+    }
+    Ok(path_add(paths, path))
+}
+
+fn _paths_delete(paths: &mut Vec<NextHop>, addr: &IpAddr, out_ifindex: u32) -> Result<(), DsError> {
+    if paths.len() == 1 && paths[0].addr == *addr && paths[0].out_ifindex == out_ifindex {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            format!("{} is the last path", addr),
+        ));
+    }
+    if !path_delete(paths, addr, out_ifindex) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!("path {} out_ifindex {} not found", addr, out_ifindex),
+        ));
+    }
+    Ok(())
+}
+
+fn _select_path(paths: &[NextHop], flow: &Flow) -> Result<NextHop, DsError> {
+    match select_path(paths, flow) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ds_error(
+            DsError::NotFound,
+            format!("no path for flow to {}", flow.dst),
+        )),
+    }
+}
+
+fn _route_entry(
+    route_key: &RouteKey,
+    route_table: &RouteTableGen,
+) -> Result<RouteIntEntryWrapped, DsError> {
+    if !route_table.contains_key(route_key) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!("cannot find prefix {}/{}", route_key.0, route_key.1),
+        ));
+    }
+    Ok(route_table.get(route_key))
+}
+
+fn _prefix_entry(
+    prefix: &IpAddr,
+    prefix_len: u8,
+    prefix_tree: &PrefixTreeGen,
+) -> Result<PrefixEntryWrapped, DsError> {
+    match prefix_tree.get(*prefix, prefix_len) {
+        Some(fe) => Ok(fe),
+        None => Err(ds_error(
+            DsError::NotFound,
+            format!("prefix {}/{} not found", prefix, prefix_len),
+        )),
+    }
+}
+
+fn _route_lookup(route_key: &RouteKey, route_table: &RouteTableGen) -> Result<Route, DsError> {
+    if !route_table.contains_key(route_key) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!(
+                "route_lookup: cannot find prefix {}/{}",
+                route_key.0, route_key.1
+            ),
+        ));
+    }
+    let re: RouteIntEntryWrapped = route_table.get(route_key);
+
+    trace!(
+        "route_lookup prefix {}/{}, found prefix {} mask {} peers {}",
+        route_key.0,
+        route_key.1,
+        read_val!(re).prefix,
+        read_val!(re).mask,
+        read_val!(re).peer_paths.len()
+    );
+    let route = read_val!(re).route();
+    Ok(route)
+}
+
+unsafe fn copy_route_to_user(route: &Route, entry: *mut RouteEntry) {
+    copy_ip_addr_to_user((*entry).prefix.addr, &route.prefix);
+    copy_ip_addr_to_user(
+        (*entry).mask.addr,
+        &prefix_len_mask(&route.prefix, route.prefix_len),
+    );
+    if let Some(path) = route.paths.first() {
+        copy_ip_addr_to_user((*entry).next_hop.addr, &path.addr);
+        (*entry).out_ifindex = path.out_ifindex;
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_lookup(
+    ds: *mut DataStore,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match ds.rib.lookup_route((*_entry).vrf_id, &prefix, prefix_len) {
+            Ok(route) => {
+                copy_route_to_user(&route, _entry);
+                DsError::Ok
+            }
+            Err(err) => err,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn route_lookup(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    ds_route_lookup(default_data_store(), _prefix, prefix_len, _entry)
+}
+
+/// Fills up to `page_size` entries, which must have their address buffers
+/// set up as for `route_lookup`, and stores the number filled in `count`.
+unsafe fn copy_routes_to_user(
+    routes: &[Route],
+    vrf_id: u32,
+    entries: *mut RouteEntry,
+    count: *mut u32,
+) {
+    for (i, route) in routes.iter().enumerate() {
+        let entry = entries.add(i);
+        copy_route_to_user(route, entry);
+        (*entry).vrf_id = vrf_id;
+    }
+    *count = routes.len() as u32;
+}
+
+/// Route key of an entry returned by a previous walk call.
+unsafe fn route_cursor_from_user(cursor: *const RouteEntry) -> Result<RouteKey, DsError> {
+    if cursor.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("walk cursor is null"),
+        ));
+    }
+    let prefix = copy_ip_addr_from_user(&(*cursor).prefix);
+    let mask = copy_ip_addr_from_user(&(*cursor).mask);
+    match mask_prefix_len(&mask) {
+        Some(prefix_len) => Ok((prefix, prefix_len)),
+        None => Err(ds_error(
+            DsError::InvalidPrefix,
+            format!("mask {} is not contiguous", mask),
+        )),
+    }
+}
+
+fn walk_args_are_valid<E>(entries: *mut E, count: *mut u32) -> Result<(), DsError> {
+    if entries.is_null() || count.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("walk entries or count is null"),
+        ));
+    }
+    Ok(())
+}
+
+fn bulk_args_are_valid<E>(
+    entries: *const E,
+    count: u32,
+    results: *mut DsError,
+) -> Result<(), DsError> {
+    if count > 0 && (entries.is_null() || results.is_null()) {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("bulk entries or results is null"),
+        ));
+    }
+    Ok(())
+}
+
+/// Hands the entries that could be read to `batch` and merges its result
+/// codes with the read failures, in entry order. A failure of the whole
+/// batch is the result of every entry handed to it.
+fn run_bulk<E>(
+    entries: Vec<Result<E, DsError>>,
+    batch: impl FnOnce(&[E]) -> Result<Vec<DsError>, DsError>,
+) -> Vec<DsError> {
+    let mut codes = vec![DsError::Ok; entries.len()];
+    let mut indexes = Vec::new();
+    let mut valid = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        match entry {
+            Ok(entry) => {
+                indexes.push(i);
+                valid.push(entry);
+            }
+            Err(err) => codes[i] = err,
+        }
+    }
+    match batch(&valid) {
+        Ok(batch_codes) => {
+            for (i, code) in indexes.into_iter().zip(batch_codes) {
+                codes[i] = code;
+            }
+        }
+        Err(err) => {
+            for i in indexes {
+                codes[i] = err;
+            }
+        }
+    }
+    codes
+}
+
+/// `Ok` if every entry succeeded, the code of the first failed one
+/// otherwise; `results` tells which.
+unsafe fn copy_results_to_user(codes: &[DsError], results: *mut DsError) -> DsError {
+    for (i, code) in codes.iter().enumerate() {
+        *results.add(i) = *code;
+    }
+    codes
+        .iter()
+        .find(|code| !matches!(code, DsError::Ok | DsError::Modified))
+        .copied()
+        .unwrap_or(DsError::Ok)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn _route_walk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    ipv4: bool,
+    peer: Option<&IpAddr>,
+    cursor: *const RouteEntry,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = walk_args_are_valid(entries, count) {
+        return err;
+    }
+    unsafe {
+        let after = if cursor.is_null() {
+            None
+        } else {
+            match route_cursor_from_user(cursor) {
+                Ok(after) => Some(after),
+                Err(err) => return err,
+            }
+        };
+        let routes = match peer {
+            Some(peer) => ds
+                .rib
+                .peer_routes(vrf_id, peer, after.as_ref(), page_size as usize),
+            None => ds
+                .rib
+                .routes(vrf_id, ipv4, after.as_ref(), page_size as usize),
+        };
+        match routes {
+            Ok(routes) => {
+                copy_routes_to_user(&routes, vrf_id, entries, count);
+                DsError::Ok
+            }
+            Err(err) => err,
+        }
+    }
+}
+
+/// First `page_size` routes of the VRF in (prefix, prefix_len) order.
+#[no_mangle]
+pub extern "C" fn ds_route_get_first(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    address_family: u32,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    _route_walk(
+        ds,
+        vrf_id,
+        address_family == 1,
+        None,
+        ptr::null(),
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn route_get_first(
+    address_family: u32,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_route_get_first(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        address_family,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+/// Next `page_size` routes after `cursor`, usually the last entry returned
+/// by the previous call. `count` is 0 at the end of the table.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_get_next(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    cursor: *const RouteEntry,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    if cursor.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("walk cursor is null"),
+        );
+    }
+    let ipv4 = unsafe { (*cursor).prefix.family == 1 };
+    _route_walk(ds, vrf_id, ipv4, None, cursor, entries, page_size, count)
+}
+
+#[no_mangle]
+pub extern "C" fn route_get_next(
+    cursor: *const RouteEntry,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_route_get_next(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        cursor,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+/// First `page_size` routes announced by `peer`.
+#[no_mangle]
+pub extern "C" fn ds_peer_route_get_first(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    peer: &IpAddrC,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let peer = unsafe { copy_ip_addr_from_user(peer) };
+    _route_walk(
+        ds,
+        vrf_id,
+        peer.is_ipv4(),
+        Some(&peer),
+        ptr::null(),
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_get_first(
+    peer: &IpAddrC,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_peer_route_get_first(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        peer,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub extern "C" fn ds_peer_route_get_next(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    peer: &IpAddrC,
+    cursor: *const RouteEntry,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    if cursor.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("walk cursor is null"),
+        );
+    }
+    let peer = unsafe { copy_ip_addr_from_user(peer) };
+    _route_walk(
+        ds,
+        vrf_id,
+        peer.is_ipv4(),
+        Some(&peer),
+        cursor,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_get_next(
+    peer: &IpAddrC,
+    cursor: *const RouteEntry,
+    entries: *mut RouteEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_peer_route_get_next(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        peer,
+        cursor,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+pub struct PeerIntEntry {
+    prefix: IpAddr,
+    out_ifindex: u32,
+    peer_route_table: RouteTable,
+    /// Routes kept over a restart that the peer did not add again yet.
+    stale: HashSet<RouteKey>,
+    restarting: bool,
+    /// Number of restarts, tells the stale timers apart.
+    restarts: u32,
+    status: PeerStatus,
+    flaps: HashMap<RouteKey, FlapState>,
+    /// Inbound policy, by id.
+    policy: Option<u32>,
+}
+
+/// What a peer keeps besides its address and routes: session state,
+/// restart and flap history, and the inbound policy.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerSession {
+    stale: HashSet<RouteKey>,
+    restarting: bool,
+    restarts: u32,
+    status: PeerStatus,
+    flaps: HashMap<RouteKey, FlapState>,
+    policy: Option<u32>,
+}
+
+impl Drop for PeerIntEntry {
+    fn drop(&mut self) {
+        trace!("PeerIntEntry::Drop {}", self.prefix);
+    }
+}
+
+impl PeerIntEntry {
+    pub fn new(_prefix: IpAddr, _out_ifindex: u32) -> PeerIntEntry {
+        PeerIntEntry {
+            prefix: _prefix,
+            out_ifindex: _out_ifindex,
+            peer_route_table: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            stale: HashSet::new(),
+            restarting: false,
+            restarts: 0,
+            status: PeerStatus::new(),
+            flaps: HashMap::new(),
+            policy: None,
+        }
+    }
+    fn peer(&self) -> Peer {
+        Peer {
+            addr: self.prefix,
+            out_ifindex: self.out_ifindex,
+        }
+    }
+    fn session(&self) -> PeerSession {
+        PeerSession {
+            stale: self.stale.clone(),
+            restarting: self.restarting,
+            restarts: self.restarts,
+            status: self.status,
+            flaps: self.flaps.clone(),
+            policy: self.policy,
+        }
+    }
+    fn set_session(&mut self, session: &PeerSession) {
+        self.stale = session.stale.clone();
+        self.restarting = session.restarting;
+        self.restarts = session.restarts;
+        self.status = session.status;
+        self.flaps = session.flaps.clone();
+        self.policy = session.policy;
+    }
+    fn dump(&self) -> Value {
+        let routes: Vec<Value> =
+            route_table_page(&self.peer_route_table, Some(&self.prefix), None, usize::MAX)
+                .iter()
+                .map(json::route_json)
+                .collect();
+        json!({
+            "addr": self.prefix.to_string(),
+            "out_ifindex": self.out_ifindex,
+            "status": json::peer_status_json(&self.status),
+            "policy": self.policy,
+            "routes": routes,
+        })
+    }
+    fn cleanup(&mut self, route_table: &RouteTableGen) {
+        trace!("PeerIntEntry::cleanup");
+        for val in read_val!(self.peer_route_table).values() {
+            write_val!(val).delete_peer(self.prefix, route_table);
+        }
+    }
+    /// Withdraws every route of the peer, which stays in the peer table.
+    fn withdraw(&mut self, route_table: &RouteTableGen) {
+        trace!("PeerIntEntry::withdraw {}", self.prefix);
+        let routes: Vec<RouteIntEntryWrapped> =
+            std::mem::take(&mut *write_val!(self.peer_route_table))
+                .into_values()
+                .collect();
+        for re in routes.iter() {
+            write_val!(re).delete_peer(self.prefix, route_table);
+        }
+        self.stale.clear();
+        self.restarting = false;
+        for state in self.flaps.values_mut() {
+            state.pending = None;
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn route_add_modify(
+        &mut self,
+        route_key: &RouteKey,
+        route_mask: &IpAddr,
+        peer_path: PeerPath,
+        peer_ip_addr: &IpAddr,
+        route_table: &mut RouteTableGen,
+        peer_table: &mut PeerTableGen,
+        context: &Arc<RouteContext>,
+    ) -> DsError {
+        trace!(
+            "PeerIntEntry::route_add_modify prefix {}/{} peer {}",
+            route_key.0,
+            route_key.1,
+            peer_ip_addr
+        );
+        let route_entry: Option<RouteIntEntryWrapped> = route_table.add_modify(
+            route_key,
+            route_mask,
+            peer_path,
+            peer_ip_addr,
+            peer_table,
+            context,
+        );
+
+        if !read_val!(self.peer_route_table).contains_key(route_key) {
+            trace!(
+                "PeerIntEntry::route_add_modify prefix {}/{} not found in peer rt. adding",
+                route_key.0,
+                route_key.1
+            );
+            match route_entry {
+                Some(re) => {
+                    write_val!(self.peer_route_table).insert(*route_key, Arc::clone(&re));
+                    DsError::Ok
+                }
+                None => ds_error(
+                    DsError::NotFound,
+                    String::from("expected route_entry, found nothing!"),
+                ),
+            }
+        } else {
+            DsError::Modified
+        }
+    }
+}
+
+fn _peer_add_modify(ip_addr: &IpAddr, peer_table: &PeerTableGen, out_ifindex: u32) -> bool {
+    trace!(
+        "peer_add_modify: key: {} out_ifindex: {}",
+        ip_addr,
+        out_ifindex
+    );
+    if peer_table.contains_key(ip_addr) {
+        let pe: PeerIntEntryWrapped = peer_table.get(ip_addr);
+        trace!("found existing. modifying");
+        write_val!(pe).out_ifindex = out_ifindex;
+        false
+    } else {
+        trace!("creating new");
+        let new_entry = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
+            PeerIntEntry::new(*ip_addr, out_ifindex),
+        ))));
+        peer_table.insert(ip_addr, new_entry);
+        true
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_add_modify(
+    ds: *mut DataStore,
+    _prefix: &IpAddrC,
+    _entry: *mut PeerEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let peer = Peer {
+            addr: copy_ip_addr_from_user(_prefix),
+            out_ifindex: (*_entry).out_ifindex,
+        };
+        ds_add_result(ds.rib.add_peer((*_entry).vrf_id, &peer))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_add_modify(_prefix: &IpAddrC, _entry: *mut PeerEntry) -> DsError {
+    ds_peer_add_modify(default_data_store(), _prefix, _entry)
+}
+
+fn _peer_lookup(ip_addr: &IpAddr, peer_table: &PeerTableGen) -> Result<Peer, DsError> {
+    trace!("peer_lookup {}", ip_addr);
+    if !peer_table.contains_key(ip_addr) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!("peer {} not found", ip_addr),
+        ));
+    }
+    let pe = peer_table.get(ip_addr);
+    let peer = read_val!(pe).peer();
+    Ok(peer)
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_lookup(
+    ds: *mut DataStore,
+    _prefix: &IpAddrC,
+    _entry: *mut PeerEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let ip_addr = copy_ip_addr_from_user(_prefix);
+        match ds.rib.lookup_peer((*_entry).vrf_id, &ip_addr) {
+            Ok(peer) => {
+                copy_ip_addr_to_user((*_entry).prefix.addr, &peer.addr);
+                (*_entry).out_ifindex = peer.out_ifindex;
+                DsError::Ok
+            }
+            Err(err) => err,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_lookup(_prefix: &IpAddrC, _entry: *mut PeerEntry) -> DsError {
+    ds_peer_lookup(default_data_store(), _prefix, _entry)
+}
+
+fn _peer_delete(
+    ip_addr: &IpAddr,
+    peer_table: &PeerTableGen,
+    route_table: &RouteTableGen,
+) -> Result<(), DsError> {
+    trace!("peer_delete {}", ip_addr);
+    if !peer_table.contains_key(ip_addr) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!("peer {} not found", ip_addr),
+        ));
+    }
+    peer_table.remove(ip_addr, route_table);
+    Ok(())
+}
+
+#[no_mangle]
+pub extern "C" fn ds_peer_delete(ds: *mut DataStore, vrf_id: u32, _prefix: &IpAddrC) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    ds_result(ds.rib.delete_peer(vrf_id, &ip_addr))
+}
+
+#[no_mangle]
+pub extern "C" fn peer_delete(_prefix: &IpAddrC) -> DsError {
+    ds_peer_delete(default_data_store(), DEFAULT_VRF_ID, _prefix)
+}
+
+/// Instead of `ds_peer_delete` when the peer restarts gracefully: its routes
+/// stay, marked stale, until it adds them again or `ds_peer_end_of_rib`.
+/// With a non-zero `stale_time_ms` the routes still stale by then go too.
+#[no_mangle]
+pub extern "C" fn ds_peer_restart(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    stale_time_ms: u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    trace!("peer_restart {} stale time {} ms", ip_addr, stale_time_ms);
+    ds_result(ds.rib.restart_peer(
+        vrf_id,
+        &ip_addr,
+        Duration::from_millis(u64::from(stale_time_ms)),
+    ))
+}
+
+#[no_mangle]
+pub extern "C" fn peer_restart(_prefix: &IpAddrC, stale_time_ms: u32) -> DsError {
+    ds_peer_restart(default_data_store(), DEFAULT_VRF_ID, _prefix, stale_time_ms)
+}
+
+/// Withdraws the routes still stale from the restart of the peer and stores
+/// their number in `swept` unless it is null.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_end_of_rib(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    swept: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    trace!("peer_end_of_rib {}", ip_addr);
+    match ds.rib.end_of_rib(vrf_id, &ip_addr) {
+        Ok(count) => {
+            if !swept.is_null() {
+                unsafe { *swept = count as u32 };
+            }
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_end_of_rib(_prefix: &IpAddrC, swept: *mut u32) -> DsError {
+    ds_peer_end_of_rib(default_data_store(), DEFAULT_VRF_ID, _prefix, swept)
+}
+
+/// Moves the session of the peer to `state`, a `PeerState` value. Peers
+/// start `Established` when they are added. Routes from the peer are only
+/// accepted while it is established, and leaving `Established` withdraws
+/// them, except on going `Down` while the peer restarts gracefully: its
+/// routes then stay stale until `ds_peer_end_of_rib` or the stale timer.
+#[no_mangle]
+pub extern "C" fn ds_peer_state_set(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    state: u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    trace!("peer_state_set {} {}", ip_addr, state);
+    match PeerState::from_u32(state) {
+        Some(state) => ds_result(ds.rib.set_peer_state(vrf_id, &ip_addr, state)),
+        None => ds_error(
+            DsError::InvalidArgument,
+            format!("unknown peer state {}", state),
+        ),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_state_set(_prefix: &IpAddrC, state: u32) -> DsError {
+    ds_peer_state_set(default_data_store(), DEFAULT_VRF_ID, _prefix, state)
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_status(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    status: *mut PeerStatus,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if status.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("status is null"));
+    }
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    match ds.rib.peer_status(vrf_id, &ip_addr) {
+        Ok(peer_status) => {
+            unsafe { *status = peer_status };
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_status(_prefix: &IpAddrC, status: *mut PeerStatus) -> DsError {
+    ds_peer_status(default_data_store(), DEFAULT_VRF_ID, _prefix, status)
+}
+
+fn _peer_route_add_modify(
+    peer_ip_addr: &IpAddr,
+    route: &Route,
+    peer_table: &mut PeerTableGen,
+    route_table: &mut RouteTableGen,
+    context: &Arc<RouteContext>,
+) -> Result<bool, DsError> {
+    trace!(
+        "peer_route_add_modify: peer {} prefix {}/{}",
+        peer_ip_addr,
+        route.prefix,
+        route.prefix_len
+    );
+    if !ip_prefix_is_valid(&route.prefix, route.prefix_len) {
+        return Err(ds_error(
+            DsError::InvalidPrefix,
+            format!(
+                "prefix {}/{} has host bits set",
+                route.prefix, route.prefix_len
+            ),
+        ));
+    }
+    if !peer_table.contains_key(peer_ip_addr) {
+        return Err(ds_error(
+            DsError::PeerNotFound,
+            format!("peer {} not found", peer_ip_addr),
+        ));
+    }
+    let paths = match path_set(&route.paths) {
+        Some(paths) => paths,
+        None => return Err(no_paths_error(&route.prefix, route.prefix_len)),
+    };
+    let pe: PeerIntEntryWrapped = peer_table.get(peer_ip_addr);
+    write_val!(pe).status.routes_received += 1;
+    if read_val!(pe).status.state != PeerState::Established {
+        return Err(ds_error(
+            DsError::PeerNotEstablished,
+            format!("peer {} is not established", peer_ip_addr),
+        ));
+    }
+    let mut update = Route {
+        paths,
+        ..route.clone()
+    };
+    let policy = read_val!(pe).policy;
+    if let Some(policy) = policy {
+        if !write_val!(context.policies).evaluate(policy, peer_ip_addr, &mut update) {
+            let route_key = (route.prefix, route.prefix_len);
+            if read_val!(read_val!(pe).peer_route_table).contains_key(&route_key) {
+                // what the peer announced before is replaced by nothing
+                _peer_route_delete(peer_ip_addr, &route_key, peer_table, route_table)?;
+            }
+            return Err(ds_error(
+                DsError::Denied,
+                format!(
+                    "policy {} denies {}/{} from peer {}",
+                    policy, route.prefix, route.prefix_len, peer_ip_addr
+                ),
+            ));
+        }
+    }
+    let config = *read_val!(context.dampening);
+    let dampened = config.and_then(|config| {
+        _dampen_update(peer_ip_addr, &pe, &update, &config, route_table, context)
+    });
+    let added = dampened.unwrap_or_else(|| {
+        let rc = write_val!(pe).route_add_modify(
+            &(route.prefix, route.prefix_len),
+            &prefix_len_mask(&route.prefix, route.prefix_len),
+            PeerPath {
+                paths: update.paths,
+                attrs: update.attrs,
+            },
+            peer_ip_addr,
+            route_table,
+            peer_table,
+            context,
+        );
+        match rc {
+            DsError::Ok => Ok(true),
+            DsError::Modified => Ok(false),
+            err => Err(err),
+        }
+    });
+    if added.is_ok() {
+        let guard = pe.lock();
+        let mut pe = guard.borrow_mut();
+        pe.stale.remove(&(route.prefix, route.prefix_len));
+        pe.status.routes_accepted += 1;
+    }
+    added
+}
+
+/// Charges a changed update of the peer to its flap history. While the path
+/// is suppressed the update is kept aside instead of installed, and the
+/// path installed before is taken out; that is the result returned. `None`
+/// means the update is installed as usual.
+fn _dampen_update(
+    peer_ip_addr: &IpAddr,
+    pe: &PeerIntEntryWrapped,
+    update: &Route,
+    config: &DampeningConfig,
+    route_table: &RouteTableGen,
+    context: &RouteContext,
+) -> Option<Result<bool, DsError>> {
+    let route_key = (update.prefix, update.prefix_len);
+    let installed = read_val!(read_val!(pe).peer_route_table)
+        .get(&route_key)
+        .cloned();
+    let previous = match &installed {
+        Some(re) => read_val!(re).peer_route(peer_ip_addr),
+        None => read_val!(pe)
+            .flaps
+            .get(&route_key)
+            .and_then(|state| state.pending.clone()),
+    };
+    let guard = pe.lock();
+    let mut pe_entry = guard.borrow_mut();
+    if previous.is_some_and(|previous| previous != *update) {
+        let state = pe_entry
+            .flaps
+            .entry(route_key)
+            .or_insert_with(FlapState::new);
+        if state.penalize(config, DAMPENING_UPDATE_PENALTY) {
+            let reuse_in = state.reuse_in(config);
+            start_reuse_timer(context, *peer_ip_addr, route_key, reuse_in);
+        }
+    }
+    let state = pe_entry.flaps.get_mut(&route_key)?;
+    if state.try_reuse(config) {
+        state.pending = None;
+    }
+    if !state.suppressed {
+        if state.is_forgotten(config) {
+            pe_entry.flaps.remove(&route_key);
+        }
+        return None;
+    }
+    trace!(
+        "peer {} route {}/{} is dampened",
+        peer_ip_addr,
+        update.prefix,
+        update.prefix_len
+    );
+    let created = installed.is_none() && state.pending.is_none();
+    state.pending = Some(update.clone());
+    if let Some(re) = installed {
+        write_val!(pe_entry.peer_route_table).remove(&route_key);
+        drop(pe_entry);
+        write_val!(re).delete_peer(*peer_ip_addr, route_table);
+    }
+    Some(Ok(created))
+}
+
+#[no_mangle]
+pub extern "C" fn ds_peer_route_add_modify(
+    ds: *mut DataStore,
+    _peer_prefix: &IpAddrC,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    ds_peer_route_add_modify_attrs(ds, _peer_prefix, _entry, ptr::null())
+}
+
+/// Like `ds_peer_route_add_modify`, with the attributes best-path selection
+/// compares. A null `attrs` adds the route with default attributes.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_route_add_modify_attrs(
+    ds: *mut DataStore,
+    _peer_prefix: &IpAddrC,
+    _entry: *mut RouteEntry,
+    attrs: *const RouteAttrs,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let peer_ip_addr = copy_ip_addr_from_user(_peer_prefix);
+        match copy_route_from_user(_entry, attrs) {
+            Ok(route) => ds_add_result(ds.rib.add_route((*_entry).vrf_id, &peer_ip_addr, &route)),
+            Err(err) => err,
+        }
+    }
+}
+
+unsafe fn copy_route_from_user(
+    entry: *const RouteEntry,
+    attrs: *const RouteAttrs,
+) -> Result<Route, DsError> {
+    let route_prefix = copy_ip_addr_from_user(&(*entry).prefix);
+    let route_mask = copy_ip_addr_from_user(&(*entry).mask);
+    let prefix_len = match mask_prefix_len(&route_mask) {
+        Some(prefix_len) => prefix_len,
+        None => {
+            return Err(ds_error(
+                DsError::InvalidPrefix,
+                format!("mask {} is not contiguous", route_mask),
+            ));
+        }
+    };
+    Ok(Route {
+        prefix: route_prefix,
+        prefix_len,
+        paths: vec![NextHop::new(
+            copy_ip_addr_from_user(&(*entry).next_hop),
+            (*entry).out_ifindex,
+        )],
+        attrs: if attrs.is_null() {
+            RouteAttrs::default()
+        } else {
+            *attrs
+        },
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_add_modify(
+    _peer_prefix: &IpAddrC,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    ds_peer_route_add_modify(default_data_store(), _peer_prefix, _entry)
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_add_modify_attrs(
+    _peer_prefix: &IpAddrC,
+    _entry: *mut RouteEntry,
+    attrs: *const RouteAttrs,
+) -> DsError {
+    ds_peer_route_add_modify_attrs(default_data_store(), _peer_prefix, _entry, attrs)
+}
+
+/// Adds or updates `count` routes of one peer in `vrf_id`, taking the table
+/// locks once. The vrf_id of the entries is not used. `attrs` is either
+/// null or holds the attributes of each entry. Every entry gets the code
+/// `ds_peer_route_add_modify` would return for it in `results`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_route_add_modify_bulk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _peer_prefix: &IpAddrC,
+    entries: *const RouteEntry,
+    attrs: *const RouteAttrs,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(entries, count, results) {
+        return err;
+    }
+    unsafe {
+        let peer_ip_addr = copy_ip_addr_from_user(_peer_prefix);
+        let routes = (0..count as usize)
+            .map(|i| {
+                let entry_attrs = if attrs.is_null() {
+                    ptr::null()
+                } else {
+                    attrs.add(i)
+                };
+                copy_route_from_user(entries.add(i), entry_attrs)
+            })
+            .collect();
+        let codes = run_bulk(routes, |routes| {
+            let added = ds.rib.add_routes(vrf_id, &peer_ip_addr, routes)?;
+            Ok(added.into_iter().map(ds_add_result).collect())
+        });
+        copy_results_to_user(&codes, results)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_add_modify_bulk(
+    _peer_prefix: &IpAddrC,
+    entries: *const RouteEntry,
+    attrs: *const RouteAttrs,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_peer_route_add_modify_bulk(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _peer_prefix,
+        entries,
+        attrs,
+        count,
+        results,
+    )
+}
+
+fn _peer_route_lookup(
+    peer_ip_addr: &IpAddr,
+    route_key: &RouteKey,
+    peer_table: &PeerTableGen,
+) -> Result<Route, DsError> {
+    trace!(
+        "peer_route_lookup: peer {} prefix {}/{}",
+        peer_ip_addr,
+        route_key.0,
+        route_key.1
+    );
+    if !peer_table.contains_key(peer_ip_addr) {
+        return Err(ds_error(
+            DsError::PeerNotFound,
+            format!("peer {} not found", peer_ip_addr),
+        ));
+    }
+    let pe: PeerIntEntryWrapped = peer_table.get(peer_ip_addr);
+    if !read_val!(read_val!(pe).peer_route_table).contains_key(route_key) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!(
+                "prefix {}/{} not found in peer {} rt",
+                route_key.0, route_key.1, peer_ip_addr
+            ),
+        ));
+    }
+    let re: RouteIntEntryWrapped =
+        Arc::clone(&read_val!(read_val!(pe).peer_route_table)[route_key]);
+    trace!(
+        "peer_route_lookup peer_prefix {}, found prefix {}/{}",
+        peer_ip_addr,
+        route_key.0,
+        route_key.1
+    );
+    let route = read_val!(re).route();
+    Ok(route)
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_route_lookup(
+    ds: *mut DataStore,
+    _peer_prefix: &IpAddrC,
+    _route_prefix: &IpAddrC,
+    prefix_len: u8,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let peer_ip_addr = copy_ip_addr_from_user(_peer_prefix);
+        let route_prefix = copy_ip_addr_from_user(_route_prefix);
+        match ds
+            .rib
+            .lookup_peer_route((*_entry).vrf_id, &peer_ip_addr, &route_prefix, prefix_len)
+        {
+            Ok(route) => {
+                copy_route_to_user(&route, _entry);
+                DsError::Ok
+            }
+            Err(err) => err,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn peer_route_lookup(
+    _peer_prefix: &IpAddrC,
+    _route_prefix: &IpAddrC,
+    prefix_len: u8,
+    _entry: *mut RouteEntry,
+) -> DsError {
+    ds_peer_route_lookup(
+        default_data_store(),
+        _peer_prefix,
+        _route_prefix,
+        prefix_len,
+        _entry,
+    )
+}
+
+fn _peer_route_delete(
+    peer_ip_addr: &IpAddr,
+    route_key: &RouteKey,
+    peer_table: &PeerTableGen,
+    route_table: &RouteTableGen,
+) -> Result<(), DsError> {
+    trace!(
+        "peer_route_delete peer {} prefix {}/{}",
+        peer_ip_addr,
+        route_key.0,
+        route_key.1
+    );
+    if !peer_table.contains_key(peer_ip_addr) {
+        return Err(ds_error(
+            DsError::PeerNotFound,
+            format!("peer {} not found", peer_ip_addr),
+        ));
+    }
+    let pe: PeerIntEntryWrapped = peer_table.get(peer_ip_addr);
+
+    if !read_val!(read_val!(pe).peer_route_table).contains_key(route_key) {
+        return Err(ds_error(
+            DsError::NotFound,
+            format!(
+                "cannot find route {}/{} for peer {}",
+                route_key.0, route_key.1, peer_ip_addr
+            ),
+        ));
+    }
+    let re = write_val!(read_val!(pe).peer_route_table).remove(route_key);
+    write_val!(pe).stale.remove(route_key);
+    if let Some(re) = re {
+        write_val!(re).delete_peer(*peer_ip_addr, route_table);
+    }
+    Ok(())
+}
+
+/// `_peer_route_delete` on behalf of the peer, which dampening counts as a
+/// flap. Withdrawing an update kept aside by dampening just drops it.
+fn _peer_route_withdraw(
+    peer_ip_addr: &IpAddr,
+    route_key: &RouteKey,
+    peer_table: &PeerTableGen,
+    route_table: &RouteTableGen,
+    context: &RouteContext,
+) -> Result<(), DsError> {
+    let config = *read_val!(context.dampening);
+    if let (Some(config), true) = (config, peer_table.contains_key(peer_ip_addr)) {
+        let pe = peer_table.get(peer_ip_addr);
+        let guard = pe.lock();
+        let mut pe_entry = guard.borrow_mut();
+        let installed = read_val!(pe_entry.peer_route_table).contains_key(route_key);
+        let pending = pe_entry
+            .flaps
+            .get(route_key)
+            .is_some_and(|state| state.pending.is_some());
+        if installed || pending {
+            let state = pe_entry
+                .flaps
+                .entry(*route_key)
+                .or_insert_with(FlapState::new);
+            if state.penalize(&config, DAMPENING_WITHDRAW_PENALTY) {
+                let reuse_in = state.reuse_in(&config);
+                start_reuse_timer(context, *peer_ip_addr, *route_key, reuse_in);
+            }
+            if state.pending.take().is_some() {
+                return Ok(());
+            }
+        }
     }
+    _peer_route_delete(peer_ip_addr, route_key, peer_table, route_table)
 }
 
-fn _route_lookup(ip_addr: &IpAddr, route_table: &RouteTableGen, _entry: *mut RouteEntry) -> i32 {
-    if !route_table.contains_key(&ip_addr) {
-        trace!("route_lookup: cannot find prefix {}", ip_addr);
-        return -1;
-    }
-    let re: RouteIntEntryWrapped = route_table.get(&ip_addr);
-
-    trace!(
-        "route_lookup prefix {}, found prefix {} mask {} next_hop {} out_ifindex {}",
-        ip_addr,
-        read_val!(re).prefix,
-        read_val!(re).mask,
-        read_val!(re).next_hop,
-        read_val!(re).out_ifindex
-    );
+#[no_mangle]
+pub extern "C" fn ds_peer_route_delete(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _peer_prefix: &IpAddrC,
+    _route_prefix: &IpAddrC,
+    prefix_len: u8,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        let mut addr_ptr: *mut u8 = (*_entry).prefix.addr;
-        copy_ip_addr_to_user(addr_ptr, &read_val!(re).prefix);
-        addr_ptr = (*_entry).mask.addr;
-        copy_ip_addr_to_user(addr_ptr, &read_val!(re).mask);
-        addr_ptr = (*_entry).next_hop.addr;
-        copy_ip_addr_to_user(addr_ptr, &read_val!(re).next_hop);
-        (*_entry).out_ifindex = read_val!(re).out_ifindex;
+        let peer_ip_addr = copy_ip_addr_from_user(_peer_prefix);
+        let route_prefix = copy_ip_addr_from_user(_route_prefix);
+        ds_result(
+            ds.rib
+                .delete_route(vrf_id, &peer_ip_addr, &route_prefix, prefix_len),
+        )
     }
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn route_lookup(_prefix: &IpAddrC, _entry: *mut RouteEntry) -> i32 {
-    let ip_addr;
+pub extern "C" fn peer_route_delete(
+    _peer_prefix: &IpAddrC,
+    _route_prefix: &IpAddrC,
+    prefix_len: u8,
+) -> DsError {
+    ds_peer_route_delete(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _peer_prefix,
+        _route_prefix,
+        prefix_len,
+    )
+}
 
+unsafe fn copy_route_keys_from_user(
+    prefixes: *const IpAddrC,
+    prefix_lens: *const u8,
+    count: u32,
+) -> Vec<Result<RouteKey, DsError>> {
+    (0..count as usize)
+        .map(|i| {
+            Ok((
+                copy_ip_addr_from_user(&*prefixes.add(i)),
+                *prefix_lens.add(i),
+            ))
+        })
+        .collect()
+}
+
+/// Withdraws `count` routes of one peer, the i-th being
+/// `prefixes[i]/prefix_lens[i]`. See `ds_peer_route_add_modify_bulk`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_peer_route_delete_bulk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _peer_prefix: &IpAddrC,
+    prefixes: *const IpAddrC,
+    prefix_lens: *const u8,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(prefixes, count, results)
+        .and_then(|()| bulk_args_are_valid(prefix_lens, count, results))
+    {
+        return err;
+    }
     unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            _route_lookup(&ip_addr, &RouteTableGen::V4(&ROUTE_TABLE_V4), _entry)
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            _route_lookup(&ip_addr, &RouteTableGen::V6(&ROUTE_TABLE_V6), _entry)
-        }
+        let peer_ip_addr = copy_ip_addr_from_user(_peer_prefix);
+        let route_keys = copy_route_keys_from_user(prefixes, prefix_lens, count);
+        let codes = run_bulk(route_keys, |route_keys| {
+            let deleted = ds.rib.delete_routes(vrf_id, &peer_ip_addr, route_keys)?;
+            Ok(deleted.into_iter().map(ds_result).collect())
+        });
+        copy_results_to_user(&codes, results)
     }
 }
 
-pub struct PeerIntEntry {
-    prefix: IpAddr,
-    out_ifindex: u32,
-    peer_route_table: RouteTable,
+#[no_mangle]
+pub extern "C" fn peer_route_delete_bulk(
+    _peer_prefix: &IpAddrC,
+    prefixes: *const IpAddrC,
+    prefix_lens: *const u8,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_peer_route_delete_bulk(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _peer_prefix,
+        prefixes,
+        prefix_lens,
+        count,
+        results,
+    )
 }
 
-impl Drop for PeerIntEntry {
-    fn drop(&mut self) {
-        trace!("PeerIntEntry::Drop {}", self.prefix);
-        match self.prefix {
-            IpAddr::V4(_ipv4) => {
-                self.cleanup(&RouteTableGen::V4(&ROUTE_TABLE_V4));
-            }
-            IpAddr::V6(_ipv6) => {
-                self.cleanup(&RouteTableGen::V6(&ROUTE_TABLE_V6));
-            }
-        }
-    }
-}
+/// Called for every peer by `peer_iterate`. A non-zero return value stops
+/// the walk. The entry is only valid for the duration of the call.
+pub type PeerIterateCb = Option<extern "C" fn(peer: *const PeerEntry, ctx: *mut c_void) -> i32>;
 
-impl PeerIntEntry {
-    pub fn new(_prefix: IpAddr, _out_ifindex: u32) -> PeerIntEntry {
-        PeerIntEntry {
-            prefix: _prefix,
-            out_ifindex: _out_ifindex,
-            peer_route_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+#[no_mangle]
+pub extern "C" fn ds_peer_iterate(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    address_family: u32,
+    cb: PeerIterateCb,
+    ctx: *mut c_void,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let cb = match cb {
+        Some(cb) => cb,
+        None => {
+            return ds_error(
+                DsError::InvalidArgument,
+                String::from("peer_iterate callback is null"),
+            )
         }
-    }
-    fn cleanup(&mut self, route_table: &RouteTableGen) {
-        trace!("PeerIntEntry::cleanup");
-        for val in read_val!(self.peer_route_table).values() {
-            write_val!(val).delete_peer(self.prefix, route_table);
+    };
+    let peers = match ds.rib.peers(vrf_id, address_family == 1) {
+        Ok(peers) => peers,
+        Err(err) => return err,
+    };
+    let mut addr = [0u8; 16];
+    for peer in peers {
+        unsafe {
+            copy_ip_addr_to_user(addr.as_mut_ptr(), &peer.addr);
+        }
+        let entry = PeerEntry {
+            prefix: IpAddrC {
+                family: address_family as u8,
+                addr: addr.as_mut_ptr(),
+            },
+            out_ifindex: peer.out_ifindex,
+            vrf_id,
+        };
+        if cb(&entry, ctx) != 0 {
+            break;
         }
     }
-    pub fn route_add_modify(
-        &mut self,
-        route_prefix: &IpAddr,
-        route_mask: &IpAddr,
-        next_hop_addr: &IpAddr,
-        out_ifindex: u32,
-        peer_ip_addr: &IpAddr,
-        route_table: &mut RouteTableGen,
-        peer_table: &mut PeerTableGen,
-    ) -> i32 {
-        let route_entry: Option<RouteIntEntryWrapped>;
-        trace!(
-            "PeerIntEntry::route_add_modify prefix {} peer {}",
-            route_prefix,
-            peer_ip_addr
-        );
-        route_entry = route_table.add_modify(
-            route_prefix,
-            route_mask,
-            next_hop_addr,
-            out_ifindex,
-            peer_ip_addr,
-            peer_table,
-        );
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn peer_iterate(
+    address_family: u32,
+    cb: PeerIterateCb,
+    ctx: *mut c_void,
+) -> DsError {
+    ds_peer_iterate(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        address_family,
+        cb,
+        ctx,
+    )
+}
 
-        if !read_val!(self.peer_route_table).contains_key(&route_prefix) {
+fn _longest_match_lookup(
+    ip_addr: &IpAddr,
+    prefix_tree: &PrefixTreeGen,
+) -> Result<PrefixEntry, DsError> {
+    match prefix_tree.get_longest_common_prefix(*ip_addr) {
+        Some(fe) => {
             trace!(
-                "PeerIntEntry::route_add_modify prefix {} not found in peer rt. adding",
-                route_prefix
+                "longest_match_lookup prefix {}, found {}/{} paths {}",
+                ip_addr,
+                read_val!(fe).prefix,
+                read_val!(fe).prefix_len,
+                read_val!(fe).paths.len()
             );
-            match route_entry {
-                Some(re) => {
-                    write_val!(self.peer_route_table).insert(read_val!(re).prefix, Arc::clone(&re));
-                    return 0;
+            let entry = (**read_val!(fe)).clone();
+            Ok(entry)
+        }
+        None => Err(ds_error(
+            DsError::NotFound,
+            format!("no prefix covers {}", ip_addr),
+        )),
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_lookup(
+    ds: *mut DataStore,
+    _prefix: &IpAddrC,
+    _entry: *mut ForwardingEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let ip_addr = copy_ip_addr_from_user(_prefix);
+        match ds.rib.longest_match((*_entry).vrf_id, &ip_addr) {
+            Ok(fe) => {
+                if let Some(path) = fe.paths.first() {
+                    copy_ip_addr_to_user((*_entry).next_hop.addr, &path.addr);
+                    (*_entry).out_ifindex = path.out_ifindex;
                 }
-                None => {
-                    trace!("expected route_entry, found nothing!");
-                    return -1;
+                if !(*_entry).prefix.addr.is_null() {
+                    copy_ip_addr_to_user((*_entry).prefix.addr, &fe.prefix);
                 }
+                (*_entry).prefix_len = fe.prefix_len;
+                DsError::Ok
             }
-        } else {
-            return 1;
+            Err(err) => err,
         }
     }
 }
 
-fn _peer_add_modify(ip_addr: &IpAddr, peer_table: &PeerTableGen, _entry: *mut PeerEntry) -> i32 {
-    let mut entry: Box<PeerEntry>;
+#[no_mangle]
+pub extern "C" fn longest_match_lookup(_prefix: &IpAddrC, _entry: *mut ForwardingEntry) -> DsError {
+    ds_longest_match_lookup(default_data_store(), _prefix, _entry)
+}
 
-    unsafe {
-        entry = Box::from_raw(_entry);
-    }
+fn _longest_match_add(prefix_tree: &PrefixTreeGen, fe: PrefixEntry) -> Result<(), DsError> {
     trace!(
-        "peer_add_modify: key: {} out_ifindex: {}",
-        ip_addr,
-        entry.out_ifindex
+        "longest_match_add {}/{} paths {}",
+        fe.prefix,
+        fe.prefix_len,
+        fe.paths.len()
     );
-    if peer_table.contains_key(&ip_addr) {
-        let pe: PeerIntEntryWrapped = peer_table.get(&ip_addr);
-        trace!("found existing. modifying");
-        write_val!(pe).out_ifindex = entry.out_ifindex;
+    let mut fe = fe;
+    fe.paths = match path_set(&fe.paths) {
+        Some(paths) => paths,
+        None => return Err(no_paths_error(&fe.prefix, fe.prefix_len)),
+    };
+    let prefix = fe.prefix;
+    let prefix_len = fe.prefix_len;
+    let new_fe: PrefixEntryWrapped = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(fe))));
+    ds_status(prefix_tree.insert(prefix, prefix_len, new_fe))
+}
 
-        let _m_entry = Box::into_raw(entry);
-        1
-    } else {
-        trace!("creating new");
-        let new_entry = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-            PeerIntEntry::new(*ip_addr, entry.out_ifindex),
-        ))));
-        let _m_entry = Box::into_raw(entry);
-        peer_table.insert(&ip_addr, new_entry);
-        0
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_add(
+    ds: *mut DataStore,
+    _prefix: &IpAddrC,
+    _entry: *mut ForwardingEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let fe = copy_prefix_entry_from_user(_prefix, _entry);
+        ds_result(ds.rib.add_prefix((*_entry).vrf_id, &fe))
     }
 }
 
 #[no_mangle]
-pub extern "C" fn peer_add_modify(_prefix: &IpAddrC, _entry: *mut PeerEntry) -> i32 {
-    let ip_addr: IpAddr;
+pub extern "C" fn longest_match_add(_prefix: &IpAddrC, _entry: *mut ForwardingEntry) -> DsError {
+    ds_longest_match_add(default_data_store(), _prefix, _entry)
+}
 
-    unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            _peer_add_modify(&ip_addr, &PeerTableGen::V4(&PEER_TABLE_V4), _entry)
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            _peer_add_modify(&ip_addr, &PeerTableGen::V6(&PEER_TABLE_V6), _entry)
-        }
+unsafe fn copy_prefix_entry_from_user(
+    prefix: *const IpAddrC,
+    entry: *const ForwardingEntry,
+) -> PrefixEntry {
+    let ip_addr = copy_ip_addr_from_user(&*prefix);
+    PrefixEntry {
+        prefix: ip_addr_apply_mask(&ip_addr, (*entry).prefix_len),
+        prefix_len: (*entry).prefix_len,
+        paths: vec![NextHop::new(
+            copy_ip_addr_from_user(&(*entry).next_hop),
+            (*entry).out_ifindex,
+        )],
     }
 }
 
-fn _peer_lookup(ip_addr: &IpAddr, peer_table: &PeerTableGen, _entry: *mut PeerEntry) -> i32 {
-    trace!("peer_lookup {}", ip_addr);
-    if !peer_table.contains_key(&ip_addr) {
-        trace!("not found");
-        return -1;
+/// Adds `count` prefixes to `vrf_id`, the i-th being `prefixes[i]` with
+/// `entries[i]`, taking the table locks once. The vrf_id of the entries is
+/// not used. See `ds_peer_route_add_modify_bulk` for `results`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_add_bulk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    prefixes: *const IpAddrC,
+    entries: *const ForwardingEntry,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(prefixes, count, results)
+        .and_then(|()| bulk_args_are_valid(entries, count, results))
+    {
+        return err;
     }
-    let pe = peer_table.get(&ip_addr);
     unsafe {
-        let addr_ptr: *mut u8 = (*_entry).prefix.addr;
-        copy_ip_addr_to_user(addr_ptr, &read_val!(pe).prefix);
-        (*_entry).out_ifindex = read_val!(pe).out_ifindex;
+        let prefix_entries = (0..count as usize)
+            .map(|i| Ok(copy_prefix_entry_from_user(prefixes.add(i), entries.add(i))))
+            .collect();
+        let codes = run_bulk(prefix_entries, |prefix_entries| {
+            let added = ds.rib.add_prefixes(vrf_id, prefix_entries)?;
+            Ok(added.into_iter().map(ds_result).collect())
+        });
+        copy_results_to_user(&codes, results)
     }
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn peer_lookup(_prefix: &IpAddrC, _entry: *mut PeerEntry) -> i32 {
-    let ip_addr: IpAddr;
+pub extern "C" fn longest_match_add_bulk(
+    prefixes: *const IpAddrC,
+    entries: *const ForwardingEntry,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_longest_match_add_bulk(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        prefixes,
+        entries,
+        count,
+        results,
+    )
+}
+
+fn _longest_match_delete(
+    ip_addr: &IpAddr,
+    prefix_len: u8,
+    prefix_tree: &PrefixTreeGen,
+) -> Result<(), DsError> {
+    trace!("longest_match_delete {}/{}", ip_addr, prefix_len);
+    ds_status(prefix_tree.remove(*ip_addr, prefix_len))
+}
+
+#[no_mangle]
+pub extern "C" fn ds_longest_match_delete(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    ds_result(ds.rib.delete_prefix(vrf_id, &ip_addr, prefix_len))
+}
+
+#[no_mangle]
+pub extern "C" fn longest_match_delete(_prefix: &IpAddrC, prefix_len: u8) -> DsError {
+    ds_longest_match_delete(default_data_store(), DEFAULT_VRF_ID, _prefix, prefix_len)
+}
 
+/// Deletes `count` prefixes, the i-th being `prefixes[i]/prefix_lens[i]`.
+/// See `ds_longest_match_add_bulk`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_delete_bulk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    prefixes: *const IpAddrC,
+    prefix_lens: *const u8,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(prefixes, count, results)
+        .and_then(|()| bulk_args_are_valid(prefix_lens, count, results))
+    {
+        return err;
+    }
     unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            _peer_lookup(&ip_addr, &PeerTableGen::V4(&PEER_TABLE_V4), _entry)
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            _peer_lookup(&ip_addr, &PeerTableGen::V6(&PEER_TABLE_V6), _entry)
-        }
+        let route_keys = copy_route_keys_from_user(prefixes, prefix_lens, count);
+        let codes = run_bulk(route_keys, |route_keys| {
+            let deleted = ds.rib.delete_prefixes(vrf_id, route_keys)?;
+            Ok(deleted.into_iter().map(ds_result).collect())
+        });
+        copy_results_to_user(&codes, results)
     }
 }
 
-fn _peer_delete(ip_addr: &IpAddr, peer_table: &PeerTableGen) -> i32 {
-    trace!("peer_delete {}", ip_addr);
-    if !peer_table.contains_key(&ip_addr) {
-        trace!("not found");
-        return -1;
+#[no_mangle]
+pub extern "C" fn longest_match_delete_bulk(
+    prefixes: *const IpAddrC,
+    prefix_lens: *const u8,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_longest_match_delete_bulk(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        prefixes,
+        prefix_lens,
+        count,
+        results,
+    )
+}
+
+unsafe fn copy_next_hop_from_user(path: *const NextHopEntry) -> Result<NextHop, DsError> {
+    if path.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("path is null"),
+        ));
+    }
+    Ok(NextHop {
+        addr: copy_ip_addr_from_user(&(*path).next_hop),
+        out_ifindex: (*path).out_ifindex,
+        weight: (*path).weight,
+    })
+}
+
+unsafe fn copy_next_hop_to_user(path: &NextHop, entry: *mut NextHopEntry) {
+    copy_ip_addr_to_user((*entry).next_hop.addr, &path.addr);
+    (*entry).out_ifindex = path.out_ifindex;
+    (*entry).weight = path.weight;
+}
+
+/// Fills up to `max_paths` entries; `count` gets the total number of paths
+/// so that callers can retry with a larger buffer.
+unsafe fn copy_paths_to_user(
+    paths: &[NextHop],
+    entries: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    if count.is_null() || (entries.is_null() && max_paths > 0) {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("paths or count is null"),
+        );
+    }
+    for (i, path) in paths.iter().take(max_paths as usize).enumerate() {
+        copy_next_hop_to_user(path, entries.add(i));
+    }
+    *count = paths.len() as u32;
+    DsError::Ok
+}
+
+unsafe fn copy_flow_from_user(flow: *const FlowKey) -> Result<Flow, DsError> {
+    if flow.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("flow is null"),
+        ));
     }
-    peer_table.remove(&ip_addr);
-    0
+    Ok(Flow {
+        src: copy_ip_addr_from_user(&(*flow).src),
+        dst: copy_ip_addr_from_user(&(*flow).dst),
+        protocol: (*flow).protocol,
+        src_port: (*flow).src_port,
+        dst_port: (*flow).dst_port,
+    })
 }
 
+/// Adds a path to an existing route. Returns `DS_ERROR_MODIFIED` if the path
+/// was already there and only its weight has been updated.
 #[no_mangle]
-pub extern "C" fn peer_delete(_prefix: &IpAddrC) -> i32 {
-    let ip_addr: IpAddr;
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_path_add(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            _peer_delete(&ip_addr, &PeerTableGen::V4(&PEER_TABLE_V4))
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            _peer_delete(&ip_addr, &PeerTableGen::V6(&PEER_TABLE_V6))
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match copy_next_hop_from_user(path) {
+            Ok(path) => ds_add_result(ds.rib.add_route_path(vrf_id, &prefix, prefix_len, &path)),
+            Err(err) => err,
         }
     }
 }
 
-fn _peer_route_add_modify(
-    peer_ip_addr: &IpAddr,
-    route_prefix: &IpAddr,
-    route_mask: &IpAddr,
-    next_hop_addr: &IpAddr,
-    peer_table: &mut PeerTableGen,
-    route_table: &mut RouteTableGen,
-    entry: Box<RouteEntry>,
-) -> i32 {
-    trace!(
-        "peer_route_add_modify: peer {} prefix {}",
-        peer_ip_addr,
-        route_prefix
-    );
-    if !peer_table.contains_key(&peer_ip_addr) {
-        trace!("not found");
-        return -1;
-    }
-    let pe: PeerIntEntryWrapped = peer_table.get(&peer_ip_addr);
-    let rc = write_val!(pe).route_add_modify(
-        route_prefix,
-        route_mask,
-        next_hop_addr,
-        entry.out_ifindex,
-        peer_ip_addr,
-        route_table,
-        peer_table,
-    );
-    let _m_entry = Box::into_raw(entry);
-    rc
+#[no_mangle]
+pub extern "C" fn route_path_add(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    ds_route_path_add(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        path,
+    )
 }
 
+/// Removes the path matching the next hop and out interface of `path`.
 #[no_mangle]
-pub extern "C" fn peer_route_add_modify(_peer_prefix: &IpAddrC, _entry: *mut RouteEntry) -> i32 {
-    let peer_ip_addr: IpAddr;
-    let route_prefix: IpAddr;
-    let route_mask: IpAddr;
-    let next_hop_addr: IpAddr;
-    let mut entry: Box<RouteEntry>;
-
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_path_delete(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        entry = Box::from_raw(_entry);
-        if _peer_prefix.family == 1 {
-            peer_ip_addr = copy_ip_addr_v4_from_user(_peer_prefix.addr);
-            route_prefix = copy_ip_addr_v4_from_user(entry.prefix.addr);
-            route_mask = copy_ip_addr_v4_from_user(entry.mask.addr);
-            next_hop_addr = copy_ip_addr_v4_from_user(entry.next_hop.addr);
-            _peer_route_add_modify(
-                &peer_ip_addr,
-                &route_prefix,
-                &route_mask,
-                &next_hop_addr,
-                &mut PeerTableGen::V4(&PEER_TABLE_V4),
-                &mut RouteTableGen::V4(&ROUTE_TABLE_V4),
-                entry,
-            )
-        } else {
-            peer_ip_addr = copy_ip_addr_v6_from_user(_peer_prefix.addr as *mut u16);
-            route_prefix = copy_ip_addr_v6_from_user(entry.prefix.addr as *mut u16);
-            route_mask = copy_ip_addr_v6_from_user(entry.mask.addr as *mut u16);
-            next_hop_addr = copy_ip_addr_v6_from_user(entry.next_hop.addr as *mut u16);
-            _peer_route_add_modify(
-                &peer_ip_addr,
-                &route_prefix,
-                &route_mask,
-                &next_hop_addr,
-                &mut PeerTableGen::V6(&PEER_TABLE_V6),
-                &mut RouteTableGen::V6(&ROUTE_TABLE_V6),
-                entry,
-            )
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match copy_next_hop_from_user(path) {
+            Ok(path) => ds_result(ds.rib.delete_route_path(
+                vrf_id,
+                &prefix,
+                prefix_len,
+                &path.addr,
+                path.out_ifindex,
+            )),
+            Err(err) => err,
         }
     }
 }
 
-fn _peer_route_lookup(
-    peer_ip_addr: &IpAddr,
-    route_prefix: &IpAddr,
-    peer_table: &PeerTableGen,
-    _entry: *mut RouteEntry,
-) -> i32 {
-    trace!(
-        "peer_route_lookup: peer {} prefix {}",
-        peer_ip_addr,
-        route_prefix
-    );
-    if !peer_table.contains_key(&peer_ip_addr) {
-        trace!("peer not found");
-        return -1;
-    }
-    let pe: PeerIntEntryWrapped = peer_table.get(&peer_ip_addr);
-    if !read_val!(read_val!(pe).peer_route_table).contains_key(&route_prefix) {
-        trace!("prefix not found in peer rt");
-        return -2;
-    }
+#[no_mangle]
+pub extern "C" fn route_path_delete(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    ds_route_path_delete(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        path,
+    )
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_lookup_paths(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        trace!(
-            "peer_route_lookup peer_prefix {}, found prefix {}",
-            peer_ip_addr,
-            route_prefix
-        );
-        let mut addr_ptr: *mut u8 = (*_entry).prefix.addr;
-        copy_ip_addr_to_user(
-            addr_ptr,
-            &read_val!(read_val!(read_val!(pe).peer_route_table)[&route_prefix]).prefix,
-        );
-        addr_ptr = (*_entry).mask.addr;
-        copy_ip_addr_to_user(
-            addr_ptr,
-            &read_val!(read_val!(read_val!(pe).peer_route_table)[&route_prefix]).mask,
-        );
-        addr_ptr = (*_entry).next_hop.addr;
-        copy_ip_addr_to_user(
-            addr_ptr,
-            &read_val!(read_val!(read_val!(pe).peer_route_table)[&route_prefix]).next_hop,
-        );
-        (*_entry).out_ifindex =
-            read_val!(read_val!(read_val!(pe).peer_route_table)[&route_prefix]).out_ifindex;
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match ds.rib.lookup_route(vrf_id, &prefix, prefix_len) {
+            Ok(route) => copy_paths_to_user(&route.paths, paths, max_paths, count),
+            Err(err) => err,
+        }
     }
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn peer_route_lookup(
-    _peer_prefix: &IpAddrC,
-    _route_prefix: &IpAddrC,
-    _entry: *mut RouteEntry,
-) -> i32 {
-    let peer_ip_addr: IpAddr;
-    let route_prefix: IpAddr;
+pub extern "C" fn route_lookup_paths(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_route_lookup_paths(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        paths,
+        max_paths,
+        count,
+    )
+}
 
+/// Fills in the peer whose path is installed for the route and the
+/// attributes it won with. Either output may be null.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_lookup_best(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    peer: *mut IpAddrC,
+    attrs: *mut RouteAttrs,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        if _peer_prefix.family == 1 {
-            peer_ip_addr = copy_ip_addr_v4_from_user(_peer_prefix.addr);
-            route_prefix = copy_ip_addr_v4_from_user(_route_prefix.addr);
-            _peer_route_lookup(
-                &peer_ip_addr,
-                &route_prefix,
-                &PeerTableGen::V4(&PEER_TABLE_V4),
-                _entry,
-            )
-        } else {
-            peer_ip_addr = copy_ip_addr_v6_from_user(_peer_prefix.addr as *mut u16);
-            route_prefix = copy_ip_addr_v6_from_user(_route_prefix.addr as *mut u16);
-            _peer_route_lookup(
-                &peer_ip_addr,
-                &route_prefix,
-                &PeerTableGen::V6(&PEER_TABLE_V6),
-                _entry,
-            )
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match ds.rib.lookup_best_route(vrf_id, &prefix, prefix_len) {
+            Ok((best_peer, route)) => {
+                if !peer.is_null() {
+                    (*peer).family = ip_addr_family(&best_peer);
+                    copy_ip_addr_to_user((*peer).addr, &best_peer);
+                }
+                if !attrs.is_null() {
+                    *attrs = route.attrs;
+                }
+                DsError::Ok
+            }
+            Err(err) => err,
         }
     }
 }
 
-fn _peer_route_delete(
-    peer_ip_addr: &IpAddr,
-    route_prefix: &IpAddr,
-    peer_table: &PeerTableGen,
-) -> i32 {
-    trace!(
-        "peer_route_delete peer {} prefix {}",
-        peer_ip_addr,
-        route_prefix
-    );
-    if !peer_table.contains_key(&peer_ip_addr) {
-        trace!("peer not found");
-        return -1;
-    }
-    let pe: PeerIntEntryWrapped = peer_table.get(&peer_ip_addr);
+#[no_mangle]
+pub extern "C" fn route_lookup_best(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    peer: *mut IpAddrC,
+    attrs: *mut RouteAttrs,
+) -> DsError {
+    ds_route_lookup_best(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        peer,
+        attrs,
+    )
+}
 
-    if !read_val!(read_val!(pe).peer_route_table).contains_key(&route_prefix) {
-        trace!(
-            "cannot find route {} for peer {}",
-            route_prefix,
-            peer_ip_addr
-        );
-        return -2;
+/// Copies the adjacent next hops the installed paths of the route resolve
+/// to through the prefix tree. `count` is 0 when nothing resolves, e.g. the
+/// next hops loop or are more than `MAX_RESOLVE_DEPTH` lookups away.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_lookup_resolved(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match ds.rib.lookup_resolved_paths(vrf_id, &prefix, prefix_len) {
+            Ok(resolved) => copy_paths_to_user(&resolved, paths, max_paths, count),
+            Err(err) => err,
+        }
     }
-    peer_table.remove(&route_prefix);
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn peer_route_delete(_peer_prefix: &IpAddrC, _route_prefix: &IpAddrC) -> i32 {
-    let peer_ip_addr: IpAddr;
-    let route_prefix: IpAddr;
+pub extern "C" fn route_lookup_resolved(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_route_lookup_resolved(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        paths,
+        max_paths,
+        count,
+    )
+}
 
-    unsafe {
-        if _peer_prefix.family == 1 {
-            peer_ip_addr = copy_ip_addr_v4_from_user(_peer_prefix.addr);
-            route_prefix = copy_ip_addr_v4_from_user(_route_prefix.addr);
-            _peer_route_delete(
-                &peer_ip_addr,
-                &route_prefix,
-                &PeerTableGen::V4(&PEER_TABLE_V4),
-            )
-        } else {
-            peer_ip_addr = copy_ip_addr_v6_from_user(_peer_prefix.addr as *mut u16);
-            route_prefix = copy_ip_addr_v6_from_user(_route_prefix.addr as *mut u16);
-            _peer_route_delete(
-                &peer_ip_addr,
-                &route_prefix,
-                &PeerTableGen::V6(&PEER_TABLE_V6),
-            )
+/// Switches the best-path algorithm, a `BestPathAlgorithm` value, and
+/// re-selects the best path of every route.
+#[no_mangle]
+pub extern "C" fn ds_best_path_algorithm_set(ds: *mut DataStore, algorithm: u32) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    match BestPathAlgorithm::from_u32(algorithm) {
+        Some(algorithm) => {
+            ds.rib.set_best_path_algorithm(algorithm);
+            DsError::Ok
         }
+        None => ds_error(
+            DsError::InvalidArgument,
+            format!("unknown best-path algorithm {}", algorithm),
+        ),
     }
 }
 
-fn _peer_iterate(peer_table: &mut PeerTableGen, _route_table: &RouteTableGen) {
-    peer_table.iterate();
+/// Turns route flap dampening on with `config`, or off with a null
+/// `config`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_dampening_set(ds: *mut DataStore, config: *const DampeningConfig) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let config = unsafe { config.as_ref() }.copied();
+    trace!("dampening_set {:?}", config);
+    ds_result(ds.rib.set_dampening(config))
 }
 
 #[no_mangle]
-pub extern "C" fn peer_iterate(address_family: u32) {
-    if address_family == 1 {
-        _peer_iterate(
-            &mut PeerTableGen::V4(&PEER_TABLE_V4),
-            &RouteTableGen::V4(&ROUTE_TABLE_V4),
-        );
-    } else {
-        _peer_iterate(
-            &mut PeerTableGen::V6(&PEER_TABLE_V6),
-            &RouteTableGen::V6(&ROUTE_TABLE_V6),
-        );
+pub extern "C" fn dampening_set(config: *const DampeningConfig) -> DsError {
+    ds_dampening_set(default_data_store(), config)
+}
+
+/// Up to `page_size` of the suppressed paths of the VRF, by peer and
+/// prefix. The entries need room for the peer and prefix addresses.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_dampened_paths_get(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    entries: *mut DampenedEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = walk_args_are_valid(entries, count) {
+        return err;
+    }
+    match ds.rib.dampened_paths(vrf_id) {
+        Ok(paths) => unsafe {
+            let paths = &paths[..paths.len().min(page_size as usize)];
+            for (i, path) in paths.iter().enumerate() {
+                let entry = entries.add(i);
+                (*entry).peer.family = ip_addr_family(&path.peer);
+                copy_ip_addr_to_user((*entry).peer.addr, &path.peer);
+                (*entry).prefix.family = ip_addr_family(&path.prefix);
+                copy_ip_addr_to_user((*entry).prefix.addr, &path.prefix);
+                (*entry).prefix_len = path.prefix_len;
+                (*entry).penalty = path.penalty;
+                (*entry).reuse_in_ms = path.reuse_in.as_millis() as u64;
+            }
+            *count = paths.len() as u32;
+            DsError::Ok
+        },
+        Err(err) => err,
     }
 }
 
-fn _longest_match_lookup(
-    ip_addr: &IpAddr,
-    prefix_tree: &PrefixTreeGen,
-    _entry: *mut ForwardingEntry,
-) -> i32 {
-    match prefix_tree.get_longest_common_prefix(*ip_addr) {
-        Some(fe) => {
-            trace!(
-                "longest_match_lookup prefix {}, found next_hop {} out_ifindex {}",
-                ip_addr,
-                read_val!(fe).next_hop,
-                read_val!(fe).out_ifindex
-            );
-            unsafe {
-                let addr_ptr: *mut u8 = (*_entry).next_hop.addr;
-                copy_ip_addr_to_user(addr_ptr, &read_val!(fe).next_hop);
-                (*_entry).out_ifindex = read_val!(fe).out_ifindex;
+#[no_mangle]
+pub extern "C" fn dampened_paths_get(
+    entries: *mut DampenedEntry,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_dampened_paths_get(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn best_path_algorithm_set(algorithm: u32) -> DsError {
+    ds_best_path_algorithm_set(default_data_store(), algorithm)
+}
+
+/// Picks one path of the route for the flow, see `select_path`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_route_select_path(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    flow: *const FlowKey,
+    path: *mut NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if path.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("path is null"));
+    }
+    unsafe {
+        let prefix = copy_ip_addr_from_user(_prefix);
+        let flow = match copy_flow_from_user(flow) {
+            Ok(flow) => flow,
+            Err(err) => return err,
+        };
+        match ds.rib.select_route_path(vrf_id, &prefix, prefix_len, &flow) {
+            Ok(selected) => {
+                copy_next_hop_to_user(&selected, path);
+                DsError::Ok
             }
-            0
+            Err(err) => err,
         }
-        None => -1,
     }
 }
 
 #[no_mangle]
-pub extern "C" fn longest_match_lookup(_prefix: &IpAddrC, _entry: *mut ForwardingEntry) -> i32 {
-    let ip_addr;
+pub extern "C" fn route_select_path(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    flow: *const FlowKey,
+    path: *mut NextHopEntry,
+) -> DsError {
+    ds_route_select_path(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        flow,
+        path,
+    )
+}
 
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_path_add(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            _longest_match_lookup(&ip_addr, &PrefixTreeGen::V4(&PREFIX_TREE4), _entry)
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            _longest_match_lookup(&ip_addr, &PrefixTreeGen::V6(&PREFIX_TREE6), _entry)
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match copy_next_hop_from_user(path) {
+            Ok(path) => ds_add_result(ds.rib.add_prefix_path(vrf_id, &prefix, prefix_len, &path)),
+            Err(err) => err,
         }
     }
 }
 
-fn _longest_match_add(
-    ip_addr: &IpAddr,
-    prefix_tree: &PrefixTreeGen,
-    fe: ForwardingEntryInt,
-) -> i32 {
-    let new_fe: ForwardingIntEntryWrapped;
-    new_fe = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(fe))));
-    prefix_tree.insert(*ip_addr, new_fe)
+#[no_mangle]
+pub extern "C" fn longest_match_path_add(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    ds_longest_match_path_add(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        path,
+    )
 }
 
 #[no_mangle]
-pub extern "C" fn longest_match_add(_prefix: &IpAddrC, _entry: *mut ForwardingEntry) -> i32 {
-    let ip_addr;
-    let rc;
-
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_path_delete(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
     unsafe {
-        let user_fe: Box<ForwardingEntry> = Box::from_raw(_entry);
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            let fe: ForwardingEntryInt = ForwardingEntryInt {
-                next_hop: copy_ip_addr_v4_from_user(user_fe.next_hop.addr),
-                out_ifindex: user_fe.out_ifindex,
-            };
-            rc = _longest_match_add(&ip_addr, &PrefixTreeGen::V4(&PREFIX_TREE4), fe);
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            let fe: ForwardingEntryInt = ForwardingEntryInt {
-                next_hop: copy_ip_addr_v6_from_user(user_fe.next_hop.addr as *mut u16),
-                out_ifindex: user_fe.out_ifindex,
-            };
-            rc = _longest_match_add(&ip_addr, &PrefixTreeGen::V6(&PREFIX_TREE6), fe);
+        let prefix = copy_ip_addr_from_user(_prefix);
+        match copy_next_hop_from_user(path) {
+            Ok(path) => ds_result(ds.rib.delete_prefix_path(
+                vrf_id,
+                &prefix,
+                prefix_len,
+                &path.addr,
+                path.out_ifindex,
+            )),
+            Err(err) => err,
         }
-        Box::into_raw(user_fe);
     }
-    return rc;
 }
 
-fn _longest_match_delete(ip_addr: &IpAddr, prefix_tree: &PrefixTreeGen) -> i32 {
-    prefix_tree.remove(*ip_addr)
+#[no_mangle]
+pub extern "C" fn longest_match_path_delete(
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+    path: *const NextHopEntry,
+) -> DsError {
+    ds_longest_match_path_delete(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        _prefix,
+        prefix_len,
+        path,
+    )
+}
+
+/// All paths of the most specific prefix covering `addr`.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_lookup_paths(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    addr: &IpAddrC,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    unsafe {
+        let ip_addr = copy_ip_addr_from_user(addr);
+        match ds.rib.longest_match(vrf_id, &ip_addr) {
+            Ok(fe) => copy_paths_to_user(&fe.paths, paths, max_paths, count),
+            Err(err) => err,
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn longest_match_delete(_prefix: &IpAddrC) -> i32 {
-    let ip_addr;
-    let rc;
+pub extern "C" fn longest_match_lookup_paths(
+    addr: &IpAddrC,
+    paths: *mut NextHopEntry,
+    max_paths: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_longest_match_lookup_paths(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        addr,
+        paths,
+        max_paths,
+        count,
+    )
+}
 
+/// Picks the path for the flow through the most specific prefix covering
+/// its destination.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn ds_longest_match_select_path(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    flow: *const FlowKey,
+    path: *mut NextHopEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if path.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("path is null"));
+    }
     unsafe {
-        if _prefix.family == 1 {
-            ip_addr = copy_ip_addr_v4_from_user(_prefix.addr);
-            rc = _longest_match_delete(&ip_addr, &PrefixTreeGen::V4(&PREFIX_TREE4));
-        } else {
-            ip_addr = copy_ip_addr_v6_from_user(_prefix.addr as *mut u16);
-            rc = _longest_match_delete(&ip_addr, &PrefixTreeGen::V6(&PREFIX_TREE6));
+        let flow = match copy_flow_from_user(flow) {
+            Ok(flow) => flow,
+            Err(err) => return err,
+        };
+        match ds.rib.select_forwarding_path(vrf_id, &flow) {
+            Ok(selected) => {
+                copy_next_hop_to_user(&selected, path);
+                DsError::Ok
+            }
+            Err(err) => err,
         }
     }
-    return rc;
 }
 
-use std::alloc::{GlobalAlloc, Layout};
+#[no_mangle]
+pub extern "C" fn longest_match_select_path(
+    flow: *const FlowKey,
+    path: *mut NextHopEntry,
+) -> DsError {
+    ds_longest_match_select_path(default_data_store(), DEFAULT_VRF_ID, flow, path)
+}
+
+use std::alloc::{GlobalAlloc, Layout, System};
 
 extern "C" {
     fn pool_alloc(size: usize) -> *mut u8;
     fn pool_free(ptr: *mut u8);
+    // pool_alloc hands out blocks aligned to max_align_t
+    static pool_max_align: usize;
 }
 
 struct MyAllocator;
 
 unsafe impl GlobalAlloc for MyAllocator {
     unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
-        //System.alloc(layout)
+        if _layout.align() > pool_max_align {
+            return System.alloc(_layout);
+        }
         pool_alloc(_layout.size())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        //System.dealloc(ptr, layout)
+        if _layout.align() > pool_max_align {
+            return System.dealloc(ptr, _layout);
+        }
         pool_free(ptr);
     }
 }
@@ -1,7 +1,11 @@
+#[allow(clippy::items_after_test_module)]
 #[cfg(test)]
 mod tests {
     extern crate data_storage_api;
 
+    use data_storage_api::{DataStore, Ftn, Ilm, NextHop, Peer, Route, RouteAttrs};
+    use std::net::{IpAddr, Ipv4Addr};
+
     extern "C" {
         fn c_rust_peer_entry_test() -> i32;
         fn c_rust_peer_route_entry_test1() -> i32;
@@ -12,6 +16,29 @@ mod tests {
         fn c_rust_prefix_tree_test1() -> i32;
         fn c_rust_ftn_test1() -> i32;
         fn c_rust_ilm_test1() -> i32;
+        fn c_rust_data_store_test1() -> i32;
+        fn c_rust_vrf_test1() -> i32;
+        fn c_rust_prefix_tree_test2() -> i32;
+        fn c_rust_route_prefix_len_test1() -> i32;
+        fn c_rust_error_test1() -> i32;
+        fn c_rust_walk_test1() -> i32;
+        fn c_rust_ecmp_test1() -> i32;
+        fn c_rust_best_path_test1() -> i32;
+        fn c_rust_notify_test1() -> i32;
+        fn c_rust_resolve_test1() -> i32;
+        fn c_rust_bulk_test1() -> i32;
+        fn c_rust_tx_test1() -> i32;
+        fn c_rust_snapshot_test1() -> i32;
+        fn c_rust_json_test1() -> i32;
+        fn c_rust_graceful_restart_test1() -> i32;
+        fn c_rust_peer_state_test1() -> i32;
+        fn c_rust_dampening_test1() -> i32;
+        fn c_rust_policy_test1() -> i32;
+        fn c_rust_label_stack_test1() -> i32;
+        fn c_rust_ilm_update_test1() -> i32;
+        fn c_rust_ilm_owner_test1() -> i32;
+        fn c_rust_label_manager_test1() -> i32;
+        fn c_rust_id_allocator_test1() -> i32;
     }
 
     #[test]
@@ -78,6 +105,999 @@ mod tests {
             assert_eq!(rc, 0);
         }
     }
+    #[test]
+    fn data_store_test1() {
+        unsafe {
+            let rc = c_rust_data_store_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn vrf_test1() {
+        unsafe {
+            let rc = c_rust_vrf_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn prefix_entry_test2() {
+        unsafe {
+            let rc = c_rust_prefix_tree_test2();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn route_prefix_len_test1() {
+        unsafe {
+            let rc = c_rust_route_prefix_len_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn error_test1() {
+        unsafe {
+            let rc = c_rust_error_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn walk_test1() {
+        unsafe {
+            let rc = c_rust_walk_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn ecmp_test1() {
+        unsafe {
+            let rc = c_rust_ecmp_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn best_path_test1() {
+        unsafe {
+            let rc = c_rust_best_path_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn notify_test1() {
+        unsafe {
+            let rc = c_rust_notify_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn resolve_test1() {
+        unsafe {
+            let rc = c_rust_resolve_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn bulk_test1() {
+        unsafe {
+            let rc = c_rust_bulk_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn tx_test1() {
+        unsafe {
+            let rc = c_rust_tx_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn snapshot_test1() {
+        unsafe {
+            let rc = c_rust_snapshot_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn json_test1() {
+        unsafe {
+            let rc = c_rust_json_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn graceful_restart_test1() {
+        unsafe {
+            let rc = c_rust_graceful_restart_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn peer_state_test1() {
+        unsafe {
+            let rc = c_rust_peer_state_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn dampening_test1() {
+        unsafe {
+            let rc = c_rust_dampening_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn policy_test1() {
+        unsafe {
+            let rc = c_rust_policy_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn label_stack_test1() {
+        unsafe {
+            let rc = c_rust_label_stack_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn ilm_update_test1() {
+        unsafe {
+            let rc = c_rust_ilm_update_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn ilm_owner_test1() {
+        unsafe {
+            let rc = c_rust_ilm_owner_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn label_manager_test1() {
+        unsafe {
+            let rc = c_rust_label_manager_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+    #[test]
+    fn id_allocator_test1() {
+        unsafe {
+            let rc = c_rust_id_allocator_test1();
+            assert_eq!(rc, 0);
+        }
+    }
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+    fn peer(addr: IpAddr) -> Peer {
+        Peer {
+            addr,
+            out_ifindex: 1,
+        }
+    }
+    fn route(prefix: IpAddr, prefix_len: u8, next_hop: IpAddr) -> Route {
+        Route {
+            prefix,
+            prefix_len,
+            paths: vec![NextHop::new(next_hop, 1)],
+            attrs: RouteAttrs::default(),
+        }
+    }
+    fn ftn(fec: IpAddr, next_hop: IpAddr, out_labels: Vec<u32>) -> Ftn {
+        Ftn {
+            fec,
+            ftn_ix: 1,
+            next_hop,
+            out_ifindex: 1,
+            out_labels,
+            vrf_id: 0,
+        }
+    }
+    fn ilm(in_label: u32, next_hop: IpAddr, out_label: u32) -> Ilm {
+        Ilm {
+            in_label,
+            in_iface: 1,
+            next_hop,
+            out_ifindex: 1,
+            out_label,
+            ilm_ix: 0,
+            owner: 1,
+        }
+    }
+    fn json_dump(ds: &DataStore) -> serde_json::Value {
+        serde_json::from_str(&ds.export_json().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rust_api_test1() {
+        use data_storage_api::{DsError, PrefixEntry};
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let peer_ip = v4(72, 0, 0, 1);
+        let prefix = v4(112, 1, 0, 0);
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(true));
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(false));
+        assert_eq!(rib.lookup_peer(0, &peer_ip), Ok(peer(peer_ip)));
+        let route = route(prefix, 16, peer_ip);
+        assert_eq!(rib.add_route(0, &peer_ip, &route), Ok(true));
+        assert_eq!(rib.lookup_route(0, &prefix, 16), Ok(route.clone()));
+        assert_eq!(
+            rib.lookup_peer_route(0, &peer_ip, &prefix, 16),
+            Ok(route.clone())
+        );
+        assert_eq!(rib.lookup_best_route(0, &prefix, 16), Ok((peer_ip, route)));
+        assert_eq!(rib.lookup_route(0, &prefix, 24), Err(DsError::NotFound));
+        assert_eq!(rib.lookup_route(9, &prefix, 16), Err(DsError::VrfNotFound));
+        assert_eq!(rib.delete_route(0, &peer_ip, &prefix, 16), Ok(()));
+        assert_eq!(rib.lookup_route(0, &prefix, 16), Err(DsError::NotFound));
+        assert_eq!(rib.delete_peer(0, &peer_ip), Ok(()));
+        assert_eq!(rib.lookup_peer(0, &peer_ip), Err(DsError::NotFound));
+
+        let entry = PrefixEntry {
+            prefix,
+            prefix_len: 16,
+            paths: vec![NextHop::new(peer_ip, 1)],
+        };
+        assert_eq!(rib.add_prefix(0, &entry), Ok(()));
+        let addr = v4(112, 1, 2, 3);
+        assert_eq!(rib.longest_match(0, &addr), Ok(entry));
+        assert_eq!(rib.delete_prefix(0, &prefix, 16), Ok(()));
+        assert_eq!(rib.longest_match(0, &addr), Err(DsError::NotFound));
+
+        let lfib = ds.lfib();
+        lfib.add_nh(&peer_ip, 1);
+        assert_eq!(lfib.add_ftn(&ftn(prefix, peer_ip, vec![100])), Ok(()));
+        assert_eq!(lfib.delete_ftn(0, &prefix, 1), Ok(()));
+        assert_eq!(lfib.delete_ftn(0, &prefix, 1), Err(DsError::NotFound));
+        let ilm = ilm(200, peer_ip, 300);
+        assert_eq!(lfib.add_ilm(&ilm), Err(DsError::NotAllocated));
+        assert_eq!(lfib.reserve_labels(1, 200, 1), Ok(()));
+        let ilm_ix = lfib.add_ilm(&ilm).unwrap();
+        assert!(ilm_ix > 0);
+        assert_eq!(lfib.add_ilm(&ilm), Err(DsError::AlreadyExists));
+        assert_eq!(lfib.delete_ilm(200, 1, ilm_ix, 0), Ok(()));
+        lfib.delete_nh(&peer_ip);
+    }
+
+    #[test]
+    fn rust_notify_test1() {
+        use data_storage_api::{Event, EventOp, PrefixEntry, EVENT_PREFIX};
+        use std::sync::{Arc, Mutex};
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let id = ds
+            .subscribe(EVENT_PREFIX, move |event: &Event| {
+                recorded.lock().unwrap().push(event.op());
+            })
+            .unwrap();
+        let prefix = v4(119, 0, 0, 0);
+        let next_hop = v4(10, 3, 0, 1);
+        let entry = PrefixEntry {
+            prefix,
+            prefix_len: 8,
+            paths: vec![NextHop::new(next_hop, 1)],
+        };
+        assert_eq!(rib.add_prefix(0, &entry), Ok(()));
+        assert_eq!(rib.add_prefix(0, &entry), Ok(()));
+        assert_eq!(
+            rib.add_prefix_path(0, &prefix, 8, &NextHop::new(next_hop, 2)),
+            Ok(true)
+        );
+        assert_eq!(rib.delete_prefix(0, &prefix, 8), Ok(()));
+        assert_eq!(ds.unsubscribe(id), Ok(()));
+        assert_eq!(rib.add_prefix(0, &entry), Ok(()));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                EventOp::Add,
+                EventOp::Modify,
+                EventOp::Modify,
+                EventOp::Delete
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_tx_rollback_test1() {
+        use data_storage_api::{DsError, Transaction};
+        use std::time::Duration;
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let lfib = ds.lfib();
+        let peer_ip = v4(86, 0, 0, 1);
+        let next_hop = v4(10, 24, 0, 1);
+        let fec = v4(131, 0, 0, 0);
+        lfib.add_nh(&next_hop, 1);
+        rib.add_peer(0, &peer(peer_ip)).unwrap();
+        rib.add_route(0, &peer_ip, &route(fec, 16, next_hop))
+            .unwrap();
+        rib.restart_peer(0, &peer_ip, Duration::from_secs(0))
+            .unwrap();
+        // the lowest XC and NHLFE indexes are free, a re-added FTN would get them
+        lfib.add_ftn(&ftn(fec, next_hop, vec![961])).unwrap();
+        lfib.add_ftn(&Ftn {
+            ftn_ix: 2,
+            ..ftn(fec, next_hop, vec![962])
+        })
+        .unwrap();
+        lfib.delete_ftn(0, &fec, 1).unwrap();
+        lfib.reserve_labels(1, 970, 1).unwrap();
+        let ilm_ix = lfib.add_ilm(&ilm(970, next_hop, 971)).unwrap();
+        let dump = ds.export_json().unwrap();
+        let status = rib.peer_status(0, &peer_ip).unwrap();
+
+        let mut tx = Transaction::new();
+        tx.delete_ftn(0, &fec, 2)
+            .delete_ilm(970, 1, ilm_ix, 1)
+            .delete_peer(0, &peer_ip)
+            .delete_ftn(0, &fec, 9);
+        let err = ds.commit(&tx).unwrap_err();
+        assert_eq!(
+            (err.op_index, err.error, err.rollback_error),
+            (3, DsError::NotFound, None)
+        );
+        assert_eq!(ds.export_json().unwrap(), dump);
+        assert_eq!(rib.peer_status(0, &peer_ip), Ok(status));
+        assert_eq!(rib.stale_routes(0, &peer_ip), Ok(vec![(fec, 16)]));
+        assert_eq!(rib.end_of_rib(0, &peer_ip), Ok(1));
+    }
+
+    #[test]
+    fn rust_tx_notify_test1() {
+        use data_storage_api::{DsError, Event, PrefixEntry, Transaction, EVENT_PREFIX};
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+        use std::time::Duration;
+
+        let ds = DataStore::new();
+        let next_hop = v4(10, 25, 0, 1);
+        let busy = v4(135, 0, 0, 0);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        ds.subscribe(EVENT_PREFIX, move |event| {
+            if let Event::Prefix { entry, .. } = event {
+                recorded.lock().unwrap().push(entry.prefix);
+            }
+            thread::sleep(Duration::from_micros(100));
+        })
+        .unwrap();
+        let entry = |prefix| PrefixEntry {
+            prefix,
+            prefix_len: 8,
+            paths: vec![NextHop::new(next_hop, 1)],
+        };
+        // the transaction fails after publishing a few events
+        let mut tx = Transaction::new();
+        for i in 0..20 {
+            tx.add_prefix(0, &entry(v4(136, i, 0, 0)));
+        }
+        tx.delete_ftn(0, &v4(136, 0, 0, 0), 1);
+        thread::scope(|scope| {
+            // another thread keeps delivering its own events meanwhile
+            scope.spawn(|| {
+                for _ in 0..200 {
+                    ds.rib().add_prefix(0, &entry(busy)).unwrap();
+                    ds.rib().delete_prefix(0, &busy, 8).unwrap();
+                }
+            });
+            for _ in 0..200 {
+                assert_eq!(
+                    ds.commit(&tx).map_err(|err| err.error),
+                    Err(DsError::NotFound)
+                );
+            }
+        });
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 400);
+        assert!(seen.iter().all(|prefix| *prefix == busy));
+    }
+
+    #[test]
+    fn rust_tx_route_undo_test1() {
+        use data_storage_api::{DampeningConfig, DsError, PolicyAction, PolicyTerm, Transaction};
+        use std::time::Duration;
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let config = DampeningConfig {
+            half_life_ms: 3_600_000,
+            reuse_threshold: 400,
+            suppress_threshold: 1200,
+            max_suppress_ms: 7_200_000,
+        };
+        assert_eq!(rib.set_dampening(Some(config)), Ok(()));
+        let term = PolicyTerm::new(PolicyAction::Permit);
+        assert_eq!(rib.add_policy_term(1, &term), Ok(0));
+        let peer_ip = v4(87, 0, 0, 1);
+        let next_hop = v4(10, 26, 0, 1);
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(true));
+        assert_eq!(rib.set_peer_policy(0, &peer_ip, Some(1)), Ok(()));
+        // one route flapped below the suppress threshold, one above it
+        let mut installed = route(v4(137, 0, 0, 0), 16, next_hop);
+        let mut dampened = route(v4(138, 0, 0, 0), 16, next_hop);
+        for metric in 0..4 {
+            installed.attrs.metric = metric.min(2);
+            dampened.attrs.metric = metric;
+            rib.add_route(0, &peer_ip, &installed).unwrap();
+            rib.add_route(0, &peer_ip, &dampened).unwrap();
+        }
+        rib.restart_peer(0, &peer_ip, Duration::from_secs(0))
+            .unwrap();
+        let penalties = |ds: &DataStore| -> Vec<(IpAddr, u32)> {
+            let paths = ds.rib().dampened_paths(0).unwrap();
+            paths
+                .iter()
+                .map(|path| (path.prefix, path.penalty))
+                .collect()
+        };
+        let before = penalties(&ds);
+        assert_eq!(before.len(), 1);
+        let status = rib.peer_status(0, &peer_ip).unwrap();
+        let hits = rib.policy_hits(1).unwrap();
+        let stale = vec![(installed.prefix, 16)];
+        assert_eq!(rib.stale_routes(0, &peer_ip), Ok(stale.clone()));
+
+        let mut changed = installed.clone();
+        changed.attrs.metric = 9;
+        dampened.attrs.metric = 9;
+        let mut update = Transaction::new();
+        update
+            .add_route(0, &peer_ip, &changed)
+            .add_route(0, &peer_ip, &dampened)
+            .delete_ftn(0, &installed.prefix, 1);
+        let mut withdraw = Transaction::new();
+        withdraw
+            .delete_route(0, &peer_ip, &installed.prefix, 16)
+            .delete_ftn(0, &installed.prefix, 1);
+        for tx in [update, withdraw].iter() {
+            let err = ds.commit(tx).unwrap_err();
+            assert_eq!((err.error, err.rollback_error), (DsError::NotFound, None));
+            assert_eq!(penalties(&ds), before);
+            assert_eq!(rib.peer_status(0, &peer_ip), Ok(status));
+            assert_eq!(rib.policy_hits(1), Ok(hits.clone()));
+            assert_eq!(rib.stale_routes(0, &peer_ip), Ok(stale.clone()));
+            assert_eq!(
+                rib.lookup_peer_route(0, &peer_ip, &installed.prefix, 16),
+                Ok(installed.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn rust_json_test1() {
+        use data_storage_api::{
+            DampeningConfig, DsError, PeerState, PolicyAction, PolicyTerm, PrefixRange,
+        };
+
+        let ds = DataStore::new();
+        let peer_ip = v4(79, 0, 0, 1);
+        let next_hop = v4(10, 9, 0, 1);
+        let fec = v4(124, 0, 0, 0);
+        assert_eq!(ds.vrf_create(6), Ok(()));
+        ds.add_nh(&next_hop, 1);
+        let rib = ds.rib();
+        assert_eq!(rib.add_peer(6, &peer(peer_ip)), Ok(true));
+        let route = route(fec, 8, next_hop);
+        assert_eq!(rib.add_route(6, &peer_ip, &route), Ok(true));
+        let lfib = ds.lfib();
+        assert_eq!(lfib.add_ftn(&ftn(fec, next_hop, vec![600])), Ok(()));
+        lfib.reserve_labels(1, 700, 1).unwrap();
+        let ilm_ix = lfib.add_ilm(&ilm(700, next_hop, 800)).unwrap();
+        // peers with a policy or a session that went down, and the store's
+        // settings
+        let policy_peer = v4(79, 0, 0, 2);
+        let down_peer = v4(79, 0, 0, 3);
+        for addr in [policy_peer, down_peer].iter() {
+            assert_eq!(rib.add_peer(6, &peer(*addr)), Ok(true));
+        }
+        let range = PrefixRange {
+            prefix: fec,
+            prefix_len: 8,
+            ge: 0,
+            le: 0,
+        };
+        assert_eq!(rib.add_prefix_range(1, &range), Ok(()));
+        let term = PolicyTerm {
+            prefix_list: Some(1),
+            set_metric: Some(5),
+            ..PolicyTerm::new(PolicyAction::Permit)
+        };
+        assert_eq!(rib.add_policy_term(1, &term), Ok(0));
+        assert_eq!(rib.set_peer_policy(6, &policy_peer, Some(1)), Ok(()));
+        assert_eq!(rib.add_route(6, &policy_peer, &route), Ok(true));
+        assert_eq!(rib.add_route(6, &down_peer, &route), Ok(true));
+        assert_eq!(rib.set_peer_state(6, &down_peer, PeerState::Down), Ok(()));
+        assert_eq!(rib.set_dampening(Some(DampeningConfig::default())), Ok(()));
+        assert_eq!(lfib.set_max_label_depth(4), Ok(()));
+
+        let json = ds.export_json().unwrap();
+        for key in [
+            "\"best_peer\": \"79.0.0.1\"",
+            "\"resolved_paths\"",
+            "\"dependent_ftns_down\"",
+            "\"xcs\"",
+            "\"nhlfes\"",
+        ]
+        .iter()
+        {
+            assert!(json.contains(key), "{} not in {}", key, json);
+        }
+        let imported = DataStore::new();
+        assert_eq!(imported.import_json(&json), Ok(()));
+        assert_eq!(imported.export_json(), Ok(json.clone()));
+        assert_eq!(
+            imported.rib().lookup_peer_route(6, &peer_ip, &fec, 8),
+            Ok(route)
+        );
+        assert_eq!(imported.lfib().ilms(None, 10)[0].ilm_ix, ilm_ix);
+        for addr in [peer_ip, policy_peer, down_peer].iter() {
+            assert_eq!(
+                imported.rib().peer_status(6, addr),
+                rib.peer_status(6, addr)
+            );
+        }
+        assert_eq!(imported.rib().peer_policy(6, &policy_peer), Ok(Some(1)));
+        assert_eq!(imported.rib().policy_hits(1), Ok(vec![1]));
+        assert_eq!(imported.rib().dampening(), Some(DampeningConfig::default()));
+        assert_eq!(imported.lfib().max_label_depth(), 4);
+        // and so does a snapshot
+        let mut snapshot = Vec::new();
+        ds.write_snapshot(&mut snapshot).unwrap();
+        let restored = DataStore::new();
+        assert_eq!(restored.read_snapshot(&mut snapshot.as_slice()), Ok(()));
+        assert_eq!(restored.export_json(), Ok(json.clone()));
+        assert_eq!(imported.import_json(&json), Err(DsError::AlreadyExists));
+        assert_eq!(
+            DataStore::new().import_json("{\"version\": 1}"),
+            Err(DsError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn rust_graceful_restart_test1() {
+        use data_storage_api::PeerState;
+        use std::thread;
+        use std::time::Duration;
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let peer_ip = v4(82, 0, 0, 1);
+        let next_hop = v4(10, 12, 0, 1);
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(true));
+        let routes: Vec<Route> = (0..3)
+            .map(|i| route(v4(127, i, 0, 0), 16, next_hop))
+            .collect();
+        for route in routes.iter() {
+            assert_eq!(rib.add_route(0, &peer_ip, route), Ok(true));
+        }
+
+        // the timer of the first restart must not sweep the second one
+        assert_eq!(
+            rib.restart_peer(0, &peer_ip, Duration::from_millis(50)),
+            Ok(())
+        );
+        assert_eq!(
+            rib.restart_peer(0, &peer_ip, Duration::from_secs(0)),
+            Ok(())
+        );
+        assert_eq!(rib.add_route(0, &peer_ip, &routes[1]), Ok(false));
+        assert_eq!(
+            rib.stale_routes(0, &peer_ip),
+            Ok(vec![(routes[0].prefix, 16), (routes[2].prefix, 16)])
+        );
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(rib.stale_routes(0, &peer_ip).unwrap().len(), 2);
+        assert_eq!(rib.delete_route(0, &peer_ip, &routes[2].prefix, 16), Ok(()));
+        assert_eq!(rib.end_of_rib(0, &peer_ip), Ok(1));
+        assert_eq!(
+            rib.peer_routes(0, &peer_ip, None, usize::MAX),
+            Ok(vec![routes[1].clone()])
+        );
+        assert_eq!(rib.stale_routes(0, &peer_ip), Ok(vec![]));
+
+        // the session dropping during a restart leaves the routes stale
+        rib.restart_peer(0, &peer_ip, Duration::from_secs(0))
+            .unwrap();
+        assert_eq!(rib.set_peer_state(0, &peer_ip, PeerState::Down), Ok(()));
+        assert_eq!(
+            rib.stale_routes(0, &peer_ip),
+            Ok(vec![(routes[1].prefix, 16)])
+        );
+        assert_eq!(
+            rib.set_peer_state(0, &peer_ip, PeerState::Established),
+            Ok(())
+        );
+        assert_eq!(rib.add_route(0, &peer_ip, &routes[1]), Ok(false));
+        assert_eq!(rib.end_of_rib(0, &peer_ip), Ok(0));
+        // outside a restart it withdraws them
+        assert_eq!(rib.set_peer_state(0, &peer_ip, PeerState::Down), Ok(()));
+        assert_eq!(rib.peer_routes(0, &peer_ip, None, usize::MAX), Ok(vec![]));
+    }
+
+    #[test]
+    fn rust_dampening_test1() {
+        use data_storage_api::{DampeningConfig, DsError};
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let config = DampeningConfig {
+            half_life_ms: 60_000,
+            reuse_threshold: 400,
+            suppress_threshold: 1000,
+            max_suppress_ms: 120_000,
+        };
+        assert_eq!(rib.set_dampening(Some(config)), Ok(()));
+        assert_eq!(rib.dampening(), Some(config));
+        let peer_ip = v4(85, 0, 0, 1);
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(true));
+        let mut route = route(v4(130, 0, 0, 0), 16, v4(10, 15, 0, 1));
+        assert_eq!(rib.add_route(0, &peer_ip, &route), Ok(true));
+        // re-sending the same update is no flap, changing it is
+        assert_eq!(rib.add_route(0, &peer_ip, &route), Ok(false));
+        for metric in 1..=3 {
+            route.attrs.metric = metric;
+            assert_eq!(rib.add_route(0, &peer_ip, &route), Ok(false));
+        }
+        assert_eq!(
+            rib.lookup_route(0, &route.prefix, 16).map(|_| ()),
+            Err(DsError::NotFound)
+        );
+        let dampened = rib.dampened_paths(0).unwrap();
+        assert_eq!(dampened.len(), 1);
+        assert_eq!(dampened[0].peer, peer_ip);
+        assert!(dampened[0].penalty > 1000 && dampened[0].penalty <= 1500);
+        assert_eq!(rib.set_dampening(None), Ok(()));
+        assert_eq!(rib.lookup_route(0, &route.prefix, 16), Ok(route));
+    }
+
+    #[test]
+    fn rust_policy_test1() {
+        use data_storage_api::{DsError, PolicyAction, PolicyTerm, PrefixRange};
+
+        let ds = DataStore::new();
+        let rib = ds.rib();
+        let range = PrefixRange {
+            prefix: v4(131, 0, 0, 0),
+            prefix_len: 8,
+            ge: 0,
+            le: 16,
+        };
+        assert_eq!(rib.add_prefix_range(1, &range), Ok(()));
+        let mut term = PolicyTerm::new(PolicyAction::Permit);
+        term.prefix_list = Some(1);
+        term.set_local_pref = Some(300);
+        assert_eq!(rib.add_policy_term(1, &term), Ok(0));
+        // a deny term can not rewrite anything
+        let mut deny = PolicyTerm::new(PolicyAction::Deny);
+        deny.set_metric = Some(1);
+        assert_eq!(rib.add_policy_term(1, &deny), Err(DsError::InvalidArgument));
+        let peer_ip = v4(86, 0, 0, 1);
+        assert_eq!(rib.add_peer(0, &peer(peer_ip)), Ok(true));
+        assert_eq!(rib.set_peer_policy(0, &peer_ip, Some(1)), Ok(()));
+        let mut route = route(v4(131, 1, 0, 0), 16, v4(10, 16, 0, 1));
+        assert_eq!(rib.add_route(0, &peer_ip, &route), Ok(true));
+        assert_eq!(
+            rib.lookup_route(0, &route.prefix, 16)
+                .map(|route| route.attrs.local_pref),
+            Ok(300)
+        );
+        route.prefix = v4(131, 1, 1, 0);
+        route.prefix_len = 24;
+        assert_eq!(rib.add_route(0, &peer_ip, &route), Err(DsError::Denied));
+        assert_eq!(rib.policy_hits(1), Ok(vec![1]));
+    }
+
+    #[test]
+    fn rust_label_stack_test1() {
+        use data_storage_api::{DsError, DEFAULT_MAX_LABEL_DEPTH, LABEL_DEPTH_LIMIT, LABEL_MAX};
+
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        assert_eq!(lfib.max_label_depth(), DEFAULT_MAX_LABEL_DEPTH);
+        let next_hop = v4(10, 17, 0, 1);
+        lfib.add_nh(&next_hop, 1);
+        let mut ftn = ftn(
+            v4(132, 0, 0, 0),
+            next_hop,
+            (16..16 + DEFAULT_MAX_LABEL_DEPTH + 1).collect(),
+        );
+        assert_eq!(lfib.add_ftn(&ftn), Err(DsError::InvalidArgument));
+        ftn.out_labels = vec![16, LABEL_MAX + 1, 18];
+        assert_eq!(lfib.add_ftn(&ftn), Err(DsError::InvalidArgument));
+        assert_eq!(
+            lfib.set_max_label_depth(LABEL_DEPTH_LIMIT + 1),
+            Err(DsError::InvalidArgument)
+        );
+        ftn.out_labels = vec![16, 17, 18];
+        assert_eq!(lfib.add_ftn(&ftn), Ok(()));
+        // the same stack shares the NHLFE, another stack gets its own
+        ftn.fec = v4(133, 0, 0, 0);
+        assert_eq!(lfib.add_ftn(&ftn), Ok(()));
+        ftn.fec = v4(134, 0, 0, 0);
+        ftn.out_labels = vec![16, 17, 19];
+        assert_eq!(lfib.add_ftn(&ftn), Ok(()));
+        let ftns = lfib.ftns(0, true, None, 10).unwrap();
+        assert_eq!(ftns.len(), 3);
+        assert_eq!(ftns[0].out_labels, vec![16, 17, 18]);
+        assert_eq!(ftns[2].out_labels, vec![16, 17, 19]);
+        assert_eq!(json_dump(&ds)["nhlfes"].as_array().map(Vec::len), Some(2));
+
+        let imported = DataStore::new();
+        assert_eq!(imported.import_json(&ds.export_json().unwrap()), Ok(()));
+        assert_eq!(imported.lfib().ftns(0, true, None, 10), Ok(ftns));
+    }
+
+    #[test]
+    fn rust_ilm_update_test1() {
+        use data_storage_api::{Event, EventOp, EVENT_ILM};
+        use std::sync::{Arc, Mutex};
+
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        let next_hops = [v4(10, 18, 0, 1), v4(10, 18, 0, 2)];
+        lfib.add_nh(&next_hops[0], 1);
+        lfib.add_nh(&next_hops[1], 2);
+        let ops = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&ops);
+        ds.subscribe(EVENT_ILM, move |event| {
+            if let Event::Ilm { op, ilm } = event {
+                seen.lock().unwrap().push((*op, ilm.out_label));
+            }
+        })
+        .unwrap();
+        let xc_nhlfe_ixs = |ds: &DataStore| {
+            let dump = json_dump(ds);
+            let nhlfes = dump["nhlfes"].as_array().map_or(0, Vec::len);
+            let entry = &dump["ilms"][0]["entries"][0];
+            (entry["xc_ix"].as_u64(), entry["nhlfe_ix"].as_u64(), nhlfes)
+        };
+
+        let mut ilm = ilm(900, next_hops[0], 901);
+        lfib.reserve_labels(1, 900, 11).unwrap();
+        ilm.ilm_ix = lfib.add_ilm(&ilm).unwrap();
+        let (xc_ix, nhlfe_ix, _) = xc_nhlfe_ixs(&ds);
+
+        // the entry moves in place and keeps its indexes
+        ilm.next_hop = next_hops[1];
+        ilm.out_ifindex = 2;
+        ilm.out_label = 902;
+        assert_eq!(lfib.add_ilm(&ilm), Ok(ilm.ilm_ix));
+        assert_eq!(lfib.ilms(None, 10), vec![ilm.clone()]);
+        assert_eq!(xc_nhlfe_ixs(&ds), (xc_ix, nhlfe_ix, 1));
+        assert_eq!(lfib.add_ilm(&ilm), Ok(ilm.ilm_ix));
+
+        // moving onto the NHLFE of another entry shares it
+        let other = Ilm {
+            in_label: 910,
+            out_label: 903,
+            ilm_ix: 0,
+            ..ilm.clone()
+        };
+        lfib.add_ilm(&other).unwrap();
+        assert_eq!(xc_nhlfe_ixs(&ds).2, 2);
+        ilm.out_label = 903;
+        assert_eq!(lfib.add_ilm(&ilm), Ok(ilm.ilm_ix));
+        assert_eq!(xc_nhlfe_ixs(&ds).2, 1);
+        assert_eq!(lfib.ilms(None, 10)[0], ilm);
+        assert_eq!(
+            *ops.lock().unwrap(),
+            vec![
+                (EventOp::Add, 901),
+                (EventOp::Modify, 902),
+                (EventOp::Add, 903),
+                (EventOp::Modify, 903),
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_ilm_owner_test1() {
+        use data_storage_api::DsError;
+
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        let next_hop = v4(10, 21, 0, 1);
+        lfib.add_nh(&next_hop, 1);
+        // the owners share one label, which no label block allows
+        lfib.set_label_checking(false);
+        let xcs = |ds: &DataStore| json_dump(ds)["xcs"].as_array().map_or(0, Vec::len);
+        let ilm = ilm(940, next_hop, 941);
+        let first_ix = lfib.add_ilm(&ilm).unwrap();
+        let second = Ilm {
+            out_label: 942,
+            owner: 2,
+            ..ilm.clone()
+        };
+        let second_ix = lfib.add_ilm(&second).unwrap();
+        assert_eq!(xcs(&ds), 2);
+        // an owner cannot rewrite the entry of another one by its index
+        assert_eq!(
+            lfib.add_ilm(&Ilm {
+                ilm_ix: second_ix,
+                out_label: 943,
+                ..ilm.clone()
+            }),
+            Err(DsError::AlreadyExists)
+        );
+        assert_eq!(lfib.ilms(None, 10)[1].out_label, 942);
+        assert_eq!(lfib.delete_ilm(940, 1, 0, 1), Ok(()));
+        assert_eq!(
+            lfib.ilms(None, 10),
+            vec![Ilm {
+                ilm_ix: second_ix,
+                ..second
+            }]
+        );
+        assert_eq!(xcs(&ds), 1);
+        // the freed index is the next one handed out
+        assert_eq!(lfib.add_ilm(&ilm), Ok(first_ix));
+    }
+
+    #[test]
+    fn rust_xc_share_test1() {
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        let next_hop = v4(10, 23, 0, 1);
+        let fec = v4(132, 0, 0, 0);
+        lfib.add_nh(&next_hop, 1);
+        lfib.reserve_labels(1, 980, 1).unwrap();
+        let xc_ixs = |ds: &DataStore| {
+            let dump = json_dump(ds);
+            let xcs = dump["xcs"].as_array().map_or(0, Vec::len);
+            let ilm = &dump["ilms"][0]["entries"][0];
+            (xcs, ilm["xc_ix"].as_u64())
+        };
+        let ftn = ftn(fec, next_hop, vec![981]);
+        let ilm = ilm(980, next_hop, 981);
+
+        // the FTN and the ILM entry forward through one XC
+        lfib.add_ftn(&ftn).unwrap();
+        let ilm_ix = lfib.add_ilm(&ilm).unwrap();
+        let (xcs, xc_ix) = xc_ixs(&ds);
+        assert_eq!(xcs, 1);
+        // deleting the FTN leaves the XC to the ILM entry
+        lfib.delete_ftn(0, &fec, 1).unwrap();
+        assert_eq!(xc_ixs(&ds), (1, xc_ix));
+        lfib.delete_ilm(980, 1, ilm_ix, 1).unwrap();
+        assert_eq!(xc_ixs(&ds), (0, None));
+
+        // and the other way around
+        lfib.add_ftn(&ftn).unwrap();
+        let ilm_ix = lfib.add_ilm(&ilm).unwrap();
+        lfib.delete_ilm(980, 1, ilm_ix, 1).unwrap();
+        assert_eq!(xc_ixs(&ds).0, 1);
+        assert_eq!(lfib.ftns(0, true, None, 10).unwrap().len(), 1);
+        lfib.delete_ftn(0, &fec, 1).unwrap();
+        assert_eq!(xc_ixs(&ds).0, 0);
+    }
+
+    #[test]
+    fn rust_label_manager_test1() {
+        use data_storage_api::{DsError, LABEL_FIRST_UNRESERVED};
+
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        let next_hop = v4(10, 22, 0, 1);
+        lfib.add_nh(&next_hop, 1);
+        let block = lfib.allocate_labels(7, 4).unwrap();
+        assert_eq!(block.start, LABEL_FIRST_UNRESERVED);
+        assert_eq!(lfib.reserve_labels(8, 100, 1), Ok(()));
+        let ilm = Ilm {
+            owner: 7,
+            ..ilm(block.start + 3, next_hop, 950)
+        };
+        lfib.add_ilm(&ilm).unwrap();
+        lfib.add_ilm(&Ilm {
+            in_iface: 2,
+            ..ilm.clone()
+        })
+        .unwrap();
+        let foreign = Ilm {
+            in_label: 100,
+            ..ilm.clone()
+        };
+        assert_eq!(lfib.add_ilm(&foreign), Err(DsError::NotAllocated));
+        assert_eq!(
+            lfib.add_ilm(&Ilm {
+                owner: 8,
+                ..foreign.clone()
+            })
+            .map(|_| ()),
+            Ok(())
+        );
+        // two entries of one label count once
+        let used: Vec<(u32, u32)> = lfib
+            .label_blocks(0, usize::MAX)
+            .iter()
+            .map(|block| (block.owner, block.used))
+            .collect();
+        assert_eq!(used, vec![(7, 1), (8, 1)]);
+        assert_eq!(lfib.release_labels(7, block.start), Err(DsError::InUse));
+        // checking off lets any owner use any label again
+        lfib.set_label_checking(false);
+        assert_eq!(lfib.add_ilm(&foreign).map(|_| ()), Ok(()));
+        // blocks go to the smallest free range they fit in, and a released
+        // block joins the free labels next to it
+        let small = lfib.allocate_labels(9, 2).unwrap();
+        assert_eq!(small.start, block.start + 4);
+        assert_eq!(lfib.release_labels(9, small.start), Ok(()));
+        assert_eq!(
+            lfib.allocate_labels(9, 100 - small.start)
+                .map(|block| block.start),
+            Ok(small.start)
+        );
+        assert_eq!(lfib.allocate_labels(9, 1).map(|block| block.start), Ok(101));
+    }
+
+    #[test]
+    fn rust_id_allocator_test1() {
+        use data_storage_api::{DsError, IdTableKind, ID_LAST};
+
+        let ds = DataStore::new();
+        let lfib = ds.lfib();
+        let next_hop = v4(10, 23, 0, 1);
+        lfib.add_nh(&next_hop, 1);
+        let entry = |in_label: u32| ilm(in_label, next_hop, in_label);
+        lfib.reserve_labels(1, 1000, 3003).unwrap();
+        // well past the 1024 entries the id tables used to stop at
+        for in_label in 1000..2100 {
+            lfib.add_ilm(&entry(in_label)).unwrap();
+        }
+        let stats = lfib.id_stats(IdTableKind::Xc);
+        assert_eq!((stats.first, stats.last, stats.used), (1, ID_LAST, 1100));
+        assert_eq!(lfib.delete_ilm(1000, 1, 0, 1), Ok(()));
+        assert_eq!(lfib.add_ilm(&entry(3000)), Ok(1));
+        assert_eq!(
+            lfib.set_id_range(IdTableKind::Ilm, 2, 2000),
+            Err(DsError::InUse)
+        );
+        assert_eq!(
+            lfib.set_id_range(IdTableKind::Ilm, 0, 10),
+            Err(DsError::InvalidArgument)
+        );
+        for in_label in 1001..2100 {
+            assert_eq!(lfib.delete_ilm(in_label, 1, 0, 1), Ok(()));
+        }
+        assert_eq!(lfib.delete_ilm(3000, 1, 0, 1), Ok(()));
+
+        // a range at the top of the index space
+        assert_eq!(
+            lfib.set_id_range(IdTableKind::Ilm, ID_LAST - 1, ID_LAST),
+            Ok(())
+        );
+        assert_eq!(lfib.add_ilm(&entry(4000)), Ok(ID_LAST - 1));
+        assert_eq!(lfib.add_ilm(&entry(4001)), Ok(ID_LAST));
+        assert_eq!(lfib.add_ilm(&entry(4002)), Err(DsError::IdExhausted));
+        let stats = lfib.id_stats(IdTableKind::Ilm);
+        assert_eq!((stats.used, stats.peak, stats.exhausted), (2, 1100, 1));
+        assert_eq!(lfib.delete_ilm(4001, 1, 0, 1), Ok(()));
+        assert_eq!(lfib.add_ilm(&entry(4002)), Ok(ID_LAST));
+
+        // the range comes back with the dump and the snapshot
+        let mut snapshot = Vec::new();
+        ds.write_snapshot(&mut snapshot).unwrap();
+        let imported = DataStore::new();
+        let restored = DataStore::new();
+        assert_eq!(imported.import_json(&ds.export_json().unwrap()), Ok(()));
+        assert_eq!(restored.read_snapshot(&mut snapshot.as_slice()), Ok(()));
+        for store in [&imported, &restored].iter() {
+            let stats = store.lfib().id_stats(IdTableKind::Ilm);
+            assert_eq!(
+                (stats.first, stats.last, stats.used),
+                (ID_LAST - 1, ID_LAST, 2)
+            );
+            assert_eq!(
+                store.lfib().add_ilm(&entry(2500)),
+                Err(DsError::IdExhausted)
+            );
+        }
+    }
 }
 
 extern crate data_storage_api;
@@ -92,6 +1112,29 @@ extern "C" {
     fn c_rust_prefix_tree_test1() -> i32;
     fn c_rust_ftn_test1() -> i32;
     fn c_rust_ilm_test1() -> i32;
+    fn c_rust_data_store_test1() -> i32;
+    fn c_rust_vrf_test1() -> i32;
+    fn c_rust_prefix_tree_test2() -> i32;
+    fn c_rust_route_prefix_len_test1() -> i32;
+    fn c_rust_error_test1() -> i32;
+    fn c_rust_walk_test1() -> i32;
+    fn c_rust_ecmp_test1() -> i32;
+    fn c_rust_best_path_test1() -> i32;
+    fn c_rust_notify_test1() -> i32;
+    fn c_rust_resolve_test1() -> i32;
+    fn c_rust_bulk_test1() -> i32;
+    fn c_rust_tx_test1() -> i32;
+    fn c_rust_snapshot_test1() -> i32;
+    fn c_rust_json_test1() -> i32;
+    fn c_rust_graceful_restart_test1() -> i32;
+    fn c_rust_peer_state_test1() -> i32;
+    fn c_rust_dampening_test1() -> i32;
+    fn c_rust_policy_test1() -> i32;
+    fn c_rust_label_stack_test1() -> i32;
+    fn c_rust_ilm_update_test1() -> i32;
+    fn c_rust_ilm_owner_test1() -> i32;
+    fn c_rust_label_manager_test1() -> i32;
+    fn c_rust_id_allocator_test1() -> i32;
 }
 
 fn main() {
@@ -105,5 +1148,28 @@ fn main() {
         c_rust_prefix_tree_test1();
         c_rust_ftn_test1();
         c_rust_ilm_test1();
+        c_rust_data_store_test1();
+        c_rust_vrf_test1();
+        c_rust_prefix_tree_test2();
+        c_rust_route_prefix_len_test1();
+        c_rust_error_test1();
+        c_rust_walk_test1();
+        c_rust_ecmp_test1();
+        c_rust_best_path_test1();
+        c_rust_notify_test1();
+        c_rust_resolve_test1();
+        c_rust_bulk_test1();
+        c_rust_tx_test1();
+        c_rust_snapshot_test1();
+        c_rust_json_test1();
+        c_rust_graceful_restart_test1();
+        c_rust_peer_state_test1();
+        c_rust_dampening_test1();
+        c_rust_policy_test1();
+        c_rust_label_stack_test1();
+        c_rust_ilm_update_test1();
+        c_rust_ilm_owner_test1();
+        c_rust_label_manager_test1();
+        c_rust_id_allocator_test1();
     }
 }
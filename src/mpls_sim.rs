@@ -1,22 +1,22 @@
-#[macro_use]
+use crate::errors::*;
+use crate::external_types::*;
+use crate::id_allocator::{IdAllocator, IdStats, IdTableKind, TableIds};
+use crate::label_manager::{LabelBlock, LabelManager, LABEL_MAX};
+use crate::notify::{Event, EventOp, Notifier, EVENT_FTN, EVENT_ILM};
+use crate::utils::*;
+use crate::{
+    bulk_args_are_valid, copy_results_to_user, data_store_from_handle, default_data_store,
+    lock_each, run_bulk, walk_args_are_valid, DataStore, VrfTable, DEFAULT_VRF_ID,
+};
+use log::*;
 use parking_lot::ReentrantMutex;
+use patricia_tree::*;
+use serde_json::{json, Value};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Bound;
 use std::sync::Arc;
-extern crate log;
-extern crate patricia_tree;
-use patricia_tree::*;
-#[path = "external_types.rs"]
-mod external_types;
-use external_types::*;
-use log::*;
-#[path = "utils.rs"]
-mod utils;
-use utils::*;
-#[path = "macros.rs"]
-#[macro_use]
-mod macros;
 
 type XcEntryWrapped = Arc<ReentrantMutex<RefCell<Box<XcEntry>>>>;
 type FtnEntryWrapped = Arc<ReentrantMutex<RefCell<Box<FtnEntry>>>>;
@@ -29,88 +29,760 @@ pub struct FecEntry {
     dependent_ftn_down_list: FtnList,
     dependent_ilm_down_list: IlmList,
 }
-type FtnTable = Arc<ReentrantMutex<RefCell<PatriciaMap<FecEntry>>>>;
-type IlmTable = Arc<ReentrantMutex<RefCell<HashMap<IlmKey, IlmList>>>>;
+
+impl FecEntry {
+    fn dump(&self, fec: &IpAddr) -> Value {
+        let ftns: Vec<Value> = self
+            .ftn_list
+            .iter()
+            .map(|ftn| read_val!(ftn).dump())
+            .collect();
+        json!({
+            "fec": fec.to_string(),
+            "ftns": ftns,
+            "dependent_ftns_down": ftn_refs(&self.dependent_ftn_down_list),
+            "dependent_ilms_down": ilm_refs(&self.dependent_ilm_down_list),
+        })
+    }
+}
+
+/// FTN entries of a dependent list, by FEC and ftn_ix.
+fn ftn_refs(ftn_list: &[FtnEntryWrapped]) -> Vec<Value> {
+    ftn_list
+        .iter()
+        .map(|ftn| {
+            let guard = ftn.lock();
+            let ftn = guard.borrow();
+            json!({"fec": ftn.fec.to_string(), "ftn_ix": ftn.ftn_ix})
+        })
+        .collect()
+}
+
+/// ILM entries of a dependent list, by incoming label and ilm_ix.
+fn ilm_refs(ilm_list: &[IlmEntryWrapped]) -> Vec<Value> {
+    ilm_list
+        .iter()
+        .map(|ilm| {
+            let ilm = read_val!(ilm).ilm();
+            json!({
+                "in_label": ilm.in_label,
+                "in_iface": ilm.in_iface,
+                "ilm_ix": ilm.ilm_ix,
+            })
+        })
+        .collect()
+}
+/// FTN and ILM tables are ordered by key, so that cursor walks seek to the
+/// cursor.
+type FtnTable = Arc<ReentrantMutex<RefCell<BTreeMap<Vec<u8>, FecEntry>>>>;
+type IlmTable = Arc<ReentrantMutex<RefCell<BTreeMap<IlmKey, IlmList>>>>;
 type XcTable = Arc<ReentrantMutex<RefCell<HashMap<XcKey, XcEntryWrapped>>>>;
 type NhlfeEntryWrapped = Arc<ReentrantMutex<RefCell<Box<NhlfeEntry>>>>;
 type NhlfeTable = Arc<ReentrantMutex<RefCell<HashMap<NhlfeKey, NhlfeEntryWrapped>>>>;
-type IdTable = Arc<ReentrantMutex<RefCell<Box<IdMap>>>>;
-type NhTable = Arc<ReentrantMutex<RefCell<PatriciaMap<u32>>>>;
-
-lazy_static! {
-    pub static ref FTN_TABLE4: FtnTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-    pub static ref FTN_TABLE6: FtnTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-    pub static ref ILM_TABLE: IlmTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref XC_TABLE: XcTable = Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref NHLFE_TABLE4: NhlfeTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref NHLFE_TABLE6: NhlfeTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new())));
-    pub static ref XC_ID_TABLE: IdTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(Box::new(IdMap {
-            ids: [false; 1024]
-        }))));
-    pub static ref NHLFE_ID_TABLE: IdTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(Box::new(IdMap {
-            ids: [false; 1024]
-        }))));
-    pub static ref ILM_ID_TABLE: IdTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(Box::new(IdMap {
-            ids: [false; 1024]
-        }))));
-    pub static ref NH_TABLE4: NhTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-    pub static ref NH_TABLE6: NhTable =
-        Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new())));
-}
-
-pub struct IdMap {
-    ids: [bool; 1024],
-}
-
-impl IdMap {
-    fn get_free(&mut self) -> u32 {
-        let mut i = 0;
-        while i < 1024 {
-            if !self.ids[i] {
-                self.ids[i] = true;
-                return (i + 1) as u32;
+type IdTable = Arc<ReentrantMutex<RefCell<IdAllocator>>>;
+pub(crate) type NhTable = Arc<ReentrantMutex<RefCell<PatriciaMap<u32>>>>;
+
+pub struct LfibVrf {
+    vrf_id: u32,
+    ftn_table4: FtnTable,
+    ftn_table6: FtnTable,
+    xc_table: XcTable,
+    notifier: Notifier,
+}
+
+impl LfibVrf {
+    fn new(vrf_id: u32, xc_table: &XcTable, notifier: &Notifier) -> LfibVrf {
+        LfibVrf {
+            vrf_id,
+            ftn_table4: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            ftn_table6: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            xc_table: Arc::clone(xc_table),
+            notifier: notifier.clone(),
+        }
+    }
+    fn locked(&self, f: &mut dyn FnMut()) {
+        let _ftns4 = self.ftn_table4.lock();
+        let _ftns6 = self.ftn_table6.lock();
+        f();
+    }
+    fn ftn_table(&self, fec: &IpAddr) -> FtnTableGen<'_> {
+        match fec {
+            IpAddr::V4(_) => FtnTableGen::V4(&self.ftn_table4),
+            IpAddr::V6(_) => FtnTableGen::V6(&self.ftn_table6),
+        }
+    }
+    fn cleanup(&self) {
+        trace!("LfibVrf::cleanup");
+        let xc_table = XcTableGen::XC(&self.xc_table);
+        for ftn_table in [&self.ftn_table4, &self.ftn_table6].iter() {
+            for fec_entry in read_val!(ftn_table).values() {
+                for ftn in fec_entry.ftn_list.iter() {
+                    self.notifier.publish(Event::Ftn {
+                        op: EventOp::Delete,
+                        ftn: read_val!(ftn).ftn(self.vrf_id),
+                    });
+                    write_val!(ftn).release_xc_list(&xc_table);
+                }
+            }
+            *write_val!(ftn_table) = BTreeMap::new();
+        }
+    }
+}
+
+impl Drop for LfibVrf {
+    fn drop(&mut self) {
+        trace!("LfibVrf::Drop");
+        self.cleanup();
+    }
+}
+
+pub struct Lfib {
+    ftn_vrf_table: VrfTable<LfibVrf>,
+    ilm_table: IlmTable,
+    xc_table: XcTable,
+    nhlfe_table4: NhlfeTable,
+    nhlfe_table6: NhlfeTable,
+    xc_id_table: IdTable,
+    nhlfe_id_table: IdTable,
+    ilm_id_table: IdTable,
+    nh_table4: NhTable,
+    nh_table6: NhTable,
+    max_label_depth: ReentrantMutex<RefCell<usize>>,
+    labels: ReentrantMutex<RefCell<LabelManager>>,
+    notifier: Notifier,
+}
+
+/// Labels an FTN entry pushes unless `Lfib::set_max_label_depth` says
+/// otherwise.
+pub const DEFAULT_MAX_LABEL_DEPTH: u32 = 8;
+/// Most labels `Lfib::set_max_label_depth` lets an FTN entry push.
+pub const LABEL_DEPTH_LIMIT: u32 = 32;
+
+impl Lfib {
+    pub(crate) fn new(notifier: &Notifier) -> Lfib {
+        let lfib = Lfib {
+            ftn_vrf_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            ilm_table: Arc::new(ReentrantMutex::new(RefCell::new(BTreeMap::new()))),
+            xc_table: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            nhlfe_table4: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            nhlfe_table6: Arc::new(ReentrantMutex::new(RefCell::new(HashMap::new()))),
+            xc_id_table: Arc::new(ReentrantMutex::new(RefCell::new(IdAllocator::default()))),
+            nhlfe_id_table: Arc::new(ReentrantMutex::new(RefCell::new(IdAllocator::default()))),
+            ilm_id_table: Arc::new(ReentrantMutex::new(RefCell::new(IdAllocator::default()))),
+            nh_table4: Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new()))),
+            nh_table6: Arc::new(ReentrantMutex::new(RefCell::new(PatriciaMap::new()))),
+            max_label_depth: ReentrantMutex::new(RefCell::new(DEFAULT_MAX_LABEL_DEPTH as usize)),
+            labels: ReentrantMutex::new(RefCell::new(LabelManager::default())),
+            notifier: notifier.clone(),
+        };
+        let _ = lfib.vrf_create(DEFAULT_VRF_ID);
+        lfib
+    }
+    fn vrf(&self, vrf_id: u32) -> Result<Arc<LfibVrf>, DsError> {
+        match read_val!(self.ftn_vrf_table).get(&vrf_id) {
+            Some(vrf) => Ok(Arc::clone(vrf)),
+            None => Err(ds_error(
+                DsError::VrfNotFound,
+                format!("Lfib::vrf {} not found", vrf_id),
+            )),
+        }
+    }
+    pub(crate) fn vrf_create(&self, vrf_id: u32) -> Result<(), DsError> {
+        if read_val!(self.ftn_vrf_table).contains_key(&vrf_id) {
+            return Err(ds_error(
+                DsError::AlreadyExists,
+                format!("Lfib::vrf_create {} already exists", vrf_id),
+            ));
+        }
+        write_val!(self.ftn_vrf_table).insert(
+            vrf_id,
+            Arc::new(LfibVrf::new(vrf_id, &self.xc_table, &self.notifier)),
+        );
+        Ok(())
+    }
+    pub(crate) fn vrf_delete(&self, vrf_id: u32) -> Result<(), DsError> {
+        let vrf = write_val!(self.ftn_vrf_table).remove(&vrf_id);
+        match vrf {
+            Some(vrf) => {
+                vrf.cleanup();
+                Ok(())
+            }
+            None => Err(ds_error(
+                DsError::VrfNotFound,
+                format!("Lfib::vrf_delete {} not found", vrf_id),
+            )),
+        }
+    }
+    /// Runs `f` with the label tables locked, so that a batch takes each
+    /// lock once rather than once per entry.
+    pub(crate) fn locked<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _vrfs = self.ftn_vrf_table.lock();
+        let vrfs: Vec<Arc<LfibVrf>> = read_val!(self.ftn_vrf_table).values().cloned().collect();
+        let _ilms = self.ilm_table.lock();
+        let _nhlfes4 = self.nhlfe_table4.lock();
+        let _nhlfes6 = self.nhlfe_table6.lock();
+        let _xcs = self.xc_table.lock();
+        let _xc_ids = self.xc_id_table.lock();
+        let _nhlfe_ids = self.nhlfe_id_table.lock();
+        let _ilm_ids = self.ilm_id_table.lock();
+        let _nhs4 = self.nh_table4.lock();
+        let _nhs6 = self.nh_table6.lock();
+        let mut f = Some(f);
+        let mut result = None;
+        lock_each(&vrfs, &|vrf, inner| vrf.locked(inner), &mut || {
+            result = f.take().map(|f| f());
+        });
+        result.expect("lock_each runs the closure once")
+    }
+    pub fn max_label_depth(&self) -> u32 {
+        *read_val!(self.max_label_depth) as u32
+    }
+    /// Sets how many labels an FTN entry may push, 1 to
+    /// `LABEL_DEPTH_LIMIT`. Entries already there keep their stacks.
+    pub fn set_max_label_depth(&self, depth: u32) -> Result<(), DsError> {
+        if depth == 0 || depth > LABEL_DEPTH_LIMIT {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "maximum label depth {} is outside 1..={}",
+                    depth, LABEL_DEPTH_LIMIT
+                ),
+            ));
+        }
+        *write_val!(self.max_label_depth) = depth as usize;
+        Ok(())
+    }
+    /// Allocates a block of `count` labels to the owner above the reserved
+    /// labels, in the smallest free range it fits in.
+    pub fn allocate_labels(&self, owner: u32, count: u32) -> Result<LabelBlock, DsError> {
+        write_val!(self.labels).allocate(owner, count)
+    }
+    /// Reserves the labels `start` to `start + count - 1` for the owner.
+    pub fn reserve_labels(&self, owner: u32, start: u32, count: u32) -> Result<(), DsError> {
+        write_val!(self.labels)
+            .reserve(owner, start, count)
+            .map(|_| ())
+    }
+    pub fn release_labels(&self, owner: u32, start: u32) -> Result<(), DsError> {
+        let guard = self.labels.lock();
+        let mut labels = guard.borrow_mut();
+        let block = labels.lookup(owner, start)?;
+        if block.used > 0 {
+            return Err(ds_error(
+                DsError::InUse,
+                format!(
+                    "{} labels of the block at {} of owner {} are in use",
+                    block.used, start, owner
+                ),
+            ));
+        }
+        labels.release(start);
+        Ok(())
+    }
+    /// Makes ILM entries fail with `NotAllocated` unless their incoming
+    /// label is in a block of their owner.
+    pub fn set_label_checking(&self, checking: bool) {
+        write_val!(self.labels).set_checking(checking);
+    }
+    /// Up to `limit` label blocks by start label, from the one starting at
+    /// `start` or the next one on, with the labels ILM entries use.
+    pub fn label_blocks(&self, start: u32, limit: usize) -> Vec<LabelBlock> {
+        read_val!(self.labels).blocks(start, limit)
+    }
+    pub(crate) fn label_checking(&self) -> bool {
+        read_val!(self.labels).checking()
+    }
+    /// Puts back the label blocks of a snapshot, before its ILM entries.
+    pub(crate) fn restore_labels(
+        &self,
+        checking: bool,
+        blocks: &[LabelBlock],
+    ) -> Result<(), DsError> {
+        let guard = self.labels.lock();
+        let mut labels = guard.borrow_mut();
+        labels.set_checking(checking);
+        for block in blocks.iter() {
+            labels.restore(block)?;
+        }
+        Ok(())
+    }
+    fn check_label_stack(&self, ftn: &Ftn) -> Result<(), DsError> {
+        let max_depth = *read_val!(self.max_label_depth);
+        if ftn.out_labels.is_empty() || ftn.out_labels.len() > max_depth {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "FTN entry {} has {} labels, expected 1 to {}",
+                    ftn.fec,
+                    ftn.out_labels.len(),
+                    max_depth
+                ),
+            ));
+        }
+        if let Some(label) = ftn.out_labels.iter().find(|label| **label > LABEL_MAX) {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "FTN entry {} pushes label {} above {}",
+                    ftn.fec, label, LABEL_MAX
+                ),
+            ));
+        }
+        Ok(())
+    }
+    pub fn add_ftn(&self, ftn: &Ftn) -> Result<(), DsError> {
+        self.check_label_stack(ftn)?;
+        let added = _ftn_add(self, ftn, 0, 0);
+        self.notifier.deliver();
+        added
+    }
+    /// Adds FTN entries in a single batch; each result is what `add_ftn`
+    /// returns for the entry.
+    pub fn add_ftns(&self, ftns: &[Ftn]) -> Vec<Result<(), DsError>> {
+        trace!("Lfib::add_ftns {}", ftns.len());
+        let results = self.locked(|| {
+            ftns.iter()
+                .map(|ftn| {
+                    self.check_label_stack(ftn)?;
+                    _ftn_add(self, ftn, 0, 0)
+                })
+                .collect()
+        });
+        self.notifier.deliver();
+        results
+    }
+    pub fn delete_ftn(&self, vrf_id: u32, fec: &IpAddr, ftn_ix: u32) -> Result<(), DsError> {
+        let deleted = _ftn_del(self, vrf_id, fec, ftn_ix);
+        self.notifier.deliver();
+        deleted
+    }
+    /// Deletes the entries matching the vrf_id, fec and ftn_ix of `ftns`.
+    pub fn delete_ftns(&self, ftns: &[Ftn]) -> Vec<Result<(), DsError>> {
+        trace!("Lfib::delete_ftns {}", ftns.len());
+        let results = self.locked(|| {
+            ftns.iter()
+                .map(|ftn| _ftn_del(self, ftn.vrf_id, &ftn.fec, ftn.ftn_ix))
+                .collect()
+        });
+        self.notifier.deliver();
+        results
+    }
+    /// Adds the ILM entry, allocating an index if `ilm.ilm_ix` is 0.
+    /// Returns the index of the entry. An entry with the same in_label,
+    /// in_iface and ilm_ix gets the next hop, out interface and out label of
+    /// `ilm` instead, keeping its owner.
+    pub fn add_ilm(&self, ilm: &Ilm) -> Result<u32, DsError> {
+        let mut ilm = ilm.clone();
+        let added = _ilm_add_update(self, &mut ilm);
+        self.notifier.deliver();
+        added.map(|()| ilm.ilm_ix)
+    }
+    /// Adds ILM entries in a single batch, see `add_ilm`.
+    pub fn add_ilms(&self, ilms: &[Ilm]) -> Vec<Result<u32, DsError>> {
+        trace!("Lfib::add_ilms {}", ilms.len());
+        let results = self.locked(|| {
+            ilms.iter()
+                .map(|ilm| {
+                    let mut ilm = ilm.clone();
+                    _ilm_add_update(self, &mut ilm).map(|()| ilm.ilm_ix)
+                })
+                .collect()
+        });
+        self.notifier.deliver();
+        results
+    }
+    /// Deletes by `ilm_ix` if it is set, by `owner` otherwise.
+    pub fn delete_ilm(
+        &self,
+        in_label: u32,
+        in_iface: u32,
+        ilm_ix: u32,
+        owner: u32,
+    ) -> Result<(), DsError> {
+        let deleted = _ilm_del(self, in_label, in_iface, ilm_ix, owner);
+        self.notifier.deliver();
+        deleted
+    }
+    /// Deletes by the in_label, in_iface, ilm_ix and owner of each of
+    /// `ilms`, see `delete_ilm`.
+    pub fn delete_ilms(&self, ilms: &[Ilm]) -> Vec<Result<(), DsError>> {
+        trace!("Lfib::delete_ilms {}", ilms.len());
+        let results = self.locked(|| {
+            ilms.iter()
+                .map(|ilm| _ilm_del(self, ilm.in_label, ilm.in_iface, ilm.ilm_ix, ilm.owner))
+                .collect()
+        });
+        self.notifier.deliver();
+        results
+    }
+    /// Up to `limit` FTN entries of the VRF in (fec, ftn_ix) order, starting
+    /// after `after` or at the first entry if it is `None`.
+    pub fn ftns(
+        &self,
+        vrf_id: u32,
+        ipv4: bool,
+        after: Option<&(IpAddr, u32)>,
+        limit: usize,
+    ) -> Result<Vec<Ftn>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let ftn_table = if ipv4 {
+            &vrf.ftn_table4
+        } else {
+            &vrf.ftn_table6
+        };
+        let guard = ftn_table.lock();
+        let fec_entries = guard.borrow();
+        let start = match after {
+            Some((fec, _)) => Bound::Included(fec_key(fec)),
+            None => Bound::Unbounded,
+        };
+        let mut ftns = Vec::new();
+        for fec_entry in fec_entries.range((start, Bound::Unbounded)).map(|(_, e)| e) {
+            let mut fec_ftns: Vec<Ftn> = fec_entry
+                .ftn_list
+                .iter()
+                .map(|ftn| read_val!(ftn).ftn(vrf_id))
+                .filter(|ftn| after.is_none_or(|after| (ftn.fec, ftn.ftn_ix) > *after))
+                .collect();
+            fec_ftns.sort_unstable_by_key(|ftn| ftn.ftn_ix);
+            ftns.extend(fec_ftns.into_iter().take(limit - ftns.len()));
+            if ftns.len() >= limit {
+                break;
+            }
+        }
+        Ok(ftns)
+    }
+    /// Up to `limit` ILM entries in (in_label, in_iface, ilm_ix) order.
+    pub fn ilms(&self, after: Option<&(u32, u32, u32)>, limit: usize) -> Vec<Ilm> {
+        let guard = self.ilm_table.lock();
+        let ilm_lists = guard.borrow();
+        let start = match after {
+            Some((in_label, in_iface, _)) => {
+                Bound::Included(IlmKey::Pkt(IlmKeyPkt::new(*in_label, *in_iface)))
+            }
+            None => Bound::Unbounded,
+        };
+        let mut ilms = Vec::new();
+        for ilm_list in ilm_lists.range((start, Bound::Unbounded)).map(|(_, l)| l) {
+            let mut key_ilms: Vec<Ilm> = ilm_list
+                .iter()
+                .map(|ilm| read_val!(ilm).ilm())
+                .filter(|ilm| {
+                    after.is_none_or(|after| (ilm.in_label, ilm.in_iface, ilm.ilm_ix) > *after)
+                })
+                .collect();
+            key_ilms.sort_unstable_by_key(|ilm| ilm.ilm_ix);
+            ilms.extend(key_ilms.into_iter().take(limit - ilms.len()));
+            if ilms.len() >= limit {
+                break;
+            }
+        }
+        ilms
+    }
+    /// FTN entries of the FEC with their indexes, in the order they were
+    /// added.
+    pub(crate) fn ftns_at(
+        &self,
+        vrf_id: u32,
+        fec: &IpAddr,
+    ) -> Result<Vec<XcIndexed<Ftn>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let ftn_table = vrf.ftn_table(fec);
+        let guard = ftn_table.table().lock();
+        let fec_entries = guard.borrow();
+        Ok(match fec_entries.get(&fec_key(fec)) {
+            Some(fec_entry) => fec_entry
+                .ftn_list
+                .iter()
+                .map(|ftn| {
+                    let guard = ftn.lock();
+                    let ftn = guard.borrow();
+                    let (xc_ix, nhlfe_ix) = xc_list_ixs(&ftn.xc_list);
+                    XcIndexed {
+                        entry: ftn.ftn(vrf_id),
+                        xc_ix,
+                        nhlfe_ix,
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+    /// ILM entries of the incoming label and interface with their indexes.
+    pub(crate) fn ilms_at(&self, in_label: u32, in_iface: u32) -> Vec<XcIndexed<Ilm>> {
+        let ilm_key = IlmKey::Pkt(IlmKeyPkt::new(in_label, in_iface));
+        match read_val!(self.ilm_table).get(&ilm_key) {
+            Some(ilm_list) => ilm_list
+                .iter()
+                .map(|ilm| {
+                    let guard = ilm.lock();
+                    let ilm = guard.borrow();
+                    let (xc_ix, nhlfe_ix) = xc_list_ixs(&ilm.xc_list);
+                    XcIndexed {
+                        entry: ilm.ilm(),
+                        xc_ix,
+                        nhlfe_ix,
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    pub(crate) fn lookup_nh(&self, addr: &IpAddr) -> Option<u32> {
+        self.nh_table(addr).lookup(*addr).ok()
+    }
+    /// Next hop tables, which the RIB resolves route next hops through.
+    pub(crate) fn nh_tables(&self) -> (NhTable, NhTable) {
+        (Arc::clone(&self.nh_table4), Arc::clone(&self.nh_table6))
+    }
+    /// Routes resolving through `addr` are not resolved again, use
+    /// `DataStore::add_nh` for that.
+    pub fn add_nh(&self, addr: &IpAddr, ifindex: u32) {
+        self.nh_table(addr).insert(*addr, ifindex);
+    }
+    pub fn delete_nh(&self, addr: &IpAddr) {
+        self.nh_table(addr).remove(*addr);
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        let vrfs_empty = read_val!(self.ftn_vrf_table).values().all(|vrf| {
+            read_val!(vrf.ftn_table4).is_empty() && read_val!(vrf.ftn_table6).is_empty()
+        });
+        vrfs_empty
+            && read_val!(self.ilm_table).is_empty()
+            && read_val!(self.labels).blocks(0, 1).is_empty()
+            && read_val!(self.nh_table4).is_empty()
+            && read_val!(self.nh_table6).is_empty()
+    }
+    /// Every FTN entry of the VRF with the indexes of its XC, IPv4 first,
+    /// entries of a FEC in the order they were added.
+    pub(crate) fn indexed_ftns(&self, vrf_id: u32) -> Result<Vec<XcIndexed<Ftn>>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let mut ftns = Vec::new();
+        for ftn_table in [&vrf.ftn_table4, &vrf.ftn_table6].iter() {
+            for fec_entry in read_val!(ftn_table).values() {
+                for ftn in fec_entry.ftn_list.iter() {
+                    let guard = ftn.lock();
+                    let ftn = guard.borrow();
+                    let (xc_ix, nhlfe_ix) = xc_list_ixs(&ftn.xc_list);
+                    ftns.push(XcIndexed {
+                        entry: ftn.ftn(vrf_id),
+                        xc_ix,
+                        nhlfe_ix,
+                    });
+                }
             }
-            i = i + 1;
         }
-        0
+        Ok(ftns)
+    }
+    /// Every ILM entry with the indexes of its XC, in (in_label, in_iface)
+    /// order, entries of a label in the order they were added.
+    pub(crate) fn indexed_ilms(&self) -> Vec<XcIndexed<Ilm>> {
+        let guard = self.ilm_table.lock();
+        let ilm_lists = guard.borrow();
+        let mut ilm_keys: Vec<&IlmKey> = ilm_lists.keys().collect();
+        ilm_keys.sort_by_key(|ilm_key| {
+            let IlmKey::Pkt(ilm_key_pkt) = **ilm_key;
+            (ilm_key_pkt.in_label, ilm_key_pkt.in_iface)
+        });
+        ilm_keys
+            .iter()
+            .flat_map(|ilm_key| ilm_lists[*ilm_key].iter())
+            .map(|ilm| {
+                let guard = ilm.lock();
+                let ilm = guard.borrow();
+                let (xc_ix, nhlfe_ix) = xc_list_ixs(&ilm.xc_list);
+                XcIndexed {
+                    entry: ilm.ilm(),
+                    xc_ix,
+                    nhlfe_ix,
+                }
+            })
+            .collect()
+    }
+    /// Adjacent next hops with their interface, IPv4 first.
+    pub(crate) fn next_hops(&self) -> Vec<(IpAddr, u32)> {
+        let mut next_hops = Vec::new();
+        for nh_table in [&self.nh_table4, &self.nh_table6].iter() {
+            for (key, ifindex) in read_val!(nh_table).iter() {
+                next_hops.push((key_addr(&key), *ifindex));
+            }
+        }
+        next_hops
+    }
+    /// FEC entries of the VRF with their FTN entries and the entries waiting
+    /// for them, IPv4 first.
+    pub(crate) fn dump_fecs(&self, vrf_id: u32) -> Result<Vec<Value>, DsError> {
+        let vrf = self.vrf(vrf_id)?;
+        let mut fecs = Vec::new();
+        for ftn_table in [&vrf.ftn_table4, &vrf.ftn_table6].iter() {
+            for (key, fec_entry) in read_val!(ftn_table).iter() {
+                fecs.push(fec_entry.dump(&key_addr(key)));
+            }
+        }
+        Ok(fecs)
+    }
+    /// ILM lists in (in_label, in_iface) order.
+    pub(crate) fn dump_ilms(&self) -> Vec<Value> {
+        let guard = self.ilm_table.lock();
+        let ilm_lists = guard.borrow();
+        let mut ilm_keys: Vec<&IlmKey> = ilm_lists.keys().collect();
+        ilm_keys.sort_by_key(|ilm_key| {
+            let IlmKey::Pkt(ilm_key_pkt) = **ilm_key;
+            (ilm_key_pkt.in_label, ilm_key_pkt.in_iface)
+        });
+        ilm_keys
+            .iter()
+            .map(|ilm_key| {
+                let IlmKey::Pkt(ilm_key_pkt) = **ilm_key;
+                let entries: Vec<Value> = ilm_lists[*ilm_key]
+                    .iter()
+                    .map(|ilm| read_val!(ilm).dump())
+                    .collect();
+                json!({
+                    "in_label": ilm_key_pkt.in_label,
+                    "in_iface": ilm_key_pkt.in_iface,
+                    "entries": entries,
+                })
+            })
+            .collect()
+    }
+    /// XC entries in xc_ix order.
+    pub(crate) fn dump_xcs(&self) -> Vec<Value> {
+        let guard = self.xc_table.lock();
+        let xcs = guard.borrow();
+        let mut xc_keys: Vec<&XcKey> = xcs.keys().collect();
+        xc_keys.sort_by_key(|xc_key| (xc_key.xc_ix, xc_key.nhlfe_ix));
+        xc_keys
+            .iter()
+            .map(|xc_key| read_val!(xcs[*xc_key]).dump())
+            .collect()
+    }
+    /// NHLFE entries in nhlfe_ix order.
+    pub(crate) fn dump_nhlfes(&self) -> Vec<Value> {
+        let mut nhlfes: Vec<(u32, Value)> = Vec::new();
+        for nhlfe_table in [&self.nhlfe_table4, &self.nhlfe_table6].iter() {
+            for nhlfe in read_val!(nhlfe_table).values() {
+                let guard = nhlfe.lock();
+                let nhlfe = guard.borrow();
+                nhlfes.push((nhlfe.nhlfe_ix, nhlfe.dump()));
+            }
+        }
+        nhlfes.sort_by_key(|(nhlfe_ix, _)| *nhlfe_ix);
+        nhlfes.into_iter().map(|(_, nhlfe)| nhlfe).collect()
+    }
+    /// Adds the FTN entry with the XC and NHLFE indexes it had, unless its
+    /// NHLFE is already there and shared.
+    pub(crate) fn restore_ftn(&self, ftn: &XcIndexed<Ftn>) -> Result<(), DsError> {
+        _ftn_add(self, &ftn.entry, ftn.xc_ix, ftn.nhlfe_ix)
+    }
+    /// Adds the ILM entry with its ilm_ix and the XC and NHLFE indexes it
+    /// had, taking the ilm_ix in the ILM id table.
+    pub(crate) fn restore_ilm(&self, ilm: &XcIndexed<Ilm>) -> Result<(), DsError> {
+        let ilm_key = IlmKey::Pkt(IlmKeyPkt::new(ilm.entry.in_label, ilm.entry.in_iface));
+        if IlmTableGen::Ilm(&self.ilm_table)
+            .lookup_by_ix(&ilm_key, ilm.entry.ilm_ix)
+            .is_some()
+        {
+            return Err(ds_error(
+                DsError::AlreadyExists,
+                format!(
+                    "ILM entry {:?} ilm_ix {} already exists",
+                    ilm_key, ilm.entry.ilm_ix
+                ),
+            ));
+        }
+        take_id(&self.ilm_id_table, ilm.entry.ilm_ix)?;
+        let added = _ilm_add(self, &ilm.entry, ilm_key, ilm.xc_ix, ilm.nhlfe_ix);
+        if added.is_err() {
+            let _ = write_val!(self.ilm_id_table).free(ilm.entry.ilm_ix);
+        }
+        added
+    }
+    /// Indexes taken in the XC, NHLFE and ILM id tables.
+    pub(crate) fn used_ids(&self) -> UsedIds {
+        UsedIds {
+            xc: read_val!(self.xc_id_table).ids(),
+            nhlfe: read_val!(self.nhlfe_id_table).ids(),
+            ilm: read_val!(self.ilm_id_table).ids(),
+        }
+    }
+    /// Gives the id tables the ranges of `ids` and marks exactly its
+    /// indexes as taken, so that the indexes handed out next are the ones
+    /// the store would have handed out when `ids` was read. The tables are
+    /// left alone unless all three accept `ids`.
+    pub(crate) fn set_used_ids(&self, ids: &UsedIds) -> Result<(), DsError> {
+        let xc = read_val!(self.xc_id_table).with_ids(&ids.xc)?;
+        let nhlfe = read_val!(self.nhlfe_id_table).with_ids(&ids.nhlfe)?;
+        let ilm = read_val!(self.ilm_id_table).with_ids(&ids.ilm)?;
+        *write_val!(self.xc_id_table) = xc;
+        *write_val!(self.nhlfe_id_table) = nhlfe;
+        *write_val!(self.ilm_id_table) = ilm;
+        Ok(())
+    }
+    fn id_table(&self, table: IdTableKind) -> &IdTable {
+        match table {
+            IdTableKind::Xc => &self.xc_id_table,
+            IdTableKind::Nhlfe => &self.nhlfe_id_table,
+            IdTableKind::Ilm => &self.ilm_id_table,
+        }
+    }
+    /// Makes the id table hand out indexes of `first..=last` only.
+    pub fn set_id_range(&self, table: IdTableKind, first: u32, last: u32) -> Result<(), DsError> {
+        write_val!(self.id_table(table)).set_range(first, last)
+    }
+    pub fn id_stats(&self, table: IdTableKind) -> IdStats {
+        read_val!(self.id_table(table)).stats()
+    }
+    fn nhlfe_table(&self, next_hop: &IpAddr) -> NhlfeTableGen<'_> {
+        match next_hop {
+            IpAddr::V4(_) => NhlfeTableGen::V4(&self.nhlfe_table4),
+            IpAddr::V6(_) => NhlfeTableGen::V6(&self.nhlfe_table6),
+        }
     }
-    fn put_free(&mut self, ix: usize) {
-        if ix > 1024 || ix == 0 {
-            trace!("idx is out of range {}", ix);
-            return;
+    fn nh_table(&self, addr: &IpAddr) -> NhTableGen<'_> {
+        match addr {
+            IpAddr::V4(_) => NhTableGen::V4(&self.nh_table4),
+            IpAddr::V6(_) => NhTableGen::V6(&self.nh_table6),
         }
-        self.ids[ix - 1] = false;
     }
 }
 
-pub enum NhTableGen {
-    V4(&'static NH_TABLE4),
-    V6(&'static NH_TABLE6),
+/// Ranges and taken indexes of the id tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UsedIds {
+    pub(crate) xc: TableIds,
+    pub(crate) nhlfe: TableIds,
+    pub(crate) ilm: TableIds,
+}
+
+/// FTN or ILM entry with the indexes of the XC and NHLFE it forwards
+/// through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct XcIndexed<T> {
+    pub(crate) entry: T,
+    pub(crate) xc_ix: u32,
+    pub(crate) nhlfe_ix: u32,
 }
 
-impl NhTableGen {
+pub enum NhTableGen<'a> {
+    V4(&'a NhTable),
+    V6(&'a NhTable),
+}
+
+impl<'a> NhTableGen<'a> {
     fn insert(&self, key: IpAddr, entry: u32) {
         match self {
-            NhTableGen::V4(_) => match key {
+            NhTableGen::V4(table) => match key {
                 IpAddr::V4(ipv4) => {
-                    write_val!(&NH_TABLE4).insert(ipv4.octets(), entry);
+                    write_val!(table).insert(ipv4.octets(), entry);
                 }
                 IpAddr::V6(_) => {
                     trace!("IPv6 is unexpected here");
                 }
             },
-            NhTableGen::V6(_) => match key {
+            NhTableGen::V6(table) => match key {
                 IpAddr::V6(ipv6) => {
-                    write_val!(&NH_TABLE6).insert(ipv6.octets(), entry);
+                    write_val!(table).insert(ipv6.octets(), entry);
                 }
                 IpAddr::V4(_) => {
                     trace!("IPV4 is unexpected here");
@@ -118,22 +790,22 @@ impl NhTableGen {
             },
         }
     }
-    fn lookup(&self, key: IpAddr) -> Result<u32, i32> {
+    pub(crate) fn lookup(&self, key: IpAddr) -> Result<u32, DsError> {
         match self {
-            NhTableGen::V4(_) => match key {
+            NhTableGen::V4(table) => match key {
                 IpAddr::V4(ipv4) => {
-                    if read_val!(&NH_TABLE4).contains_key(ipv4.octets()) {
-                        return Ok(*read_val!(&NH_TABLE4).get(ipv4.octets()).unwrap());
+                    if read_val!(table).contains_key(ipv4.octets()) {
+                        return Ok(*read_val!(table).get(ipv4.octets()).unwrap());
                     }
                 }
                 IpAddr::V6(_) => {
                     trace!("IPV6 is unexpected here");
                 }
             },
-            NhTableGen::V6(_) => match key {
+            NhTableGen::V6(table) => match key {
                 IpAddr::V6(ipv6) => {
-                    if read_val!(&NH_TABLE6).contains_key(ipv6.octets()) {
-                        return Ok(*read_val!(&NH_TABLE6).get(ipv6.octets()).unwrap());
+                    if read_val!(table).contains_key(ipv6.octets()) {
+                        return Ok(*read_val!(table).get(ipv6.octets()).unwrap());
                     }
                 }
                 IpAddr::V4(_) => {
@@ -141,21 +813,21 @@ impl NhTableGen {
                 }
             },
         }
-        Err(-1)
+        Err(DsError::NotFound)
     }
     fn remove(&self, key: IpAddr) {
         match self {
-            NhTableGen::V4(_) => match key {
+            NhTableGen::V4(table) => match key {
                 IpAddr::V4(ipv4) => {
-                    write_val!(&NH_TABLE4).remove(ipv4.octets());
+                    write_val!(table).remove(ipv4.octets());
                 }
                 IpAddr::V6(_) => {
                     trace!("IPv6 is unexpected here");
                 }
             },
-            NhTableGen::V6(_) => match key {
+            NhTableGen::V6(table) => match key {
                 IpAddr::V6(ipv6) => {
-                    write_val!(&NH_TABLE6).remove(ipv6.octets());
+                    write_val!(table).remove(ipv6.octets());
                 }
                 IpAddr::V4(_) => {
                     trace!("IPv4 is unexpected here");
@@ -165,35 +837,71 @@ impl NhTableGen {
     }
 }
 
-pub enum FtnTableGen {
-    V4(&'static FTN_TABLE4),
-    V6(&'static FTN_TABLE6),
+pub enum FtnTableGen<'a> {
+    V4(&'a FtnTable),
+    V6(&'a FtnTable),
 }
 
 fn insert_list<E>(list: &mut Vec<E>, entry: E) {
     trace!("insert_list");
     list.push(entry);
 }
-fn list_is_empty<E>(list: &Vec<E>) -> bool {
+fn list_is_empty<E>(list: &[E]) -> bool {
     trace!("list_is_empty");
-    list.len() == 0
+    list.is_empty()
+}
+
+fn fec_key(fec: &IpAddr) -> Vec<u8> {
+    match fec {
+        IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+        IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+    }
+}
+
+/// Address of a FEC or next hop table key.
+fn key_addr(key: &[u8]) -> IpAddr {
+    if key.len() == 4 {
+        let mut v4 = [0u8; 4];
+        v4.copy_from_slice(key);
+        IpAddr::from(v4)
+    } else {
+        let mut v6 = [0u8; 16];
+        v6.copy_from_slice(key);
+        IpAddr::from(v6)
+    }
 }
 
-impl FtnTableGen {
+impl<'a> FtnTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &FtnTable {
+        match self {
+            FtnTableGen::V4(table) => table,
+            FtnTableGen::V6(table) => table,
+        }
+    }
+    fn family_matches(&self, addr: &IpAddr) -> bool {
+        match self {
+            FtnTableGen::V4(_) => {
+                if addr.is_ipv6() {
+                    trace!("IPv6 is not expected here!");
+                    return false;
+                }
+            }
+            FtnTableGen::V6(_) => {
+                if addr.is_ipv4() {
+                    trace!("IPv4 is not expected here!");
+                    return false;
+                }
+            }
+        }
+        true
+    }
     fn lookup_list(
         ftn_list: &FtnList,
-        on_element: &Fn(&FtnEntryWrapped) -> bool,
+        on_element: &dyn Fn(&FtnEntryWrapped) -> bool,
     ) -> Option<FtnEntryWrapped> {
         trace!("FtnTableGen::lookup_list");
-        let len: usize = ftn_list.len();
-        let mut i = 0;
-        while i < len {
-            if on_element(&ftn_list[i]) {
-                return Some(Arc::clone(&ftn_list[i]));
-            }
-            i += 1;
-        }
-        None
+        ftn_list.iter().find(|e| on_element(e)).map(Arc::clone)
     }
     fn remove_from_list(ftn_list: &mut FtnList, ftn_ix: u32) -> Option<FtnEntryWrapped> {
         trace!("FtnTableGen::remove_from_list");
@@ -211,336 +919,182 @@ impl FtnTableGen {
     }
     fn lookup(&self, key: &FtnKey, ftn_ix: u32) -> Option<FtnEntryWrapped> {
         trace!("FtnTableGen::lookup");
-        match self {
-            FtnTableGen::V4(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V4(ipv4) => {
-                        if read_val!(FTN_TABLE4).contains_key(ipv4.octets()) {
-                            return FtnTableGen::lookup_list(
-                                &read_val!(FTN_TABLE4).get(ipv4.octets()).unwrap().ftn_list,
-                                &|ie| ftn_ix == read_val!(ie).ftn_ix,
-                            );
-                        }
-                    }
-                    IpAddr::V6(_) => {
-                        trace!("IPv6 is not expected here!");
-                    }
-                },
-            },
-            FtnTableGen::V6(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V6(ipv6) => {
-                        if read_val!(FTN_TABLE6).contains_key(ipv6.octets()) {
-                            return FtnTableGen::lookup_list(
-                                &read_val!(FTN_TABLE6).get(ipv6.octets()).unwrap().ftn_list,
-                                &|ie| ftn_ix == read_val!(ie).ftn_ix,
-                            );
-                        }
-                    }
-                    IpAddr::V4(_) => {
-                        trace!("IPv4 is not expected here!");
-                    }
-                },
-            },
+        match key {
+            FtnKey::IP(key_ip) => {
+                if !self.family_matches(&key_ip.prefix) {
+                    return None;
+                }
+                let k = fec_key(&key_ip.prefix);
+                if read_val!(self.table()).contains_key(&k) {
+                    return FtnTableGen::lookup_list(
+                        &read_val!(self.table()).get(&k).unwrap().ftn_list,
+                        &|ie| ftn_ix == read_val!(ie).ftn_ix,
+                    );
+                }
+            }
         }
         None
     }
     fn insert(&self, key: FtnKey, entry: FtnEntryWrapped) {
         trace!("FtnTableGen::insert");
-        match self {
-            FtnTableGen::V4(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V4(ipv4) => {
-                        if !read_val!(FTN_TABLE4).contains_key(ipv4.octets()) {
-                            trace!("not found, create new");
-                            write_val!(FTN_TABLE4).insert(
-                                ipv4.octets(),
-                                FecEntry {
-                                    ftn_list: Vec::new(),
-                                    dependent_ftn_down_list: Vec::new(),
-                                    dependent_ilm_down_list: Vec::new(),
-                                },
-                            );
-                        }
-                        insert_list(
-                            &mut write_val!(FTN_TABLE4)
-                                .get_mut(ipv4.octets())
-                                .unwrap()
-                                .ftn_list,
-                            entry,
-                        );
-                    }
-                    IpAddr::V6(_) => {
-                        trace!("IPv6 is not expected here!");
-                    }
-                },
-            },
-            FtnTableGen::V6(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V6(ipv6) => {
-                        if !read_val!(FTN_TABLE6).contains_key(ipv6.octets()) {
-                            trace!("not found, create new");
-                            write_val!(FTN_TABLE6).insert(
-                                ipv6.octets(),
-                                FecEntry {
-                                    ftn_list: Vec::new(),
-                                    dependent_ftn_down_list: Vec::new(),
-                                    dependent_ilm_down_list: Vec::new(),
-                                },
-                            );
-                        }
-                        insert_list(
-                            &mut write_val!(FTN_TABLE6)
-                                .get_mut(ipv6.octets())
-                                .unwrap()
-                                .ftn_list,
-                            entry,
-                        );
-                    }
-                    IpAddr::V4(_) => {
-                        trace!("IPv4 is not expected here!");
-                    }
-                },
-            },
+        match key {
+            FtnKey::IP(key_ip) => {
+                if !self.family_matches(&key_ip.prefix) {
+                    return;
+                }
+                let k = fec_key(&key_ip.prefix);
+                if !read_val!(self.table()).contains_key(&k) {
+                    trace!("not found, create new");
+                    write_val!(self.table()).insert(
+                        k.clone(),
+                        FecEntry {
+                            ftn_list: Vec::new(),
+                            dependent_ftn_down_list: Vec::new(),
+                            dependent_ilm_down_list: Vec::new(),
+                        },
+                    );
+                }
+                insert_list(
+                    &mut write_val!(self.table()).get_mut(&k).unwrap().ftn_list,
+                    entry,
+                );
+            }
         }
     }
-    fn process_ftn_dependent_entry(dep_ftn: &FtnEntryWrapped, fec: &IpAddr) {
-        match fec {
-            IpAddr::V4(ipv4) => {
-                if read_val!(FTN_TABLE4).contains_key(ipv4.octets()) {
-                    match FtnTableGen::lookup_list(
-                        &read_val!(FTN_TABLE4).get(ipv4.octets()).unwrap().ftn_list,
-                        &|ie| true == read_val!(ie).state,
-                    ) {
-                        Some(parent_ftn) => {
-                            write_val!(parent_ftn).add_to_ftn_up_list(Arc::clone(dep_ftn));
-                        }
-                        None => {
-                            insert_list(
-                                &mut write_val!(FTN_TABLE4)
-                                    .get_mut(ipv4.octets())
-                                    .unwrap()
-                                    .dependent_ftn_down_list,
-                                Arc::clone(&dep_ftn),
-                            );
-                        }
-                    }
+    fn process_ftn_dependent_entry(&self, dep_ftn: &FtnEntryWrapped, fec: &IpAddr) {
+        let k = fec_key(fec);
+        if read_val!(self.table()).contains_key(&k) {
+            match FtnTableGen::lookup_list(
+                &read_val!(self.table()).get(&k).unwrap().ftn_list,
+                &|ie| read_val!(ie).state,
+            ) {
+                Some(parent_ftn) => {
+                    write_val!(parent_ftn).add_to_ftn_up_list(Arc::clone(dep_ftn));
                 }
-            }
-            IpAddr::V6(ipv6) => {
-                if read_val!(FTN_TABLE6).contains_key(ipv6.octets()) {
-                    match FtnTableGen::lookup_list(
-                        &read_val!(FTN_TABLE6).get(ipv6.octets()).unwrap().ftn_list,
-                        &|ie| true == read_val!(ie).state,
-                    ) {
-                        Some(parent_ftn) => {
-                            write_val!(parent_ftn).add_to_ftn_up_list(Arc::clone(&dep_ftn));
-                        }
-                        None => {
-                            insert_list(
-                                &mut write_val!(FTN_TABLE6)
-                                    .get_mut(ipv6.octets())
-                                    .unwrap()
-                                    .dependent_ftn_down_list,
-                                Arc::clone(&dep_ftn),
-                            );
-                        }
-                    }
+                None => {
+                    insert_list(
+                        &mut write_val!(self.table())
+                            .get_mut(&k)
+                            .unwrap()
+                            .dependent_ftn_down_list,
+                        Arc::clone(dep_ftn),
+                    );
                 }
             }
         }
     }
-    fn process_ilm_dependent_entry(dep_ilm: IlmEntryWrapped, fec: &IpAddr) {
-        match fec {
-            IpAddr::V4(ipv4) => {
-                if read_val!(FTN_TABLE4).contains_key(ipv4.octets()) {
-                    match FtnTableGen::lookup_list(
-                        &read_val!(FTN_TABLE4).get(ipv4.octets()).unwrap().ftn_list,
-                        &|ie| true == read_val!(ie).state,
-                    ) {
-                        Some(parent_ilm) => {
-                            write_val!(parent_ilm).add_to_ilm_up_list(Arc::clone(&dep_ilm));
-                        }
-                        None => {
-                            insert_list(
-                                &mut write_val!(FTN_TABLE4)
-                                    .get_mut(ipv4.octets())
-                                    .unwrap()
-                                    .dependent_ilm_down_list,
-                                Arc::clone(&dep_ilm),
-                            );
-                        }
-                    }
+    fn process_ilm_dependent_entry(&self, dep_ilm: IlmEntryWrapped, fec: &IpAddr) {
+        let k = fec_key(fec);
+        if read_val!(self.table()).contains_key(&k) {
+            match FtnTableGen::lookup_list(
+                &read_val!(self.table()).get(&k).unwrap().ftn_list,
+                &|ie| read_val!(ie).state,
+            ) {
+                Some(parent_ilm) => {
+                    write_val!(parent_ilm).add_to_ilm_up_list(Arc::clone(&dep_ilm));
                 }
-            }
-            IpAddr::V6(ipv6) => {
-                if read_val!(FTN_TABLE6).contains_key(ipv6.octets()) {
-                    match FtnTableGen::lookup_list(
-                        &read_val!(FTN_TABLE6).get(ipv6.octets()).unwrap().ftn_list,
-                        &|ie| true == read_val!(ie).state,
-                    ) {
-                        Some(parent_ilm) => {
-                            write_val!(parent_ilm).add_to_ilm_up_list(Arc::clone(&dep_ilm));
-                        }
-                        None => {
-                            insert_list(
-                                &mut write_val!(FTN_TABLE6)
-                                    .get_mut(ipv6.octets())
-                                    .unwrap()
-                                    .dependent_ilm_down_list,
-                                Arc::clone(&dep_ilm),
-                            );
-                        }
-                    }
+                None => {
+                    insert_list(
+                        &mut write_val!(self.table())
+                            .get_mut(&k)
+                            .unwrap()
+                            .dependent_ilm_down_list,
+                        Arc::clone(&dep_ilm),
+                    );
                 }
             }
         }
     }
     fn remove(&self, key: &FtnKey, ftn_ix: u32) {
         trace!("FtnTableGen::remove");
-        match self {
-            FtnTableGen::V4(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V4(ipv4) => {
-                        let removed_ftn = FtnTableGen::remove_from_list(
-                            &mut write_val!(FTN_TABLE4)
-                                .get_mut(ipv4.octets())
-                                .unwrap()
-                                .ftn_list,
-                            ftn_ix,
-                        );
-                        if removed_ftn.is_some() {
-                            let removed_ftn_unwarp = Arc::clone(&removed_ftn.unwrap());
-                            write_val!(removed_ftn_unwarp).clean_ftn_up_list();
-                            write_val!(removed_ftn_unwarp).clean_ilm_up_list();
-                        }
-                        if list_is_empty(
-                            &read_val!(FTN_TABLE4).get(ipv4.octets()).unwrap().ftn_list,
-                        ) && list_is_empty(
-                            &read_val!(FTN_TABLE4)
-                                .get(ipv4.octets())
-                                .unwrap()
-                                .dependent_ftn_down_list,
-                        ) && list_is_empty(
-                            &read_val!(FTN_TABLE4)
-                                .get(ipv4.octets())
-                                .unwrap()
-                                .dependent_ilm_down_list,
-                        ) {
-                            write_val!(FTN_TABLE4).remove(ipv4.octets());
-                        }
-                    }
-                    IpAddr::V6(_) => {
-                        trace!("IPv6 is not expected here!");
-                    }
-                },
-            },
-            FtnTableGen::V6(_) => match key {
-                FtnKey::IP(key_ip) => match key_ip.prefix {
-                    IpAddr::V6(ipv6) => {
-                        let removed_ftn = FtnTableGen::remove_from_list(
-                            &mut write_val!(FTN_TABLE6)
-                                .get_mut(ipv6.octets())
-                                .unwrap()
-                                .ftn_list,
-                            ftn_ix,
-                        );
-                        if removed_ftn.is_some() {
-                            let removed_ftn_unwarp = Arc::clone(&removed_ftn.unwrap());
-                            write_val!(removed_ftn_unwarp).clean_ftn_up_list();
-                            write_val!(removed_ftn_unwarp).clean_ilm_up_list();
-                        }
-                        if list_is_empty(
-                            &read_val!(FTN_TABLE6).get(ipv6.octets()).unwrap().ftn_list,
-                        ) && list_is_empty(
-                            &read_val!(FTN_TABLE6)
-                                .get(ipv6.octets())
-                                .unwrap()
-                                .dependent_ftn_down_list,
-                        ) && list_is_empty(
-                            &read_val!(FTN_TABLE6)
-                                .get(ipv6.octets())
-                                .unwrap()
-                                .dependent_ilm_down_list,
-                        ) {
-                            write_val!(FTN_TABLE6).remove(ipv6.octets());
-                        }
-                    }
-                    IpAddr::V4(_) => {
-                        trace!("IPv4 is not expected here!");
-                    }
-                },
-            },
+        match key {
+            FtnKey::IP(key_ip) => {
+                if !self.family_matches(&key_ip.prefix) {
+                    return;
+                }
+                let k = fec_key(&key_ip.prefix);
+                let removed_ftn = FtnTableGen::remove_from_list(
+                    &mut write_val!(self.table()).get_mut(&k).unwrap().ftn_list,
+                    ftn_ix,
+                );
+                if let Some(removed_ftn_unwarp) = removed_ftn {
+                    write_val!(removed_ftn_unwarp).clean_ftn_up_list(self);
+                    write_val!(removed_ftn_unwarp).clean_ilm_up_list(self);
+                }
+                if list_is_empty(&read_val!(self.table()).get(&k).unwrap().ftn_list)
+                    && list_is_empty(
+                        &read_val!(self.table())
+                            .get(&k)
+                            .unwrap()
+                            .dependent_ftn_down_list,
+                    )
+                    && list_is_empty(
+                        &read_val!(self.table())
+                            .get(&k)
+                            .unwrap()
+                            .dependent_ilm_down_list,
+                    )
+                {
+                    write_val!(self.table()).remove(&k);
+                }
+            }
         }
     }
 }
 
-pub enum NhlfeTableGen {
-    V4(&'static NHLFE_TABLE4),
-    V6(&'static NHLFE_TABLE6),
+pub enum NhlfeTableGen<'a> {
+    V4(&'a NhlfeTable),
+    V6(&'a NhlfeTable),
 }
 
-impl NhlfeTableGen {
-    fn lookup(&self, key: &NhlfeKey) -> Option<NhlfeEntryWrapped> {
-        trace!("NhlfeTableGen::lookup");
+impl<'a> NhlfeTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &NhlfeTable {
         match self {
-            NhlfeTableGen::V4(_) => {
-                if read_val!(NHLFE_TABLE4).contains_key(key) {
-                    return Some(Arc::clone(&read_val!(NHLFE_TABLE4)[key]));
-                }
-            }
-            NhlfeTableGen::V6(_) => {
-                if read_val!(NHLFE_TABLE6).contains_key(key) {
-                    return Some(Arc::clone(&read_val!(NHLFE_TABLE6)[key]));
-                }
-            }
+            NhlfeTableGen::V4(table) => table,
+            NhlfeTableGen::V6(table) => table,
         }
-        None
+    }
+    fn lookup(&self, key: &NhlfeKey) -> Option<NhlfeEntryWrapped> {
+        trace!("NhlfeTableGen::lookup");
+        read_val!(self.table()).get(key).map(Arc::clone)
     }
     fn insert(&self, key: NhlfeKey, entry: NhlfeEntryWrapped) {
         trace!("NhlfeTableGen::insert");
-        match self {
-            NhlfeTableGen::V4(_) => {
-                write_val!(NHLFE_TABLE4).insert(key, Arc::clone(&entry));
-            }
-            NhlfeTableGen::V6(_) => {
-                write_val!(NHLFE_TABLE6).insert(key, Arc::clone(&entry));
-            }
-        }
+        write_val!(self.table()).insert(key, Arc::clone(&entry));
     }
     fn remove(&self, key: &NhlfeKey) {
         trace!("NhlfeTableGen::remove");
-        match self {
-            NhlfeTableGen::V4(_) => {
-                write_val!(NHLFE_TABLE4).remove(key);
-            }
-            NhlfeTableGen::V6(_) => {
-                write_val!(NHLFE_TABLE6).remove(key);
-            }
-        }
+        write_val!(self.table()).remove(key);
     }
 }
 
-pub enum XcTableGen {
-    XC(&'static XC_TABLE),
+pub enum XcTableGen<'a> {
+    XC(&'a XcTable),
 }
 
-impl XcTableGen {
+impl<'a> XcTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &XcTable {
+        match self {
+            XcTableGen::XC(table) => table,
+        }
+    }
     fn lookup(&self, key: &XcKey) -> Option<XcEntryWrapped> {
         trace!("XcTableGen::lookup");
-        if read_val!(XC_TABLE).contains_key(key) {
+        if read_val!(self.table()).contains_key(key) {
             trace!("found");
-            return Some(Arc::clone(&read_val!(XC_TABLE)[key]));
+            return Some(Arc::clone(&read_val!(self.table())[key]));
         }
         None
     }
     fn insert(&self, key: &XcKey, entry: XcEntryWrapped) {
         trace!("XcTableGen::insert");
-        write_val!(XC_TABLE).insert(*key, Arc::clone(&entry));
+        write_val!(self.table()).insert(*key, Arc::clone(&entry));
     }
     fn remove(&self, key: &XcKey) {
         trace!("XcTableGen::remove");
-        write_val!(XC_TABLE).remove(key);
+        write_val!(self.table()).remove(key);
     }
 }
 
@@ -550,7 +1104,7 @@ pub struct FtnKeyIp {
 
 impl FtnKeyIp {
     fn new(prefix: IpAddr) -> FtnKeyIp {
-        FtnKeyIp { prefix: prefix }
+        FtnKeyIp { prefix }
     }
 }
 
@@ -562,62 +1116,137 @@ trait MplsEntry {
     fn get_xc_list(&mut self) -> &mut XcList;
     fn add_xc_entry(&mut self, entry: XcEntryWrapped) {
         trace!("add_xc_entry");
+        write_val!(entry).users += 1;
         self.get_xc_list().push(entry);
     }
-    fn free_xc_list(&mut self) {
-        trace!("freeing xc_list");
-        let xc_list = self.get_xc_list();
-        let len: usize = xc_list.len();
-        let mut i = 0;
-        while i < len {
-            XcTableGen::XC(&XC_TABLE).remove(&write_val!(xc_list[i]).xc_key);
-            i += 1;
+    fn release_xc_list(&mut self, xc_table: &XcTableGen) {
+        trace!("releasing xc_list");
+        _release_xc_list(xc_table, std::mem::take(self.get_xc_list()));
+    }
+}
+
+/// Drops the XCs an entry no longer forwards through, removing those no
+/// other entry uses from the XC table.
+fn _release_xc_list(xc_table: &XcTableGen, xc_list: XcList) {
+    for xc in xc_list.into_iter() {
+        let guard = xc.lock();
+        let mut xc = guard.borrow_mut();
+        xc.users -= 1;
+        if xc.users == 0 {
+            xc_table.remove(&xc.xc_key);
         }
     }
-    fn iterate_xc_list(&mut self, on_xc: &Fn(&XcEntryWrapped) -> bool) -> Option<XcEntryWrapped> {
-        trace!("iterating xc_list");
-        let xc_list = self.get_xc_list();
-        let len: usize = xc_list.len();
-        let mut i = 0;
-        while i < len {
-            if !on_xc(&xc_list[i]) {
-                return Some(Arc::clone(&xc_list[i]));
+}
+
+/// XC and NHLFE index of the first XC of the list.
+fn xc_list_ixs(xc_list: &[XcEntryWrapped]) -> (u32, u32) {
+    match xc_list.first() {
+        Some(xc) => {
+            let xc_key = read_val!(xc).xc_key;
+            (xc_key.xc_ix, xc_key.nhlfe_ix)
+        }
+        None => (0, 0),
+    }
+}
+
+/// Next hop, out interface and label stack of the first NHLFE of the list.
+fn xc_list_nhlfe(xc_list: &[XcEntryWrapped]) -> (IpAddr, u32, Vec<u32>) {
+    for xc in xc_list.iter() {
+        if let Some(nhlfe) = &read_val!(xc).nhlfe {
+            match &read_val!(nhlfe).nhlfe_key {
+                NhlfeKey::IP(nhlfe_k_ip) => {
+                    return (
+                        nhlfe_k_ip.next_hop,
+                        nhlfe_k_ip.out_iface,
+                        nhlfe_k_ip.out_labels.clone(),
+                    );
+                }
             }
-            i += 1;
         }
-        None
     }
+    (IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0, Vec::new())
 }
 
 pub struct FtnEntry {
     fec: IpAddr,
     ftn_ix: u32,
+    vrf_id: u32,
     xc_list: XcList,
     is_dependent: bool,
     dependent_ftn_up_list: FtnList,
     dependent_ilm_up_list: IlmList,
     state: bool,
+    notifier: Notifier,
 }
 
 impl FtnEntry {
-    fn new(fec: IpAddr, idx: u32, dependent: bool) -> FtnEntry {
+    fn new(fec: IpAddr, idx: u32, dependent: bool, vrf: &LfibVrf) -> FtnEntry {
         FtnEntry {
-            fec: fec,
+            fec,
             ftn_ix: idx,
+            vrf_id: vrf.vrf_id,
             xc_list: Vec::new(),
             is_dependent: dependent,
             dependent_ftn_up_list: Vec::new(),
             dependent_ilm_up_list: Vec::new(),
             state: false,
+            notifier: vrf.notifier.clone(),
+        }
+    }
+    fn ftn(&self, vrf_id: u32) -> Ftn {
+        let (next_hop, out_ifindex, out_labels) = xc_list_nhlfe(&self.xc_list);
+        Ftn {
+            fec: self.fec,
+            ftn_ix: self.ftn_ix,
+            next_hop,
+            out_ifindex,
+            out_labels,
+            vrf_id,
         }
     }
     fn up(&mut self) {
-        self.state = true;
+        trace!(
+            "FtnEntry::up fec {} ftn_ix {} dependent {}",
+            self.fec,
+            self.ftn_ix,
+            self.is_dependent
+        );
+        if !self.state {
+            self.state = true;
+            self.notify(EventOp::Up);
+        }
     }
-    fn down(&mut self) {
-        self.state = false;
-        self.clean_ftn_up_list();
-        self.clean_ilm_up_list();
+    fn down(&mut self, ftn_table: &FtnTableGen) {
+        if self.state {
+            self.state = false;
+            self.notify(EventOp::Down);
+        }
+        self.clean_ftn_up_list(ftn_table);
+        self.clean_ilm_up_list(ftn_table);
+    }
+    fn notify(&self, op: EventOp) {
+        if self.notifier.wants(EVENT_FTN) {
+            self.notifier.publish(Event::Ftn {
+                op,
+                ftn: self.ftn(self.vrf_id),
+            });
+        }
+    }
+    fn dump(&self) -> Value {
+        let ftn = self.ftn(self.vrf_id);
+        let (xc_ix, nhlfe_ix) = xc_list_ixs(&self.xc_list);
+        json!({
+            "ftn_ix": ftn.ftn_ix,
+            "next_hop": ftn.next_hop.to_string(),
+            "out_ifindex": ftn.out_ifindex,
+            "out_labels": ftn.out_labels,
+            "xc_ix": xc_ix,
+            "nhlfe_ix": nhlfe_ix,
+            "dependent": self.is_dependent,
+            "up": self.state,
+            "dependent_ftns_up": ftn_refs(&self.dependent_ftn_up_list),
+            "dependent_ilms_up": ilm_refs(&self.dependent_ilm_up_list),
+        })
     }
     fn add_to_ftn_up_list(&mut self, dep_ftn: FtnEntryWrapped) {
         write_val!(dep_ftn).up();
@@ -627,30 +1256,16 @@ impl FtnEntry {
         write_val!(dep_ilm).up();
         self.dependent_ilm_up_list.push(dep_ilm);
     }
-    fn clean_ftn_up_list(&mut self) {
-        loop {
-            match self.dependent_ftn_up_list.pop() {
-                Some(dep_ftn) => {
-                    write_val!(dep_ftn).down();
-                    FtnTableGen::process_ftn_dependent_entry(&dep_ftn, &self.fec);
-                }
-                None => {
-                    break;
-                }
-            }
+    fn clean_ftn_up_list(&mut self, ftn_table: &FtnTableGen) {
+        while let Some(dep_ftn) = self.dependent_ftn_up_list.pop() {
+            write_val!(dep_ftn).down(ftn_table);
+            ftn_table.process_ftn_dependent_entry(&dep_ftn, &self.fec);
         }
     }
-    fn clean_ilm_up_list(&mut self) {
-        loop {
-            match self.dependent_ilm_up_list.pop() {
-                Some(dep_ilm) => {
-                    write_val!(dep_ilm).down();
-                    FtnTableGen::process_ilm_dependent_entry(dep_ilm, &self.fec);
-                }
-                None => {
-                    break;
-                }
-            }
+    fn clean_ilm_up_list(&mut self, ftn_table: &FtnTableGen) {
+        while let Some(dep_ilm) = self.dependent_ilm_up_list.pop() {
+            write_val!(dep_ilm).down();
+            ftn_table.process_ilm_dependent_entry(dep_ilm, &self.fec);
         }
     }
 }
@@ -661,10 +1276,11 @@ impl MplsEntry for FtnEntry {
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct NhlfeKeyIp {
     next_hop: IpAddr,
-    out_label: u32,
+    /// Top of the stack first.
+    out_labels: Vec<u32>,
     out_iface: u32,
     trunk_id: u16,
     lsp_id: u16,
@@ -672,21 +1288,39 @@ pub struct NhlfeKeyIp {
     egress: IpAddr,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub enum NhlfeKey {
     IP(NhlfeKeyIp),
 }
 
+impl NhlfeKey {
+    fn dump(&self) -> Value {
+        match self {
+            NhlfeKey::IP(nhlfe_k_ip) => json!({
+                "next_hop": nhlfe_k_ip.next_hop.to_string(),
+                "out_labels": nhlfe_k_ip.out_labels,
+                "out_iface": nhlfe_k_ip.out_iface,
+                "trunk_id": nhlfe_k_ip.trunk_id,
+                "lsp_id": nhlfe_k_ip.lsp_id,
+                "ingress": nhlfe_k_ip.ingress.to_string(),
+                "egress": nhlfe_k_ip.egress.to_string(),
+            }),
+        }
+    }
+}
+
 pub struct NhlfeEntry {
     nhlfe_key: NhlfeKey,
     nhlfe_ix: u32,
     xc_ix: u32,
+    nhlfe_id_table: IdTable,
 }
 
 impl NhlfeEntry {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         next_hop: IpAddr,
-        out_label: u32,
+        out_labels: Vec<u32>,
         out_iface: u32,
         trunk_id: u16,
         lsp_id: u16,
@@ -694,28 +1328,44 @@ impl NhlfeEntry {
         egress: IpAddr,
         _xc_ix: u32,
         _nhlfe_ix: u32,
+        nhlfe_id_table: &IdTable,
     ) -> NhlfeEntry {
         let nhlfe_k: NhlfeKey = NhlfeKey::IP(NhlfeKeyIp {
-            next_hop: next_hop,
-            out_label: out_label,
-            out_iface: out_iface,
-            trunk_id: trunk_id,
-            lsp_id: lsp_id,
-            ingress: ingress,
-            egress: egress,
+            next_hop,
+            out_labels,
+            out_iface,
+            trunk_id,
+            lsp_id,
+            ingress,
+            egress,
         });
         NhlfeEntry {
             nhlfe_key: nhlfe_k,
             xc_ix: _xc_ix,
             nhlfe_ix: _nhlfe_ix,
+            nhlfe_id_table: Arc::clone(nhlfe_id_table),
         }
     }
+    fn dump(&self) -> Value {
+        json!({
+            "nhlfe_ix": self.nhlfe_ix,
+            "xc_ix": self.xc_ix,
+            "key": self.nhlfe_key.dump(),
+        })
+    }
 }
 
 impl Drop for NhlfeEntry {
     fn drop(&mut self) {
         trace!("Drop for NhlfeEntry");
-        write_val!(NHLFE_ID_TABLE).put_free(self.nhlfe_ix as usize);
+        if let Err(err) = write_val!(self.nhlfe_id_table).free(self.nhlfe_ix) {
+            error!(
+                "NHLFE entry {} frees its index: {:?} {}",
+                self.nhlfe_ix,
+                err,
+                last_error_detail()
+            );
+        }
     }
 }
 
@@ -734,15 +1384,33 @@ pub struct XcKey {
 pub struct XcEntry {
     xc_key: XcKey,
     nhlfe: Option<NhlfeEntryWrapped>,
+    /// FTN and ILM entries forwarding through the XC.
+    users: u32,
+    xc_id_table: IdTable,
+    nhlfe_table4: NhlfeTable,
+    nhlfe_table6: NhlfeTable,
 }
 
 impl XcEntry {
-    fn new(key: &XcKey, entry: Option<NhlfeEntryWrapped>) -> XcEntry {
+    fn new(key: &XcKey, entry: Option<NhlfeEntryWrapped>, lfib: &Lfib) -> XcEntry {
         XcEntry {
             xc_key: *key,
             nhlfe: entry,
+            users: 0,
+            xc_id_table: Arc::clone(&lfib.xc_id_table),
+            nhlfe_table4: Arc::clone(&lfib.nhlfe_table4),
+            nhlfe_table6: Arc::clone(&lfib.nhlfe_table6),
         }
     }
+    fn dump(&self) -> Value {
+        json!({
+            "xc_ix": self.xc_key.xc_ix,
+            "nhlfe_ix": self.xc_key.nhlfe_ix,
+            "in_iface": self.xc_key.in_iface,
+            "label": self.xc_key.gen_label.label,
+            "nhlfe": self.nhlfe.as_ref().map(|nhlfe| read_val!(nhlfe).nhlfe_key.dump()),
+        })
+    }
     fn set_nhlfe(&mut self, entry: Option<NhlfeEntryWrapped>) {
         trace!("setting NHLFE reference for XcEntry");
         self.nhlfe = entry;
@@ -753,10 +1421,10 @@ impl XcEntry {
             Some(n) => match &read_val!(n).nhlfe_key {
                 NhlfeKey::IP(nhlfe_ip) => match nhlfe_ip.next_hop {
                     IpAddr::V4(_) => {
-                        NhlfeTableGen::V4(&NHLFE_TABLE4).remove(&read_val!(n).nhlfe_key);
+                        NhlfeTableGen::V4(&self.nhlfe_table4).remove(&read_val!(n).nhlfe_key);
                     }
                     IpAddr::V6(_) => {
-                        NhlfeTableGen::V6(&NHLFE_TABLE6).remove(&read_val!(n).nhlfe_key);
+                        NhlfeTableGen::V6(&self.nhlfe_table6).remove(&read_val!(n).nhlfe_key);
                     }
                 },
             },
@@ -770,28 +1438,33 @@ impl XcEntry {
 impl Drop for XcEntry {
     fn drop(&mut self) {
         trace!("Drop for XcEntry");
-        write_val!(XC_ID_TABLE).put_free(self.xc_key.xc_ix as usize);
+        if let Err(err) = write_val!(self.xc_id_table).free(self.xc_key.xc_ix) {
+            error!(
+                "XC entry {} frees its index: {:?} {}",
+                self.xc_key.xc_ix,
+                err,
+                last_error_detail()
+            );
+        }
         self.cleanup();
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+/// Ordered by in_label, then in_iface.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
 pub struct IlmKeyPkt {
-    in_iface: u32,
     in_label: u32,
+    in_iface: u32,
 }
 
 impl IlmKeyPkt {
     fn new(in_label: u32, in_iface: u32) -> IlmKeyPkt {
-        IlmKeyPkt {
-            in_iface: in_iface,
-            in_label: in_label,
-        }
+        IlmKeyPkt { in_iface, in_label }
     }
 }
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
 pub enum IlmKey {
-    PKT(IlmKeyPkt),
+    Pkt(IlmKeyPkt),
 }
 
 pub struct IlmEntry {
@@ -800,23 +1473,68 @@ pub struct IlmEntry {
     xc_list: XcList,
     owner: u32,
     state: bool,
+    notifier: Notifier,
 }
 
 impl IlmEntry {
-    fn new(ilm_key: IlmKey, ilm_ix: u32, owner: u32) -> IlmEntry {
+    fn new(ilm_key: IlmKey, ilm_ix: u32, owner: u32, notifier: &Notifier) -> IlmEntry {
         IlmEntry {
-            ilm_key: ilm_key,
-            ilm_ix: ilm_ix,
+            ilm_key,
+            ilm_ix,
             xc_list: Vec::new(),
-            owner: owner,
+            owner,
             state: false,
+            notifier: notifier.clone(),
         }
     }
-    fn up(&mut self) {
-        self.state = true;
+    fn ilm(&self) -> Ilm {
+        let (next_hop, out_ifindex, out_labels) = xc_list_nhlfe(&self.xc_list);
+        let IlmKey::Pkt(ilm_key_pkt) = self.ilm_key;
+        Ilm {
+            in_label: ilm_key_pkt.in_label,
+            in_iface: ilm_key_pkt.in_iface,
+            next_hop,
+            out_ifindex,
+            out_label: out_labels.first().copied().unwrap_or(0),
+            ilm_ix: self.ilm_ix,
+            owner: self.owner,
+        }
+    }
+    fn dump(&self) -> Value {
+        let ilm = self.ilm();
+        let (xc_ix, nhlfe_ix) = xc_list_ixs(&self.xc_list);
+        json!({
+            "ilm_ix": ilm.ilm_ix,
+            "owner": ilm.owner,
+            "next_hop": ilm.next_hop.to_string(),
+            "out_ifindex": ilm.out_ifindex,
+            "out_label": ilm.out_label,
+            "xc_ix": xc_ix,
+            "nhlfe_ix": nhlfe_ix,
+            "up": self.state,
+        })
+    }
+    fn up(&mut self) {
+        trace!("IlmEntry::up {:?} ilm_ix {}", self.ilm_key, self.ilm_ix);
+        if !self.state {
+            self.state = true;
+            self.notify(EventOp::Up);
+        }
     }
     fn down(&mut self) {
-        self.state = false;
+        trace!("IlmEntry::down {:?} ilm_ix {}", self.ilm_key, self.ilm_ix);
+        if self.state {
+            self.state = false;
+            self.notify(EventOp::Down);
+        }
+    }
+    fn notify(&self, op: EventOp) {
+        if self.notifier.wants(EVENT_ILM) {
+            self.notifier.publish(Event::Ilm {
+                op,
+                ilm: self.ilm(),
+            });
+        }
     }
 }
 
@@ -826,38 +1544,36 @@ impl MplsEntry for IlmEntry {
     }
 }
 
-pub enum IlmTableGen {
-    ILM(&'static ILM_TABLE),
+pub enum IlmTableGen<'a> {
+    Ilm(&'a IlmTable),
 }
 
-impl IlmTableGen {
+impl<'a> IlmTableGen<'a> {
+    #[inline]
+    fn table(&self) -> &IlmTable {
+        match self {
+            IlmTableGen::Ilm(table) => table,
+        }
+    }
     fn lookup_list(
         &self,
         ilm_list: &IlmList,
-        compare: &Fn(&IlmEntryWrapped) -> bool,
+        compare: &dyn Fn(&IlmEntryWrapped) -> bool,
     ) -> Option<IlmEntryWrapped> {
         trace!("IlmTableGen::lookup_list");
-        let len: usize = ilm_list.len();
-        let mut i = 0;
-        while i < len {
-            if compare(&ilm_list[i]) {
-                return Some(Arc::clone(&ilm_list[i]));
-            }
-            i += 1;
-        }
-        None
+        ilm_list.iter().find(|e| compare(e)).map(Arc::clone)
     }
     fn lookup_by_owner(&self, ilm_key: &IlmKey, owner: u32) -> Option<IlmEntryWrapped> {
-        if read_val!(&ILM_TABLE).contains_key(ilm_key) {
-            return self.lookup_list(&read_val!(&ILM_TABLE)[ilm_key], &|ie| {
+        if read_val!(self.table()).contains_key(ilm_key) {
+            return self.lookup_list(&read_val!(self.table())[ilm_key], &|ie| {
                 owner == read_val!(ie).owner
             });
         }
         None
     }
     fn lookup_by_ix(&self, ilm_key: &IlmKey, ilm_ix: u32) -> Option<IlmEntryWrapped> {
-        if read_val!(&ILM_TABLE).contains_key(ilm_key) {
-            return self.lookup_list(&read_val!(&ILM_TABLE)[ilm_key], &|ie| {
+        if read_val!(self.table()).contains_key(ilm_key) {
+            return self.lookup_list(&read_val!(self.table())[ilm_key], &|ie| {
                 ilm_ix == read_val!(ie).ilm_ix
             });
         }
@@ -865,13 +1581,13 @@ impl IlmTableGen {
     }
     fn insert(&mut self, ilm_key: IlmKey, ilm_entry: IlmEntryWrapped) {
         trace!("IlmTableGen::insert");
-        if !read_val!(&ILM_TABLE).contains_key(&ilm_key) {
+        if !read_val!(self.table()).contains_key(&ilm_key) {
             trace!("creating new entry");
             let ilm_list: IlmList = Vec::new();
-            write_val!(&ILM_TABLE).insert(ilm_key, ilm_list);
+            write_val!(self.table()).insert(ilm_key, ilm_list);
         }
         trace!("insetion to list");
-        match write_val!(&ILM_TABLE).get_mut(&ilm_key) {
+        match write_val!(self.table()).get_mut(&ilm_key) {
             Some(ll) => {
                 insert_list(ll, ilm_entry);
             }
@@ -880,89 +1596,114 @@ impl IlmTableGen {
             }
         }
     }
-    fn remove(&mut self, ilm_key: &IlmKey) {
-        write_val!(&ILM_TABLE).remove(ilm_key);
+    /// Takes entry `ilm_ix` out of the list of `ilm_key`, and the key out
+    /// of the table with the last entry.
+    fn remove(&mut self, ilm_key: &IlmKey, ilm_ix: u32) -> Option<IlmEntryWrapped> {
+        trace!("IlmTableGen::remove");
+        let guard = self.table().lock();
+        let mut ilm_lists = guard.borrow_mut();
+        let ilm_list = ilm_lists.get_mut(ilm_key)?;
+        let i = ilm_list
+            .iter()
+            .position(|ilm| read_val!(ilm).ilm_ix == ilm_ix)?;
+        let removed_ilm = ilm_list.remove(i);
+        if list_is_empty(ilm_list) {
+            ilm_lists.remove(ilm_key);
+        }
+        Some(removed_ilm)
     }
 }
 
-struct FtnAddDataInt {
-    fec: IpAddr,
-    next_hop: IpAddr,
-    out_ifindex: u32,
-    out_label: u32,
-    ftn_ix: u32,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ftn {
+    pub fec: IpAddr,
+    pub ftn_ix: u32,
+    pub next_hop: IpAddr,
+    pub out_ifindex: u32,
+    /// Labels to push, top of the stack first.
+    pub out_labels: Vec<u32>,
+    pub vrf_id: u32,
 }
 
-impl FtnAddDataInt {
-    pub fn new(
-        _fec: IpAddr,
-        _next_hop: IpAddr,
-        _out_ifindex: u32,
-        _out_label: u32,
-        _ftn_ix: u32,
-    ) -> FtnAddDataInt {
-        FtnAddDataInt {
-            fec: _fec,
-            next_hop: _next_hop,
-            out_ifindex: _out_ifindex,
-            out_label: _out_label,
-            ftn_ix: _ftn_ix,
-        }
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ilm {
+    pub in_label: u32,
+    pub in_iface: u32,
+    pub next_hop: IpAddr,
+    pub out_ifindex: u32,
+    pub out_label: u32,
+    pub ilm_ix: u32,
+    pub owner: u32,
 }
 
-unsafe fn convert_ftn_add_to_internal(ftn_add_data: *mut FtnAddData) -> Result<FtnAddDataInt, i32> {
-    let ftn_add_int: FtnAddDataInt;
-    let fec: IpAddr;
-    let next_hop: IpAddr;
+/// Copies the entry of the caller, whose label stack may hold at most
+/// `max_depth` labels; the count is checked before the stack is read.
+pub(crate) unsafe fn convert_ftn_add_to_internal(
+    ftn_add_data: *mut FtnAddData,
+    max_depth: u32,
+) -> Result<Ftn, DsError> {
+    if ftn_add_data.is_null() || (*ftn_add_data).out_label.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("ftn_add data or out_label is null"),
+        ));
+    }
+    let out_label_number = (*ftn_add_data).out_label_number;
+    if out_label_number == 0 || out_label_number > max_depth {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            format!(
+                "FTN entry has {} labels, expected 1 to {}",
+                out_label_number, max_depth
+            ),
+        ));
+    }
+    let out_labels = std::slice::from_raw_parts(
+        (*ftn_add_data).out_label,
+        (*ftn_add_data).out_label_number as usize,
+    );
+    Ok(Ftn {
+        fec: copy_ip_addr_from_user(&(*ftn_add_data).fec),
+        ftn_ix: (*ftn_add_data).ftn_ix,
+        next_hop: copy_ip_addr_from_user(&(*ftn_add_data).next_hop),
+        out_ifindex: (*ftn_add_data).out_ifindex,
+        out_labels: out_labels.to_vec(),
+        vrf_id: (*ftn_add_data).vrf_id,
+    })
+}
 
-    let mut addr_ptr: *mut u8 = (*ftn_add_data).fec.addr;
-    if (*ftn_add_data).fec.family == 1 {
-        fec = copy_ip_addr_v4_from_user(addr_ptr);
+/// `old_ix` when restoring an entry with the index it had, a new index
+/// otherwise.
+fn take_id(id_table: &IdTable, old_ix: u32) -> Result<u32, DsError> {
+    let guard = id_table.lock();
+    let mut ids = guard.borrow_mut();
+    if old_ix > 0 {
+        ids.reserve(old_ix).map(|()| old_ix)
     } else {
-        fec = copy_ip_addr_v6_from_user(addr_ptr as *mut u16);
+        ids.allocate()
     }
-    addr_ptr = (*ftn_add_data).next_hop.addr;
-    if (*ftn_add_data).next_hop.family == 1 {
-        next_hop = copy_ip_addr_v4_from_user(addr_ptr);
-    } else {
-        next_hop = copy_ip_addr_v6_from_user(addr_ptr as *mut u16);
-    }
-    ftn_add_int = FtnAddDataInt::new(
-        fec,
-        next_hop,
-        (*ftn_add_data).out_ifindex,
-        *(*ftn_add_data).out_label,
-        (*ftn_add_data).ftn_ix,
-    );
-    Ok(ftn_add_int)
 }
 
 fn _create_nhlfe_and_xc(
+    lfib: &Lfib,
     nhlfe_k: NhlfeKey,
     old_xc_ix: u32,
     old_nhlfe_ix: u32,
-) -> Option<XcEntryWrapped> {
+) -> Result<XcEntryWrapped, DsError> {
     let xc_entry: XcEntryWrapped;
-    let nhlfe: Option<NhlfeEntryWrapped>;
+    let xc_table = XcTableGen::XC(&lfib.xc_table);
     let create_xc_entry = |xc_key: XcKey| {
         let new_xc_entry: XcEntryWrapped = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-            XcEntry::new(&xc_key, None),
+            XcEntry::new(&xc_key, None, lfib),
         ))));
         trace!("insert new XcEntry to XC_TABLE");
-        XcTableGen::XC(&XC_TABLE).insert(&xc_key, Arc::clone(&new_xc_entry));
+        xc_table.insert(&xc_key, Arc::clone(&new_xc_entry));
         new_xc_entry
     };
 
-    match &nhlfe_k {
-        NhlfeKey::IP(nhlfe_k_ip) => {
-            if nhlfe_k_ip.next_hop.is_ipv4() {
-                nhlfe = NhlfeTableGen::V4(&NHLFE_TABLE4).lookup(&nhlfe_k);
-            } else {
-                nhlfe = NhlfeTableGen::V6(&NHLFE_TABLE6).lookup(&nhlfe_k);
-            }
-        }
-    }
+    let nhlfe: Option<NhlfeEntryWrapped> = match &nhlfe_k {
+        NhlfeKey::IP(nhlfe_k_ip) => lfib.nhlfe_table(&nhlfe_k_ip.next_hop).lookup(&nhlfe_k),
+    };
 
     let xc_key: XcKey;
     match nhlfe {
@@ -974,7 +1715,7 @@ fn _create_nhlfe_and_xc(
                 xc_ix: read_val!(nhlfe_exists).xc_ix,
                 nhlfe_ix: read_val!(nhlfe_exists).nhlfe_ix,
             };
-            let xc_entry_o = XcTableGen::XC(&XC_TABLE).lookup(&xc_key);
+            let xc_entry_o = xc_table.lookup(&xc_key);
             match xc_entry_o {
                 Some(o) => {
                     trace!("found XC for NHLFE");
@@ -986,507 +1727,819 @@ fn _create_nhlfe_and_xc(
             }
         }
         None => {
-            let xc_ix;
-            let nhlfe_ix;
-            if old_xc_ix > 0 {
-                xc_ix = old_xc_ix;
-            } else {
-                xc_ix = write_val!(XC_ID_TABLE).get_free();
-            }
-            if old_nhlfe_ix > 0 {
-                nhlfe_ix = old_nhlfe_ix;
-            } else {
-                nhlfe_ix = write_val!(NHLFE_ID_TABLE).get_free();
-            }
-            if xc_ix == 0 {
-                trace!("cannot allocate xc ix");
-                write_val!(NHLFE_ID_TABLE).put_free(nhlfe_ix as usize);
-                return None;
-            }
-            if nhlfe_ix == 0 {
-                trace!("cannot allocate nhlfe_ix");
-                write_val!(XC_ID_TABLE).put_free(xc_ix as usize);
-                return None;
-            }
+            let xc_ix = take_id(&lfib.xc_id_table, old_xc_ix)?;
+            let nhlfe_ix = match take_id(&lfib.nhlfe_id_table, old_nhlfe_ix) {
+                Ok(nhlfe_ix) => nhlfe_ix,
+                Err(err) => {
+                    let _ = write_val!(lfib.xc_id_table).free(xc_ix);
+                    return Err(err);
+                }
+            };
             xc_key = XcKey {
                 in_iface: 0,
                 gen_label: GenLabel { label: 0 },
-                xc_ix: xc_ix as u32,
-                nhlfe_ix: nhlfe_ix as u32,
+                xc_ix,
+                nhlfe_ix,
             };
             xc_entry = create_xc_entry(xc_key);
-            let nhlfe_entry: NhlfeEntryWrapped;
-            match &nhlfe_k {
-                NhlfeKey::IP(nhlfe_k_ip) => {
-                    nhlfe_entry = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-                        NhlfeEntry::new(
-                            nhlfe_k_ip.next_hop,
-                            nhlfe_k_ip.out_label,
-                            nhlfe_k_ip.out_iface,
-                            0,
-                            0,
-                            nhlfe_k_ip.ingress,
-                            nhlfe_k_ip.egress,
-                            xc_ix,
-                            nhlfe_ix,
-                        ),
-                    ))));
-                }
-            }
+            let nhlfe_entry: NhlfeEntryWrapped = match &nhlfe_k {
+                NhlfeKey::IP(nhlfe_k_ip) => Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
+                    NhlfeEntry::new(
+                        nhlfe_k_ip.next_hop,
+                        nhlfe_k_ip.out_labels.clone(),
+                        nhlfe_k_ip.out_iface,
+                        0,
+                        0,
+                        nhlfe_k_ip.ingress,
+                        nhlfe_k_ip.egress,
+                        xc_ix,
+                        nhlfe_ix,
+                        &lfib.nhlfe_id_table,
+                    ),
+                )))),
+            };
             write_val!(xc_entry).set_nhlfe(Some(Arc::clone(&nhlfe_entry)));
             match &nhlfe_k {
                 NhlfeKey::IP(nhlfe_k_ip) => {
-                    if nhlfe_k_ip.next_hop.is_ipv4() {
-                        NhlfeTableGen::V4(&NHLFE_TABLE4).insert(nhlfe_k, nhlfe_entry);
-                    } else {
-                        NhlfeTableGen::V6(&NHLFE_TABLE6).insert(nhlfe_k, nhlfe_entry);
-                    }
+                    let nhlfe_table = lfib.nhlfe_table(&nhlfe_k_ip.next_hop);
+                    nhlfe_table.insert(nhlfe_k, nhlfe_entry);
                 }
             }
         }
     }
-    Some(xc_entry)
+    Ok(xc_entry)
 }
 
-fn _ftn_add(ftn_add_data_int: &FtnAddDataInt) -> i32 {
-    let xc_entry: XcEntryWrapped;
+fn _ftn_add(lfib: &Lfib, ftn: &Ftn, old_xc_ix: u32, old_nhlfe_ix: u32) -> Result<(), DsError> {
+    let vrf = lfib.vrf(ftn.vrf_id)?;
     let nhlfe_k: NhlfeKey = NhlfeKey::IP(NhlfeKeyIp {
-        next_hop: ftn_add_data_int.next_hop,
-        out_label: ftn_add_data_int.out_label,
-        out_iface: ftn_add_data_int.out_ifindex,
+        next_hop: ftn.next_hop,
+        out_labels: ftn.out_labels.clone(),
+        out_iface: ftn.out_ifindex,
         trunk_id: 0,
         lsp_id: 0,
         ingress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
         egress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
     });
 
-    match _create_nhlfe_and_xc(nhlfe_k, 0, 0) {
-        Some(ret_xc_entry) => {
-            xc_entry = ret_xc_entry;
-        }
-        None => {
-            trace!("cannot create XC entry!");
-            return -1;
-        }
-    }
-    let mut is_dependent: bool = false;
-    match ftn_add_data_int.next_hop {
-        IpAddr::V4(_) => match NhTableGen::V4(&NH_TABLE4).lookup(ftn_add_data_int.next_hop) {
-            Ok(_) => {}
-            Err(_) => {
-                is_dependent = true;
+    let xc_entry: XcEntryWrapped =
+        match _create_nhlfe_and_xc(lfib, nhlfe_k, old_xc_ix, old_nhlfe_ix) {
+            Ok(ret_xc_entry) => ret_xc_entry,
+            Err(err) => {
+                trace!("cannot create XC entry!");
+                return Err(err);
             }
-        },
-        IpAddr::V6(_) => match NhTableGen::V6(&NH_TABLE6).lookup(ftn_add_data_int.next_hop) {
-            Ok(_) => {}
-            Err(_) => {
-                is_dependent = true;
-            }
-        },
-    }
+        };
+    let is_dependent: bool = lfib.nh_table(&ftn.next_hop).lookup(ftn.next_hop).is_err();
     trace!("FTN entry is dependent {}", is_dependent);
     let ftn_entry: FtnEntryWrapped = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-        FtnEntry::new(ftn_add_data_int.fec, ftn_add_data_int.ftn_ix, is_dependent),
+        FtnEntry::new(ftn.fec, ftn.ftn_ix, is_dependent, &vrf),
     ))));
+    write_val!(ftn_entry).add_xc_entry(xc_entry);
+    vrf.ftn_table(&ftn.fec)
+        .insert(FtnKey::IP(FtnKeyIp::new(ftn.fec)), Arc::clone(&ftn_entry));
+    write_val!(ftn_entry).notify(EventOp::Add);
     if !is_dependent {
         write_val!(ftn_entry).up();
     }
-    write_val!(ftn_entry).add_xc_entry(xc_entry);
-    match ftn_add_data_int.fec {
-        IpAddr::V4(_) => {
-            FtnTableGen::V4(&FTN_TABLE4)
-                .insert(FtnKey::IP(FtnKeyIp::new(ftn_add_data_int.fec)), ftn_entry);
-        }
-        IpAddr::V6(_) => {
-            FtnTableGen::V6(&FTN_TABLE6)
-                .insert(FtnKey::IP(FtnKeyIp::new(ftn_add_data_int.fec)), ftn_entry);
-        }
-    }
-    0
+    Ok(())
 }
 
 #[no_mangle]
-pub extern "C" fn ftn_add(ftn_add_data: *mut FtnAddData) -> i32 {
-    let ftn_add_int: FtnAddDataInt;
+pub extern "C" fn ds_ftn_add(ds: *mut DataStore, ftn_add_data: *mut FtnAddData) -> DsError {
     trace!("ftn_add");
-    unsafe {
-        match convert_ftn_add_to_internal(ftn_add_data) {
-            Ok(ret_val) => {
-                ftn_add_int = ret_val;
-            }
-            Err(_) => {
-                return -1;
-            }
-        }
-    }
-    _ftn_add(&ftn_add_int)
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let max_depth = ds.lfib.max_label_depth();
+    let ftn = match unsafe { convert_ftn_add_to_internal(ftn_add_data, max_depth) } {
+        Ok(ftn) => ftn,
+        Err(err) => return err,
+    };
+    ds_result(ds.lfib.add_ftn(&ftn))
 }
 
-struct FtnDelDataInt {
-    fec: IpAddr,
-    ftn_ix: u32,
+#[no_mangle]
+pub extern "C" fn ftn_add(ftn_add_data: *mut FtnAddData) -> DsError {
+    ds_ftn_add(default_data_store(), ftn_add_data)
 }
 
-impl FtnDelDataInt {
-    pub fn new(_fec: IpAddr, _ftn_ix: u32) -> FtnDelDataInt {
-        FtnDelDataInt {
-            fec: _fec,
-            ftn_ix: _ftn_ix,
-        }
+/// Adds `count` FTN entries taking the table locks once. Every entry gets
+/// the code `ds_ftn_add` would return for it in `results`; the call returns
+/// `Ok` if all of them succeeded, the code of the first failed one
+/// otherwise.
+#[no_mangle]
+pub extern "C" fn ds_ftn_add_bulk(
+    ds: *mut DataStore,
+    entries: *mut FtnAddData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    trace!("ftn_add_bulk {}", count);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(entries, count, results) {
+        return err;
+    }
+    let max_depth = ds.lfib.max_label_depth();
+    unsafe {
+        let ftns = (0..count as usize)
+            .map(|i| convert_ftn_add_to_internal(entries.add(i), max_depth))
+            .collect();
+        let codes = run_bulk(ftns, |ftns| {
+            Ok(ds.lfib.add_ftns(ftns).into_iter().map(ds_result).collect())
+        });
+        copy_results_to_user(&codes, results)
     }
 }
 
-unsafe fn convert_ftn_del_to_internal(ftn_del_data: *mut FtnDelData) -> Result<FtnDelDataInt, i32> {
-    let ftn_del_int: FtnDelDataInt;
-    let fec: IpAddr;
-
-    let addr_ptr: *mut u8 = (*ftn_del_data).fec.addr;
-    if (*ftn_del_data).fec.family == 1 {
-        fec = copy_ip_addr_v4_from_user(addr_ptr);
-    } else {
-        fec = copy_ip_addr_v6_from_user(addr_ptr as *mut u16);
-    }
-    ftn_del_int = FtnDelDataInt::new(fec, (*ftn_del_data).ftn_ix);
-    Ok(ftn_del_int)
+#[no_mangle]
+pub extern "C" fn ftn_add_bulk(
+    entries: *mut FtnAddData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_ftn_add_bulk(default_data_store(), entries, count, results)
 }
 
-fn _ftn_del(ftn_del_data_int: &FtnDelDataInt) -> i32 {
-    match ftn_del_data_int.fec {
-        IpAddr::V4(_) => {
-            match FtnTableGen::V4(&FTN_TABLE4).lookup(
-                &FtnKey::IP(FtnKeyIp::new(ftn_del_data_int.fec)),
-                ftn_del_data_int.ftn_ix,
-            ) {
-                Some(e) => {
-                    write_val!(e).free_xc_list();
-                    FtnTableGen::V4(&FTN_TABLE4).remove(
-                        &FtnKey::IP(FtnKeyIp::new(ftn_del_data_int.fec)),
-                        ftn_del_data_int.ftn_ix,
-                    );
-                }
-                None => {
-                    trace!("cannot find FTN entry");
-                    return -1;
-                }
-            }
-        }
-        IpAddr::V6(_) => {
-            match FtnTableGen::V6(&FTN_TABLE6).lookup(
-                &FtnKey::IP(FtnKeyIp::new(ftn_del_data_int.fec)),
-                ftn_del_data_int.ftn_ix,
-            ) {
-                Some(e) => {
-                    write_val!(e).free_xc_list();
-                    FtnTableGen::V6(&FTN_TABLE6).remove(
-                        &FtnKey::IP(FtnKeyIp::new(ftn_del_data_int.fec)),
-                        ftn_del_data_int.ftn_ix,
-                    );
-                }
-                None => {
-                    trace!("cannot find FTN entry");
-                    return -1;
-                }
-            }
+fn _ftn_del(lfib: &Lfib, vrf_id: u32, fec: &IpAddr, ftn_ix: u32) -> Result<(), DsError> {
+    let vrf = lfib.vrf(vrf_id)?;
+    let ftn_table = vrf.ftn_table(fec);
+    match ftn_table.lookup(&FtnKey::IP(FtnKeyIp::new(*fec)), ftn_ix) {
+        Some(e) => {
+            read_val!(e).notify(EventOp::Delete);
+            write_val!(e).release_xc_list(&XcTableGen::XC(&lfib.xc_table));
+            ftn_table.remove(&FtnKey::IP(FtnKeyIp::new(*fec)), ftn_ix);
+            Ok(())
         }
+        None => Err(ds_error(
+            DsError::NotFound,
+            format!("cannot find FTN entry {} ftn_ix {}", fec, ftn_ix),
+        )),
     }
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn ftn_del(ftn_del_data: *mut FtnDelData) -> i32 {
-    let ftn_del_int: FtnDelDataInt;
+pub extern "C" fn ds_ftn_del(ds: *mut DataStore, ftn_del_data: *mut FtnDelData) -> DsError {
     trace!("ftn_del");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if ftn_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("ftn_del data is null"),
+        );
+    }
     unsafe {
-        match convert_ftn_del_to_internal(ftn_del_data) {
-            Ok(ret_val) => {
-                ftn_del_int = ret_val;
-            }
-            Err(_) => {
-                trace!("cannot convert ftn_del to internal");
-                return -1;
-            }
-        }
+        let fec: IpAddr = copy_ip_addr_from_user(&(*ftn_del_data).fec);
+        ds_result(
+            ds.lfib
+                .delete_ftn((*ftn_del_data).vrf_id, &fec, (*ftn_del_data).ftn_ix),
+        )
     }
-    _ftn_del(&ftn_del_int)
-}
-
-unsafe fn convert_ilm_add_to_internal(ilm_add_data: *mut IlmAddData) -> Result<IlmAddDataInt, i32> {
-    let ilm_add_int: IlmAddDataInt;
-    let next_hop: IpAddr;
-
-    let addr_ptr: *mut u8 = (*ilm_add_data).next_hop.addr;
-    if (*ilm_add_data).next_hop.family == 1 {
-        next_hop = copy_ip_addr_v4_from_user(addr_ptr);
-    } else {
-        next_hop = copy_ip_addr_v6_from_user(addr_ptr as *mut u16);
-    }
-    ilm_add_int = IlmAddDataInt::new(
-        (*ilm_add_data).in_label,
-        (*ilm_add_data).in_iface,
-        next_hop,
-        (*ilm_add_data).out_ifindex,
-        (*ilm_add_data).out_label,
-        (*ilm_add_data).ilm_ix,
-        (*ilm_add_data).owner,
-    );
-    Ok(ilm_add_int)
 }
 
-unsafe fn convert_ilm_del_to_internal(ilm_del_data: *mut IlmDelData) -> Result<IlmDelDataInt, i32> {
-    let ilm_del_int: IlmDelDataInt;
-
-    ilm_del_int = IlmDelDataInt::new(
-        (*ilm_del_data).in_label,
-        (*ilm_del_data).in_iface,
-        (*ilm_del_data).ilm_ix,
-        (*ilm_del_data).owner,
-    );
-    Ok(ilm_del_int)
+#[no_mangle]
+pub extern "C" fn ftn_del(ftn_del_data: *mut FtnDelData) -> DsError {
+    ds_ftn_del(default_data_store(), ftn_del_data)
 }
 
-pub struct IlmAddDataInt {
-    pub in_label: u32,
-    pub in_iface: u32,
-    pub next_hop: IpAddr,
-    pub out_ifindex: u32,
-    pub out_label: u32,
-    pub ilm_ix: u32,
-    pub owner: u32,
+/// Deletes `count` FTN entries, see `ds_ftn_add_bulk`.
+#[no_mangle]
+pub extern "C" fn ds_ftn_del_bulk(
+    ds: *mut DataStore,
+    entries: *mut FtnDelData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    trace!("ftn_del_bulk {}", count);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(entries, count, results) {
+        return err;
+    }
+    unsafe {
+        let ftns = (0..count as usize)
+            .map(|i| {
+                let entry = entries.add(i);
+                Ok(Ftn {
+                    fec: copy_ip_addr_from_user(&(*entry).fec),
+                    ftn_ix: (*entry).ftn_ix,
+                    next_hop: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                    out_ifindex: 0,
+                    out_labels: Vec::new(),
+                    vrf_id: (*entry).vrf_id,
+                })
+            })
+            .collect();
+        let codes = run_bulk(ftns, |ftns| {
+            Ok(ds
+                .lfib
+                .delete_ftns(ftns)
+                .into_iter()
+                .map(ds_result)
+                .collect())
+        });
+        copy_results_to_user(&codes, results)
+    }
 }
 
-impl IlmAddDataInt {
-    fn new(
-        in_label: u32,
-        in_iface: u32,
-        next_hop: IpAddr,
-        out_ifindex: u32,
-        out_label: u32,
-        ilm_ix: u32,
-        owner: u32,
-    ) -> IlmAddDataInt {
-        IlmAddDataInt {
-            in_label: in_label,
-            in_iface: in_iface,
-            next_hop: next_hop,
-            out_ifindex: out_ifindex,
-            out_label: out_label,
-            ilm_ix: ilm_ix,
-            owner: owner,
-        }
-    }
+#[no_mangle]
+pub extern "C" fn ftn_del_bulk(
+    entries: *mut FtnDelData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_ftn_del_bulk(default_data_store(), entries, count, results)
 }
 
-pub struct IlmDelDataInt {
-    pub in_label: u32,
-    pub in_iface: u32,
-    pub ilm_ix: u32,
-    pub owner: u32,
+pub(crate) unsafe fn convert_ilm_add_to_internal(
+    ilm_add_data: *mut IlmAddData,
+) -> Result<Ilm, DsError> {
+    if ilm_add_data.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("ilm_add data is null"),
+        ));
+    }
+    Ok(Ilm {
+        in_label: (*ilm_add_data).in_label,
+        in_iface: (*ilm_add_data).in_iface,
+        next_hop: copy_ip_addr_from_user(&(*ilm_add_data).next_hop),
+        out_ifindex: (*ilm_add_data).out_ifindex,
+        out_label: (*ilm_add_data).out_label,
+        ilm_ix: (*ilm_add_data).ilm_ix,
+        owner: (*ilm_add_data).owner,
+    })
 }
 
-impl IlmDelDataInt {
-    fn new(in_label: u32, in_iface: u32, ilm_ix: u32, owner: u32) -> IlmDelDataInt {
-        IlmDelDataInt {
-            in_label: in_label,
-            in_iface: in_iface,
-            ilm_ix: ilm_ix,
-            owner: owner,
-        }
-    }
+fn ilm_nhlfe_key(ilm: &Ilm) -> NhlfeKey {
+    NhlfeKey::IP(NhlfeKeyIp {
+        next_hop: ilm.next_hop,
+        out_labels: vec![ilm.out_label],
+        out_iface: ilm.out_ifindex,
+        trunk_id: 0,
+        lsp_id: 0,
+        ingress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        egress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+    })
 }
 
 fn _ilm_add(
-    ilm_add_int: &mut IlmAddDataInt,
+    lfib: &Lfib,
+    ilm: &Ilm,
     ilm_key: IlmKey,
     old_xc_ix: u32,
     old_nhlfe_ix: u32,
-) -> i32 {
+) -> Result<(), DsError> {
     trace!("_ilm_add");
-    let xc_entry: XcEntryWrapped;
-    let nhlfe_k: NhlfeKey = NhlfeKey::IP(NhlfeKeyIp {
-        next_hop: ilm_add_int.next_hop,
-        out_label: ilm_add_int.out_label,
-        out_iface: ilm_add_int.out_ifindex,
-        trunk_id: 0,
-        lsp_id: 0,
-        ingress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-        egress: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-    });
-
-    match _create_nhlfe_and_xc(nhlfe_k, old_xc_ix, old_nhlfe_ix) {
-        Some(ret_xc_entry) => {
-            xc_entry = ret_xc_entry;
-        }
-        None => {
-            trace!("Cannot create XC entry!");
-            return -1;
-        }
-    }
+    let xc_entry: XcEntryWrapped =
+        match _create_nhlfe_and_xc(lfib, ilm_nhlfe_key(ilm), old_xc_ix, old_nhlfe_ix) {
+            Ok(ret_xc_entry) => ret_xc_entry,
+            Err(err) => {
+                trace!("Cannot create XC entry!");
+                return Err(err);
+            }
+        };
     let ilm_entry: IlmEntryWrapped = Arc::new(ReentrantMutex::new(RefCell::new(Box::new(
-        IlmEntry::new(ilm_key, ilm_add_int.ilm_ix, ilm_add_int.owner),
+        IlmEntry::new(ilm_key, ilm.ilm_ix, ilm.owner, &lfib.notifier),
     ))));
     write_val!(ilm_entry).add_xc_entry(xc_entry);
-    IlmTableGen::ILM(&ILM_TABLE).insert(ilm_key, ilm_entry);
-    0
+    IlmTableGen::Ilm(&lfib.ilm_table).insert(ilm_key, Arc::clone(&ilm_entry));
+    write_val!(lfib.labels).take(ilm.owner, ilm.in_label);
+    read_val!(ilm_entry).notify(EventOp::Add);
+    Ok(())
 }
 
-fn _ilm_update(
-    ilm_add_int: &mut IlmAddDataInt,
-    existing_ilm: IlmEntryWrapped,
-    ilm_key: IlmKey,
-) -> i32 {
+/// Points the NHLFE of `xc` at `nhlfe_k`, keeping its index. The entry is
+/// in the table under the new key before it leaves the old one.
+fn _rekey_nhlfe(lfib: &Lfib, xc: &XcEntryWrapped, nhlfe_k: &NhlfeKey) -> bool {
+    let nhlfe = match &read_val!(xc).nhlfe {
+        Some(nhlfe) => Arc::clone(nhlfe),
+        None => return false,
+    };
+    let old_key = read_val!(nhlfe).nhlfe_key.clone();
+    let (NhlfeKey::IP(old_key_ip), NhlfeKey::IP(nhlfe_k_ip)) = (&old_key, nhlfe_k);
+    lfib.nhlfe_table(&nhlfe_k_ip.next_hop)
+        .insert(nhlfe_k.clone(), Arc::clone(&nhlfe));
+    write_val!(nhlfe).nhlfe_key = nhlfe_k.clone();
+    lfib.nhlfe_table(&old_key_ip.next_hop).remove(&old_key);
+    true
+}
+
+/// Moves the entry to the next hop, out interface and out label of `ilm`
+/// make-before-break: the new NHLFE and XC are in place before the old
+/// ones go. An XC only this entry uses keeps its xc_ix and nhlfe_ix.
+fn _ilm_update(lfib: &Lfib, ilm: &Ilm, existing_ilm: IlmEntryWrapped) -> Result<(), DsError> {
     trace!("_ilm_update");
-    let xc_entry: Option<XcEntryWrapped> = write_val!(existing_ilm).iterate_xc_list(&|xc| {
-        match &read_val!(xc).nhlfe {
-            Some(nhlfe) => match &read_val!(nhlfe).nhlfe_key {
-                NhlfeKey::IP(nhlfe_k_ip) => {
-                    if nhlfe_k_ip.next_hop == ilm_add_int.next_hop {
-                        trace!("next hops equal");
-                        return false;
-                    }
-                }
-            },
-            None => {
-                trace!("Expected to find nhlfe");
-            }
-        }
-        true
-    });
-    match xc_entry {
-        None => {
-            _ilm_add(ilm_add_int, ilm_key, 0, 0);
-        }
-        Some(existing_xc_entry) => {
-            trace!("updating ILM!");
-        }
-    }
-    0
+    let current = read_val!(existing_ilm).ilm();
+    if current.next_hop == ilm.next_hop
+        && current.out_ifindex == ilm.out_ifindex
+        && current.out_label == ilm.out_label
+    {
+        trace!("ILM entry unchanged");
+        return Ok(());
+    }
+    let nhlfe_k = ilm_nhlfe_key(ilm);
+    let guard = existing_ilm.lock();
+    let mut entry = guard.borrow_mut();
+    let exclusive = entry.xc_list.len() == 1 && read_val!(entry.xc_list[0]).users == 1;
+    let shared = match &nhlfe_k {
+        NhlfeKey::IP(nhlfe_k_ip) => lfib
+            .nhlfe_table(&nhlfe_k_ip.next_hop)
+            .lookup(&nhlfe_k)
+            .is_some(),
+    };
+    if !(exclusive && !shared && _rekey_nhlfe(lfib, &entry.xc_list[0], &nhlfe_k)) {
+        let xc_entry = _create_nhlfe_and_xc(lfib, nhlfe_k, 0, 0)?;
+        let old_xc_list = std::mem::take(&mut entry.xc_list);
+        entry.add_xc_entry(xc_entry);
+        _release_xc_list(&XcTableGen::XC(&lfib.xc_table), old_xc_list);
+    }
+    entry.notify(EventOp::Modify);
+    Ok(())
 }
 
-fn _ilm_add_update(ilm_add_int: &mut IlmAddDataInt) -> i32 {
-    let ilm_key = IlmKey::PKT(IlmKeyPkt::new(ilm_add_int.in_label, ilm_add_int.in_iface));
-    if ilm_add_int.ilm_ix > 0 {
-        match IlmTableGen::ILM(&ILM_TABLE).lookup_by_ix(&ilm_key, ilm_add_int.ilm_ix) {
-            Some(existing_ilm) => {
-                trace!("ILM entry already exists");
-                return _ilm_update(ilm_add_int, existing_ilm, ilm_key);
-            }
-            None => {
-                trace!("creating ILM entry");
-            }
-        }
-    } else {
-        match write_val!(ILM_ID_TABLE).get_free() {
-            0 => {
-                trace!("Cannot allocate ILM IX");
-                return -1;
-            }
-            allocated_ix => {
-                ilm_add_int.ilm_ix = allocated_ix;
-            }
-        }
-    }
-    match IlmTableGen::ILM(&ILM_TABLE).lookup_by_owner(&ilm_key, ilm_add_int.owner) {
-        Some(_) => {
+fn _ilm_add_update(lfib: &Lfib, ilm: &mut Ilm) -> Result<(), DsError> {
+    let ilm_key = IlmKey::Pkt(IlmKeyPkt::new(ilm.in_label, ilm.in_iface));
+    if ilm.ilm_ix > 0 {
+        if let Some(existing_ilm) =
+            IlmTableGen::Ilm(&lfib.ilm_table).lookup_by_ix(&ilm_key, ilm.ilm_ix)
+        {
             trace!("ILM entry already exists");
-            return -1;
-        }
-        None => {
-            trace!("creating ILM entry");
+            let owner = read_val!(existing_ilm).owner;
+            if owner != ilm.owner {
+                return Err(ds_error(
+                    DsError::AlreadyExists,
+                    format!(
+                        "ILM entry {:?} ilm_ix {} belongs to owner {}",
+                        ilm_key, ilm.ilm_ix, owner
+                    ),
+                ));
+            }
+            read_val!(lfib.labels).check(owner, ilm.in_label)?;
+            return _ilm_update(lfib, ilm, existing_ilm);
         }
     }
-    _ilm_add(ilm_add_int, ilm_key, 0, 0)
+    read_val!(lfib.labels).check(ilm.owner, ilm.in_label)?;
+    if IlmTableGen::Ilm(&lfib.ilm_table)
+        .lookup_by_owner(&ilm_key, ilm.owner)
+        .is_some()
+    {
+        return Err(ds_error(
+            DsError::AlreadyExists,
+            format!("ILM entry {:?} owner {} already exists", ilm_key, ilm.owner),
+        ));
+    }
+    trace!("creating ILM entry");
+    // an ilm_ix of the caller is taken as it is, or not at all
+    ilm.ilm_ix = take_id(&lfib.ilm_id_table, ilm.ilm_ix)?;
+    let added = _ilm_add(lfib, ilm, ilm_key, 0, 0);
+    if added.is_err() {
+        let _ = write_val!(lfib.ilm_id_table).free(ilm.ilm_ix);
+    }
+    added
 }
 
 #[no_mangle]
-pub extern "C" fn ilm_add(ilm_add_data: *mut IlmAddData) -> i32 {
-    let mut ilm_add_int: IlmAddDataInt;
+pub extern "C" fn ds_ilm_add(ds: *mut DataStore, ilm_add_data: *mut IlmAddData) -> DsError {
     trace!("ilm_add");
-    unsafe {
-        match convert_ilm_add_to_internal(ilm_add_data) {
-            Ok(ret_val) => {
-                ilm_add_int = ret_val;
-            }
-            Err(_) => {
-                trace!("cannot convert ilm_add to internal");
-                return -1;
-            }
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ilm = match unsafe { convert_ilm_add_to_internal(ilm_add_data) } {
+        Ok(ilm) => ilm,
+        Err(err) => {
+            trace!("cannot convert ilm_add to internal");
+            return err;
         }
+    };
+    ds_result(ds.lfib.add_ilm(&ilm))
+}
+
+#[no_mangle]
+pub extern "C" fn ilm_add(ilm_add_data: *mut IlmAddData) -> DsError {
+    ds_ilm_add(default_data_store(), ilm_add_data)
+}
+
+/// Adds `count` ILM entries, see `ds_ftn_add_bulk`.
+#[no_mangle]
+pub extern "C" fn ds_ilm_add_bulk(
+    ds: *mut DataStore,
+    entries: *mut IlmAddData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    trace!("ilm_add_bulk {}", count);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(entries, count, results) {
+        return err;
+    }
+    unsafe {
+        let ilms = (0..count as usize)
+            .map(|i| convert_ilm_add_to_internal(entries.add(i)))
+            .collect();
+        let codes = run_bulk(ilms, |ilms| {
+            Ok(ds.lfib.add_ilms(ilms).into_iter().map(ds_result).collect())
+        });
+        copy_results_to_user(&codes, results)
     }
-    _ilm_add_update(&mut ilm_add_int)
 }
 
-fn _ilm_del(ilm_del_int: &IlmDelDataInt) -> i32 {
-    let ilm_key = IlmKey::PKT(IlmKeyPkt::new(ilm_del_int.in_label, ilm_del_int.in_iface));
-    let ilm_entry;
-    if ilm_del_int.ilm_ix > 0 {
-        ilm_entry = IlmTableGen::ILM(&ILM_TABLE).lookup_by_ix(&ilm_key, ilm_del_int.ilm_ix);
+#[no_mangle]
+pub extern "C" fn ilm_add_bulk(
+    entries: *mut IlmAddData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_ilm_add_bulk(default_data_store(), entries, count, results)
+}
+
+fn _ilm_del(
+    lfib: &Lfib,
+    in_label: u32,
+    in_iface: u32,
+    ilm_ix: u32,
+    owner: u32,
+) -> Result<(), DsError> {
+    let ilm_key = IlmKey::Pkt(IlmKeyPkt::new(in_label, in_iface));
+    let ilm_entry = if ilm_ix > 0 {
+        IlmTableGen::Ilm(&lfib.ilm_table).lookup_by_ix(&ilm_key, ilm_ix)
     } else {
-        ilm_entry = IlmTableGen::ILM(&ILM_TABLE).lookup_by_owner(&ilm_key, ilm_del_int.owner);
-    }
+        IlmTableGen::Ilm(&lfib.ilm_table).lookup_by_owner(&ilm_key, owner)
+    };
     match ilm_entry {
-        None => {
-            trace!("ILM entry is not found");
-            return -1;
-        }
+        None => Err(ds_error(
+            DsError::NotFound,
+            format!("ILM entry {:?} is not found", ilm_key),
+        )),
         Some(existing_ilm) => {
+            read_val!(existing_ilm).notify(EventOp::Delete);
             trace!("_ilm_del: freeing xc list");
-            write_val!(existing_ilm).free_xc_list();
+            write_val!(existing_ilm).release_xc_list(&XcTableGen::XC(&lfib.xc_table));
             trace!("_ilm_del: removing ilm entry");
-            IlmTableGen::ILM(&ILM_TABLE).remove(&ilm_key);
+            let ilm_ix = read_val!(existing_ilm).ilm_ix;
+            IlmTableGen::Ilm(&lfib.ilm_table).remove(&ilm_key, ilm_ix);
+            write_val!(lfib.labels).put(read_val!(existing_ilm).owner, in_label);
+            if let Err(err) = write_val!(lfib.ilm_id_table).free(ilm_ix) {
+                error!(
+                    "ILM entry {} frees its index: {:?} {}",
+                    ilm_ix,
+                    err,
+                    last_error_detail()
+                );
+            }
+            Ok(())
         }
     }
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn ilm_del(ilm_del_data: *mut IlmDelData) -> i32 {
-    let ilm_del_int: IlmDelDataInt;
+pub extern "C" fn ds_ilm_del(ds: *mut DataStore, ilm_del_data: *mut IlmDelData) -> DsError {
     trace!("ilm_del");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if ilm_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("ilm_del data is null"),
+        );
+    }
     unsafe {
-        match convert_ilm_del_to_internal(ilm_del_data) {
-            Ok(ret_val) => {
-                ilm_del_int = ret_val;
-            }
-            Err(_) => {
-                trace!("cannot convert ilm_del to internal");
-                return -1;
-            }
-        }
+        ds_result(ds.lfib.delete_ilm(
+            (*ilm_del_data).in_label,
+            (*ilm_del_data).in_iface,
+            (*ilm_del_data).ilm_ix,
+            (*ilm_del_data).owner,
+        ))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ilm_del(ilm_del_data: *mut IlmDelData) -> DsError {
+    ds_ilm_del(default_data_store(), ilm_del_data)
+}
+
+/// Deletes `count` ILM entries, see `ds_ftn_add_bulk`.
+#[no_mangle]
+pub extern "C" fn ds_ilm_del_bulk(
+    ds: *mut DataStore,
+    entries: *mut IlmDelData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    trace!("ilm_del_bulk {}", count);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = bulk_args_are_valid(entries, count, results) {
+        return err;
+    }
+    unsafe {
+        let ilms = (0..count as usize)
+            .map(|i| {
+                let entry = entries.add(i);
+                Ok(Ilm {
+                    in_label: (*entry).in_label,
+                    in_iface: (*entry).in_iface,
+                    next_hop: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                    out_ifindex: 0,
+                    out_label: 0,
+                    ilm_ix: (*entry).ilm_ix,
+                    owner: (*entry).owner,
+                })
+            })
+            .collect();
+        let codes = run_bulk(ilms, |ilms| {
+            Ok(ds
+                .lfib
+                .delete_ilms(ilms)
+                .into_iter()
+                .map(ds_result)
+                .collect())
+        });
+        copy_results_to_user(&codes, results)
     }
-    _ilm_del(&ilm_del_int)
 }
 
 #[no_mangle]
-pub extern "C" fn nh_add_del(nh_add_del_data: *mut NhAddDel) -> i32 {
-    let addr: IpAddr;
-    let ifindex: u32;
-    let is_add: bool;
+pub extern "C" fn ilm_del_bulk(
+    entries: *mut IlmDelData,
+    count: u32,
+    results: *mut DsError,
+) -> DsError {
+    ds_ilm_del_bulk(default_data_store(), entries, count, results)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_nh_add_del(ds: *mut DataStore, nh_add_del_data: *mut NhAddDel) -> DsError {
     trace!("nh_add_del");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if nh_add_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("nh_add_del data is null"),
+        );
+    }
     unsafe {
-        let mut addr_ptr: *mut u8 = (*nh_add_del_data).addr.addr;
-        if (*nh_add_del_data).addr.family == 1 {
-            addr = copy_ip_addr_v4_from_user(addr_ptr);
+        let addr: IpAddr = copy_ip_addr_from_user(&(*nh_add_del_data).addr);
+        if (*nh_add_del_data).is_add {
+            ds.add_nh(&addr, (*nh_add_del_data).ifindex);
         } else {
-            addr = copy_ip_addr_v6_from_user(addr_ptr as *mut u16);
+            ds.delete_nh(&addr);
         }
-        ifindex = (*nh_add_del_data).ifindex;
-        is_add = (*nh_add_del_data).is_add;
     }
-    match addr {
-        IpAddr::V4(_) => {
-            if is_add {
-                NhTableGen::V4(&NH_TABLE4).insert(addr, ifindex);
-            } else {
-                NhTableGen::V4(&NH_TABLE4).remove(addr);
-            }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn nh_add_del(nh_add_del_data: *mut NhAddDel) -> DsError {
+    ds_nh_add_del(default_data_store(), nh_add_del_data)
+}
+
+/// Sets how many labels `ds_ftn_add` accepts in a stack, see
+/// `DEFAULT_MAX_LABEL_DEPTH`; at most `LABEL_DEPTH_LIMIT`.
+#[no_mangle]
+pub extern "C" fn ds_max_label_depth_set(ds: *mut DataStore, depth: u32) -> DsError {
+    trace!("max_label_depth_set {}", depth);
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.lfib.set_max_label_depth(depth))
+}
+
+#[no_mangle]
+pub extern "C" fn max_label_depth_set(depth: u32) -> DsError {
+    ds_max_label_depth_set(default_data_store(), depth)
+}
+
+unsafe fn copy_ftns_to_user(ftns: &[Ftn], entries: *mut FtnAddData, count: *mut u32) {
+    for (i, ftn) in ftns.iter().enumerate() {
+        let entry = entries.add(i);
+        copy_ip_addr_to_user((*entry).fec.addr, &ftn.fec);
+        copy_ip_addr_to_user((*entry).next_hop.addr, &ftn.next_hop);
+        (*entry).ftn_ix = ftn.ftn_ix;
+        (*entry).out_ifindex = ftn.out_ifindex;
+        if !(*entry).out_label.is_null() {
+            let room = ((*entry).out_label_number as usize).min(ftn.out_labels.len());
+            std::ptr::copy_nonoverlapping(ftn.out_labels.as_ptr(), (*entry).out_label, room);
         }
-        IpAddr::V6(_) => {
-            if is_add {
-                NhTableGen::V6(&NH_TABLE6).insert(addr, ifindex);
-            } else {
-                NhTableGen::V6(&NH_TABLE6).remove(addr);
-            }
+        (*entry).out_label_number = ftn.out_labels.len() as u32;
+        (*entry).vrf_id = ftn.vrf_id;
+    }
+    *count = ftns.len() as u32;
+}
+
+fn _ftn_walk(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    ipv4: bool,
+    after: Option<&(IpAddr, u32)>,
+    entries: *mut FtnAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = walk_args_are_valid(entries, count) {
+        return err;
+    }
+    match ds.lfib.ftns(vrf_id, ipv4, after, page_size as usize) {
+        Ok(ftns) => {
+            unsafe { copy_ftns_to_user(&ftns, entries, count) };
+            DsError::Ok
         }
+        Err(err) => err,
     }
-    0
+}
+
+/// First `page_size` FTN entries of the VRF in (fec, ftn_ix) order. The
+/// entries must have their address and out_label buffers set up, with
+/// `out_label_number` saying how many labels fit in the latter. It is set
+/// to the depth of the stack, of which only the labels that fit are copied.
+#[no_mangle]
+pub extern "C" fn ds_ftn_get_first(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    address_family: u32,
+    entries: *mut FtnAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    _ftn_walk(
+        ds,
+        vrf_id,
+        address_family == 1,
+        None,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn ftn_get_first(
+    address_family: u32,
+    entries: *mut FtnAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_ftn_get_first(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        address_family,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+/// Next `page_size` FTN entries after the fec and ftn_ix of `cursor`.
+/// `count` is 0 at the end of the table.
+#[no_mangle]
+pub extern "C" fn ds_ftn_get_next(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    cursor: *const FtnAddData,
+    entries: *mut FtnAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    if cursor.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("walk cursor is null"),
+        );
+    }
+    let after = unsafe { (copy_ip_addr_from_user(&(*cursor).fec), (*cursor).ftn_ix) };
+    _ftn_walk(
+        ds,
+        vrf_id,
+        after.0.is_ipv4(),
+        Some(&after),
+        entries,
+        page_size,
+        count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn ftn_get_next(
+    cursor: *const FtnAddData,
+    entries: *mut FtnAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_ftn_get_next(
+        default_data_store(),
+        DEFAULT_VRF_ID,
+        cursor,
+        entries,
+        page_size,
+        count,
+    )
+}
+
+unsafe fn copy_ilms_to_user(ilms: &[Ilm], entries: *mut IlmAddData, count: *mut u32) {
+    for (i, ilm) in ilms.iter().enumerate() {
+        let entry = entries.add(i);
+        (*entry).in_label = ilm.in_label;
+        (*entry).in_iface = ilm.in_iface;
+        copy_ip_addr_to_user((*entry).next_hop.addr, &ilm.next_hop);
+        (*entry).out_ifindex = ilm.out_ifindex;
+        (*entry).out_label = ilm.out_label;
+        (*entry).ilm_ix = ilm.ilm_ix;
+        (*entry).owner = ilm.owner;
+    }
+    *count = ilms.len() as u32;
+}
+
+fn _ilm_walk(
+    ds: *mut DataStore,
+    after: Option<&(u32, u32, u32)>,
+    entries: *mut IlmAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if let Err(err) = walk_args_are_valid(entries, count) {
+        return err;
+    }
+    let ilms = ds.lfib.ilms(after, page_size as usize);
+    unsafe { copy_ilms_to_user(&ilms, entries, count) };
+    DsError::Ok
+}
+
+/// First `page_size` ILM entries in (in_label, in_iface, ilm_ix) order. The
+/// entries must have their next_hop buffers set up.
+#[no_mangle]
+pub extern "C" fn ds_ilm_get_first(
+    ds: *mut DataStore,
+    entries: *mut IlmAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    _ilm_walk(ds, None, entries, page_size, count)
+}
+
+#[no_mangle]
+pub extern "C" fn ilm_get_first(
+    entries: *mut IlmAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_ilm_get_first(default_data_store(), entries, page_size, count)
+}
+
+/// Next `page_size` ILM entries after the in_label, in_iface and ilm_ix of
+/// `cursor`. `count` is 0 at the end of the table.
+#[no_mangle]
+pub extern "C" fn ds_ilm_get_next(
+    ds: *mut DataStore,
+    cursor: *const IlmAddData,
+    entries: *mut IlmAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    if cursor.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("walk cursor is null"),
+        );
+    }
+    let after = unsafe { ((*cursor).in_label, (*cursor).in_iface, (*cursor).ilm_ix) };
+    _ilm_walk(ds, Some(&after), entries, page_size, count)
+}
+
+#[no_mangle]
+pub extern "C" fn ilm_get_next(
+    cursor: *const IlmAddData,
+    entries: *mut IlmAddData,
+    page_size: u32,
+    count: *mut u32,
+) -> DsError {
+    ds_ilm_get_next(default_data_store(), cursor, entries, page_size, count)
 }
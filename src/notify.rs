@@ -0,0 +1,397 @@
+use crate::errors::*;
+use crate::external_types::*;
+use crate::utils::*;
+use crate::{data_store_from_handle, default_data_store, DataStore};
+use crate::{Ftn, Ilm, Peer, PrefixEntry, Route};
+use log::*;
+use parking_lot::{Mutex, ReentrantMutex};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+
+pub const EVENT_ROUTE: u32 = 1;
+pub const EVENT_PEER: u32 = 1 << 1;
+pub const EVENT_PREFIX: u32 = 1 << 2;
+pub const EVENT_FTN: u32 = 1 << 3;
+pub const EVENT_ILM: u32 = 1 << 4;
+pub const EVENT_ALL: u32 = EVENT_ROUTE | EVENT_PEER | EVENT_PREFIX | EVENT_FTN | EVENT_ILM;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOp {
+    Add = 0,
+    Modify = 1,
+    Delete = 2,
+    /// FTN or ILM became usable, e.g. its next hop resolved, or a peer
+    /// session got established.
+    Up = 3,
+    Down = 4,
+}
+
+/// Route events carry the installed (best) route, so a change of a path
+/// that does not win best-path selection is not reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Route {
+        vrf_id: u32,
+        op: EventOp,
+        route: Route,
+    },
+    Peer {
+        vrf_id: u32,
+        op: EventOp,
+        peer: Peer,
+    },
+    Prefix {
+        vrf_id: u32,
+        op: EventOp,
+        entry: PrefixEntry,
+    },
+    Ftn {
+        op: EventOp,
+        ftn: Ftn,
+    },
+    Ilm {
+        op: EventOp,
+        ilm: Ilm,
+    },
+}
+
+impl Event {
+    /// The `EVENT_*` bit of the event.
+    pub fn event_type(&self) -> u32 {
+        match self {
+            Event::Route { .. } => EVENT_ROUTE,
+            Event::Peer { .. } => EVENT_PEER,
+            Event::Prefix { .. } => EVENT_PREFIX,
+            Event::Ftn { .. } => EVENT_FTN,
+            Event::Ilm { .. } => EVENT_ILM,
+        }
+    }
+    pub fn op(&self) -> EventOp {
+        match self {
+            Event::Route { op, .. }
+            | Event::Peer { op, .. }
+            | Event::Prefix { op, .. }
+            | Event::Ftn { op, .. }
+            | Event::Ilm { op, .. } => *op,
+        }
+    }
+}
+
+type EventCallback = Box<dyn Fn(&Event) + Send + Sync>;
+
+struct Subscription {
+    id: u32,
+    mask: u32,
+    callback: EventCallback,
+}
+
+struct NotifierInner {
+    subscribers: Mutex<Vec<Arc<Subscription>>>,
+    /// Union of the subscription masks, so nobody pays for unwanted events.
+    interest: AtomicU32,
+    next_id: AtomicU32,
+    pending: Mutex<VecDeque<Event>>,
+    delivering: ReentrantMutex<Cell<bool>>,
+    /// Events of the transactions being committed, per committing thread;
+    /// they join `pending` only once their transaction is committed.
+    staged: Mutex<Vec<(ThreadId, Vec<Event>)>>,
+    /// Number of entries in `staged`, so that publishing skips it when no
+    /// transaction is being committed.
+    staging: AtomicU32,
+}
+
+/// Tables publish events while they hold their locks; the events are queued
+/// and handed to the subscribers by `deliver` once the locks are released.
+#[derive(Clone)]
+pub(crate) struct Notifier {
+    inner: Arc<NotifierInner>,
+}
+
+impl Notifier {
+    pub(crate) fn new() -> Notifier {
+        Notifier {
+            inner: Arc::new(NotifierInner {
+                subscribers: Mutex::new(Vec::new()),
+                interest: AtomicU32::new(0),
+                next_id: AtomicU32::new(1),
+                pending: Mutex::new(VecDeque::new()),
+                delivering: ReentrantMutex::new(Cell::new(false)),
+                staged: Mutex::new(Vec::new()),
+                staging: AtomicU32::new(0),
+            }),
+        }
+    }
+    pub(crate) fn subscribe(&self, mask: u32, callback: EventCallback) -> Result<u32, DsError> {
+        if mask == 0 || mask & !EVENT_ALL != 0 {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                format!("invalid event mask {:#x}", mask),
+            ));
+        }
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        trace!("Notifier::subscribe id {} mask {:#x}", id, mask);
+        let mut subscribers = self.inner.subscribers.lock();
+        subscribers.push(Arc::new(Subscription { id, mask, callback }));
+        self.inner.interest.fetch_or(mask, Ordering::Relaxed);
+        Ok(id)
+    }
+    pub(crate) fn unsubscribe(&self, id: u32) -> Result<(), DsError> {
+        trace!("Notifier::unsubscribe id {}", id);
+        let mut subscribers = self.inner.subscribers.lock();
+        let len = subscribers.len();
+        subscribers.retain(|subscription| subscription.id != id);
+        if subscribers.len() == len {
+            return Err(ds_error(
+                DsError::NotFound,
+                format!("subscription {} not found", id),
+            ));
+        }
+        let interest = subscribers.iter().fold(0, |mask, s| mask | s.mask);
+        self.inner.interest.store(interest, Ordering::Relaxed);
+        Ok(())
+    }
+    /// Whether anyone subscribed to `event_type`; lets callers skip building
+    /// events nobody wants.
+    pub(crate) fn wants(&self, event_type: u32) -> bool {
+        self.inner.interest.load(Ordering::Relaxed) & event_type != 0
+    }
+    pub(crate) fn publish(&self, event: Event) {
+        if self.wants(event.event_type()) {
+            trace!("Notifier::publish {:?}", event);
+            if self.inner.staging.load(Ordering::SeqCst) > 0 {
+                let current = thread::current().id();
+                let mut staged = self.inner.staged.lock();
+                if let Some((_, events)) = staged.iter_mut().find(|(id, _)| *id == current) {
+                    events.push(event);
+                    return;
+                }
+            }
+            self.inner.pending.lock().push_back(event);
+        }
+    }
+    /// Keeps the events the calling thread publishes apart until `unstage`,
+    /// so that the events of a transaction reach nobody before it is
+    /// committed. The thread delivers nothing meanwhile, since it holds the
+    /// table locks.
+    pub(crate) fn stage(&self) {
+        let current = thread::current().id();
+        self.inner.staged.lock().push((current, Vec::new()));
+        self.inner.staging.fetch_add(1, Ordering::SeqCst);
+    }
+    /// Queues the events staged by the calling thread for delivery if
+    /// `commit`, drops them otherwise.
+    pub(crate) fn unstage(&self, commit: bool) {
+        let current = thread::current().id();
+        let events = {
+            let mut staged = self.inner.staged.lock();
+            match staged.iter().position(|(id, _)| *id == current) {
+                Some(pos) => staged.remove(pos).1,
+                None => return,
+            }
+        };
+        self.inner.staging.fetch_sub(1, Ordering::SeqCst);
+        if commit {
+            self.inner.pending.lock().extend(events);
+        }
+    }
+    fn is_staging(&self) -> bool {
+        if self.inner.staging.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        let current = thread::current().id();
+        self.inner
+            .staged
+            .lock()
+            .iter()
+            .any(|(id, _)| *id == current)
+    }
+    /// Runs the callbacks for the queued events in publish order. Must be
+    /// called without table locks held. Events published by a callback are
+    /// delivered by the outermost call, after the current one.
+    pub(crate) fn deliver(&self) {
+        if self.is_staging() {
+            return;
+        }
+        let delivering = self.inner.delivering.lock();
+        if delivering.get() {
+            return;
+        }
+        delivering.set(true);
+        loop {
+            let event = self.inner.pending.lock().pop_front();
+            let event = match event {
+                Some(event) => event,
+                None => break,
+            };
+            let subscribers = self.inner.subscribers.lock().clone();
+            for subscription in subscribers.iter() {
+                if subscription.mask & event.event_type() != 0 {
+                    (subscription.callback)(&event);
+                }
+            }
+        }
+        delivering.set(false);
+    }
+}
+
+pub type EventCb = Option<extern "C" fn(*const EventEntry, *mut c_void)>;
+
+/// The context pointer belongs to the C caller, which must make it usable
+/// from whichever thread changes the store.
+struct CallbackCtx(*mut c_void);
+
+unsafe impl Send for CallbackCtx {}
+unsafe impl Sync for CallbackCtx {}
+
+fn ip_addr_c(addr: Option<&IpAddr>, buf: &mut [u8; 16]) -> IpAddrC {
+    match addr {
+        Some(addr) => {
+            unsafe { copy_ip_addr_to_user(buf.as_mut_ptr(), addr) };
+            IpAddrC {
+                family: ip_addr_family(addr),
+                addr: buf.as_mut_ptr(),
+            }
+        }
+        None => IpAddrC {
+            family: 0,
+            addr: ptr::null_mut(),
+        },
+    }
+}
+
+fn call_event_cb(
+    cb: extern "C" fn(*const EventEntry, *mut c_void),
+    ctx: *mut c_void,
+    event: &Event,
+) {
+    let mut prefix_buf = [0u8; 16];
+    let mut next_hop_buf = [0u8; 16];
+    let (vrf_id, prefix, prefix_len, next_hop, out_ifindex) = match event {
+        Event::Route { vrf_id, route, .. } => {
+            let path = route.paths.first();
+            (
+                *vrf_id,
+                Some(&route.prefix),
+                route.prefix_len,
+                path.map(|path| &path.addr),
+                path.map_or(0, |path| path.out_ifindex),
+            )
+        }
+        Event::Peer { vrf_id, peer, .. } => (
+            *vrf_id,
+            Some(&peer.addr),
+            max_prefix_len(&peer.addr),
+            None,
+            peer.out_ifindex,
+        ),
+        Event::Prefix { vrf_id, entry, .. } => {
+            let path = entry.paths.first();
+            (
+                *vrf_id,
+                Some(&entry.prefix),
+                entry.prefix_len,
+                path.map(|path| &path.addr),
+                path.map_or(0, |path| path.out_ifindex),
+            )
+        }
+        Event::Ftn { ftn, .. } => (
+            ftn.vrf_id,
+            Some(&ftn.fec),
+            max_prefix_len(&ftn.fec),
+            Some(&ftn.next_hop),
+            ftn.out_ifindex,
+        ),
+        Event::Ilm { ilm, .. } => (0, None, 0, Some(&ilm.next_hop), ilm.out_ifindex),
+    };
+    let (in_label, in_iface, out_label, index) = match event {
+        Event::Ftn { ftn, .. } => (
+            0,
+            0,
+            ftn.out_labels.first().copied().unwrap_or(0),
+            ftn.ftn_ix,
+        ),
+        Event::Ilm { ilm, .. } => (ilm.in_label, ilm.in_iface, ilm.out_label, ilm.ilm_ix),
+        _ => (0, 0, 0, 0),
+    };
+    let entry = EventEntry {
+        event_type: event.event_type(),
+        op: event.op(),
+        vrf_id,
+        prefix: ip_addr_c(prefix, &mut prefix_buf),
+        prefix_len,
+        next_hop: ip_addr_c(next_hop, &mut next_hop_buf),
+        out_ifindex,
+        in_label,
+        in_iface,
+        out_label,
+        index,
+    };
+    cb(&entry, ctx);
+}
+
+/// Calls `cb` with `ctx` for every event whose `EVENT_*` bit is in
+/// `event_mask`. The entry and its addresses are only valid during the
+/// call. The callback runs without table locks held and may call back into
+/// the store; the events that causes are delivered after it returns.
+#[no_mangle]
+pub extern "C" fn ds_subscribe(
+    ds: *mut DataStore,
+    event_mask: u32,
+    cb: EventCb,
+    ctx: *mut c_void,
+    id: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let cb = match cb {
+        Some(cb) => cb,
+        None => {
+            return ds_error(DsError::InvalidArgument, String::from("callback is null"));
+        }
+    };
+    if id.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("id is null"));
+    }
+    let ctx = CallbackCtx(ctx);
+    let callback = move |event: &Event| call_event_cb(cb, ctx.0, event);
+    match ds.notifier.subscribe(event_mask, Box::new(callback)) {
+        Ok(subscription_id) => {
+            unsafe { *id = subscription_id };
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn subscribe(
+    event_mask: u32,
+    cb: EventCb,
+    ctx: *mut c_void,
+    id: *mut u32,
+) -> DsError {
+    ds_subscribe(default_data_store(), event_mask, cb, ctx, id)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_unsubscribe(ds: *mut DataStore, id: u32) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.notifier.unsubscribe(id))
+}
+
+#[no_mangle]
+pub extern "C" fn unsubscribe(id: u32) -> DsError {
+    ds_unsubscribe(default_data_store(), id)
+}
@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Session state of a peer, `Established` when it is added. Only an
+/// established peer may install routes; leaving `Established` withdraws them
+/// all, unless the peer goes `Down` during a graceful restart.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// Administratively stopped or never started.
+    Idle = 0,
+    Established = 1,
+    /// The session was up and went down.
+    Down = 2,
+}
+
+impl PeerState {
+    pub fn from_u32(value: u32) -> Option<PeerState> {
+        match value {
+            0 => Some(PeerState::Idle),
+            1 => Some(PeerState::Established),
+            2 => Some(PeerState::Down),
+            _ => None,
+        }
+    }
+
+    /// A session can only go down after it was established; every state
+    /// can be stopped or come up.
+    pub fn can_move_to(&self, state: PeerState) -> bool {
+        !(state == PeerState::Down && *self != PeerState::Established)
+    }
+}
+
+/// State and counters of a peer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub state: PeerState,
+    /// Number of times the peer left `Established`.
+    pub flaps: u32,
+    /// When the state last changed or the peer was added, in milliseconds
+    /// since the Unix epoch.
+    pub last_change_ms: u64,
+    /// Route adds and updates the peer sent, rejected ones included.
+    pub routes_received: u64,
+    pub routes_accepted: u64,
+}
+
+impl PeerStatus {
+    /// Peers start established, as the store has always treated them, so
+    /// that callers without a session manager keep working.
+    pub(crate) fn new() -> PeerStatus {
+        PeerStatus {
+            state: PeerState::Established,
+            flaps: 0,
+            last_change_ms: now_ms(),
+            routes_received: 0,
+            routes_accepted: 0,
+        }
+    }
+    pub(crate) fn set_state(&mut self, state: PeerState) {
+        if self.state == PeerState::Established {
+            self.flaps += 1;
+        }
+        self.state = state;
+        self.last_change_ms = now_ms();
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
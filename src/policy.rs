@@ -0,0 +1,549 @@
+//! Inbound route policy. A policy is an ordered list of terms; the first
+//! term whose conditions all match a route decides whether it is permitted,
+//! possibly with its metric, local preference or next hop rewritten. A
+//! route no term matches is denied, as is every route of a peer whose
+//! policy does not exist.
+
+use crate::ecmp::path_set;
+use crate::errors::*;
+use crate::external_types::*;
+use crate::utils::*;
+use crate::{data_store_from_handle, default_data_store, DataStore, Route, DEFAULT_VRF_ID};
+use log::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+pub const POLICY_SET_METRIC: u32 = 1;
+pub const POLICY_SET_LOCAL_PREF: u32 = 1 << 1;
+pub const POLICY_SET_NEXT_HOP: u32 = 1 << 2;
+
+/// Prefixes inside `prefix`/`prefix_len` with a length from `ge` to `le`.
+/// Without `ge` and `le` (both 0) only the prefix itself matches, `ge`
+/// alone goes up to the longest prefix, `le` alone starts at `prefix_len`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixRange {
+    pub prefix: IpAddr,
+    pub prefix_len: u8,
+    pub ge: u8,
+    pub le: u8,
+}
+
+impl PrefixRange {
+    fn lengths(&self) -> (u8, u8) {
+        match (self.ge, self.le) {
+            (0, 0) => (self.prefix_len, self.prefix_len),
+            (ge, 0) => (ge, max_prefix_len(&self.prefix)),
+            (0, le) => (self.prefix_len, le),
+            (ge, le) => (ge, le),
+        }
+    }
+    pub(crate) fn check(&self) -> Result<(), DsError> {
+        let (ge, le) = self.lengths();
+        if !ip_prefix_is_valid(&self.prefix, self.prefix_len)
+            || ge < self.prefix_len
+            || ge > le
+            || le > max_prefix_len(&self.prefix)
+        {
+            return Err(ds_error(
+                DsError::InvalidPrefix,
+                format!(
+                    "invalid prefix range {}/{} ge {} le {}",
+                    self.prefix, self.prefix_len, self.ge, self.le
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn matches(&self, prefix: &IpAddr, prefix_len: u8) -> bool {
+        let (ge, le) = self.lengths();
+        prefix.is_ipv4() == self.prefix.is_ipv4()
+            && (ge..=le).contains(&prefix_len)
+            && ip_addr_apply_mask(prefix, self.prefix_len) == self.prefix
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    Permit = 0,
+    Deny = 1,
+}
+
+impl PolicyAction {
+    pub fn from_u32(value: u32) -> Option<PolicyAction> {
+        match value {
+            0 => Some(PolicyAction::Permit),
+            1 => Some(PolicyAction::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Conditions left `None` match every route. The next hop and interface
+/// match if any path of the route has them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyTerm {
+    pub prefix_list: Option<u32>,
+    pub peer: Option<IpAddr>,
+    pub next_hop: Option<IpAddr>,
+    pub out_ifindex: Option<u32>,
+    pub action: PolicyAction,
+    /// Only applied by permitting terms.
+    pub set_metric: Option<u32>,
+    pub set_local_pref: Option<u32>,
+    /// Replaces the address of every path.
+    pub set_next_hop: Option<IpAddr>,
+}
+
+impl PolicyTerm {
+    pub fn new(action: PolicyAction) -> PolicyTerm {
+        PolicyTerm {
+            prefix_list: None,
+            peer: None,
+            next_hop: None,
+            out_ifindex: None,
+            action,
+            set_metric: None,
+            set_local_pref: None,
+            set_next_hop: None,
+        }
+    }
+}
+
+struct CountedTerm {
+    term: PolicyTerm,
+    hits: u64,
+}
+
+/// A prefix list as snapshots keep it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PrefixListSnapshot {
+    pub(crate) list_id: u32,
+    pub(crate) ranges: Vec<PrefixRange>,
+}
+
+/// A policy with the hits of each term, as snapshots keep it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PolicySnapshot {
+    pub(crate) policy_id: u32,
+    pub(crate) terms: Vec<(PolicyTerm, u64)>,
+}
+
+/// Prefix lists and policies of a store, by id. Id 0 stands for none in
+/// the C API and is not a valid id.
+#[derive(Default)]
+pub(crate) struct Policies {
+    prefix_lists: HashMap<u32, Vec<PrefixRange>>,
+    policies: HashMap<u32, Vec<CountedTerm>>,
+}
+
+fn check_id(id: u32) -> Result<(), DsError> {
+    if id == 0 {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("id 0 is reserved"),
+        ));
+    }
+    Ok(())
+}
+
+impl Policies {
+    pub(crate) fn add_prefix_range(
+        &mut self,
+        list_id: u32,
+        range: PrefixRange,
+    ) -> Result<(), DsError> {
+        check_id(list_id)?;
+        range.check()?;
+        let list = self.prefix_lists.entry(list_id).or_default();
+        if !list.contains(&range) {
+            list.push(range);
+        }
+        Ok(())
+    }
+    pub(crate) fn delete_prefix_list(&mut self, list_id: u32) -> Result<(), DsError> {
+        match self.prefix_lists.remove(&list_id) {
+            Some(_) => Ok(()),
+            None => Err(ds_error(
+                DsError::NotFound,
+                format!("prefix list {} not found", list_id),
+            )),
+        }
+    }
+    /// Appends `term` to the policy, creating it. Returns the term index.
+    pub(crate) fn add_term(&mut self, policy_id: u32, term: PolicyTerm) -> Result<usize, DsError> {
+        check_id(policy_id)?;
+        let sets = term.set_metric.is_some()
+            || term.set_local_pref.is_some()
+            || term.set_next_hop.is_some();
+        if sets && term.action == PolicyAction::Deny {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                String::from("a deny term cannot set attributes"),
+            ));
+        }
+        let terms = self.policies.entry(policy_id).or_default();
+        terms.push(CountedTerm { term, hits: 0 });
+        Ok(terms.len() - 1)
+    }
+    pub(crate) fn delete_policy(&mut self, policy_id: u32) -> Result<(), DsError> {
+        match self.policies.remove(&policy_id) {
+            Some(_) => Ok(()),
+            None => Err(ds_error(
+                DsError::NotFound,
+                format!("policy {} not found", policy_id),
+            )),
+        }
+    }
+    pub(crate) fn contains(&self, policy_id: u32) -> bool {
+        self.policies.contains_key(&policy_id)
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.prefix_lists.is_empty() && self.policies.is_empty()
+    }
+    /// Prefix lists by id.
+    pub(crate) fn prefix_list_snapshots(&self) -> Vec<PrefixListSnapshot> {
+        let mut lists: Vec<PrefixListSnapshot> = self
+            .prefix_lists
+            .iter()
+            .map(|(list_id, ranges)| PrefixListSnapshot {
+                list_id: *list_id,
+                ranges: ranges.clone(),
+            })
+            .collect();
+        lists.sort_by_key(|list| list.list_id);
+        lists
+    }
+    /// Policies by id.
+    pub(crate) fn policy_snapshots(&self) -> Vec<PolicySnapshot> {
+        let mut policies: Vec<PolicySnapshot> = self
+            .policies
+            .iter()
+            .map(|(policy_id, terms)| PolicySnapshot {
+                policy_id: *policy_id,
+                terms: terms
+                    .iter()
+                    .map(|counted| (counted.term.clone(), counted.hits))
+                    .collect(),
+            })
+            .collect();
+        policies.sort_by_key(|policy| policy.policy_id);
+        policies
+    }
+    /// Adds the prefix lists and policies of a snapshot, checking them as
+    /// `add_prefix_range` and `add_term` do, with their hits.
+    pub(crate) fn restore(
+        &mut self,
+        lists: &[PrefixListSnapshot],
+        policies: &[PolicySnapshot],
+    ) -> Result<(), DsError> {
+        for list in lists.iter() {
+            for range in list.ranges.iter() {
+                self.add_prefix_range(list.list_id, *range)?;
+            }
+        }
+        for policy in policies.iter() {
+            for (term, hits) in policy.terms.iter() {
+                let ix = self.add_term(policy.policy_id, term.clone())?;
+                if let Some(counted) = self
+                    .policies
+                    .get_mut(&policy.policy_id)
+                    .and_then(|terms| terms.get_mut(ix))
+                {
+                    counted.hits = *hits;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Times each term of the policy decided a route, in term order.
+    pub(crate) fn hits(&self, policy_id: u32) -> Result<Vec<u64>, DsError> {
+        match self.policies.get(&policy_id) {
+            Some(terms) => Ok(terms.iter().map(|counted| counted.hits).collect()),
+            None => Err(ds_error(
+                DsError::NotFound,
+                format!("policy {} not found", policy_id),
+            )),
+        }
+    }
+    /// Puts back hits taken by `hits`, for a policy whose terms did not
+    /// change since.
+    pub(crate) fn set_hits(&mut self, policy_id: u32, hits: &[u64]) -> Result<(), DsError> {
+        match self.policies.get_mut(&policy_id) {
+            Some(terms) if terms.len() == hits.len() => {
+                for (counted, hits) in terms.iter_mut().zip(hits.iter()) {
+                    counted.hits = *hits;
+                }
+                Ok(())
+            }
+            Some(terms) => Err(ds_error(
+                DsError::InvalidArgument,
+                format!(
+                    "policy {} has {} terms, not {}",
+                    policy_id,
+                    terms.len(),
+                    hits.len()
+                ),
+            )),
+            None => Err(ds_error(
+                DsError::NotFound,
+                format!("policy {} not found", policy_id),
+            )),
+        }
+    }
+    fn term_matches(&self, term: &PolicyTerm, peer: &IpAddr, route: &Route) -> bool {
+        let in_list = |list_id| {
+            self.prefix_lists.get(list_id).is_some_and(|list| {
+                list.iter()
+                    .any(|range| range.matches(&route.prefix, route.prefix_len))
+            })
+        };
+        term.prefix_list.as_ref().is_none_or(in_list)
+            && term.peer.is_none_or(|addr| addr == *peer)
+            && term
+                .next_hop
+                .is_none_or(|addr| route.paths.iter().any(|path| path.addr == addr))
+            && term
+                .out_ifindex
+                .is_none_or(|ifindex| route.paths.iter().any(|path| path.out_ifindex == ifindex))
+    }
+    /// Runs `route` of `peer` through the policy and counts the deciding
+    /// term. Returns whether the route is permitted; a permitted route
+    /// comes back with the attributes the term sets.
+    pub(crate) fn evaluate(&mut self, policy_id: u32, peer: &IpAddr, route: &mut Route) -> bool {
+        let ix = match self.policies.get(&policy_id) {
+            Some(terms) => terms
+                .iter()
+                .position(|counted| self.term_matches(&counted.term, peer, route)),
+            None => return false,
+        };
+        let counted = match ix.and_then(|ix| self.policies.get_mut(&policy_id)?.get_mut(ix)) {
+            Some(counted) => counted,
+            None => return false,
+        };
+        counted.hits += 1;
+        let term = &counted.term;
+        if term.action == PolicyAction::Deny {
+            return false;
+        }
+        if let Some(metric) = term.set_metric {
+            route.attrs.metric = metric;
+        }
+        if let Some(local_pref) = term.set_local_pref {
+            route.attrs.local_pref = local_pref;
+        }
+        if let Some(next_hop) = term.set_next_hop {
+            for path in route.paths.iter_mut() {
+                path.addr = next_hop;
+            }
+            if let Some(paths) = path_set(&route.paths) {
+                route.paths = paths;
+            }
+        }
+        true
+    }
+}
+
+/// Address of `addr`, `None` if it is null.
+unsafe fn optional_ip_addr_from_user(addr: &IpAddrC) -> Option<IpAddr> {
+    if addr.addr.is_null() {
+        None
+    } else {
+        Some(copy_ip_addr_from_user(addr))
+    }
+}
+
+unsafe fn policy_term_from_user(entry: *const PolicyTermEntry) -> Result<PolicyTerm, DsError> {
+    let entry = match entry.as_ref() {
+        Some(entry) => entry,
+        None => {
+            return Err(ds_error(
+                DsError::InvalidArgument,
+                String::from("term is null"),
+            ))
+        }
+    };
+    let action = PolicyAction::from_u32(entry.action).ok_or_else(|| {
+        ds_error(
+            DsError::InvalidArgument,
+            format!("unknown policy action {}", entry.action),
+        )
+    })?;
+    let set = |flag: u32| entry.set_flags & flag != 0;
+    let set_next_hop = if set(POLICY_SET_NEXT_HOP) {
+        match optional_ip_addr_from_user(&entry.set_next_hop) {
+            Some(next_hop) => Some(next_hop),
+            None => {
+                return Err(ds_error(
+                    DsError::InvalidArgument,
+                    String::from("next hop to set is null"),
+                ))
+            }
+        }
+    } else {
+        None
+    };
+    Ok(PolicyTerm {
+        prefix_list: Some(entry.prefix_list_id).filter(|id| *id != 0),
+        peer: optional_ip_addr_from_user(&entry.peer),
+        next_hop: optional_ip_addr_from_user(&entry.next_hop),
+        out_ifindex: Some(entry.out_ifindex).filter(|ifindex| *ifindex != 0),
+        action,
+        set_metric: Some(entry.metric).filter(|_| set(POLICY_SET_METRIC)),
+        set_local_pref: Some(entry.local_pref).filter(|_| set(POLICY_SET_LOCAL_PREF)),
+        set_next_hop,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn ds_prefix_list_add(
+    ds: *mut DataStore,
+    list_id: u32,
+    entry: *const PrefixListEntry,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let entry = match unsafe { entry.as_ref() } {
+        Some(entry) => entry,
+        None => return ds_error(DsError::InvalidArgument, String::from("entry is null")),
+    };
+    let range = PrefixRange {
+        prefix: unsafe { copy_ip_addr_from_user(&entry.prefix) },
+        prefix_len: entry.prefix_len,
+        ge: entry.ge,
+        le: entry.le,
+    };
+    trace!("prefix_list_add {} {:?}", list_id, range);
+    ds_result(ds.rib().add_prefix_range(list_id, &range))
+}
+
+#[no_mangle]
+pub extern "C" fn prefix_list_add(list_id: u32, entry: *const PrefixListEntry) -> DsError {
+    ds_prefix_list_add(default_data_store(), list_id, entry)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_prefix_list_delete(ds: *mut DataStore, list_id: u32) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.rib().delete_prefix_list(list_id))
+}
+
+#[no_mangle]
+pub extern "C" fn prefix_list_delete(list_id: u32) -> DsError {
+    ds_prefix_list_delete(default_data_store(), list_id)
+}
+
+/// Appends the term to the policy, creating the policy, and stores its
+/// index in `term_ix` unless that is null.
+#[no_mangle]
+pub extern "C" fn ds_policy_term_add(
+    ds: *mut DataStore,
+    policy_id: u32,
+    term: *const PolicyTermEntry,
+    term_ix: *mut u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let term = match unsafe { policy_term_from_user(term) } {
+        Ok(term) => term,
+        Err(err) => return err,
+    };
+    trace!("policy_term_add {} {:?}", policy_id, term);
+    match ds.rib().add_policy_term(policy_id, &term) {
+        Ok(ix) => {
+            if !term_ix.is_null() {
+                unsafe { *term_ix = ix as u32 };
+            }
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn policy_term_add(
+    policy_id: u32,
+    term: *const PolicyTermEntry,
+    term_ix: *mut u32,
+) -> DsError {
+    ds_policy_term_add(default_data_store(), policy_id, term, term_ix)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_policy_delete(ds: *mut DataStore, policy_id: u32) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    ds_result(ds.rib().delete_policy(policy_id))
+}
+
+#[no_mangle]
+pub extern "C" fn policy_delete(policy_id: u32) -> DsError {
+    ds_policy_delete(default_data_store(), policy_id)
+}
+
+/// Number of routes term `term_ix` of the policy decided.
+#[no_mangle]
+pub extern "C" fn ds_policy_term_hits(
+    ds: *mut DataStore,
+    policy_id: u32,
+    term_ix: u32,
+    hits: *mut u64,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    if hits.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("hits is null"));
+    }
+    match ds.rib().policy_hits(policy_id) {
+        Ok(counts) => match counts.get(term_ix as usize) {
+            Some(count) => {
+                unsafe { *hits = *count };
+                DsError::Ok
+            }
+            None => ds_error(
+                DsError::NotFound,
+                format!("policy {} has no term {}", policy_id, term_ix),
+            ),
+        },
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn policy_term_hits(policy_id: u32, term_ix: u32, hits: *mut u64) -> DsError {
+    ds_policy_term_hits(default_data_store(), policy_id, term_ix, hits)
+}
+
+/// Attaches inbound policy `policy_id` to the peer, 0 detaches it.
+#[no_mangle]
+pub extern "C" fn ds_peer_policy_set(
+    ds: *mut DataStore,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    policy_id: u32,
+) -> DsError {
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    let ip_addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    trace!("peer_policy_set {} {}", ip_addr, policy_id);
+    let policy_id = Some(policy_id).filter(|id| *id != 0);
+    ds_result(ds.rib().set_peer_policy(vrf_id, &ip_addr, policy_id))
+}
+
+#[no_mangle]
+pub extern "C" fn peer_policy_set(_prefix: &IpAddrC, policy_id: u32) -> DsError {
+    ds_peer_policy_set(default_data_store(), DEFAULT_VRF_ID, _prefix, policy_id)
+}
@@ -0,0 +1,80 @@
+use crate::ecmp::{path_add, NextHop};
+use log::*;
+use std::net::IpAddr;
+
+/// Next hops further away than this are left unresolved.
+pub const MAX_RESOLVE_DEPTH: usize = 8;
+
+/// What a single lookup of a next hop address found.
+pub(crate) enum Via {
+    /// The address is on a link: an adjacent next hop or inside a connected
+    /// prefix, reached through the interface.
+    Connected(u32),
+    /// The address is reached through the paths of the covering prefix.
+    Paths(Vec<NextHop>),
+    Unresolved,
+}
+
+/// Result of resolving the paths of a route.
+#[derive(Default)]
+pub(crate) struct Resolution {
+    /// Adjacent next hops with their out interface. A resolved path keeps
+    /// the weight of the route path it came from.
+    pub(crate) paths: Vec<NextHop>,
+    /// Every address that was looked up, so that the route can be resolved
+    /// again when what covers one of them changes.
+    pub(crate) looked_up: Vec<IpAddr>,
+}
+
+/// Resolves `paths` recursively through `lookup`. A path through a cycle or
+/// more than `MAX_RESOLVE_DEPTH` lookups away contributes nothing.
+pub(crate) fn resolve(paths: &[NextHop], lookup: &dyn Fn(&IpAddr) -> Via) -> Resolution {
+    let mut resolution = Resolution::default();
+    let mut chain = Vec::new();
+    for path in paths {
+        resolve_path(path, path.weight, lookup, &mut chain, &mut resolution);
+    }
+    resolution
+}
+
+fn resolve_path(
+    path: &NextHop,
+    weight: u32,
+    lookup: &dyn Fn(&IpAddr) -> Via,
+    chain: &mut Vec<IpAddr>,
+    resolution: &mut Resolution,
+) {
+    if chain.contains(&path.addr) {
+        trace!("resolve: {} loops back through {:?}", path.addr, chain);
+        return;
+    }
+    if chain.len() == MAX_RESOLVE_DEPTH {
+        trace!("resolve: {} is too deep, {:?}", path.addr, chain);
+        return;
+    }
+    if !resolution.looked_up.contains(&path.addr) {
+        resolution.looked_up.push(path.addr);
+    }
+    match lookup(&path.addr) {
+        Via::Connected(out_ifindex) => {
+            path_add(
+                &mut resolution.paths,
+                &NextHop {
+                    addr: path.addr,
+                    out_ifindex,
+                    weight,
+                },
+            );
+        }
+        Via::Paths(via_paths) => {
+            chain.push(path.addr);
+            for via_path in via_paths.iter() {
+                resolve_path(via_path, weight, lookup, chain, resolution);
+            }
+            chain.pop();
+        }
+        Via::Unresolved => {
+            trace!("resolve: {} is unreachable", path.addr);
+        }
+    }
+}
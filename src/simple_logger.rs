@@ -20,5 +20,5 @@ static LOGGER: SimpleLogger = SimpleLogger;
 
 #[no_mangle]
 pub extern "C" fn init_logger() {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Trace));
+    let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Trace));
 }
@@ -0,0 +1,846 @@
+//! Binary snapshot of a whole store, for a warm restart.
+//!
+//! Integers are little endian. A snapshot is
+//!
+//! ```text
+//! "DSSN"  u32 version  u32 best-path algorithm  optional dampening config
+//! prefix lists  policies  vrfs  next hops  ftns  ilms
+//! xc ids  nhlfe ids  ilm ids  u32 max label depth
+//! label checking  label blocks
+//! u32 FNV-1a hash of everything before it
+//! ```
+//!
+//! where every list is a u32 count followed by the items, an address is its
+//! family (1 for IPv4, 2 for IPv6) followed by its 4 or 16 octets, a flag
+//! is a u8 of 0 or 1, an optional item is a flag followed by the item if
+//! the flag is 1, and the ids of a table are the u32 first and last index
+//! of its range followed by the list of taken indexes.
+//!
+//! Graceful restarts and dampening penalties are not kept: both run on
+//! timers of the store they started in. A restored peer has no stale
+//! routes and no suppressed paths.
+
+use crate::errors::*;
+use crate::id_allocator::TableIds;
+use crate::mpls_sim::{UsedIds, XcIndexed};
+use crate::policy::{PolicySnapshot, PrefixListSnapshot};
+use crate::utils::*;
+use crate::{
+    data_store_from_handle, default_data_store, BestPathAlgorithm, DampeningConfig, DataStore, Ftn,
+    Ilm, LabelBlock, NextHop, Peer, PeerState, PeerStatus, PolicyAction, PolicyTerm, PrefixEntry,
+    PrefixRange, Route, RouteAttrs, DEFAULT_VRF_ID,
+};
+use log::*;
+use std::ffi::CStr;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"DSSN";
+
+/// Version of the snapshots the store writes. Snapshots of any other
+/// version are refused.
+pub const SNAPSHOT_VERSION: u32 = 5;
+
+fn invalid_data(detail: String) -> DsError {
+    ds_error(DsError::InvalidData, detail)
+}
+
+pub(crate) fn io_error(err: std::io::Error, path: &Path) -> DsError {
+    ds_error(DsError::Io, format!("{}: {}", path.display(), err))
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DsError> {
+        if self.bytes.len() - self.pos < len {
+            return Err(invalid_data(format!(
+                "snapshot truncated at offset {}",
+                self.pos
+            )));
+        }
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+}
+
+/// What the snapshot is made of.
+trait Record: Sized {
+    fn put(&self, out: &mut Vec<u8>);
+    fn get(input: &mut Input) -> Result<Self, DsError>;
+}
+
+impl Record for u8 {
+    fn put(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+    fn get(input: &mut Input) -> Result<u8, DsError> {
+        Ok(input.take(1)?[0])
+    }
+}
+
+impl Record for u32 {
+    fn put(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn get(input: &mut Input) -> Result<u32, DsError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(input.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+impl Record for u64 {
+    fn put(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn get(input: &mut Input) -> Result<u64, DsError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(input.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Record for bool {
+    fn put(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self));
+    }
+    fn get(input: &mut Input) -> Result<bool, DsError> {
+        match u8::get(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            flag => Err(invalid_data(format!("flag {} is neither 0 nor 1", flag))),
+        }
+    }
+}
+
+impl Record for IpAddr {
+    fn put(&self, out: &mut Vec<u8>) {
+        out.push(ip_addr_family(self));
+        out.extend_from_slice(&ip_addr_octets(self));
+    }
+    fn get(input: &mut Input) -> Result<IpAddr, DsError> {
+        match u8::get(input)? {
+            1 => {
+                let mut v4 = [0u8; 4];
+                v4.copy_from_slice(input.take(4)?);
+                Ok(IpAddr::V4(Ipv4Addr::from(v4)))
+            }
+            2 => {
+                let mut v6 = [0u8; 16];
+                v6.copy_from_slice(input.take(16)?);
+                Ok(IpAddr::V6(Ipv6Addr::from(v6)))
+            }
+            family => Err(invalid_data(format!("unknown address family {}", family))),
+        }
+    }
+}
+
+impl<T: Record> Record for Option<T> {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.is_some().put(out);
+        if let Some(item) = self {
+            item.put(out);
+        }
+    }
+    fn get(input: &mut Input) -> Result<Option<T>, DsError> {
+        if bool::get(input)? {
+            Ok(Some(T::get(input)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Record> Record for Vec<T> {
+    fn put(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).put(out);
+        for item in self.iter() {
+            item.put(out);
+        }
+    }
+    fn get(input: &mut Input) -> Result<Vec<T>, DsError> {
+        let count = u32::get(input)?;
+        // not preallocated, a corrupt count runs out of input instead of memory
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(T::get(input)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Index of an id table, which is never 0.
+fn get_index(input: &mut Input) -> Result<u32, DsError> {
+    let ix = u32::get(input)?;
+    if ix == 0 {
+        return Err(invalid_data(String::from("index 0 out of range")));
+    }
+    Ok(ix)
+}
+
+impl Record for Peer {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.addr.put(out);
+        self.out_ifindex.put(out);
+    }
+    fn get(input: &mut Input) -> Result<Peer, DsError> {
+        Ok(Peer {
+            addr: IpAddr::get(input)?,
+            out_ifindex: u32::get(input)?,
+        })
+    }
+}
+
+impl Record for PeerStatus {
+    fn put(&self, out: &mut Vec<u8>) {
+        (self.state as u32).put(out);
+        self.flaps.put(out);
+        self.last_change_ms.put(out);
+        self.routes_received.put(out);
+        self.routes_accepted.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PeerStatus, DsError> {
+        let state = u32::get(input)?;
+        Ok(PeerStatus {
+            state: PeerState::from_u32(state)
+                .ok_or_else(|| invalid_data(format!("unknown peer state {}", state)))?,
+            flaps: u32::get(input)?,
+            last_change_ms: u64::get(input)?,
+            routes_received: u64::get(input)?,
+            routes_accepted: u64::get(input)?,
+        })
+    }
+}
+
+/// A peer with its session status and inbound policy.
+pub(crate) struct PeerSnapshot {
+    pub(crate) peer: Peer,
+    pub(crate) status: PeerStatus,
+    pub(crate) policy: Option<u32>,
+}
+
+impl Record for PeerSnapshot {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.peer.put(out);
+        self.status.put(out);
+        self.policy.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PeerSnapshot, DsError> {
+        Ok(PeerSnapshot {
+            peer: Peer::get(input)?,
+            status: PeerStatus::get(input)?,
+            policy: Option::get(input)?,
+        })
+    }
+}
+
+impl Record for DampeningConfig {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.half_life_ms.put(out);
+        self.reuse_threshold.put(out);
+        self.suppress_threshold.put(out);
+        self.max_suppress_ms.put(out);
+    }
+    fn get(input: &mut Input) -> Result<DampeningConfig, DsError> {
+        Ok(DampeningConfig {
+            half_life_ms: u32::get(input)?,
+            reuse_threshold: u32::get(input)?,
+            suppress_threshold: u32::get(input)?,
+            max_suppress_ms: u32::get(input)?,
+        })
+    }
+}
+
+impl Record for PrefixRange {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.prefix.put(out);
+        self.prefix_len.put(out);
+        self.ge.put(out);
+        self.le.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PrefixRange, DsError> {
+        Ok(PrefixRange {
+            prefix: IpAddr::get(input)?,
+            prefix_len: u8::get(input)?,
+            ge: u8::get(input)?,
+            le: u8::get(input)?,
+        })
+    }
+}
+
+impl Record for PrefixListSnapshot {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.list_id.put(out);
+        self.ranges.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PrefixListSnapshot, DsError> {
+        Ok(PrefixListSnapshot {
+            list_id: u32::get(input)?,
+            ranges: Vec::get(input)?,
+        })
+    }
+}
+
+/// A policy term and its hits.
+impl Record for (PolicyTerm, u64) {
+    fn put(&self, out: &mut Vec<u8>) {
+        let (term, hits) = self;
+        term.prefix_list.put(out);
+        term.peer.put(out);
+        term.next_hop.put(out);
+        term.out_ifindex.put(out);
+        (term.action as u32).put(out);
+        term.set_metric.put(out);
+        term.set_local_pref.put(out);
+        term.set_next_hop.put(out);
+        hits.put(out);
+    }
+    fn get(input: &mut Input) -> Result<(PolicyTerm, u64), DsError> {
+        let prefix_list = Option::get(input)?;
+        let peer = Option::get(input)?;
+        let next_hop = Option::get(input)?;
+        let out_ifindex = Option::get(input)?;
+        let action = u32::get(input)?;
+        let term = PolicyTerm {
+            prefix_list,
+            peer,
+            next_hop,
+            out_ifindex,
+            action: PolicyAction::from_u32(action)
+                .ok_or_else(|| invalid_data(format!("unknown policy action {}", action)))?,
+            set_metric: Option::get(input)?,
+            set_local_pref: Option::get(input)?,
+            set_next_hop: Option::get(input)?,
+        };
+        Ok((term, u64::get(input)?))
+    }
+}
+
+impl Record for PolicySnapshot {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.policy_id.put(out);
+        self.terms.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PolicySnapshot, DsError> {
+        Ok(PolicySnapshot {
+            policy_id: u32::get(input)?,
+            terms: Vec::get(input)?,
+        })
+    }
+}
+
+impl Record for NextHop {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.addr.put(out);
+        self.out_ifindex.put(out);
+        self.weight.put(out);
+    }
+    fn get(input: &mut Input) -> Result<NextHop, DsError> {
+        Ok(NextHop {
+            addr: IpAddr::get(input)?,
+            out_ifindex: u32::get(input)?,
+            weight: u32::get(input)?,
+        })
+    }
+}
+
+impl Record for RouteAttrs {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.admin_distance.put(out);
+        self.origin.put(out);
+        self.metric.put(out);
+        self.local_pref.put(out);
+    }
+    fn get(input: &mut Input) -> Result<RouteAttrs, DsError> {
+        Ok(RouteAttrs {
+            admin_distance: u8::get(input)?,
+            origin: u8::get(input)?,
+            metric: u32::get(input)?,
+            local_pref: u32::get(input)?,
+        })
+    }
+}
+
+/// A route as one peer announced it.
+pub(crate) struct PeerRoute {
+    pub(crate) peer: IpAddr,
+    pub(crate) route: Route,
+}
+
+impl Record for PeerRoute {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.peer.put(out);
+        self.route.prefix.put(out);
+        self.route.prefix_len.put(out);
+        self.route.attrs.put(out);
+        self.route.paths.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PeerRoute, DsError> {
+        Ok(PeerRoute {
+            peer: IpAddr::get(input)?,
+            route: Route {
+                prefix: IpAddr::get(input)?,
+                prefix_len: u8::get(input)?,
+                attrs: RouteAttrs::get(input)?,
+                paths: Vec::get(input)?,
+            },
+        })
+    }
+}
+
+impl Record for PrefixEntry {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.prefix.put(out);
+        self.prefix_len.put(out);
+        self.paths.put(out);
+    }
+    fn get(input: &mut Input) -> Result<PrefixEntry, DsError> {
+        Ok(PrefixEntry {
+            prefix: IpAddr::get(input)?,
+            prefix_len: u8::get(input)?,
+            paths: Vec::get(input)?,
+        })
+    }
+}
+
+/// Next hop of the LFIB next hop table and its interface.
+impl Record for (IpAddr, u32) {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.0.put(out);
+        self.1.put(out);
+    }
+    fn get(input: &mut Input) -> Result<(IpAddr, u32), DsError> {
+        Ok((IpAddr::get(input)?, u32::get(input)?))
+    }
+}
+
+impl Record for XcIndexed<Ftn> {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.entry.vrf_id.put(out);
+        self.entry.fec.put(out);
+        self.entry.ftn_ix.put(out);
+        self.entry.next_hop.put(out);
+        self.entry.out_ifindex.put(out);
+        self.entry.out_labels.put(out);
+        self.xc_ix.put(out);
+        self.nhlfe_ix.put(out);
+    }
+    fn get(input: &mut Input) -> Result<XcIndexed<Ftn>, DsError> {
+        Ok(XcIndexed {
+            entry: Ftn {
+                vrf_id: u32::get(input)?,
+                fec: IpAddr::get(input)?,
+                ftn_ix: u32::get(input)?,
+                next_hop: IpAddr::get(input)?,
+                out_ifindex: u32::get(input)?,
+                out_labels: Vec::get(input)?,
+            },
+            xc_ix: get_index(input)?,
+            nhlfe_ix: get_index(input)?,
+        })
+    }
+}
+
+impl Record for XcIndexed<Ilm> {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.entry.in_label.put(out);
+        self.entry.in_iface.put(out);
+        self.entry.ilm_ix.put(out);
+        self.entry.owner.put(out);
+        self.entry.next_hop.put(out);
+        self.entry.out_ifindex.put(out);
+        self.entry.out_label.put(out);
+        self.xc_ix.put(out);
+        self.nhlfe_ix.put(out);
+    }
+    fn get(input: &mut Input) -> Result<XcIndexed<Ilm>, DsError> {
+        Ok(XcIndexed {
+            entry: Ilm {
+                in_label: u32::get(input)?,
+                in_iface: u32::get(input)?,
+                ilm_ix: get_index(input)?,
+                owner: u32::get(input)?,
+                next_hop: IpAddr::get(input)?,
+                out_ifindex: u32::get(input)?,
+                out_label: u32::get(input)?,
+            },
+            xc_ix: get_index(input)?,
+            nhlfe_ix: get_index(input)?,
+        })
+    }
+}
+
+/// The usage of a block is not kept, the restored ILM entries count it again.
+impl Record for TableIds {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.first.put(out);
+        self.last.put(out);
+        self.used.put(out);
+    }
+    fn get(input: &mut Input) -> Result<TableIds, DsError> {
+        Ok(TableIds {
+            first: u32::get(input)?,
+            last: u32::get(input)?,
+            used: Vec::get(input)?,
+        })
+    }
+}
+
+impl Record for LabelBlock {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.owner.put(out);
+        self.start.put(out);
+        self.count.put(out);
+        self.is_static.put(out);
+    }
+    fn get(input: &mut Input) -> Result<LabelBlock, DsError> {
+        Ok(LabelBlock {
+            owner: u32::get(input)?,
+            start: u32::get(input)?,
+            count: u32::get(input)?,
+            is_static: bool::get(input)?,
+            used: 0,
+        })
+    }
+}
+
+pub(crate) struct VrfSnapshot {
+    pub(crate) vrf_id: u32,
+    pub(crate) peers: Vec<PeerSnapshot>,
+    pub(crate) routes: Vec<PeerRoute>,
+    pub(crate) prefixes: Vec<PrefixEntry>,
+}
+
+impl Record for VrfSnapshot {
+    fn put(&self, out: &mut Vec<u8>) {
+        self.vrf_id.put(out);
+        self.peers.put(out);
+        self.routes.put(out);
+        self.prefixes.put(out);
+    }
+    fn get(input: &mut Input) -> Result<VrfSnapshot, DsError> {
+        Ok(VrfSnapshot {
+            vrf_id: u32::get(input)?,
+            peers: Vec::get(input)?,
+            routes: Vec::get(input)?,
+            prefixes: Vec::get(input)?,
+        })
+    }
+}
+
+/// The state a store is rebuilt from, also what `json::import` reads.
+pub(crate) struct Snapshot {
+    pub(crate) best_path: BestPathAlgorithm,
+    pub(crate) dampening: Option<DampeningConfig>,
+    pub(crate) prefix_lists: Vec<PrefixListSnapshot>,
+    pub(crate) policies: Vec<PolicySnapshot>,
+    pub(crate) vrfs: Vec<VrfSnapshot>,
+    pub(crate) next_hops: Vec<(IpAddr, u32)>,
+    pub(crate) ftns: Vec<XcIndexed<Ftn>>,
+    pub(crate) ilms: Vec<XcIndexed<Ilm>>,
+    pub(crate) ids: UsedIds,
+    pub(crate) max_label_depth: u32,
+    pub(crate) label_checking: bool,
+    pub(crate) label_blocks: Vec<LabelBlock>,
+}
+
+impl Snapshot {
+    /// Must run with the tables locked, for the parts to agree.
+    fn capture(ds: &DataStore) -> Result<Snapshot, DsError> {
+        let rib = ds.rib();
+        let lfib = ds.lfib();
+        let mut vrfs = Vec::new();
+        let mut ftns = Vec::new();
+        for vrf_id in rib.vrf_ids() {
+            let mut peers = rib.peers(vrf_id, true)?;
+            peers.extend(rib.peers(vrf_id, false)?);
+            peers.sort_by_key(|peer| peer.addr);
+            let mut routes = Vec::new();
+            let mut peer_snapshots = Vec::new();
+            for peer in peers.into_iter() {
+                for route in rib.peer_routes(vrf_id, &peer.addr, None, usize::MAX)? {
+                    routes.push(PeerRoute {
+                        peer: peer.addr,
+                        route,
+                    });
+                }
+                peer_snapshots.push(PeerSnapshot {
+                    status: rib.peer_status(vrf_id, &peer.addr)?,
+                    policy: rib.peer_policy(vrf_id, &peer.addr)?,
+                    peer,
+                });
+            }
+            vrfs.push(VrfSnapshot {
+                vrf_id,
+                peers: peer_snapshots,
+                routes,
+                prefixes: rib.prefixes(vrf_id)?,
+            });
+            ftns.extend(lfib.indexed_ftns(vrf_id)?);
+        }
+        Ok(Snapshot {
+            best_path: rib.best_path_algorithm(),
+            dampening: rib.dampening(),
+            prefix_lists: rib.prefix_list_snapshots(),
+            policies: rib.policy_snapshots(),
+            vrfs,
+            next_hops: lfib.next_hops(),
+            ftns,
+            ilms: lfib.indexed_ilms(),
+            ids: lfib.used_ids(),
+            max_label_depth: lfib.max_label_depth(),
+            label_checking: lfib.label_checking(),
+            label_blocks: lfib.label_blocks(0, usize::MAX),
+        })
+    }
+    /// Loads the snapshot into the empty store `ds`. Readers see the store
+    /// either empty or restored and events go out once it is.
+    pub(crate) fn restore(&self, ds: &DataStore) -> Result<(), DsError> {
+        ds.notifier.stage();
+        let restored = ds.rib().locked(|| ds.lfib().locked(|| self.apply(ds)));
+        // a failed restore is not undone, so what it left is reported too
+        ds.notifier.unstage(true);
+        ds.notifier.deliver();
+        restored
+    }
+    /// Next hops and prefixes go in before the routes and LSPs depending on
+    /// them, so that everything is resolved once. Routes go in as they were
+    /// stored, before the peers get their policies back, and dampening is
+    /// turned on last, so that restoring neither evaluates policies nor
+    /// counts flaps.
+    fn apply(&self, ds: &DataStore) -> Result<(), DsError> {
+        if !ds.rib().is_empty() || !ds.lfib().is_empty() {
+            return Err(ds_error(
+                DsError::AlreadyExists,
+                String::from("snapshot can only be restored into an empty store"),
+            ));
+        }
+        let rib = ds.rib();
+        let lfib = ds.lfib();
+        rib.set_best_path_algorithm(self.best_path);
+        rib.restore_policies(&self.prefix_lists, &self.policies)?;
+        lfib.set_max_label_depth(self.max_label_depth)?;
+        for vrf in self.vrfs.iter() {
+            if vrf.vrf_id != DEFAULT_VRF_ID {
+                ds.vrf_create(vrf.vrf_id)?;
+            }
+        }
+        for (addr, ifindex) in self.next_hops.iter() {
+            lfib.add_nh(addr, *ifindex);
+        }
+        for vrf in self.vrfs.iter() {
+            for peer in vrf.peers.iter() {
+                rib.add_peer(vrf.vrf_id, &peer.peer)?;
+            }
+            for entry in vrf.prefixes.iter() {
+                rib.add_prefix(vrf.vrf_id, entry)?;
+            }
+            for peer_route in vrf.routes.iter() {
+                rib.add_route(vrf.vrf_id, &peer_route.peer, &peer_route.route)?;
+            }
+            for peer in vrf.peers.iter() {
+                rib.restore_peer_status(vrf.vrf_id, &peer.peer.addr, &peer.status, peer.policy)?;
+            }
+        }
+        for ftn in self.ftns.iter() {
+            lfib.restore_ftn(ftn)?;
+        }
+        lfib.restore_labels(self.label_checking, &self.label_blocks)?;
+        for ilm in self.ilms.iter() {
+            lfib.restore_ilm(ilm)?;
+        }
+        lfib.set_used_ids(&self.ids)?;
+        rib.set_dampening(self.dampening)
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        SNAPSHOT_VERSION.put(&mut out);
+        (self.best_path as u32).put(&mut out);
+        self.dampening.put(&mut out);
+        self.prefix_lists.put(&mut out);
+        self.policies.put(&mut out);
+        self.vrfs.put(&mut out);
+        self.next_hops.put(&mut out);
+        self.ftns.put(&mut out);
+        self.ilms.put(&mut out);
+        self.ids.xc.put(&mut out);
+        self.ids.nhlfe.put(&mut out);
+        self.ids.ilm.put(&mut out);
+        self.max_label_depth.put(&mut out);
+        self.label_checking.put(&mut out);
+        self.label_blocks.put(&mut out);
+        fnv1a(&out).put(&mut out);
+        out
+    }
+    fn decode(bytes: &[u8]) -> Result<Snapshot, DsError> {
+        if bytes.len() < MAGIC.len() + 8 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data(String::from("not a data store snapshot")));
+        }
+        let (data, checksum) = bytes.split_at(bytes.len() - 4);
+        let mut input = Input {
+            bytes: data,
+            pos: MAGIC.len(),
+        };
+        let version = u32::get(&mut input)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "snapshot version {} is not supported",
+                version
+            )));
+        }
+        let expected = u32::get(&mut Input {
+            bytes: checksum,
+            pos: 0,
+        })?;
+        if fnv1a(data) != expected {
+            return Err(invalid_data(String::from("snapshot checksum mismatch")));
+        }
+        let best_path = u32::get(&mut input)?;
+        let best_path = BestPathAlgorithm::from_u32(best_path)
+            .ok_or_else(|| invalid_data(format!("unknown best path algorithm {}", best_path)))?;
+        let snapshot = Snapshot {
+            best_path,
+            dampening: Option::get(&mut input)?,
+            prefix_lists: Vec::get(&mut input)?,
+            policies: Vec::get(&mut input)?,
+            vrfs: Vec::get(&mut input)?,
+            next_hops: Vec::get(&mut input)?,
+            ftns: Vec::get(&mut input)?,
+            ilms: Vec::get(&mut input)?,
+            ids: UsedIds {
+                xc: TableIds::get(&mut input)?,
+                nhlfe: TableIds::get(&mut input)?,
+                ilm: TableIds::get(&mut input)?,
+            },
+            max_label_depth: u32::get(&mut input)?,
+            label_checking: bool::get(&mut input)?,
+            label_blocks: Vec::get(&mut input)?,
+        };
+        if input.pos != data.len() {
+            return Err(invalid_data(format!(
+                "{} unexpected bytes at the end of the snapshot",
+                data.len() - input.pos
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+pub(crate) fn write<W: Write>(ds: &DataStore, writer: &mut W) -> Result<(), DsError> {
+    let snapshot = ds
+        .rib()
+        .locked(|| ds.lfib().locked(|| Snapshot::capture(ds)))?;
+    let bytes = snapshot.encode();
+    trace!("snapshot::write {} bytes", bytes.len());
+    writer
+        .write_all(&bytes)
+        .and_then(|()| writer.flush())
+        .map_err(|err| ds_error(DsError::Io, format!("cannot write snapshot: {}", err)))
+}
+
+pub(crate) fn read<R: Read>(ds: &DataStore, reader: &mut R) -> Result<(), DsError> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|err| ds_error(DsError::Io, format!("cannot read snapshot: {}", err)))?;
+    trace!("snapshot::read {} bytes", bytes.len());
+    Snapshot::decode(&bytes)?.restore(ds)
+}
+
+pub(crate) fn save(ds: &DataStore, path: &Path) -> Result<(), DsError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let saved = fs::File::create(&tmp_path)
+        .map_err(|err| io_error(err, &tmp_path))
+        .and_then(|mut file| {
+            write(ds, &mut file)?;
+            file.sync_all().map_err(|err| io_error(err, &tmp_path))
+        })
+        .and_then(|()| fs::rename(&tmp_path, path).map_err(|err| io_error(err, path)));
+    if saved.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    saved
+}
+
+pub(crate) fn load(ds: &DataStore, path: &Path) -> Result<(), DsError> {
+    let mut file = fs::File::open(path).map_err(|err| io_error(err, path))?;
+    read(ds, &mut file)
+}
+
+pub(crate) fn path_from_user(path: *const c_char) -> Result<PathBuf, DsError> {
+    if path.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("path is null"),
+        ));
+    }
+    match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("path is not valid UTF-8"),
+        )),
+    }
+}
+
+/// Writes the whole store to the file at `path`. The file is replaced only
+/// once the snapshot is complete, a crash in between leaves the previous
+/// one in place.
+#[no_mangle]
+pub extern "C" fn ds_snapshot_save(ds: *mut DataStore, path: *const c_char) -> DsError {
+    trace!("snapshot_save");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    match path_from_user(path) {
+        Ok(path) => ds_result(ds.save_snapshot(path)),
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn snapshot_save(path: *const c_char) -> DsError {
+    ds_snapshot_save(default_data_store(), path)
+}
+
+/// Loads the snapshot in the file at `path` into `ds`, which must be empty:
+/// only the default VRF, with no peers, routes, prefixes, next hops, FTN or
+/// ILM entries, label blocks, prefix lists or policies. XC, NHLFE and ILM
+/// entries keep the indexes they had when the snapshot was saved, peers
+/// their status and policy. Graceful restarts in progress and dampening
+/// penalties are not restored. Returns `DS_ERROR_INVALID_DATA` for a
+/// corrupt snapshot or one of another version.
+#[no_mangle]
+pub extern "C" fn ds_snapshot_restore(ds: *mut DataStore, path: *const c_char) -> DsError {
+    trace!("snapshot_restore");
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    match path_from_user(path) {
+        Ok(path) => ds_result(ds.load_snapshot(path)),
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn snapshot_restore(path: *const c_char) -> DsError {
+    ds_snapshot_restore(default_data_store(), path)
+}
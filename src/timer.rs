@@ -0,0 +1,121 @@
+//! Timers of a store. One thread per store sleeps until the earliest
+//! deadline of a heap and runs what is due, so that the number of pending
+//! timers does not cost threads. The thread starts with the first timer and
+//! stops with the store; timers still pending then are dropped.
+
+use log::*;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type TimerJob = Box<dyn FnOnce() + Send>;
+
+struct Deadline {
+    at: Instant,
+    /// Keeps timers of the same deadline in the order they were started.
+    seq: u64,
+    job: TimerJob,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Deadline) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Deadline) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed, so that the heap hands out the earliest deadline first.
+impl Ord for Deadline {
+    fn cmp(&self, other: &Deadline) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct TimerState {
+    deadlines: BinaryHeap<Deadline>,
+    next_seq: u64,
+    running: bool,
+    stopped: bool,
+}
+
+struct TimerInner {
+    state: Mutex<TimerState>,
+    wakeup: Condvar,
+}
+
+#[derive(Clone)]
+pub(crate) struct Timer {
+    inner: Arc<TimerInner>,
+}
+
+impl Timer {
+    pub(crate) fn new() -> Timer {
+        Timer {
+            inner: Arc::new(TimerInner {
+                state: Mutex::new(TimerState::default()),
+                wakeup: Condvar::new(),
+            }),
+        }
+    }
+    /// Runs `job` on the timer thread once `after` is over. Jobs run one at
+    /// a time, without the timer lock, so they may start timers themselves.
+    pub(crate) fn start(&self, after: Duration, job: impl FnOnce() + Send + 'static) {
+        let mut state = self.inner.state.lock();
+        if state.stopped {
+            return;
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.deadlines.push(Deadline {
+            at: Instant::now() + after,
+            seq,
+            job: Box::new(job),
+        });
+        if !state.running {
+            state.running = true;
+            let inner = Arc::clone(&self.inner);
+            thread::spawn(move || run(&inner));
+        }
+        self.inner.wakeup.notify_one();
+    }
+    /// Ends the thread and drops the pending timers.
+    pub(crate) fn stop(&self) {
+        let mut state = self.inner.state.lock();
+        state.stopped = true;
+        state.deadlines.clear();
+        self.inner.wakeup.notify_one();
+    }
+}
+
+fn run(inner: &TimerInner) {
+    trace!("timer thread started");
+    let mut state = inner.state.lock();
+    while !state.stopped {
+        let at = match state.deadlines.peek() {
+            Some(deadline) => deadline.at,
+            None => {
+                inner.wakeup.wait(&mut state);
+                continue;
+            }
+        };
+        if at > Instant::now() {
+            inner.wakeup.wait_until(&mut state, at);
+            continue;
+        }
+        if let Some(deadline) = state.deadlines.pop() {
+            MutexGuard::unlocked(&mut state, deadline.job);
+        }
+    }
+    trace!("timer thread stopped");
+}
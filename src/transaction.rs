@@ -0,0 +1,822 @@
+use crate::errors::*;
+use crate::external_types::*;
+use crate::mpls_sim::{
+    convert_ftn_add_to_internal, convert_ilm_add_to_internal, XcIndexed, LABEL_DEPTH_LIMIT,
+};
+use crate::utils::*;
+use crate::{
+    copy_prefix_entry_from_user, copy_route_from_user, data_store_from_handle, default_data_store,
+    DataStore, Ftn, Ilm, Peer, PeerSession, PrefixEntry, Route, RouteAttrs, RouteKey,
+};
+use log::*;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone)]
+enum TxOp {
+    AddPeer {
+        vrf_id: u32,
+        peer: Peer,
+    },
+    DeletePeer {
+        vrf_id: u32,
+        addr: IpAddr,
+    },
+    AddRoute {
+        vrf_id: u32,
+        peer: IpAddr,
+        route: Route,
+    },
+    DeleteRoute {
+        vrf_id: u32,
+        peer: IpAddr,
+        prefix: IpAddr,
+        prefix_len: u8,
+    },
+    AddPrefix {
+        vrf_id: u32,
+        entry: PrefixEntry,
+    },
+    DeletePrefix {
+        vrf_id: u32,
+        prefix: IpAddr,
+        prefix_len: u8,
+    },
+    AddNh {
+        addr: IpAddr,
+        ifindex: u32,
+    },
+    DeleteNh {
+        addr: IpAddr,
+    },
+    AddFtn(Ftn),
+    DeleteFtn {
+        vrf_id: u32,
+        fec: IpAddr,
+        ftn_ix: u32,
+    },
+    AddIlm(Ilm),
+    DeleteIlm {
+        in_label: u32,
+        in_iface: u32,
+        ilm_ix: u32,
+        owner: u32,
+    },
+    /// Undo only: puts back what the peer announced for the prefix, without
+    /// policies, dampening or the session of the peer seeing it.
+    RestoreRoute {
+        vrf_id: u32,
+        peer: IpAddr,
+        route_key: RouteKey,
+        route: Option<Route>,
+    },
+    /// Undo only: puts back the session state of a peer, the stale routes,
+    /// flap history and counters its routes changed, once it and its
+    /// routes are there again.
+    RestorePeer {
+        vrf_id: u32,
+        addr: IpAddr,
+        session: PeerSession,
+    },
+    /// Undo only: puts back the hits route changes counted on a policy.
+    RestorePolicyHits {
+        policy_id: u32,
+        hits: Vec<u64>,
+    },
+    /// Undo only: puts back the FTN entries the FEC had, with their XC and
+    /// NHLFE indexes.
+    RestoreFtns {
+        vrf_id: u32,
+        fec: IpAddr,
+        ftns: Vec<XcIndexed<Ftn>>,
+    },
+    /// Undo only: puts back the ILM entries the incoming label had, with
+    /// their ilm_ix and XC and NHLFE indexes.
+    RestoreIlms {
+        in_label: u32,
+        in_iface: u32,
+        ilms: Vec<XcIndexed<Ilm>>,
+    },
+}
+
+impl TxOp {
+    /// Applies the operation and returns the ones undoing it, in the order
+    /// they have to be applied.
+    fn apply(&self, ds: &DataStore) -> Result<Vec<TxOp>, DsError> {
+        trace!("TxOp::apply {:?}", self);
+        match self {
+            TxOp::AddPeer { vrf_id, peer } => {
+                let undo = match ds.rib.lookup_peer(*vrf_id, &peer.addr) {
+                    Ok(old) => TxOp::AddPeer {
+                        vrf_id: *vrf_id,
+                        peer: old,
+                    },
+                    Err(_) => TxOp::DeletePeer {
+                        vrf_id: *vrf_id,
+                        addr: peer.addr,
+                    },
+                };
+                ds.rib.add_peer(*vrf_id, peer)?;
+                Ok(vec![undo])
+            }
+            TxOp::DeletePeer { vrf_id, addr } => {
+                let peer = ds.rib.lookup_peer(*vrf_id, addr)?;
+                let session = ds.rib.peer_session(*vrf_id, addr)?;
+                let routes = ds.rib.peer_routes(*vrf_id, addr, None, usize::MAX)?;
+                ds.rib.delete_peer(*vrf_id, addr)?;
+                let mut undo = vec![TxOp::AddPeer {
+                    vrf_id: *vrf_id,
+                    peer,
+                }];
+                undo.extend(routes.into_iter().map(|route| TxOp::RestoreRoute {
+                    vrf_id: *vrf_id,
+                    peer: *addr,
+                    route_key: (route.prefix, route.prefix_len),
+                    route: Some(route),
+                }));
+                undo.push(TxOp::RestorePeer {
+                    vrf_id: *vrf_id,
+                    addr: *addr,
+                    session,
+                });
+                Ok(undo)
+            }
+            TxOp::AddRoute {
+                vrf_id,
+                peer,
+                route,
+            } => {
+                let undo = route_undo(ds, *vrf_id, peer, &(route.prefix, route.prefix_len))?;
+                if let Err(err) = ds.rib.add_route(*vrf_id, peer, route) {
+                    // a denied update still withdraws the route and counts
+                    return Err(undo_failed(ds, &undo, err));
+                }
+                Ok(undo)
+            }
+            TxOp::DeleteRoute {
+                vrf_id,
+                peer,
+                prefix,
+                prefix_len,
+            } => {
+                ds.rib
+                    .lookup_peer_route(*vrf_id, peer, prefix, *prefix_len)?;
+                let undo = route_undo(ds, *vrf_id, peer, &(*prefix, *prefix_len))?;
+                ds.rib.delete_route(*vrf_id, peer, prefix, *prefix_len)?;
+                Ok(undo)
+            }
+            TxOp::AddPrefix { vrf_id, entry } => {
+                let undo = match ds
+                    .rib
+                    .lookup_prefix(*vrf_id, &entry.prefix, entry.prefix_len)
+                {
+                    Ok(old) => TxOp::AddPrefix {
+                        vrf_id: *vrf_id,
+                        entry: old,
+                    },
+                    Err(_) => TxOp::DeletePrefix {
+                        vrf_id: *vrf_id,
+                        prefix: entry.prefix,
+                        prefix_len: entry.prefix_len,
+                    },
+                };
+                ds.rib.add_prefix(*vrf_id, entry)?;
+                Ok(vec![undo])
+            }
+            TxOp::DeletePrefix {
+                vrf_id,
+                prefix,
+                prefix_len,
+            } => {
+                let entry = ds.rib.lookup_prefix(*vrf_id, prefix, *prefix_len)?;
+                ds.rib.delete_prefix(*vrf_id, prefix, *prefix_len)?;
+                Ok(vec![TxOp::AddPrefix {
+                    vrf_id: *vrf_id,
+                    entry,
+                }])
+            }
+            TxOp::AddNh { addr, ifindex } => {
+                let undo = match ds.lfib.lookup_nh(addr) {
+                    Some(old) => TxOp::AddNh {
+                        addr: *addr,
+                        ifindex: old,
+                    },
+                    None => TxOp::DeleteNh { addr: *addr },
+                };
+                ds.add_nh(addr, *ifindex);
+                Ok(vec![undo])
+            }
+            TxOp::DeleteNh { addr } => {
+                let undo = ds.lfib.lookup_nh(addr).map(|old| TxOp::AddNh {
+                    addr: *addr,
+                    ifindex: old,
+                });
+                ds.delete_nh(addr);
+                Ok(undo.into_iter().collect())
+            }
+            TxOp::AddFtn(ftn) => {
+                let ftns = ds.lfib.ftns_at(ftn.vrf_id, &ftn.fec)?;
+                ds.lfib.add_ftn(ftn)?;
+                Ok(vec![TxOp::RestoreFtns {
+                    vrf_id: ftn.vrf_id,
+                    fec: ftn.fec,
+                    ftns,
+                }])
+            }
+            TxOp::DeleteFtn {
+                vrf_id,
+                fec,
+                ftn_ix,
+            } => {
+                let ftns = ds.lfib.ftns_at(*vrf_id, fec)?;
+                ds.lfib.delete_ftn(*vrf_id, fec, *ftn_ix)?;
+                Ok(vec![TxOp::RestoreFtns {
+                    vrf_id: *vrf_id,
+                    fec: *fec,
+                    ftns,
+                }])
+            }
+            TxOp::AddIlm(ilm) => {
+                let ilms = ds.lfib.ilms_at(ilm.in_label, ilm.in_iface);
+                ds.lfib.add_ilm(ilm)?;
+                Ok(vec![TxOp::RestoreIlms {
+                    in_label: ilm.in_label,
+                    in_iface: ilm.in_iface,
+                    ilms,
+                }])
+            }
+            TxOp::DeleteIlm {
+                in_label,
+                in_iface,
+                ilm_ix,
+                owner,
+            } => {
+                let ilms = ds.lfib.ilms_at(*in_label, *in_iface);
+                ds.lfib.delete_ilm(*in_label, *in_iface, *ilm_ix, *owner)?;
+                Ok(vec![TxOp::RestoreIlms {
+                    in_label: *in_label,
+                    in_iface: *in_iface,
+                    ilms,
+                }])
+            }
+            TxOp::RestoreRoute {
+                vrf_id,
+                peer,
+                route_key,
+                route,
+            } => {
+                ds.rib
+                    .restore_peer_route(*vrf_id, peer, route_key, route.as_ref())?;
+                Ok(Vec::new())
+            }
+            TxOp::RestorePeer {
+                vrf_id,
+                addr,
+                session,
+            } => {
+                ds.rib.restore_peer_session(*vrf_id, addr, session)?;
+                Ok(Vec::new())
+            }
+            TxOp::RestorePolicyHits { policy_id, hits } => {
+                ds.rib.restore_policy_hits(*policy_id, hits)?;
+                Ok(Vec::new())
+            }
+            TxOp::RestoreFtns { vrf_id, fec, ftns } => {
+                for ftn in ds.lfib.ftns_at(*vrf_id, fec)? {
+                    ds.lfib.delete_ftn(*vrf_id, fec, ftn.entry.ftn_ix)?;
+                }
+                for ftn in ftns.iter() {
+                    ds.lfib.restore_ftn(ftn)?;
+                }
+                Ok(Vec::new())
+            }
+            TxOp::RestoreIlms {
+                in_label,
+                in_iface,
+                ilms,
+            } => {
+                while let Some(ilm) = ds.lfib.ilms_at(*in_label, *in_iface).first() {
+                    ds.lfib
+                        .delete_ilm(*in_label, *in_iface, ilm.entry.ilm_ix, 0)?;
+                }
+                for ilm in ilms.iter() {
+                    ds.lfib.restore_ilm(ilm)?;
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// Undo steps of a change to the route the peer announces for `route_key`.
+/// Besides the route they put back the session of the peer and the hits of
+/// its policy, which going through policies, dampening and the counters of
+/// the peer changes too.
+fn route_undo(
+    ds: &DataStore,
+    vrf_id: u32,
+    peer: &IpAddr,
+    route_key: &RouteKey,
+) -> Result<Vec<TxOp>, DsError> {
+    let session = ds.rib.peer_session(vrf_id, peer)?;
+    let route = ds
+        .rib
+        .lookup_peer_route(vrf_id, peer, &route_key.0, route_key.1)
+        .ok();
+    let mut undo = vec![
+        TxOp::RestoreRoute {
+            vrf_id,
+            peer: *peer,
+            route_key: *route_key,
+            route,
+        },
+        TxOp::RestorePeer {
+            vrf_id,
+            addr: *peer,
+            session,
+        },
+    ];
+    if let Some(policy_id) = ds.rib.peer_policy(vrf_id, peer)? {
+        // the policy the peer is attached to may be deleted
+        if let Ok(hits) = ds.rib.policy_hits(policy_id) {
+            undo.push(TxOp::RestorePolicyHits { policy_id, hits });
+        }
+    }
+    Ok(undo)
+}
+
+/// Runs the undo steps of an operation that failed after changing the
+/// store, and returns its error with the detail it had.
+fn undo_failed(ds: &DataStore, undo: &[TxOp], err: DsError) -> DsError {
+    let detail = last_error_detail();
+    for op in undo.iter() {
+        if let Err(undo_err) = op.apply(ds) {
+            error!(
+                "undo_failed {:?} {:?} {}",
+                op,
+                undo_err,
+                last_error_detail()
+            );
+        }
+    }
+    ds_error(err, detail)
+}
+
+/// Operation of a transaction that failed, and why. `rollback_error` is
+/// set when undoing the operations before it failed too, in which case the
+/// store may hold part of the transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxError {
+    pub op_index: usize,
+    pub error: DsError,
+    pub rollback_error: Option<DsError>,
+}
+
+/// Changes staged to be applied together by `DataStore::commit`. Nothing
+/// is checked or applied while staging.
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    ops: Vec<TxOp>,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction::default()
+    }
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+    pub fn add_peer(&mut self, vrf_id: u32, peer: &Peer) -> &mut Transaction {
+        self.stage(TxOp::AddPeer {
+            vrf_id,
+            peer: peer.clone(),
+        })
+    }
+    pub fn delete_peer(&mut self, vrf_id: u32, addr: &IpAddr) -> &mut Transaction {
+        self.stage(TxOp::DeletePeer {
+            vrf_id,
+            addr: *addr,
+        })
+    }
+    pub fn add_route(&mut self, vrf_id: u32, peer: &IpAddr, route: &Route) -> &mut Transaction {
+        self.stage(TxOp::AddRoute {
+            vrf_id,
+            peer: *peer,
+            route: route.clone(),
+        })
+    }
+    pub fn delete_route(
+        &mut self,
+        vrf_id: u32,
+        peer: &IpAddr,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> &mut Transaction {
+        self.stage(TxOp::DeleteRoute {
+            vrf_id,
+            peer: *peer,
+            prefix: *prefix,
+            prefix_len,
+        })
+    }
+    pub fn add_prefix(&mut self, vrf_id: u32, entry: &PrefixEntry) -> &mut Transaction {
+        self.stage(TxOp::AddPrefix {
+            vrf_id,
+            entry: entry.clone(),
+        })
+    }
+    pub fn delete_prefix(
+        &mut self,
+        vrf_id: u32,
+        prefix: &IpAddr,
+        prefix_len: u8,
+    ) -> &mut Transaction {
+        self.stage(TxOp::DeletePrefix {
+            vrf_id,
+            prefix: *prefix,
+            prefix_len,
+        })
+    }
+    pub fn add_nh(&mut self, addr: &IpAddr, ifindex: u32) -> &mut Transaction {
+        self.stage(TxOp::AddNh {
+            addr: *addr,
+            ifindex,
+        })
+    }
+    pub fn delete_nh(&mut self, addr: &IpAddr) -> &mut Transaction {
+        self.stage(TxOp::DeleteNh { addr: *addr })
+    }
+    pub fn add_ftn(&mut self, ftn: &Ftn) -> &mut Transaction {
+        self.stage(TxOp::AddFtn(ftn.clone()))
+    }
+    pub fn delete_ftn(&mut self, vrf_id: u32, fec: &IpAddr, ftn_ix: u32) -> &mut Transaction {
+        self.stage(TxOp::DeleteFtn {
+            vrf_id,
+            fec: *fec,
+            ftn_ix,
+        })
+    }
+    pub fn add_ilm(&mut self, ilm: &Ilm) -> &mut Transaction {
+        self.stage(TxOp::AddIlm(ilm.clone()))
+    }
+    pub fn delete_ilm(
+        &mut self,
+        in_label: u32,
+        in_iface: u32,
+        ilm_ix: u32,
+        owner: u32,
+    ) -> &mut Transaction {
+        self.stage(TxOp::DeleteIlm {
+            in_label,
+            in_iface,
+            ilm_ix,
+            owner,
+        })
+    }
+    fn stage(&mut self, op: TxOp) -> &mut Transaction {
+        self.ops.push(op);
+        self
+    }
+    /// Applies the operations in order. When one fails, the undo steps of
+    /// the ones before it run in reverse order; a failing undo step does not
+    /// stop the others.
+    pub(crate) fn apply(&self, ds: &DataStore) -> Result<(), TxError> {
+        let mut undo_log: Vec<Vec<TxOp>> = Vec::with_capacity(self.ops.len());
+        for (op_index, op) in self.ops.iter().enumerate() {
+            match op.apply(ds) {
+                Ok(undo) => undo_log.push(undo),
+                Err(error) => {
+                    let detail = format!(
+                        "transaction op {} failed: {}",
+                        op_index,
+                        last_error_detail()
+                    );
+                    trace!("Transaction::apply {}", detail);
+                    let rollback_error = match Transaction::undo(ds, &undo_log) {
+                        Some((err, undo_detail)) => {
+                            ds_error(err, format!("{}; rollback failed: {}", detail, undo_detail));
+                            Some(err)
+                        }
+                        None => {
+                            ds_error(error, detail);
+                            None
+                        }
+                    };
+                    return Err(TxError {
+                        op_index,
+                        error,
+                        rollback_error,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Runs every undo step of the log, latest first, and returns the first
+    /// failure with its detail.
+    fn undo(ds: &DataStore, undo_log: &[Vec<TxOp>]) -> Option<(DsError, String)> {
+        let mut failure = None;
+        for undo in undo_log.iter().rev().flatten() {
+            if let Err(err) = undo.apply(ds) {
+                let detail = format!("{:?}: {}", undo, last_error_detail());
+                error!("Transaction::undo {:?} {}", err, detail);
+                failure.get_or_insert((err, detail));
+            }
+        }
+        failure
+    }
+}
+
+fn tx_from_handle<'a>(tx: *mut Transaction) -> Result<&'a mut Transaction, DsError> {
+    if tx.is_null() {
+        return Err(ds_error(
+            DsError::InvalidArgument,
+            String::from("transaction is null"),
+        ));
+    }
+    Ok(unsafe { &mut *tx })
+}
+
+/// Starts an empty transaction. It is freed by `ds_tx_commit` or
+/// `ds_tx_abort`, whichever is called.
+#[no_mangle]
+pub extern "C" fn ds_tx_begin() -> *mut Transaction {
+    Box::into_raw(Box::new(Transaction::new()))
+}
+
+/// Drops the transaction without applying anything.
+#[no_mangle]
+pub extern "C" fn ds_tx_abort(tx: *mut Transaction) {
+    if !tx.is_null() {
+        drop(unsafe { Box::from_raw(tx) });
+    }
+}
+
+/// Applies the transaction to the store all-or-nothing and frees it. On
+/// failure `failed_op`, which may be null, is set to the index of the
+/// operation that failed, in staging order. `RollbackFailed` means undoing
+/// the operations before it failed as well and the store may hold part of
+/// the transaction; `ds_last_error` tells which undo step failed.
+#[no_mangle]
+pub extern "C" fn ds_tx_commit(
+    ds: *mut DataStore,
+    tx: *mut Transaction,
+    failed_op: *mut u32,
+) -> DsError {
+    if tx.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("transaction is null"),
+        );
+    }
+    let tx = unsafe { Box::from_raw(tx) };
+    let ds = match data_store_from_handle(ds) {
+        Ok(ds) => ds,
+        Err(err) => return err,
+    };
+    match ds.commit(&tx) {
+        Ok(()) => DsError::Ok,
+        Err(tx_err) => {
+            if !failed_op.is_null() {
+                unsafe { *failed_op = tx_err.op_index as u32 };
+            }
+            match tx_err.rollback_error {
+                Some(_) => DsError::RollbackFailed,
+                None => tx_err.error,
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn tx_commit(tx: *mut Transaction, failed_op: *mut u32) -> DsError {
+    ds_tx_commit(default_data_store(), tx, failed_op)
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_peer_add_modify(
+    tx: *mut Transaction,
+    _prefix: &IpAddrC,
+    _entry: *mut PeerEntry,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if _entry.is_null() {
+        return ds_error(DsError::InvalidArgument, String::from("peer entry is null"));
+    }
+    unsafe {
+        let peer = Peer {
+            addr: copy_ip_addr_from_user(_prefix),
+            out_ifindex: (*_entry).out_ifindex,
+        };
+        tx.add_peer((*_entry).vrf_id, &peer);
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_peer_delete(
+    tx: *mut Transaction,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    let addr = unsafe { copy_ip_addr_from_user(_prefix) };
+    tx.delete_peer(vrf_id, &addr);
+    DsError::Ok
+}
+
+/// A null `attrs` stages the route with default attributes.
+#[no_mangle]
+pub extern "C" fn ds_tx_peer_route_add_modify(
+    tx: *mut Transaction,
+    _peer_prefix: &IpAddrC,
+    _entry: *mut RouteEntry,
+    attrs: *const RouteAttrs,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if _entry.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("route entry is null"),
+        );
+    }
+    unsafe {
+        let peer = copy_ip_addr_from_user(_peer_prefix);
+        match copy_route_from_user(_entry, attrs) {
+            Ok(route) => {
+                tx.add_route((*_entry).vrf_id, &peer, &route);
+                DsError::Ok
+            }
+            Err(err) => err,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_peer_route_delete(
+    tx: *mut Transaction,
+    vrf_id: u32,
+    _peer_prefix: &IpAddrC,
+    _route_prefix: &IpAddrC,
+    prefix_len: u8,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    unsafe {
+        let peer = copy_ip_addr_from_user(_peer_prefix);
+        let prefix = copy_ip_addr_from_user(_route_prefix);
+        tx.delete_route(vrf_id, &peer, &prefix, prefix_len);
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_longest_match_add(
+    tx: *mut Transaction,
+    _prefix: &IpAddrC,
+    _entry: *mut ForwardingEntry,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if _entry.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("forwarding entry is null"),
+        );
+    }
+    unsafe {
+        let entry = copy_prefix_entry_from_user(_prefix, _entry);
+        tx.add_prefix((*_entry).vrf_id, &entry);
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_longest_match_delete(
+    tx: *mut Transaction,
+    vrf_id: u32,
+    _prefix: &IpAddrC,
+    prefix_len: u8,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    let prefix = unsafe { copy_ip_addr_from_user(_prefix) };
+    tx.delete_prefix(vrf_id, &prefix, prefix_len);
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_nh_add_del(
+    tx: *mut Transaction,
+    nh_add_del_data: *mut NhAddDel,
+) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if nh_add_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("nh_add_del data is null"),
+        );
+    }
+    unsafe {
+        let addr = copy_ip_addr_from_user(&(*nh_add_del_data).addr);
+        if (*nh_add_del_data).is_add {
+            tx.add_nh(&addr, (*nh_add_del_data).ifindex);
+        } else {
+            tx.delete_nh(&addr);
+        }
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_ftn_add(tx: *mut Transaction, ftn_add_data: *mut FtnAddData) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    // the depth of the store is checked on commit
+    match unsafe { convert_ftn_add_to_internal(ftn_add_data, LABEL_DEPTH_LIMIT) } {
+        Ok(ftn) => {
+            tx.add_ftn(&ftn);
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_ftn_del(tx: *mut Transaction, ftn_del_data: *mut FtnDelData) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if ftn_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("ftn_del data is null"),
+        );
+    }
+    unsafe {
+        let fec = copy_ip_addr_from_user(&(*ftn_del_data).fec);
+        tx.delete_ftn((*ftn_del_data).vrf_id, &fec, (*ftn_del_data).ftn_ix);
+    }
+    DsError::Ok
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_ilm_add(tx: *mut Transaction, ilm_add_data: *mut IlmAddData) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    match unsafe { convert_ilm_add_to_internal(ilm_add_data) } {
+        Ok(ilm) => {
+            tx.add_ilm(&ilm);
+            DsError::Ok
+        }
+        Err(err) => err,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ds_tx_ilm_del(tx: *mut Transaction, ilm_del_data: *mut IlmDelData) -> DsError {
+    let tx = match tx_from_handle(tx) {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+    if ilm_del_data.is_null() {
+        return ds_error(
+            DsError::InvalidArgument,
+            String::from("ilm_del data is null"),
+        );
+    }
+    unsafe {
+        tx.delete_ilm(
+            (*ilm_del_data).in_label,
+            (*ilm_del_data).in_iface,
+            (*ilm_del_data).ilm_ix,
+            (*ilm_del_data).owner,
+        );
+    }
+    DsError::Ok
+}
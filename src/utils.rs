@@ -1,3 +1,4 @@
+use crate::external_types::IpAddrC;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[allow(dead_code)]
@@ -37,3 +38,90 @@ pub unsafe fn copy_ip_addr_v6_from_user(addr_ptr: *mut u16) -> IpAddr {
         *addr_ptr.wrapping_add(7),
     ))
 }
+
+pub unsafe fn copy_ip_addr_from_user(addr: &IpAddrC) -> IpAddr {
+    if addr.family == 1 {
+        copy_ip_addr_v4_from_user(addr.addr)
+    } else {
+        copy_ip_addr_v6_from_user(addr.addr as *mut u16)
+    }
+}
+
+/// Family as `IpAddrC` carries it, 1 for IPv4.
+pub fn ip_addr_family(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 1,
+        IpAddr::V6(_) => 2,
+    }
+}
+
+pub fn max_prefix_len(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+pub fn ip_addr_octets(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+        IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+    }
+}
+
+/// One byte per address bit, cut at `prefix_len`, so that a byte-keyed
+/// trie matches on bit boundaries.
+pub fn ip_addr_prefix_bits(addr: &IpAddr, prefix_len: u8) -> Vec<u8> {
+    let octets = ip_addr_octets(addr);
+    (0..prefix_len as usize)
+        .map(|bit| (octets[bit / 8] >> (7 - bit % 8)) & 1)
+        .collect()
+}
+
+pub fn ip_addr_apply_mask(addr: &IpAddr, prefix_len: u8) -> IpAddr {
+    let mut octets = ip_addr_octets(addr);
+    for (i, octet) in octets.iter_mut().enumerate() {
+        let bits_left = (prefix_len as usize).saturating_sub(i * 8);
+        if bits_left < 8 {
+            *octet &= !(0xffu8 >> bits_left);
+        }
+    }
+    match addr {
+        IpAddr::V4(_) => {
+            let mut v4 = [0u8; 4];
+            v4.copy_from_slice(&octets);
+            IpAddr::V4(Ipv4Addr::from(v4))
+        }
+        IpAddr::V6(_) => {
+            let mut v6 = [0u8; 16];
+            v6.copy_from_slice(&octets);
+            IpAddr::V6(Ipv6Addr::from(v6))
+        }
+    }
+}
+
+/// Netmask of `prefix_len` bits in the family of `addr`.
+pub fn prefix_len_mask(addr: &IpAddr, prefix_len: u8) -> IpAddr {
+    let all_ones = match addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from([0xffu8; 4])),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from([0xffu8; 16])),
+    };
+    ip_addr_apply_mask(&all_ones, prefix_len)
+}
+
+/// Prefix length of a netmask, `None` if the mask is not contiguous.
+pub fn mask_prefix_len(mask: &IpAddr) -> Option<u8> {
+    let prefix_len = ip_addr_octets(mask)
+        .iter()
+        .map(|octet| octet.count_ones())
+        .sum::<u32>() as u8;
+    if prefix_len_mask(mask, prefix_len) != *mask {
+        return None;
+    }
+    Some(prefix_len)
+}
+
+/// True if `prefix_len` fits the address and no host bits are set.
+pub fn ip_prefix_is_valid(addr: &IpAddr, prefix_len: u8) -> bool {
+    prefix_len <= max_prefix_len(addr) && ip_addr_apply_mask(addr, prefix_len) == *addr
+}